@@ -0,0 +1,73 @@
+// Compares `BossName` (an interned atom) with `Arc<str>` and `String` for
+// equality-heavy workloads, like matching tweets to followed bosses. Run with
+// `cargo bench --bench boss_name`.
+extern crate petronel;
+
+use petronel::model::BossName;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const BOSSES: usize = 200;
+const LOOKUPS: usize = 1_000_000;
+
+fn names() -> Vec<String> {
+    (0..BOSSES)
+        .map(|i| format!("Lvl {} Ozorotter Variant {}", i % 150, i))
+        .collect()
+}
+
+fn nanos_per_op(elapsed: Duration, ops: usize) -> f64 {
+    let nanos = elapsed.as_secs() as f64 * 1e9 + f64::from(elapsed.subsec_nanos());
+    nanos / ops as f64
+}
+
+// Looks up names in a set of followed bosses, as the worker does for
+// every tweet, then compares pairs of names directly
+fn run<T, F>(label: &str, make: F)
+where
+    T: Clone + Eq + Hash,
+    F: Fn(&str) -> T,
+{
+    let names = names();
+    let followed = names
+        .iter()
+        .step_by(2)
+        .map(|name| make(name.as_str()))
+        .collect::<HashSet<_>>();
+    let tweets = names.iter().map(|name| make(name.as_str())).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut found = 0;
+    for i in 0..LOOKUPS {
+        if followed.contains(&tweets[i % tweets.len()]) {
+            found += 1;
+        }
+    }
+    let lookup = nanos_per_op(start.elapsed(), LOOKUPS);
+
+    let start = Instant::now();
+    let mut equal = 0;
+    for i in 0..LOOKUPS {
+        let a = tweets[i % tweets.len()].clone();
+        if a == tweets[(i * 7) % tweets.len()] {
+            equal += 1;
+        }
+    }
+    let compare = nanos_per_op(start.elapsed(), LOOKUPS);
+
+    println!(
+        "{:<10} lookup: {:>7.1} ns/op   clone+eq: {:>7.1} ns/op   ({} found, {} equal)",
+        label, lookup, compare, found, equal
+    );
+}
+
+// The closures can't be replaced with the `From` impls, which aren't generic
+// over the lifetime of the name
+#[allow(clippy::redundant_closure)]
+fn main() {
+    run("BossName", |name| BossName::from(name));
+    run("Arc<str>", |name| Arc::<str>::from(name));
+    run("String", |name| name.to_string());
+}
@@ -9,7 +9,7 @@ extern crate tokio_core;
 
 use futures::{Future, Stream};
 use hyper_tls::HttpsConnector;
-use petronel::{ClientBuilder, Token};
+use petronel::{ClientBuilder, MapperCheck, Token, TokenExt};
 use petronel::error::*;
 use std::time::Duration;
 use tokio_core::reactor::{Core, Interval};
@@ -19,12 +19,12 @@ fn env(name: &str) -> Result<String> {
 }
 
 quick_main!(|| -> Result<()> {
-    let token = Token::new(
+    let token = Token::new_checked(
         env("CONSUMER_KEY")?,
         env("CONSUMER_SECRET")?,
         env("ACCESS_TOKEN")?,
         env("ACCESS_TOKEN_SECRET")?,
-    );
+    )?;
 
     let mut core = Core::new().chain_err(|| "failed to create Core")?;
 
@@ -34,7 +34,10 @@ quick_main!(|| -> Result<()> {
         .connector(HttpsConnector::new(4, &handle).chain_err(|| "HTTPS error")?)
         .build(&handle);
 
-    let (client, worker) = ClientBuilder::from_hyper_client(&hyper_client, &token).build();
+    // Nothing subscribes to messages here, so there's no mapper to check
+    let (client, worker) = ClientBuilder::from_hyper_client(&hyper_client, &token)
+        .with_mapper_check(MapperCheck::Ignore)
+        .build()?;
 
     // Fetch boss list once per 5 seconds
     let interval = Interval::new(Duration::new(5, 0), &handle)
@@ -6,7 +6,7 @@ extern crate petronel;
 extern crate tokio_core;
 
 use futures::Stream;
-use petronel::Token;
+use petronel::{Token, TokenExt};
 use petronel::error::*;
 use tokio_core::reactor::Core;
 
@@ -15,12 +15,12 @@ fn env(name: &str) -> Result<String> {
 }
 
 quick_main!(|| -> Result<()> {
-    let token = Token::new(
+    let token = Token::new_checked(
         env("CONSUMER_KEY")?,
         env("CONSUMER_SECRET")?,
         env("ACCESS_TOKEN")?,
         env("ACCESS_TOKEN_SECRET")?,
-    );
+    )?;
 
     let mut core = Core::new().chain_err(|| "failed to create Core")?;
 
@@ -1,9 +1,23 @@
-use futures::Sink;
-use std::collections::HashMap;
+#[cfg(any(feature = "gbf-compat", feature = "http-service"))]
+use bytes::Bytes;
+use error::Error;
+use futures::{Async, Poll, Sink, Stream};
+use futures::task::{self, Task};
+use model::{Message, MessageFilter, MessageKind};
+use protocol::OwnedMessage;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::hash::Hash;
+use std::rc::Rc;
+use std::sync::Arc;
 
+// Subscribers are sent items that were already mapped (e.g., serialized)
+// by the `MessageMapper`. Unless the mapper is sequenced, a message is
+// mapped once, and every recipient is given a reference to the same item.
 pub trait Subscriber {
-    type Item;
+    type Item: SharedPayload;
 
     fn send(&mut self, message: &Self::Item) -> Result<(), ()>;
     fn maybe_send(&mut self, message: Option<&Self::Item>) -> Result<(), ()> {
@@ -13,12 +27,30 @@ pub trait Subscriber {
             Ok(())
         }
     }
+
+    // Whether the subscriber has stopped accepting messages without
+    // disconnecting, e.g., if the other end stopped reading. Stalled
+    // subscribers are evicted by the worker's idle sweep, if enabled.
+    fn is_stalled(&mut self) -> bool {
+        false
+    }
 }
 
+// Mapped items whose clones share the original's contents, so that a
+// payload serialized once isn't copied for each recipient. Wrap other
+// mappers with `protocol::shared` to get an `Arc`.
+pub trait SharedPayload: Clone {}
+
+impl<T> SharedPayload for Arc<T> {}
+impl<T> SharedPayload for Rc<T> {}
+#[cfg(any(feature = "gbf-compat", feature = "http-service"))]
+impl SharedPayload for Bytes {}
+impl SharedPayload for () {}
+
 impl<S> Subscriber for S
 where
     S: Sink,
-    S::SinkItem: Clone,
+    S::SinkItem: SharedPayload,
 {
     type Item = S::SinkItem;
 
@@ -29,6 +61,225 @@ where
     }
 }
 
+// Converts messages into subscriber items. This is implemented for the
+// functions given to `ClientBuilder::filter_map_message`, and for
+// `SeqMapper` and `DynamicMapper`.
+pub trait MessageMapper<T> {
+    // `seq` is the number of messages already sent to the subscriber, and
+    // is only meaningful if `is_sequenced` returns true
+    fn map(&self, seq: u64, message: Message) -> Option<T>;
+
+    // If set, messages are mapped separately for each subscriber, instead
+    // of once for all subscribers
+    fn is_sequenced(&self) -> bool {
+        false
+    }
+
+    // Replaces the mapper with a `BoxMapper<T>`, given as `Any` since
+    // events aren't generic over `T`. Returns false if the mapper can't be
+    // replaced.
+    fn replace(&self, _mapper: Box<Any>) -> bool {
+        false
+    }
+}
+
+impl<F, T> MessageMapper<T> for F
+where
+    F: Fn(Message) -> Option<T>,
+{
+    fn map(&self, _seq: u64, message: Message) -> Option<T> {
+        self(message)
+    }
+}
+
+// A mapper set by `ClientBuilder::filter_map_message_seq`. Each subscriber's
+// sequence numbers start at 0, and increase by 1 for every message sent to
+// it, so gaps mean messages were missed. Since they're `u64`, they
+// effectively never wrap around.
+#[derive(Clone, Debug)]
+pub struct SeqMapper<G>(pub(crate) G);
+
+impl<G, T> MessageMapper<T> for SeqMapper<G>
+where
+    G: Fn(u64, Message) -> Option<T>,
+{
+    fn map(&self, seq: u64, message: Message) -> Option<T> {
+        (self.0)(seq, message)
+    }
+
+    fn is_sequenced(&self) -> bool {
+        true
+    }
+}
+
+pub type BoxMapper<T> = Box<Fn(Message) -> Option<T>>;
+
+// A mapper set by `ClientBuilder::with_dynamic_mapper`, which can be
+// replaced with `Client::set_message_mapper` while the worker is running
+pub struct DynamicMapper<T>(RefCell<BoxMapper<T>>);
+
+impl<T> DynamicMapper<T> {
+    pub(crate) fn new(mapper: BoxMapper<T>) -> Self {
+        DynamicMapper(RefCell::new(mapper))
+    }
+}
+
+impl<T: 'static> MessageMapper<T> for DynamicMapper<T> {
+    fn map(&self, _seq: u64, message: Message) -> Option<T> {
+        (self.0.borrow())(message)
+    }
+
+    fn replace(&self, mapper: Box<Any>) -> bool {
+        match mapper.downcast::<BoxMapper<T>>() {
+            Ok(mapper) => {
+                *self.0.borrow_mut() = *mapper;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl<T> fmt::Debug for DynamicMapper<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DynamicMapper")
+    }
+}
+
+// A message to be sent to one or more broadcasts. Unless the mapper is
+// sequenced, it's mapped up front, so it's only mapped once. The original
+// message is kept for stream subscribers, which aren't sent mapped items.
+pub(crate) enum Outgoing<'a, T> {
+    Mapped(Message<'a>, Option<T>),
+    Unmapped(Message<'a>),
+    Skipped,
+}
+
+impl<'a, T> Outgoing<'a, T> {
+    pub(crate) fn new<F>(message: Message<'a>, mapper: &F) -> Self
+    where
+        F: MessageMapper<T>,
+    {
+        if mapper.is_sequenced() {
+            Outgoing::Unmapped(message)
+        } else {
+            let item = mapper.map(0, message.clone());
+            Outgoing::Mapped(message, item)
+        }
+    }
+
+    // A message that isn't sent to anyone
+    pub(crate) fn none() -> Self {
+        Outgoing::Skipped
+    }
+}
+
+// The number of messages a `MessageStream` holds before dropping the oldest
+pub(crate) const MESSAGE_STREAM_CAPACITY: usize = 256;
+
+// Messages waiting to be taken from a `MessageStream`
+#[derive(Debug)]
+struct MessageQueue {
+    messages: VecDeque<OwnedMessage>,
+    capacity: usize,
+    // Whether messages were dropped because the queue was full
+    desynced: bool,
+    // Set once the `MessageStream` is dropped
+    closed: bool,
+    task: Option<Task>,
+}
+
+impl MessageQueue {
+    fn notify(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+// The worker's end of a `MessageStream`. Unlike other subscribers, it's
+// given owned copies of messages, without going through the mapper.
+#[derive(Clone, Debug)]
+pub(crate) struct MessageSender(Rc<RefCell<MessageQueue>>);
+
+impl MessageSender {
+    fn send(&self, message: &Message) -> Result<(), ()> {
+        let mut queue = self.0.borrow_mut();
+        if queue.closed {
+            return Err(());
+        }
+
+        if queue.messages.len() >= queue.capacity {
+            queue.messages.pop_front();
+            queue.desynced = true;
+        }
+
+        queue.messages.push_back(message.clone().into());
+        queue.notify();
+        Ok(())
+    }
+}
+
+// The stream ends once every copy of the sender is dropped, i.e., after the
+// subscription is unsubscribed
+impl Drop for MessageSender {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.0.try_borrow_mut() {
+            queue.notify();
+        }
+    }
+}
+
+// Messages for a subscription created by `Client::message_stream`
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct MessageStream(Rc<RefCell<MessageQueue>>);
+
+impl MessageStream {
+    pub(crate) fn new(capacity: usize) -> (MessageSender, Self) {
+        let queue = Rc::new(RefCell::new(MessageQueue {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            desynced: false,
+            closed: false,
+            task: None,
+        }));
+
+        (MessageSender(queue.clone()), MessageStream(queue))
+    }
+
+    // Whether messages were dropped because the stream wasn't read quickly
+    // enough. When full, the oldest message is dropped to make room.
+    pub fn is_desynced(&self) -> bool {
+        self.0.borrow().desynced
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = OwnedMessage;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let senders = Rc::strong_count(&self.0) - 1;
+        let mut queue = self.0.borrow_mut();
+
+        if let Some(message) = queue.messages.pop_front() {
+            Ok(Async::Ready(Some(message)))
+        } else if senders == 0 {
+            Ok(Async::Ready(None))
+        } else {
+            queue.task = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        self.0.borrow_mut().closed = true;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NoOpSubscriber;
 impl Subscriber for NoOpSubscriber {
@@ -39,8 +290,66 @@ impl Subscriber for NoOpSubscriber {
     }
 }
 
+#[derive(Clone)]
+enum Target<S> {
+    Mapped(S),
+    Stream(MessageSender),
+}
+
+// A subscriber can be in multiple broadcasts (e.g., all subscribers, and
+// the followers of a boss), sharing the same filter and sequence number
+struct Entry<S> {
+    target: Target<S>,
+    filter: MessageFilter,
+    // Number of messages sent to the subscriber, when sequencing
+    seq: Rc<Cell<u64>>,
+}
+
+impl<S: Subscriber> Entry<S> {
+    // Returns whether the message was sent, i.e., the subscriber accepts
+    // messages of this kind, and the mapper didn't return `None`
+    fn send_unmapped<F>(&mut self, message: &Message, mapper: &F) -> Result<bool, ()>
+    where
+        F: MessageMapper<S::Item>,
+    {
+        if !self.filter.contains(message.kind()) {
+            return Ok(false);
+        }
+
+        let subscriber = match self.target {
+            Target::Mapped(ref mut subscriber) => subscriber,
+            Target::Stream(ref sender) => return sender.send(message).map(|_| true),
+        };
+
+        let seq = self.seq.get();
+        match mapper.map(seq, message.clone()) {
+            Some(item) => {
+                subscriber.send(&item)?;
+                self.seq.set(seq + 1);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Like `send_unmapped`, for a message that was already mapped
+    fn send_mapped(&mut self, message: &Message, item: Option<&S::Item>) -> Result<bool, ()> {
+        if !self.filter.contains(message.kind()) {
+            return Ok(false);
+        }
+
+        match (&mut self.target, item) {
+            (&mut Target::Mapped(ref mut subscriber), Some(item)) => {
+                subscriber.send(item).map(|_| true)
+            }
+            (&mut Target::Mapped(_), None) => Ok(false),
+            (&mut Target::Stream(ref sender), _) => sender.send(message).map(|_| true),
+        }
+    }
+}
+
 pub struct Broadcast<Id, S> {
-    subscribers: HashMap<Id, S>,
+    subscribers: HashMap<Id, Entry<S>>,
 }
 
 impl<Id, S> Broadcast<Id, S>
@@ -57,32 +366,211 @@ where
 
 impl<Id, S> Broadcast<Id, S>
 where
-    Id: Eq + Hash,
+    Id: Clone + Eq + Hash,
     S: Subscriber,
 {
     pub fn is_empty(&self) -> bool {
         self.subscribers.is_empty()
     }
 
-    pub fn get(&self, id: &Id) -> Option<&S> {
-        self.subscribers.get(id)
+    pub fn contains(&self, id: &Id) -> bool {
+        self.subscribers.contains_key(id)
     }
 
-    pub fn get_mut(&mut self, id: &Id) -> Option<&mut S> {
-        self.subscribers.get_mut(id)
+    // Whether the subscriber was added with `subscribe_stream`
+    pub(crate) fn is_stream(&self, id: &Id) -> bool {
+        match self.subscribers.get(id) {
+            Some(&Entry {
+                target: Target::Stream(_),
+                ..
+            }) => true,
+            _ => false,
+        }
     }
 
-    pub fn subscribe(&mut self, id: Id, subscriber: S) -> Option<S> {
-        self.subscribers.insert(id, subscriber)
+    // Whether the subscriber is sent mapped messages and accepts messages of
+    // this kind, so the only reason it wouldn't be sent one is the mapper
+    pub(crate) fn accepts_mapped(&self, id: &Id, kind: MessageKind) -> bool {
+        match self.subscribers.get(id) {
+            Some(&Entry {
+                target: Target::Mapped(_),
+                filter,
+                ..
+            }) => filter.contains(kind),
+            _ => false,
+        }
     }
 
-    pub fn unsubscribe(&mut self, id: &Id) -> Option<S> {
-        self.subscribers.remove(id)
+    pub fn subscribe(&mut self, id: Id, subscriber: S, filter: MessageFilter) {
+        self.insert(id, Target::Mapped(subscriber), filter);
     }
 
-    pub(crate) fn maybe_send(&mut self, message: Option<&S::Item>) {
-        if let Some(msg) = message {
-            self.send(msg)
+    // Adds a subscriber that's sent owned copies of messages, bypassing the
+    // mapper. Sends only fail once the stream is dropped.
+    pub(crate) fn subscribe_stream(
+        &mut self,
+        id: Id,
+        sender: MessageSender,
+        filter: MessageFilter,
+    ) {
+        self.insert(id, Target::Stream(sender), filter);
+    }
+
+    fn insert(&mut self, id: Id, target: Target<S>, filter: MessageFilter) {
+        let entry = Entry {
+            target,
+            filter,
+            seq: Rc::new(Cell::new(0)),
+        };
+
+        self.subscribers.insert(id, entry);
+    }
+
+    // Adds a subscriber from this broadcast to another, with the same
+    // filter and sequence number. Returns whether the subscriber exists.
+    pub fn share_with(&self, id: &Id, other: &mut Self) -> bool
+    where
+        S: Clone,
+    {
+        match self.subscribers.get(id) {
+            Some(entry) => {
+                let shared = Entry {
+                    target: entry.target.clone(),
+                    filter: entry.filter,
+                    seq: entry.seq.clone(),
+                };
+                other.subscribers.insert(id.clone(), shared);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns the previous filter, if the subscriber exists
+    pub fn set_filter(&mut self, id: &Id, filter: MessageFilter) -> Option<MessageFilter> {
+        self.subscribers
+            .get_mut(id)
+            .map(|entry| ::std::mem::replace(&mut entry.filter, filter))
+    }
+
+    // Returns whether the subscriber existed
+    pub fn unsubscribe(&mut self, id: &Id) -> bool {
+        self.subscribers.remove(id).is_some()
+    }
+
+    // Stream subscribers are never stalled, since they drop old messages
+    // instead of blocking
+    pub fn stalled(&mut self) -> HashSet<Id> {
+        self.subscribers
+            .iter_mut()
+            .filter_map(|(id, entry)| {
+                let stalled = match entry.target {
+                    Target::Mapped(ref mut subscriber) => subscriber.is_stalled(),
+                    Target::Stream(_) => false,
+                };
+
+                if stalled {
+                    Some(id.clone())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn send_outgoing<F>(
+        &mut self,
+        message: &Outgoing<S::Item>,
+        mapper: &F,
+        evicted: &mut Vec<Id>,
+    ) -> usize
+    where
+        F: MessageMapper<S::Item>,
+    {
+        self.send_outgoing_where(message, mapper, evicted, |_| true)
+    }
+
+    // Like `send_outgoing`, but only to subscribers whose IDs match
+    pub(crate) fn send_outgoing_where<F, P>(
+        &mut self,
+        message: &Outgoing<S::Item>,
+        mapper: &F,
+        evicted: &mut Vec<Id>,
+        predicate: P,
+    ) -> usize
+    where
+        F: MessageMapper<S::Item>,
+        P: Fn(&Id) -> bool,
+    {
+        let message = match *message {
+            Outgoing::Mapped(ref message, ref item) => {
+                return self.send_mapped_where(message, item.as_ref(), evicted, predicate);
+            }
+            Outgoing::Unmapped(ref message) => message,
+            Outgoing::Skipped => return 0,
+        };
+
+        let mut sent = 0;
+
+        self.subscribers.retain(|id, entry| {
+            if !predicate(id) {
+                return true;
+            }
+
+            match entry.send_unmapped(message, mapper) {
+                Ok(true) => sent += 1,
+                Ok(false) => {}
+                Err(()) => {
+                    evicted.push(id.clone());
+                    return false;
+                }
+            }
+            true
+        });
+
+        sent
+    }
+
+    pub(crate) fn send_message<F>(
+        &mut self,
+        message: Message,
+        mapper: &F,
+        evicted: &mut Vec<Id>,
+    ) -> usize
+    where
+        F: MessageMapper<S::Item>,
+    {
+        self.send_outgoing(&Outgoing::new(message, mapper), mapper, evicted)
+    }
+
+    // Sends a message to a single subscriber, without removing it if the
+    // send fails. Returns whether the message was sent.
+    pub(crate) fn send_message_to<F>(
+        &mut self,
+        id: &Id,
+        message: Message,
+        mapper: &F,
+    ) -> Result<bool, ()>
+    where
+        F: MessageMapper<S::Item>,
+    {
+        match self.subscribers.get_mut(id) {
+            Some(entry) => entry.send_unmapped(&message, mapper),
+            None => Ok(false),
+        }
+    }
+
+    // Like `send_message_to`, for a message that was already mapped. Stream
+    // subscribers are sent `message`.
+    pub(crate) fn send_mapped_to(
+        &mut self,
+        id: &Id,
+        message: &Message,
+        item: Option<&S::Item>,
+    ) -> Result<bool, ()> {
+        match self.subscribers.get_mut(id) {
+            Some(entry) => entry.send_mapped(message, item),
+            None => Ok(false),
         }
     }
 
@@ -90,9 +578,42 @@ where
         self.subscribers.len()
     }
 
-    pub fn send(&mut self, message: &S::Item) {
-        // Remove any subscribers that return an error
-        self.subscribers
-            .retain(|_, subscriber| subscriber.send(message).is_ok())
+    // Sends a message that was already mapped, or the message itself to
+    // stream subscribers, to the subscribers whose IDs match `predicate`.
+    // Returns the number of subscribers the message was
+    // sent to, skipping subscribers that don't accept messages of this kind.
+    // Any subscribers that return an error are removed, and their IDs are
+    // appended to `evicted`.
+    pub(crate) fn send_mapped_where<P>(
+        &mut self,
+        message: &Message,
+        item: Option<&S::Item>,
+        evicted: &mut Vec<Id>,
+        predicate: P,
+    ) -> usize
+    where
+        P: Fn(&Id) -> bool,
+    {
+        let mut sent = 0;
+
+        self.subscribers.retain(|id, entry| {
+            if !predicate(id) {
+                return true;
+            }
+
+            match entry.send_mapped(message, item) {
+                Ok(true) => {
+                    sent += 1;
+                    true
+                }
+                Ok(false) => true,
+                Err(()) => {
+                    evicted.push(id.clone());
+                    false
+                }
+            }
+        });
+
+        sent
     }
 }
@@ -1,6 +1,11 @@
+use std::cmp::max;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct CircularBuffer<T> {
     buffer: Vec<T>,
+    // The maximum number of items. This can be more than the capacity of
+    // `buffer`, after `remove_oldest` frees space.
+    capacity: usize,
     next_index: usize,
 }
 
@@ -8,17 +13,32 @@ impl<T> CircularBuffer<T> {
     pub fn with_capacity(capacity: usize) -> Self {
         CircularBuffer {
             buffer: Vec::with_capacity(capacity),
+            capacity,
             next_index: 0,
         }
     }
 
-    pub fn push(&mut self, item: T) {
-        if self.buffer.len() < self.buffer.capacity() {
+    // Returns the oldest item if it was overwritten, once the buffer is full
+    pub fn push(&mut self, item: T) -> Option<T> {
+        let evicted = if self.buffer.len() < self.capacity {
+            self.reserve_one();
             self.buffer.push(item);
+            None
         } else {
-            self.buffer[self.next_index] = item;
+            Some(::std::mem::replace(&mut self.buffer[self.next_index], item))
+        };
+
+        self.next_index = (self.next_index + 1) % self.capacity;
+        evicted
+    }
+
+    // Makes room for one more item if the buffer isn't full. Space is added
+    // the way `Vec` grows, but never past the buffer's capacity.
+    fn reserve_one(&mut self) {
+        let len = self.buffer.len();
+        if len == self.buffer.capacity() && len < self.capacity {
+            self.buffer.reserve_exact(max(len, 1).min(self.capacity - len));
         }
-        self.next_index = (self.next_index + 1) % self.buffer.capacity();
     }
 
     pub fn as_slices(&self) -> (&[T], &[T]) {
@@ -29,6 +49,66 @@ impl<T> CircularBuffer<T> {
     pub fn as_unordered_slice(&self) -> &[T] {
         self.buffer.as_slice()
     }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    // Removes up to `count` of the oldest items, and frees the space they
+    // used. The buffer can still grow back to its original capacity.
+    // Returns the number of items removed.
+    pub fn remove_oldest(&mut self, count: usize) -> usize {
+        let count = count.min(self.buffer.len());
+        if count == 0 {
+            return 0;
+        }
+
+        // Rotate so the oldest item is first, then drop the oldest items
+        self.buffer.rotate_left(self.next_index);
+        self.buffer.drain(..count);
+        self.buffer.shrink_to_fit();
+        self.next_index = self.buffer.len() % self.capacity;
+
+        count
+    }
+
+    // Inserts `item` after the newest item whose key is less than or equal
+    // to its key, assuming the items are already ordered by that key. Like
+    // `push`, the oldest item is returned if the buffer was full, which may
+    // be `item` itself if it's older than everything in the buffer.
+    pub fn insert_by_key<K, F>(&mut self, item: T, f: F) -> Option<T>
+    where
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        // Rotate so the oldest item is first, like `remove_oldest`
+        self.buffer.rotate_left(self.next_index);
+
+        let key = f(&item);
+        let index = self.buffer
+            .iter()
+            .rposition(|existing| f(existing) <= key)
+            .map_or(0, |i| i + 1);
+
+        let evicted = if self.buffer.len() < self.capacity {
+            self.reserve_one();
+            self.buffer.insert(index, item);
+            None
+        } else if index == 0 {
+            Some(item)
+        } else {
+            let oldest = self.buffer.remove(0);
+            self.buffer.insert(index - 1, item);
+            Some(oldest)
+        };
+
+        self.next_index = self.buffer.len() % self.capacity;
+        evicted
+    }
 }
 
 #[cfg(test)]
@@ -60,6 +140,27 @@ mod test {
         unordered_eq(&buf, vec![2, 3]);
     }
 
+    #[test]
+    fn push_returns_evicted() {
+        let mut buf = CircularBuffer::with_capacity(3);
+
+        assert_eq!(buf.push(0), None);
+        assert_eq!(buf.push(1), None);
+        assert_eq!(buf.push(2), None);
+
+        // Evicted oldest first, across multiple wrap-arounds
+        for i in 3..10 {
+            assert_eq!(buf.push(i), Some(i - 3));
+        }
+
+        // After removing items, nothing is evicted until it's full again
+        buf.remove_oldest(2);
+        assert_eq!(buf.push(10), None);
+        assert_eq!(buf.push(11), None);
+        assert_eq!(buf.push(12), Some(9));
+        assert_eq!(buf.as_slices(), (&[10, 11][..], &[12][..]));
+    }
+
     #[test]
     fn unordered() {
         let mut buf = CircularBuffer::with_capacity(4);
@@ -85,6 +186,60 @@ mod test {
         unordered_eq(&buf, vec![95, 96, 97, 98, 99]);
     }
 
+    #[test]
+    fn remove_oldest() {
+        let mut buf = CircularBuffer::with_capacity(5);
+
+        for i in 0..8 {
+            buf.push(i);
+        }
+
+        assert_eq!(buf.remove_oldest(2), 2);
+        assert_eq!(buf.len(), 3);
+        assert_eq!(buf.as_slices(), (&[][..], &[5, 6, 7][..]));
+
+        // Space for the removed items is freed, but the buffer can still
+        // fill back up to its capacity
+        assert_eq!(buf.buffer.capacity(), 3);
+        for i in 8..12 {
+            buf.push(i);
+        }
+        unordered_eq(&buf, vec![7, 8, 9, 10, 11]);
+        assert_eq!(buf.buffer.capacity(), 5);
+
+        assert_eq!(buf.remove_oldest(10), 5);
+        assert!(buf.is_empty());
+        assert_eq!(buf.remove_oldest(1), 0);
+    }
+
+    #[test]
+    fn insert_by_key() {
+        let mut buf = CircularBuffer::with_capacity(4);
+
+        for i in &[10, 20, 40] {
+            buf.push(*i);
+        }
+
+        assert_eq!(buf.insert_by_key(30, |i| *i), None);
+        assert_eq!(buf.as_slices(), (&[10, 20, 30, 40][..], &[][..]));
+
+        // Once full, the oldest item is evicted
+        assert_eq!(buf.insert_by_key(15, |i| *i), Some(10));
+        assert_eq!(buf.as_slices(), (&[15, 20, 30, 40][..], &[][..]));
+
+        // Including the inserted item, if it's the oldest
+        assert_eq!(buf.insert_by_key(5, |i| *i), Some(5));
+        assert_eq!(buf.as_slices(), (&[15, 20, 30, 40][..], &[][..]));
+
+        // Items with equal keys stay in insertion order
+        assert_eq!(buf.insert_by_key(41, |i| *i / 10), Some(15));
+        assert_eq!(buf.insert_by_key(35, |i| *i / 10), Some(20));
+        assert_eq!(buf.as_slices(), (&[30, 35, 40, 41][..], &[][..]));
+
+        buf.push(50);
+        assert_eq!(buf.as_slices(), (&[35, 40, 41][..], &[50][..]));
+    }
+
     #[test]
     fn as_slices() {
         let mut buf = CircularBuffer::with_capacity(5);
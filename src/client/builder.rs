@@ -1,20 +1,30 @@
 use Token;
-use broadcast::{Broadcast, NoOpSubscriber, Subscriber};
-use circular_buffer::CircularBuffer;
-use client::{Client, Event, Worker};
-use client::worker::RaidBossEntry;
+use broadcast::{Broadcast, DynamicMapper, MessageMapper, NoOpSubscriber, SeqMapper, Subscriber};
+use clock::{Clock, SystemClock};
+use client::{Client, Event, Worker, WorkerConfig};
+use client::worker::{Coalescing, HeartbeatSplay, IdleSweep, ImageChecks, RaidBossEntry,
+                     RecentUsers};
 use error::*;
 use futures::Stream;
 use futures::unsync::mpsc;
 use hyper;
 use hyper::client::Connect;
 use id_pool::IdPool;
-use image_hash::{self, BossImageHash, HyperImageHasher, ImageHasher};
+use image::DynamicImage;
+use image_hash::{self, BossImageHash, DynImageChecker, ExactHashMatcher, HyperImageHasher,
+                 ImageChecker, ImageHasher, Pacing, PacingOptions, TranslationMatcher};
 use metrics::{self, Metrics};
-use model::{Message, RaidBossMetadata};
-use raid::{RaidInfo, RaidInfoStream};
-use std::collections::HashMap;
+use model::{BossName, Message, MessageKind, ProfileImageSize, RaidBossMetadata, TweetId};
+use protocol::{self, PayloadOptions};
+use raid::{RaidInfo, RaidInfoStream, StreamReplacer, StreamWarnings, SwitchableStream};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
 use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tweet_store::{StoreFactory, TweetStore};
 
 #[derive(Clone, Debug)]
 pub struct ClientBuilder<H, S, Sub, F, M> {
@@ -25,10 +35,157 @@ pub struct ClientBuilder<H, S, Sub, F, M> {
     bosses: Vec<RaidBossMetadata>,
     subscriber_type: PhantomData<Sub>,
     metrics: M,
+    options: Options,
+}
+
+// Settings that don't affect the builder's type parameters
+#[derive(Clone, Debug, Default)]
+struct Options {
+    clock: Option<Rc<Clock>>,
+    image_hash_pacing: Option<PacingOptions>,
+    tweet_coalescing_window: Option<Duration>,
+    idle_subscriber_timeout: Option<Duration>,
+    heartbeat_splay_window: Option<Duration>,
+    image_check: Option<ImageCheckOptions>,
+    global_history_limit: Option<usize>,
+    unacked_buffer_size: Option<usize>,
+    max_detached_sessions: Option<usize>,
+    image_hash_concurrency: Option<usize>,
+    image_fetch_concurrency: Option<usize>,
+    unique_user_capacity: Option<usize>,
+    gap_threshold: Option<TweetId>,
+    straggler_threshold: Option<Duration>,
+    stream_warnings: Option<StreamWarnings>,
+    max_tweet_age: Option<Duration>,
+    tweet_store: StoreFactory,
+    id_pool: Option<IdPool>,
+    mapper_check: MapperCheck,
+    stats_in_heartbeat: bool,
+    stream_replacer: Option<StreamReplacer>,
+    boss_confirmation_threshold: Option<usize>,
+    boss_confirmation_window: Option<Duration>,
+    // Lowercased, since screen names are case-insensitive
+    user_blocklist: HashSet<String>,
+    review_translations: bool,
+    translation_matcher: Option<Rc<TranslationMatcher>>,
+    debug_image_hashes: bool,
+    translation_history_sharing: Option<bool>,
+    sequencing: bool,
+}
+
+#[derive(Clone)]
+struct ImageCheckOptions {
+    checker: DynImageChecker,
+    max_age: Duration,
+}
+
+impl fmt::Debug for ImageCheckOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageCheckOptions")
+            .field("max_age", &self.max_age)
+            .finish()
+    }
 }
 
 const DEFAULT_HISTORY_SIZE: usize = 10;
-const MAX_CONCURRENT_IMAGE_HASHER_REQUESTS: usize = 5;
+const DEFAULT_IMAGE_HASH_CONCURRENCY: usize = 5;
+const DEFAULT_IMAGE_FETCH_CONCURRENCY: usize = 5;
+const DEFAULT_UNACKED_BUFFER_SIZE: usize = 100;
+const DEFAULT_MAX_DETACHED_SESSIONS: usize = 1000;
+const DEFAULT_UNIQUE_USER_CAPACITY: usize = 256;
+const DEFAULT_STRAGGLER_THRESHOLD_SECS: u64 = 10;
+const DEFAULT_BOSS_CONFIRMATION_WINDOW_SECS: u64 = 3600;
+
+// What `build` should do if `filter_map_message` returns `None` for the
+// heartbeat or boss list messages, in which case subscribers never get them
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MapperCheck {
+    Ignore,
+    // Count each unmappable message with `Metrics::inc_unmappable_message`
+    Warn,
+    Error,
+}
+
+impl Default for MapperCheck {
+    fn default() -> Self {
+        MapperCheck::Warn
+    }
+}
+
+type BuildResult<H, S, Sub, F, M> = ::std::result::Result<
+    (Client<Sub, <M as Metrics>::Export>, Worker<H, S, Sub, F, M>),
+    BuildError,
+>;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    ZeroHistorySize,
+    ZeroUnackedBufferSize,
+    ZeroImageHashConcurrency,
+    ZeroImageFetchConcurrency,
+    ZeroUniqueUserCapacity,
+    ZeroDuration(&'static str),
+    MissingClock(&'static str),
+    DuplicateBosses(Vec<BossName>),
+    MapperReturnedNone(&'static str),
+    SequencingWithoutSeqMapper,
+    SeqMapperWithoutSequencing,
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        ErrorKind::Build(e).into()
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::DuplicateBosses(ref names) => {
+                write!(f, "{}: ", self.description())?;
+                for (i, name) in names.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", name)?;
+                }
+                Ok(())
+            }
+            BuildError::MapperReturnedNone(option)
+            | BuildError::ZeroDuration(option)
+            | BuildError::MissingClock(option) => {
+                write!(f, "{} for {}", self.description(), option)
+            }
+            _ => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl StdError for BuildError {
+    fn description(&self) -> &str {
+        match *self {
+            BuildError::ZeroHistorySize => "history size must be greater than 0",
+            BuildError::ZeroUnackedBufferSize => "unacked buffer size must be greater than 0",
+            BuildError::ZeroImageHashConcurrency => {
+                "image hash concurrency must be greater than 0"
+            }
+            BuildError::ZeroImageFetchConcurrency => {
+                "image fetch concurrency must be greater than 0"
+            }
+            BuildError::ZeroUniqueUserCapacity => "unique user capacity must be greater than 0",
+            BuildError::ZeroDuration(_) => "duration must be greater than 0",
+            BuildError::MissingClock(_) => "`with_clock` must be called",
+            BuildError::DuplicateBosses(_) => "duplicate bosses",
+            BuildError::MapperReturnedNone(_) => "filter_map_message returned None",
+            BuildError::SequencingWithoutSeqMapper => {
+                "sequencing requires a mapper set with filter_map_message_seq"
+            }
+            BuildError::SeqMapperWithoutSequencing => {
+                "filter_map_message_seq requires sequencing to be enabled"
+            }
+        }
+    }
+}
 
 impl ClientBuilder<(), (), (), (), metrics::NoOp> {
     pub fn new() -> Self {
@@ -40,6 +197,7 @@ impl ClientBuilder<(), (), (), (), metrics::NoOp> {
             bosses: Vec::new(),
             subscriber_type: PhantomData,
             metrics: metrics::NoOp,
+            options: Options::default(),
         }
     }
 }
@@ -56,8 +214,9 @@ impl<'a, C>
 {
     pub fn from_hyper_client(hyper_client: &'a hyper::Client<C>, token: &Token) -> Self {
         let stream = RaidInfoStream::with_client(hyper_client, token);
+        let stream_warnings = stream.warnings();
 
-        let image_hasher = HyperImageHasher(hyper_client);
+        let image_hasher = HyperImageHasher::new(hyper_client);
 
         ClientBuilder {
             stream,
@@ -67,20 +226,111 @@ impl<'a, C>
             filter_map_message: (|_| None) as fn(Message) -> Option<()>,
             subscriber_type: PhantomData,
             metrics: metrics::NoOp,
+            options: Options {
+                clock: Some(Rc::new(SystemClock::new(hyper_client.handle()))),
+                stream_warnings: Some(stream_warnings),
+                ..Options::default()
+            },
         }
     }
 }
 
+impl<H, Sub, F, M> ClientBuilder<H, RaidInfoStream, Sub, F, M> {
+    // Collapse whitespace and newlines in the extra text of raid tweets into
+    // single spaces. Off by default, which preserves the original text.
+    pub fn with_collapse_text_newlines(mut self, collapse: bool) -> Self {
+        self.stream = self.stream.with_collapse_text_newlines(collapse);
+        self
+    }
+
+    // Store this rendition of each user's profile image in tweets, so that
+    // subscribers don't have to change the URL themselves
+    pub fn with_profile_image_size(mut self, size: ProfileImageSize) -> Self {
+        self.stream = self.stream.with_profile_image_size(size);
+        self
+    }
+
+    // Stop the worker with an error if a message from Twitter can't be
+    // deserialized, instead of skipping it
+    pub fn with_fail_on_parse_error(mut self, fail: bool) -> Self {
+        self.stream = self.stream.with_fail_on_parse_error(fail);
+        self
+    }
+}
+
+impl<'a, C, S, Sub, F, M> ClientBuilder<HyperImageHasher<'a, C>, S, Sub, F, M>
+where
+    C: Connect,
+{
+    // Additional normalization applied to boss images (after the language-
+    // specific name is cropped out) before they're hashed, e.g., to remove
+    // colored banners. By default, images are hashed unchanged.
+    pub fn with_image_preprocessor<P>(mut self, preprocessor: P) -> Self
+    where
+        P: Fn(&DynamicImage) -> DynamicImage + Send + Sync + 'static,
+    {
+        self.image_hasher = self.image_hasher
+            .with_preprocessor(Arc::new(preprocessor));
+        self
+    }
+
+    // Hash boss images whose width to height ratio is within `tolerance` of
+    // 1 without cropping out the name, since square images have no name
+    // banner. By default, every image is cropped.
+    pub fn with_square_image_tolerance(mut self, tolerance: f64) -> Self {
+        self.image_hasher = self.image_hasher.with_square_image_tolerance(tolerance);
+        self
+    }
+}
+
 impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
     pub fn with_history_size(mut self, size: usize) -> Self {
         self.history_size = size;
         self
     }
 
+    // Allocate subscriber IDs from `id_pool`, e.g., one that never recycles
+    // IDs, so that tests can tell which subscriber is which
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_id_pool(mut self, id_pool: IdPool) -> Self {
+        self.options.id_pool = Some(id_pool);
+        self
+    }
+
+    // Where each boss' recent tweets are kept, given the boss' name and the
+    // history size. By default, they're kept in memory (`tweet_store::in_memory`).
+    pub fn with_tweet_store<T>(mut self, store: T) -> Self
+    where
+        T: Fn(&BossName, usize) -> Box<TweetStore> + 'static,
+    {
+        self.options.tweet_store = StoreFactory::new(store);
+        self
+    }
+
     pub fn with_stream<S2>(self, stream: S2) -> ClientBuilder<H, S2, Sub, F, M>
     where
-        S: Stream<Item = RaidInfo, Error = Error>,
+        S2: Stream<Item = RaidInfo, Error = Error>,
+    {
+        ClientBuilder {
+            stream,
+            history_size: self.history_size,
+            image_hasher: self.image_hasher,
+            bosses: self.bosses,
+            filter_map_message: self.filter_map_message,
+            subscriber_type: self.subscriber_type,
+            metrics: self.metrics,
+            options: self.options,
+        }
+    }
+
+    // Allows the stream to be replaced later with `Client::replace_stream`
+    pub fn with_dynamic_stream(mut self) -> ClientBuilder<H, SwitchableStream, Sub, F, M>
+    where
+        S: Stream<Item = RaidInfo, Error = Error> + 'static,
     {
+        let (stream, replacer) = SwitchableStream::new(Box::new(self.stream));
+        self.options.stream_replacer = Some(replacer);
+
         ClientBuilder {
             stream,
             history_size: self.history_size,
@@ -89,6 +339,7 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            options: self.options,
         }
     }
 
@@ -101,6 +352,7 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            options: self.options,
         }
     }
 
@@ -116,9 +368,13 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: PhantomData,
             metrics: self.metrics,
+            options: self.options,
         }
     }
 
+    // Converts messages into the subscriber's item type, e.g., serialized
+    // bytes. This is the extension point for custom serialization formats.
+    // Messages mapped to `None` are not sent.
     pub fn filter_map_message<F2, T>(self, f: F2) -> ClientBuilder<H, S, Sub, F2, M>
     where
         F2: Fn(Message) -> Option<T>,
@@ -131,6 +387,78 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: f,
             subscriber_type: self.subscriber_type,
             metrics: self.metrics,
+            options: self.options,
+        }
+    }
+
+    // Like `filter_map_message`, but the mapper is also given the sequence
+    // number of the message for the subscriber it's being sent to, so that
+    // subscribers can detect missed messages. Each message is mapped once
+    // per subscriber. Requires `with_sequencing(true)`.
+    pub fn filter_map_message_seq<G, T>(self, g: G) -> ClientBuilder<H, S, Sub, SeqMapper<G>, M>
+    where
+        G: Fn(u64, Message) -> Option<T>,
+    {
+        ClientBuilder {
+            stream: self.stream,
+            history_size: self.history_size,
+            image_hasher: self.image_hasher,
+            bosses: self.bosses,
+            filter_map_message: SeqMapper(g),
+            subscriber_type: self.subscriber_type,
+            metrics: self.metrics,
+            options: self.options,
+        }
+    }
+
+    // For code written before messages could be skipped by the mapper
+    #[deprecated(note = "use `filter_map_message`, returning `Some`")]
+    pub fn map_message<F2, T>(
+        self,
+        f: F2,
+    ) -> ClientBuilder<H, S, Sub, impl Fn(Message) -> Option<T>, M>
+    where
+        F2: Fn(Message) -> T,
+    {
+        self.filter_map_message(move |message| Some(f(message)))
+    }
+
+    // Excludes optional fields from messages before they're given to the
+    // mapper set by `filter_map_message`, so it must be called after that
+    pub fn with_payload_options<T>(
+        self,
+        options: PayloadOptions,
+    ) -> ClientBuilder<H, S, Sub, impl Fn(Message) -> Option<T>, M>
+    where
+        F: Fn(Message) -> Option<T>,
+    {
+        ClientBuilder {
+            stream: self.stream,
+            history_size: self.history_size,
+            image_hasher: self.image_hasher,
+            bosses: self.bosses,
+            filter_map_message: protocol::shape_payload(options, self.filter_map_message),
+            subscriber_type: self.subscriber_type,
+            metrics: self.metrics,
+            options: self.options,
+        }
+    }
+
+    // Allows the mapper set by `filter_map_message` to be replaced later
+    // with `Client::set_message_mapper`, so it must be called after that
+    pub fn with_dynamic_mapper<T>(self) -> ClientBuilder<H, S, Sub, DynamicMapper<T>, M>
+    where
+        F: Fn(Message) -> Option<T> + 'static,
+    {
+        ClientBuilder {
+            stream: self.stream,
+            history_size: self.history_size,
+            image_hasher: self.image_hasher,
+            bosses: self.bosses,
+            filter_map_message: DynamicMapper::new(Box::new(self.filter_map_message)),
+            subscriber_type: self.subscriber_type,
+            metrics: self.metrics,
+            options: self.options,
         }
     }
 
@@ -146,6 +474,7 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
             filter_map_message: self.filter_map_message,
             subscriber_type: self.subscriber_type,
             metrics,
+            options: self.options,
         }
     }
 
@@ -154,12 +483,340 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
         self
     }
 
-    pub fn build(self) -> (Client<Sub, M::Export>, Worker<H, S, Sub, F, M>)
+    pub fn with_clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.options.clock = Some(Rc::new(clock));
+        self
+    }
+
+    // Limit the total number of tweets kept in history across all bosses.
+    // When over the limit, the oldest tweets from the least recently seen
+    // bosses are removed first.
+    pub fn with_global_history_limit(mut self, limit: usize) -> Self {
+        self.options.global_history_limit = Some(limit);
+        self
+    }
+
+    // The maximum number of unacknowledged tweets kept for each reliable
+    // subscription. When full, the oldest tweets are dropped.
+    pub fn with_unacked_buffer_size(mut self, size: usize) -> Self {
+        self.options.unacked_buffer_size = Some(size);
+        self
+    }
+
+    // The maximum number of reliable sessions kept after their subscription
+    // ends, waiting to be resumed. When over the limit, the sessions that
+    // were detached first are dropped.
+    pub fn with_max_detached_sessions(mut self, max: usize) -> Self {
+        self.options.max_detached_sessions = Some(max);
+        self
+    }
+
+    // The maximum number of fetched images being hashed at once.
+    // `HyperImageHasher` hashes on a thread pool with this many threads.
+    pub fn with_image_hash_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.image_hash_concurrency = Some(concurrency);
+        self
+    }
+
+    // The maximum number of boss images being downloaded at once
+    pub fn with_image_fetch_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.image_fetch_concurrency = Some(concurrency);
+        self
+    }
+
+    // The number of recent users remembered for each boss, for counting
+    // unique users in `Client::boss_activity`. Once full, the user that was
+    // added the longest ago is forgotten.
+    pub fn with_unique_user_capacity(mut self, capacity: usize) -> Self {
+        self.options.unique_user_capacity = Some(capacity);
+        self
+    }
+
+    // Instead of linking bosses with matching image hashes as translations,
+    // list them in `Client::pending_translations` until they're confirmed
+    pub fn with_translation_review(mut self, enabled: bool) -> Self {
+        self.options.review_translations = enabled;
+        self
+    }
+
+    // Don't add or announce a new boss until it has been seen this many
+    // times. Tweets seen before then are dropped.
+    pub fn with_boss_confirmation_threshold(mut self, threshold: usize) -> Self {
+        self.options.boss_confirmation_threshold = Some(threshold);
+        self
+    }
+
+    // The time since a new boss was first seen in which it has to reach the
+    // confirmation threshold. Sightings of bosses that don't reach it in time
+    // are forgotten, and with `with_idle_subscriber_timeout`, they're also
+    // cleared on each sweep. Defaults to an hour.
+    pub fn with_boss_confirmation_window(mut self, window: Duration) -> Self {
+        self.options.boss_confirmation_window = Some(window);
+        self
+    }
+
+    // Ignore raid tweets from these users (by screen name, ignoring case)
+    pub fn with_user_blocklist(mut self, screen_names: HashSet<String>) -> Self {
+        self.options.user_blocklist = screen_names
+            .into_iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        self
+    }
+
+    // Send a `Message::Gap` to a boss' followers before any tweet whose ID
+    // is lower than the boss' last tweet, or higher by more than
+    // `threshold`. Tweet IDs are Snowflake IDs, which grow by about 2^22
+    // per millisecond.
+    pub fn with_gap_detection(mut self, threshold: TweetId) -> Self {
+        self.options.gap_threshold = Some(threshold);
+        self
+    }
+
+    // Tweets can arrive out of order. A tweet that's older than the newest
+    // tweet in its boss' history by more than `threshold` is inserted at its
+    // place in the history, instead of being added as the newest tweet.
+    // Defaults to 10 seconds.
+    pub fn with_straggler_threshold(mut self, threshold: Duration) -> Self {
+        self.options.straggler_threshold = Some(threshold);
+        self
+    }
+
+    // Don't send a tweet to followers as it arrives if it's older than the
+    // newest tweet in its boss' history by more than `max_age`. The tweet
+    // is still added to the boss' history.
+    pub fn with_max_tweet_age(mut self, max_age: Duration) -> Self {
+        self.options.max_tweet_age = Some(max_age);
+        self
+    }
+
+    // Whether a tweet for a boss is also added to the recent tweets of its
+    // translations (the default). Either way, the tweet is still sent to
+    // the translations' followers.
+    pub fn with_translation_history_sharing(mut self, enabled: bool) -> Self {
+        self.options.translation_history_sharing = Some(enabled);
+        self
+    }
+
+    // Send `HeartbeatWithStats` messages instead of `Heartbeat`
+    pub fn with_stats_in_heartbeat(mut self, enabled: bool) -> Self {
+        self.options.stats_in_heartbeat = enabled;
+        self
+    }
+
+    // Decides which bosses are translations of each other, instead of
+    // requiring their image hashes to be equal
+    pub fn with_translation_matcher<T>(mut self, matcher: T) -> Self
+    where
+        T: TranslationMatcher + 'static,
+    {
+        self.options.translation_matcher = Some(Rc::new(matcher));
+        self
+    }
+
+    // Include each boss' image hash in `Client::debug_bosses`
+    pub fn with_debug_image_hashes(mut self, enabled: bool) -> Self {
+        self.options.debug_image_hashes = enabled;
+        self
+    }
+
+    pub fn with_mapper_check(mut self, check: MapperCheck) -> Self {
+        self.options.mapper_check = check;
+        self
+    }
+
+    // Report the stream's warning counts with `Metrics::set_stream_warnings`.
+    // Set by `from_hyper_client`, for the stream it creates.
+    pub fn with_stream_warnings(mut self, warnings: StreamWarnings) -> Self {
+        self.options.stream_warnings = Some(warnings);
+        self
+    }
+
+    // Requires a clock
+    pub fn with_image_hash_pacing(mut self, options: PacingOptions) -> Self {
+        self.options.image_hash_pacing = Some(options);
+        self
+    }
+
+    // Batch tweets for each boss that arrive within `window` of each other
+    // into a single `TweetList` broadcast. Requires a clock.
+    pub fn with_tweet_coalescing(mut self, window: Duration) -> Self {
+        self.options.tweet_coalescing_window = Some(window);
+        self
+    }
+
+    // Number every message sent to each subscriber, including heartbeats,
+    // using the mapper set by `filter_map_message_seq`
+    pub fn with_sequencing(mut self, sequencing: bool) -> Self {
+        self.options.sequencing = sequencing;
+        self
+    }
+
+    // Spread each heartbeat over `window`, instead of sending it to every
+    // subscriber at once. The window should be shorter than the interval
+    // between heartbeats. Requires a clock.
+    pub fn with_heartbeat_splay(mut self, window: Duration) -> Self {
+        self.options.heartbeat_splay_window = Some(window);
+        self
+    }
+
+    // Evict subscribers that stay stalled (see `Subscriber::is_stalled`)
+    // for at least `timeout`. Reliable sessions that stay detached for at
+    // least `timeout` are also dropped. Requires a clock.
+    pub fn with_idle_subscriber_timeout(mut self, timeout: Duration) -> Self {
+        self.options.idle_subscriber_timeout = Some(timeout);
+        self
+    }
+
+    // Check whether a boss' image still exists when a tweet without an image
+    // arrives for it, if the image is older than `max_age`. Deleted images
+    // are cleared, so the boss takes the image from its next tweet that has one.
+    pub fn with_image_checker<C>(mut self, checker: C, max_age: Duration) -> Self
+    where
+        C: ImageChecker + 'static,
+    {
+        self.options.image_check = Some(ImageCheckOptions {
+            checker: image_hash::boxed_image_checker(checker),
+            max_age,
+        });
+        self
+    }
+
+    pub fn build(mut self) -> BuildResult<H, S, Sub, F, M>
+    where
+        S: Stream<Item = RaidInfo, Error = Error>,
+        H: ImageHasher,
+        Sub: Subscriber + Clone,
+        F: MessageMapper<Sub::Item>,
+        M: Metrics,
+    {
+        let unmappable = self.validate()?;
+        for kind in unmappable {
+            self.metrics.inc_unmappable_message(kind);
+        }
+
+        Ok(self.build_unchecked())
+    }
+
+    // Returns the kinds of messages that the mapper can't represent, if
+    // they should only be warned about
+    fn validate<T>(&self) -> ::std::result::Result<Vec<MessageKind>, BuildError>
+    where
+        F: MessageMapper<T>,
+    {
+        let options = &self.options;
+
+        if self.history_size == 0 {
+            return Err(BuildError::ZeroHistorySize);
+        }
+
+        if options.unacked_buffer_size == Some(0) {
+            return Err(BuildError::ZeroUnackedBufferSize);
+        }
+
+        if options.image_hash_concurrency == Some(0) {
+            return Err(BuildError::ZeroImageHashConcurrency);
+        }
+
+        if options.image_fetch_concurrency == Some(0) {
+            return Err(BuildError::ZeroImageFetchConcurrency);
+        }
+
+        if options.unique_user_capacity == Some(0) {
+            return Err(BuildError::ZeroUniqueUserCapacity);
+        }
+
+        if options.boss_confirmation_window == Some(Duration::from_secs(0)) {
+            return Err(BuildError::ZeroDuration("boss confirmation window"));
+        }
+
+        let timed_options = [
+            (
+                "image hash pacing",
+                options.image_hash_pacing.map(|pacing| pacing.interval),
+            ),
+            ("tweet coalescing", options.tweet_coalescing_window),
+            ("idle subscriber timeout", options.idle_subscriber_timeout),
+            ("heartbeat splay", options.heartbeat_splay_window),
+        ];
+
+        for &(name, duration) in timed_options.iter() {
+            match duration {
+                Some(duration) if duration == Duration::from_secs(0) => {
+                    return Err(BuildError::ZeroDuration(name));
+                }
+                Some(_) if options.clock.is_none() => {
+                    return Err(BuildError::MissingClock(name));
+                }
+                _ => {}
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut duplicates = Vec::new();
+        for boss_data in self.bosses.iter() {
+            let name = &boss_data.boss.name;
+            if !seen.insert(name) && !duplicates.contains(name) {
+                duplicates.push(name.clone());
+            }
+        }
+
+        if !duplicates.is_empty() {
+            return Err(BuildError::DuplicateBosses(duplicates));
+        }
+
+        match (options.sequencing, self.filter_map_message.is_sequenced()) {
+            (true, false) => return Err(BuildError::SequencingWithoutSeqMapper),
+            (false, true) => return Err(BuildError::SeqMapperWithoutSequencing),
+            _ => {}
+        }
+
+        let mut unmappable = Vec::new();
+        if options.mapper_check != MapperCheck::Ignore {
+            let heartbeat = if options.stats_in_heartbeat {
+                (
+                    "HeartbeatWithStats",
+                    Message::HeartbeatWithStats {
+                        subscribers: 0,
+                        bosses: 0,
+                    },
+                )
+            } else {
+                ("Heartbeat", Message::Heartbeat)
+            };
+
+            let probes = [
+                heartbeat,
+                ("BossList", Message::BossList(&[])),
+            ];
+
+            for &(name, ref message) in probes.iter() {
+                if self.filter_map_message.map(0, message.clone()).is_some() {
+                    continue;
+                }
+
+                if options.mapper_check == MapperCheck::Error {
+                    return Err(BuildError::MapperReturnedNone(name));
+                }
+
+                unmappable.push(message.kind());
+            }
+        }
+
+        Ok(unmappable)
+    }
+
+    // Builds without validating the configuration, as `build` used to.
+    // TODO: Remove once callers have moved to `build`
+    pub fn build_unchecked(self) -> (Client<Sub, M::Export>, Worker<H, S, Sub, F, M>)
     where
         S: Stream<Item = RaidInfo, Error = Error>,
         H: ImageHasher,
         Sub: Subscriber + Clone, // TODO: Change Sub to not require Clone
-        F: Fn(Message) -> Option<Sub::Item>,
+        F: MessageMapper<Sub::Item>,
         M: Metrics,
     {
         let (tx, rx) = mpsc::unbounded();
@@ -174,50 +831,613 @@ impl<H, S, Sub, F, M> ClientBuilder<H, S, Sub, F, M> {
         let to_read_error = |()| Ok(Event::ClientReadError);
         let rx = rx.or_else(to_read_error as fn(()) -> Result<Event<Sub, M::Export>>);
 
-        let (hash_requester, hash_receiver) =
-            image_hash::channel(self.image_hasher, MAX_CONCURRENT_IMAGE_HASHER_REQUESTS);
+        let mut options = self.options;
+        let stream_replacer = options.stream_replacer.take();
+        let image_check = options.image_check.take();
+        let id_pool = options.id_pool.take().unwrap_or_else(IdPool::new);
+
+        let config = WorkerConfig {
+            history_size: self.history_size,
+            global_history_limit: options.global_history_limit,
+            unacked_buffer_size: options
+                .unacked_buffer_size
+                .unwrap_or(DEFAULT_UNACKED_BUFFER_SIZE),
+            max_detached_sessions: options
+                .max_detached_sessions
+                .unwrap_or(DEFAULT_MAX_DETACHED_SESSIONS),
+            image_fetch_concurrency: options
+                .image_fetch_concurrency
+                .unwrap_or(DEFAULT_IMAGE_FETCH_CONCURRENCY),
+            image_hash_concurrency: options
+                .image_hash_concurrency
+                .unwrap_or(DEFAULT_IMAGE_HASH_CONCURRENCY),
+            unique_user_capacity: options
+                .unique_user_capacity
+                .unwrap_or(DEFAULT_UNIQUE_USER_CAPACITY),
+            gap_threshold: options.gap_threshold,
+            straggler_threshold: options
+                .straggler_threshold
+                .unwrap_or(Duration::from_secs(DEFAULT_STRAGGLER_THRESHOLD_SECS)),
+            max_tweet_age: options.max_tweet_age,
+            image_hash_pacing: options.image_hash_pacing,
+            tweet_coalescing_window: options.tweet_coalescing_window,
+            idle_subscriber_timeout: options.idle_subscriber_timeout,
+            heartbeat_splay_window: options.heartbeat_splay_window,
+            image_check_age: image_check.as_ref().map(|check| check.max_age),
+            boss_confirmation_threshold: options.boss_confirmation_threshold.unwrap_or(1),
+            boss_confirmation_window: options
+                .boss_confirmation_window
+                .unwrap_or(Duration::from_secs(DEFAULT_BOSS_CONFIRMATION_WINDOW_SECS)),
+            review_translations: options.review_translations,
+            translation_history_sharing: options.translation_history_sharing.unwrap_or(true),
+            stats_in_heartbeat: options.stats_in_heartbeat,
+            debug_image_hashes: options.debug_image_hashes,
+            dynamic_stream: stream_replacer.is_some(),
+            sequencing: options.sequencing,
+        };
+
+        let clock = || {
+            options
+                .clock
+                .as_ref()
+                .expect("`with_clock` must be called to use time-based options")
+        };
+
+        let pacing = options
+            .image_hash_pacing
+            .map(|pacing| Pacing::new(pacing, &**clock()));
 
-        let filter_map_hashes = |msg: BossImageHash| match msg.image_hash {
-            Some(image_hash) => Some(Event::NewImageHash {
+        let coalescing = options
+            .tweet_coalescing_window
+            .map(|window| Coalescing::new(&**clock(), window));
+
+        let idle_sweep = options
+            .idle_subscriber_timeout
+            .map(|timeout| IdleSweep::new(&**clock(), timeout));
+
+        let heartbeat_splay = options
+            .heartbeat_splay_window
+            .map(|window| HeartbeatSplay::new(&**clock(), window));
+
+        let image_checks =
+            image_check.map(|check| ImageChecks::new(check.checker, check.max_age));
+
+        let (hash_requester, hash_receiver) = image_hash::channel(
+            self.image_hasher,
+            config.image_fetch_concurrency,
+            config.image_hash_concurrency,
+            pacing,
+        );
+
+        let map_hashes = |msg: BossImageHash| match msg.image_hash {
+            Some(image_hash) => Event::NewImageHash {
                 boss_name: msg.boss_name,
                 image_hash,
-            }),
-            _ => None,
+            },
+            None => Event::ImageHashFailed(msg.boss_name),
         };
 
-        let hash_events = hash_receiver
-            .filter_map(filter_map_hashes as fn(BossImageHash) -> Option<Event<Sub, M::Export>>);
-
-        let cached_boss_list = (self.filter_map_message)(Message::BossList(&[]));
+        let hash_events =
+            hash_receiver.map(map_hashes as fn(BossImageHash) -> Event<Sub, M::Export>);
 
         let mut bosses = HashMap::new();
         for boss_data in self.bosses.into_iter() {
             let boss_name = boss_data.boss.name.clone();
+            let seeded_image = boss_data.boss.image.clone();
             let entry = RaidBossEntry {
+                // The image's actual age is unknown, so assume the oldest
+                image_seen_at: boss_data.first_seen,
+                hash_links: HashSet::new(),
                 boss_data,
                 broadcast: Broadcast::new(),
-                recent_tweets: CircularBuffer::with_capacity(self.history_size),
+                recent_tweets: options.tweet_store.create(&boss_name, self.history_size),
+                recent_users: RecentUsers::with_capacity(config.unique_user_capacity),
+                seeded_image,
+                last_tweet_id: None,
             };
 
             bosses.insert(boss_name, entry);
         }
 
-        let mut worker = Worker {
+        let worker = Worker {
             hash_requester,
-            id_pool: IdPool::new(),
+            id_pool,
             events: stream_events.select(rx.select(hash_events)),
             bosses,
-            tweet_history_size: self.history_size,
+            tweet_history_size: config.history_size,
             requested_bosses: HashMap::new(),
             subscribers: Broadcast::new(),
-            heartbeat: (self.filter_map_message)(Message::Heartbeat),
+            heartbeat: if config.sequencing {
+                None
+            } else {
+                self.filter_map_message.map(0, Message::Heartbeat)
+            },
             filter_map_message: self.filter_map_message,
-            cached_boss_list,
+            cached_boss_list: None,
+            cached_boss_list_is_stale: true,
+            tweet_store: options.tweet_store.clone(),
+            boss_list_revision: 0,
             metrics: self.metrics,
+            clock: options.clock.clone(),
+            coalescing,
+            idle_sweep,
+            heartbeat_splay,
+            image_checks,
+            global_history_limit: config.global_history_limit,
+            buffered_tweets: 0,
+            history_eviction_order: config.global_history_limit.map(|_| BinaryHeap::new()),
+            evicted: Vec::new(),
+            reliable_sessions: HashMap::new(),
+            next_detach_order: 0,
+            next_tweet_seq: 1,
+            stats_in_heartbeat: config.stats_in_heartbeat,
+            stream_replacer,
+            review_translations: config.review_translations,
+            translation_matcher: options
+                .translation_matcher
+                .clone()
+                .unwrap_or_else(|| Rc::new(ExactHashMatcher)),
+            pending_translations: Vec::new(),
+            rejected_translations: HashSet::new(),
+            pending_image_hashes: HashSet::new(),
+            muted_subscribers: HashMap::new(),
+            boss_confirmation_threshold: config.boss_confirmation_threshold,
+            unconfirmed_bosses: HashMap::new(),
+            keyed_subscribers: HashMap::new(),
+            user_blocklist: options.user_blocklist.clone(),
+            stream_warnings: options.stream_warnings.clone(),
+            unacked_buffer_size: config.unacked_buffer_size,
+            config,
         };
 
-        worker.update_cached_boss_list();
-
         (Client(tx), worker)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use client::harness::{self, Recorded};
+    use clock::ManualClock;
+    use futures::Future;
+    use model::{Language, RaidBoss};
+
+    fn metadata(name: &str) -> RaidBossMetadata {
+        RaidBossMetadata {
+            boss: RaidBoss {
+                name: name.into(),
+                level: 60,
+                image: None,
+                language: Language::English,
+                muted: false,
+                translations: Default::default(),
+            },
+            first_seen: Utc.timestamp(0, 0),
+            last_seen: Utc.timestamp(0, 0),
+            image_hash: None,
+        }
+    }
+
+    fn build_error<M, F>(
+        builder: harness::TestBuilder<M, harness::Recorder, F>,
+    ) -> Option<BuildError>
+    where
+        M: Metrics,
+        F: MessageMapper<Recorded>,
+    {
+        builder.build().err()
+    }
+
+    #[test]
+    fn valid_configuration() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_bosses(vec![metadata("Lvl 60 Ozorotter")]);
+        assert_eq!(build_error(builder), None);
+    }
+
+    #[test]
+    fn zero_history_size() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_history_size(0);
+        assert_eq!(build_error(builder), Some(BuildError::ZeroHistorySize));
+    }
+
+    #[test]
+    fn zero_unacked_buffer_size() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_unacked_buffer_size(0);
+        assert_eq!(build_error(builder), Some(BuildError::ZeroUnackedBufferSize));
+    }
+
+    #[test]
+    fn zero_image_hash_concurrency() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_image_hash_concurrency(0);
+        assert_eq!(build_error(builder), Some(BuildError::ZeroImageHashConcurrency));
+    }
+
+    #[test]
+    fn zero_image_fetch_concurrency() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_image_fetch_concurrency(0);
+        assert_eq!(build_error(builder), Some(BuildError::ZeroImageFetchConcurrency));
+    }
+
+    #[test]
+    fn zero_unique_user_capacity() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_unique_user_capacity(0);
+        assert_eq!(build_error(builder), Some(BuildError::ZeroUniqueUserCapacity));
+    }
+
+    #[test]
+    fn time_options_without_clock() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_tweet_coalescing(Duration::from_secs(5));
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::MissingClock("tweet coalescing"))
+        );
+
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_idle_subscriber_timeout(Duration::from_secs(60));
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::MissingClock("idle subscriber timeout"))
+        );
+
+        let (builder, _, _) = harness::builder();
+        let builder = builder
+            .with_clock(ManualClock::new(Utc.timestamp(0, 0)))
+            .with_tweet_coalescing(Duration::from_secs(5))
+            .with_idle_subscriber_timeout(Duration::from_secs(60));
+        assert_eq!(build_error(builder), None);
+    }
+
+    #[test]
+    fn zero_durations() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder
+            .with_clock(ManualClock::new(Utc.timestamp(0, 0)))
+            .with_image_hash_pacing(PacingOptions {
+                max_per_interval: 1,
+                interval: Duration::from_secs(0),
+            });
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::ZeroDuration("image hash pacing"))
+        );
+
+        // Zero durations are reported even when the clock is missing
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_tweet_coalescing(Duration::from_secs(0));
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::ZeroDuration("tweet coalescing"))
+        );
+    }
+
+    #[test]
+    fn duplicate_bosses() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_bosses(vec![
+            metadata("Lvl 60 Ozorotter"),
+            metadata("Lvl 75 Luminiera Omega"),
+            metadata("Lvl 60 Ozorotter"),
+            metadata("Lvl 75 Luminiera Omega"),
+            metadata("Lvl 60 Ozorotter"),
+        ]);
+
+        let error = build_error(builder);
+        assert_eq!(
+            error,
+            Some(BuildError::DuplicateBosses(vec![
+                "Lvl 60 Ozorotter".into(),
+                "Lvl 75 Luminiera Omega".into(),
+            ]))
+        );
+        assert_eq!(
+            error.unwrap().to_string(),
+            "duplicate bosses: Lvl 60 Ozorotter, Lvl 75 Luminiera Omega"
+        );
+    }
+
+    fn heartbeat_only(message: Message) -> Option<Recorded> {
+        match message {
+            Message::Heartbeat => Some(Recorded::Heartbeat),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn mapper_returns_none_with_error() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder
+            .filter_map_message(heartbeat_only)
+            .with_mapper_check(MapperCheck::Error);
+
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::MapperReturnedNone("BossList"))
+        );
+    }
+
+    #[test]
+    fn mapper_returns_none_with_warning() {
+        let mut h = harness::Harness::with_json_metrics_and_builder(|b| {
+            b.filter_map_message(heartbeat_only as harness::TestMapper)
+        });
+
+        let metrics = h.metrics();
+        assert_eq!(metrics["unmappable_messages"]["BossList"], 1);
+        assert!(metrics["unmappable_messages"]["Heartbeat"].is_null());
+    }
+
+    fn record_seq(seq: u64, message: Message) -> Option<Recorded> {
+        harness::record(message).map(|recorded| Recorded::Sequenced(seq, Box::new(recorded)))
+    }
+
+    #[test]
+    fn sequencing_requires_seq_mapper() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_sequencing(true);
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::SequencingWithoutSeqMapper)
+        );
+
+        let (builder, _, _) = harness::builder();
+        let builder = builder.filter_map_message_seq(record_seq);
+        assert_eq!(
+            build_error(builder),
+            Some(BuildError::SeqMapperWithoutSequencing)
+        );
+
+        let (builder, _, _) = harness::builder();
+        let builder = builder
+            .filter_map_message_seq(record_seq)
+            .with_sequencing(true);
+        assert_eq!(build_error(builder), None);
+    }
+
+    #[test]
+    fn mapper_returns_none_ignored() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder
+            .filter_map_message(|_| None)
+            .with_mapper_check(MapperCheck::Error)
+            .with_mapper_check(MapperCheck::Ignore);
+
+        assert_eq!(build_error(builder), None);
+    }
+
+    // A custom serializer with a line-based text format
+    fn to_text(message: Message) -> Option<String> {
+        let text = match message {
+            Message::Heartbeat => "heartbeat".to_string(),
+            Message::Tweet(t) => format!("tweet {} {}", t.boss_name, t.raid_id),
+            Message::BossRemove(name) => format!("remove {}", name),
+            _ => return None,
+        };
+
+        Some(text)
+    }
+
+    #[test]
+    fn default_config() {
+        let mut h = harness::Harness::new();
+        let config = h.client.config();
+
+        assert_eq!(
+            h.request(config),
+            WorkerConfig {
+                history_size: DEFAULT_HISTORY_SIZE,
+                global_history_limit: None,
+                unacked_buffer_size: DEFAULT_UNACKED_BUFFER_SIZE,
+                max_detached_sessions: DEFAULT_MAX_DETACHED_SESSIONS,
+                image_fetch_concurrency: DEFAULT_IMAGE_FETCH_CONCURRENCY,
+                image_hash_concurrency: DEFAULT_IMAGE_HASH_CONCURRENCY,
+                unique_user_capacity: DEFAULT_UNIQUE_USER_CAPACITY,
+                gap_threshold: None,
+                straggler_threshold: Duration::from_secs(DEFAULT_STRAGGLER_THRESHOLD_SECS),
+                max_tweet_age: None,
+                image_hash_pacing: None,
+                tweet_coalescing_window: None,
+                idle_subscriber_timeout: None,
+                heartbeat_splay_window: None,
+                image_check_age: None,
+                boss_confirmation_threshold: 1,
+                boss_confirmation_window: Duration::from_secs(
+                    DEFAULT_BOSS_CONFIRMATION_WINDOW_SECS,
+                ),
+                review_translations: false,
+                translation_history_sharing: true,
+                stats_in_heartbeat: false,
+                debug_image_hashes: false,
+                dynamic_stream: false,
+                sequencing: false,
+            }
+        );
+    }
+
+    #[test]
+    fn config() {
+        let pacing = PacingOptions {
+            max_per_interval: 2,
+            interval: Duration::from_secs(60),
+        };
+
+        let mut h = harness::Harness::with_builder(|b| {
+            b.with_clock(ManualClock::new(Utc.timestamp(0, 0)))
+                .with_history_size(20)
+                .with_global_history_limit(100)
+                .with_unacked_buffer_size(50)
+                .with_max_detached_sessions(20)
+                .with_image_fetch_concurrency(3)
+                .with_image_hash_concurrency(1)
+                .with_unique_user_capacity(16)
+                .with_gap_detection(1000)
+                .with_straggler_threshold(Duration::from_secs(5))
+                .with_max_tweet_age(Duration::from_secs(60))
+                .with_image_hash_pacing(pacing)
+                .with_tweet_coalescing(Duration::from_secs(2))
+                .with_idle_subscriber_timeout(Duration::from_secs(30))
+                .with_heartbeat_splay(Duration::from_secs(8))
+                .with_image_checker(harness::MockChecker::default(), Duration::from_secs(86400))
+                .with_boss_confirmation_threshold(3)
+                .with_boss_confirmation_window(Duration::from_secs(600))
+                .with_translation_review(true)
+                .with_translation_history_sharing(false)
+                .with_stats_in_heartbeat(true)
+                .with_debug_image_hashes(true)
+        });
+        let config = h.client.config();
+
+        assert_eq!(
+            h.request(config),
+            WorkerConfig {
+                history_size: 20,
+                global_history_limit: Some(100),
+                unacked_buffer_size: 50,
+                max_detached_sessions: 20,
+                image_fetch_concurrency: 3,
+                image_hash_concurrency: 1,
+                unique_user_capacity: 16,
+                gap_threshold: Some(1000),
+                straggler_threshold: Duration::from_secs(5),
+                max_tweet_age: Some(Duration::from_secs(60)),
+                image_hash_pacing: Some(pacing),
+                tweet_coalescing_window: Some(Duration::from_secs(2)),
+                idle_subscriber_timeout: Some(Duration::from_secs(30)),
+                heartbeat_splay_window: Some(Duration::from_secs(8)),
+                image_check_age: Some(Duration::from_secs(86400)),
+                boss_confirmation_threshold: 3,
+                boss_confirmation_window: Duration::from_secs(600),
+                review_translations: true,
+                translation_history_sharing: false,
+                stats_in_heartbeat: true,
+                debug_image_hashes: true,
+                dynamic_stream: false,
+                sequencing: false,
+            }
+        );
+    }
+
+    #[test]
+    fn custom_serializer() {
+        let (builder, hasher, raids) = harness::builder();
+        let builder = builder
+            .with_subscriber::<mpsc::UnboundedSender<Arc<String>>>()
+            .filter_map_message(protocol::shared(to_text))
+            .with_mapper_check(MapperCheck::Ignore);
+
+        let mut h = harness::Harness::from_builder(builder, hasher, raids);
+        let (tx, rx) = mpsc::unbounded();
+        let subscription = h.client.subscribe(tx);
+        let mut subscription = h.request(subscription);
+
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+        h.push(harness::raid_info("Lvl 60 Ozorotter", 1, 0));
+        h.client.heartbeat();
+        h.client.remove_bosses(|_| true);
+        h.run();
+
+        let messages = rx.take(3).collect().wait().unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.as_str()).collect::<Vec<_>>(),
+            vec![
+                "tweet Lvl 60 Ozorotter 00000001",
+                "heartbeat",
+                "remove Lvl 60 Ozorotter",
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_map_message() {
+        fn run<F>(mut h: harness::Harness<::metrics::NoOp, harness::Recorder, F>) -> Vec<Recorded>
+        where
+            F: Fn(Message) -> Option<Recorded>,
+        {
+            let client: ::Petronel<harness::Recorder> = h.client.clone();
+            let recorder = harness::Recorder::new();
+            let subscription = client.subscribe(recorder.clone());
+            let mut subscription = h.request(subscription);
+            subscription.follow("Lvl 60 Ozorotter");
+            h.run();
+            h.push(harness::raid_info("Lvl 60 Ozorotter", 1, 0));
+
+            let bosses = client.bosses();
+            let mut messages = recorder.take();
+            messages.push(Recorded::BossList(h.request(bosses)));
+            messages
+        }
+
+        let (builder, hasher, raids) = harness::builder();
+        let builder = builder.map_message(|message| harness::record(message).unwrap());
+        let messages = run(harness::Harness::from_builder(builder, hasher, raids));
+
+        assert_eq!(messages, run(harness::Harness::new()));
+        assert_eq!(messages.len(), 3);
+    }
+
+    #[test]
+    fn payload_options() {
+        let (builder, hasher, raids) = harness::builder();
+        let builder = builder.with_payload_options(PayloadOptions {
+            include_boss_image: false,
+            include_user_image: true,
+            include_text: false,
+        });
+
+        let mut h = harness::Harness::from_builder(builder, hasher, raids);
+        let recorder = harness::Recorder::new();
+        let subscription = h.client.subscribe(recorder.clone());
+        let mut subscription = h.request(subscription);
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        let image = "http://example.com/a.png";
+        let mut info = harness::raid_info_with_image("Lvl 60 Ozorotter", 1, 0, image);
+        info.tweet.user_image = Some("http://example.com/user.png".into());
+        info.tweet.text = Some("Help".into());
+        h.push(info.clone());
+        info.tweet.seq = 1; // Assigned by the worker
+
+        let mut shaped_tweet = info.tweet.clone();
+        shaped_tweet.text = None;
+
+        let boss = h.client.boss("Lvl 60 Ozorotter");
+        let boss = h.request(boss).expect("boss not found");
+        let mut shaped_boss = boss.clone();
+        shaped_boss.image = None;
+
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Recorded::BossUpdate(shaped_boss),
+                Recorded::Tweet(shaped_tweet),
+            ]
+        );
+
+        // The data stored in the worker is complete
+        assert_eq!(boss.image, Some(image.into()));
+        let tweets = h.client.tweets("Lvl 60 Ozorotter");
+        assert_eq!(*h.request(tweets)[0], info.tweet);
+    }
+
+    #[test]
+    fn build_unchecked_skips_validation() {
+        let (builder, _, _) = harness::builder();
+        let builder = builder.with_bosses(vec![
+            metadata("Lvl 60 Ozorotter"),
+            metadata("Lvl 60 Ozorotter"),
+        ]);
+
+        let (_client, worker) = builder.build_unchecked();
+        assert_eq!(worker.bosses.len(), 1);
+    }
+}
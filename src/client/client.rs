@@ -1,9 +1,24 @@
-use super::{AsyncResult, Event, RemoveBossesPredicate, Subscription};
+use super::{ActivityStats, AsyncResult, Event, ImageHashStatus, MapperReplacement,
+            RemoveBossesPlanner, RemoveBossesPredicate, ResumeToken, Subscription, WorkerConfig,
+            WorkerStats};
+use broadcast::{BoxMapper, MessageStream, Subscriber, MESSAGE_STREAM_CAPACITY};
+use error::*;
+use futures::Stream;
 use futures::unsync::{mpsc, oneshot};
 use id_pool::Id as SubId;
-use model::{BossName, RaidBoss, RaidBossMetadata, RaidTweet};
+use model::{BossName, DebugRaidBoss, Language, Message, MessageFilter, RaidBoss, RaidBossMetadata,
+            RaidId, RaidTweet, TweetOrder};
+use raid::{BoxRaidInfoStream, RaidInfo, Replacement};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+/// A handle for communicating with a `Worker`.
+///
+/// `Client` is cheap to clone, but it is backed by single-threaded
+/// (`futures::unsync`) channels, so it is never `Send` or `Sync`,
+/// regardless of `Sub` and `M`. Clients must be used on the same thread
+/// as the event loop running the `Worker`.
 #[derive(Debug)]
 pub struct Client<Sub, M = ()>(pub(crate) mpsc::UnboundedSender<Event<Sub, M>>);
 
@@ -14,7 +29,7 @@ impl<Sub, M> Clone for Client<Sub, M> {
 }
 
 impl<Sub, M> Client<Sub, M> {
-    fn send(&self, event: Event<Sub, M>) {
+    pub(crate) fn send(&self, event: Event<Sub, M>) {
         let _ = self.0.unbounded_send(event);
     }
 
@@ -28,8 +43,71 @@ impl<Sub, M> Client<Sub, M> {
     }
 
     pub fn subscribe(&self, subscriber: Sub) -> AsyncResult<Subscription<Sub, M>> {
+        self.subscribe_with_filter(subscriber, MessageFilter::all())
+    }
+
+    // Like `subscribe`, but the subscriber is only sent messages whose kind
+    // is in the filter. This also applies to responses to its own requests.
+    pub fn subscribe_with_filter(
+        &self,
+        subscriber: Sub,
+        filter: MessageFilter,
+    ) -> AsyncResult<Subscription<Sub, M>> {
         self.request(|sender| Event::SubscriberSubscribe {
             subscriber,
+            filter,
+            key: None,
+            sender,
+            client: self.clone(),
+        })
+    }
+
+    // Like `subscribe`, but if another subscription was made with the same
+    // key, it's unsubscribed (including its follows) before this one is
+    // added. This is useful if a retried request could subscribe twice for
+    // the same connection.
+    pub fn subscribe_keyed(&self, key: u64, subscriber: Sub) -> AsyncResult<Subscription<Sub, M>> {
+        self.request(|sender| Event::SubscriberSubscribe {
+            subscriber,
+            filter: MessageFilter::all(),
+            key: Some(key),
+            sender,
+            client: self.clone(),
+        })
+    }
+
+    // Like `subscribe_with_filter`, but messages are read from the returned
+    // stream as owned copies, instead of being mapped and pushed to a
+    // subscriber. The stream ends once the subscription is dropped. If it
+    // isn't read quickly enough, the oldest messages are dropped, and
+    // `MessageStream::is_desynced` returns true.
+    pub fn message_stream(
+        &self,
+        filter: MessageFilter,
+    ) -> (AsyncResult<Subscription<Sub, M>>, MessageStream) {
+        let (message_sender, stream) = MessageStream::new(MESSAGE_STREAM_CAPACITY);
+
+        let subscription = self.request(|sender| Event::SubscriberSubscribeStream {
+            message_sender,
+            filter,
+            sender,
+            client: self.clone(),
+        });
+
+        (subscription, stream)
+    }
+
+    // Subscribes with the reliable delivery state of a previous subscription,
+    // redelivering any unacknowledged messages in order. If the token is
+    // unknown, this is the same as `subscribe`.
+    pub fn resume_subscription(
+        &self,
+        subscriber: Sub,
+        resume_token: ResumeToken,
+    ) -> AsyncResult<Subscription<Sub, M>> {
+        self.request(|sender| Event::SubscriberResume {
+            subscriber,
+            resume_token,
             sender,
             client: self.clone(),
         })
@@ -43,10 +121,47 @@ impl<Sub, M> Client<Sub, M> {
         self.send(Event::SubscriberFollow { id, boss_name });
     }
 
+    pub(crate) fn subscriber_follow_many(&self, id: SubId, names: Vec<BossName>) {
+        self.send(Event::SubscriberFollowMany { id, names });
+    }
+
+    pub(crate) fn subscriber_unfollow_many(&self, id: SubId, names: Vec<BossName>) {
+        self.send(Event::SubscriberUnfollowMany { id, names });
+    }
+
+    pub(crate) fn subscriber_follow_with_catch_up(&self, id: SubId, boss_name: BossName) {
+        self.send(Event::SubscriberFollowWithCatchUp { id, boss_name });
+    }
+
+    pub(crate) fn subscriber_follow_with_backlog(&self, id: SubId, boss_name: BossName) {
+        self.send(Event::SubscriberFollowWithBacklog { id, boss_name });
+    }
+
+    pub(crate) fn subscriber_follow_reliable(
+        &self,
+        id: SubId,
+        resume_token: ResumeToken,
+        boss_name: BossName,
+    ) {
+        self.send(Event::SubscriberFollowReliable {
+            id,
+            resume_token,
+            boss_name,
+        });
+    }
+
+    pub(crate) fn subscriber_ack(&self, resume_token: ResumeToken, seq: u64) {
+        self.send(Event::SubscriberAck { resume_token, seq });
+    }
+
     pub(crate) fn subscriber_unfollow(&self, id: SubId, boss_name: BossName) {
         self.send(Event::SubscriberUnfollow { id, boss_name });
     }
 
+    pub(crate) fn subscriber_set_muted(&self, id: SubId, muted: bool) {
+        self.send(Event::SubscriberSetMuted { id, muted });
+    }
+
     pub(crate) fn subscriber_get_bosses(&self, id: SubId) {
         self.send(Event::SubscriberGetBosses(id))
     }
@@ -55,28 +170,258 @@ impl<Sub, M> Client<Sub, M> {
         self.send(Event::SubscriberGetTweets { id, boss_name })
     }
 
+    // Fetch and hash the boss' image again, even if it already has a hash.
+    // Translations are matched again once the new hash is computed.
+    pub fn rehash_boss<B>(&self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        self.send(Event::ClientRehashBoss(boss_name.into()));
+    }
+
+    // Replaces the translation matcher with a `HammingMatcher` using this
+    // threshold, then matches bosses that already have image hashes again.
+    // Existing translations are kept, even if they no longer match. Custom
+    // matchers that don't support `TranslationMatcher::with_hash_threshold`
+    // are left unchanged.
+    pub fn set_hash_threshold(&self, threshold: u32) {
+        self.send(Event::ClientSetHashThreshold(threshold));
+    }
+
+    // While a boss is muted, its tweets are still recorded, but aren't sent
+    // to its followers or the followers of its translations. Returns false
+    // if the boss doesn't exist.
+    pub fn set_boss_muted<B>(&self, boss_name: B, muted: bool) -> AsyncResult<bool>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientSetBossMuted {
+            boss_name: boss_name.into(),
+            muted,
+            sender: tx,
+        })
+    }
+
+    // Pairs of bosses with matching image hashes, which haven't been linked
+    // because `ClientBuilder::with_translation_review` is enabled
+    pub fn pending_translations(&self) -> AsyncResult<Vec<(BossName, BossName)>> {
+        self.request(Event::ClientGetPendingTranslations)
+    }
+
+    pub fn confirm_translation<A, B>(&self, a: A, b: B)
+    where
+        A: Into<BossName>,
+        B: Into<BossName>,
+    {
+        self.send(Event::ClientConfirmTranslation(a.into(), b.into()));
+    }
+
+    // Drops a pending translation. The pair isn't proposed again, unless
+    // one of the bosses is removed and comes back.
+    pub fn reject_translation<A, B>(&self, a: A, b: B)
+    where
+        A: Into<BossName>,
+        B: Into<BossName>,
+    {
+        self.send(Event::ClientRejectTranslation(a.into(), b.into()));
+    }
+
     pub fn bosses(&self) -> AsyncResult<Vec<RaidBoss>> {
         self.request(Event::ClientGetBosses)
     }
 
+    // Like `bosses`, along with the current boss list revision
+    pub fn bosses_versioned(&self) -> AsyncResult<(u64, Vec<RaidBoss>)> {
+        self.request(Event::ClientGetBossesVersioned)
+    }
+
+    // A number that changes whenever a boss is added, removed, or updated,
+    // so that the boss list only needs to be fetched again if it's changed
+    pub fn boss_list_revision(&self) -> AsyncResult<u64> {
+        self.request(Event::ClientGetBossListRevision)
+    }
+
+    // Bosses along with information that's only useful for debugging, such
+    // as image hashes if `ClientBuilder::with_debug_image_hashes` is set
+    pub fn debug_bosses(&self) -> AsyncResult<Vec<DebugRaidBoss>> {
+        self.request(Event::ClientGetDebugBosses)
+    }
+
+    pub fn bosses_by_language(&self, language: Language) -> AsyncResult<Vec<RaidBoss>> {
+        self.request(|tx| Event::ClientGetBossesByLanguage {
+            language,
+            sender: tx,
+        })
+    }
+
+    // If no boss exists with this exact name, but exactly one boss has it
+    // as a translation, that boss is returned instead
+    pub fn boss<B>(&self, boss_name: B) -> AsyncResult<Option<RaidBoss>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetBoss {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
     pub fn tweets<B>(&self, boss_name: B) -> AsyncResult<Vec<Arc<RaidTweet>>>
     where
         B: Into<BossName>,
     {
         self.request(|tx| Event::ClientGetTweets {
             boss_name: boss_name.into(),
+            order: None,
+            include_translations: false,
+            sender: tx,
+        })
+    }
+
+    // Like `tweets`, for multiple bosses in a single request
+    pub fn tweets_many(
+        &self,
+        boss_names: Vec<BossName>,
+    ) -> AsyncResult<HashMap<BossName, Vec<Arc<RaidTweet>>>> {
+        self.request(|tx| Event::ClientGetTweetsMany {
+            boss_names,
+            sender: tx,
+        })
+    }
+
+    pub fn sorted_tweets<B>(
+        &self,
+        boss_name: B,
+        order: TweetOrder,
+    ) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweets {
+            boss_name: boss_name.into(),
+            order: Some(order),
+            include_translations: false,
+            sender: tx,
+        })
+    }
+
+    // Like `sorted_tweets`, but also includes the recent tweets of the
+    // boss' translations. This is mostly useful with translation history
+    // sharing disabled, since otherwise they're already included.
+    pub fn sorted_tweets_with_translations<B>(
+        &self,
+        boss_name: B,
+        order: TweetOrder,
+    ) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweets {
+            boss_name: boss_name.into(),
+            order: Some(order),
+            include_translations: true,
             sender: tx,
         })
     }
 
+    // Recent tweets for a boss with a sequence number greater than `seq`,
+    // oldest first
+    pub fn tweets_since_seq<B>(&self, boss_name: B, seq: u64) -> AsyncResult<Vec<Arc<RaidTweet>>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetTweetsSinceSeq {
+            boss_name: boss_name.into(),
+            seq,
+            sender: tx,
+        })
+    }
+
+    // Raid IDs of the recent tweets for a boss, newest first
+    pub fn raid_ids<B>(&self, boss_name: B) -> AsyncResult<Vec<RaidId>>
+    where
+        B: Into<BossName>,
+    {
+        self.request(|tx| Event::ClientGetRaidIds {
+            boss_name: boss_name.into(),
+            sender: tx,
+        })
+    }
+
+    // The newest tweet in any boss' recent tweets, by creation time, then
+    // tweet ID
+    pub fn latest_tweet(&self) -> AsyncResult<Option<Arc<RaidTweet>>> {
+        self.request(Event::ClientGetLatestTweet)
+    }
+
+    // Bosses that have been followed, but haven't been seen yet, along with
+    // their follower counts
+    pub fn pending_follows(&self) -> AsyncResult<Vec<(BossName, usize)>> {
+        self.request(Event::ClientGetPendingFollows)
+    }
+
+    // The number of bosses the subscriber follows, including bosses that
+    // haven't been seen yet, or 0 if it isn't subscribed
+    pub fn subscriber_follow_count(&self, id: &SubId) -> AsyncResult<usize> {
+        self.request(|tx| Event::ClientGetSubscriberFollowCount {
+            id: id.clone(),
+            sender: tx,
+        })
+    }
+
+    // Bosses whose last tweet is older than `older_than`, according to the
+    // builder's clock, or the system time if it doesn't have one
+    pub fn stale_bosses(&self, older_than: Duration) -> AsyncResult<Vec<BossName>> {
+        self.request(|sender| Event::ClientGetStaleBosses { older_than, sender })
+    }
+
+    // Returns `None` if the boss doesn't exist
+    pub fn boss_activity<B>(&self, boss_name: B) -> AsyncResult<Option<ActivityStats>>
+    where
+        B: Into<BossName>,
+    {
+        let boss_name = boss_name.into();
+        self.request(|sender| Event::ClientGetBossActivity { boss_name, sender })
+    }
+
+    pub fn stats(&self) -> AsyncResult<WorkerStats> {
+        self.request(Event::ClientGetStats)
+    }
+
+    pub fn config(&self) -> AsyncResult<WorkerConfig> {
+        self.request(Event::ClientGetConfig)
+    }
+
+    // For checking whether the image hash pipeline is stuck
+    pub fn image_hash_status(&self) -> AsyncResult<ImageHashStatus> {
+        self.request(Event::ClientGetImageHashStatus)
+    }
+
     pub fn export_metadata(&self) -> AsyncResult<Vec<RaidBossMetadata>> {
         self.request(Event::ClientExportMetadata)
     }
 
+    // Like `export_metadata`, but bosses are sent in batches, with other
+    // events handled in between. Each boss is exported as it is when its
+    // batch is sent, so bosses removed before then are skipped.
+    pub fn export_metadata_stream(&self) -> impl Stream<Item = RaidBossMetadata, Error = Error> {
+        let (tx, rx) = mpsc::unbounded();
+        self.send(Event::ClientExportMetadataStream {
+            sender: tx,
+            client: self.clone(),
+        });
+
+        rx.map_err(|()| ErrorKind::Closed.into())
+    }
+
     pub fn export_metrics(&self) -> AsyncResult<M> {
         self.request(Event::ClientExportMetrics)
     }
 
+    pub fn export_metrics_and_reset(&self) -> AsyncResult<M> {
+        self.request(Event::ClientExportMetricsAndReset)
+    }
+
     pub fn remove_bosses<F>(&self, f: F)
     where
         F: Fn(&RaidBossMetadata) -> bool + 'static,
@@ -86,6 +431,73 @@ impl<Sub, M> Client<Sub, M> {
         ))));
     }
 
+    // Returns the metadata of the bosses that `remove_bosses` would remove
+    // with the same predicate, without removing anything
+    pub fn remove_bosses_dry_run<F>(&self, f: F) -> AsyncResult<Vec<RaidBossMetadata>>
+    where
+        F: Fn(&RaidBossMetadata) -> bool + 'static,
+    {
+        self.request(|sender| Event::ClientRemoveBossesDryRun {
+            predicate: RemoveBossesPredicate(Box::new(f)),
+            sender,
+        })
+    }
+
+    // Like `remove_bosses`, but the planner is given a snapshot of every
+    // boss, so it can decide based on more than one boss at a time. It
+    // returns the names of the bosses to remove.
+    pub fn remove_bosses_planned<F>(&self, f: F)
+    where
+        F: Fn(&[RaidBossMetadata]) -> Vec<BossName> + 'static,
+    {
+        self.send(Event::ClientRemoveBossesPlanned(RemoveBossesPlanner(
+            Box::new(f),
+        )));
+    }
+
+    // Replaces the worker's raid tweet stream, and completes once the old
+    // stream has been dropped. Fails unless the client was built with
+    // `ClientBuilder::with_dynamic_stream`.
+    pub fn replace_stream(&self, stream: BoxRaidInfoStream) -> AsyncResult<()> {
+        self.request(|sender| Event::ClientReplaceStream(Replacement { stream, sender }))
+    }
+
+    // Replaces the worker's message mapper, and completes once messages are
+    // mapped with the new one. Fails unless the client was built with
+    // `ClientBuilder::with_dynamic_mapper`. Payload options from the
+    // builder aren't applied to the new mapper.
+    pub fn set_message_mapper<G>(&self, mapper: G) -> AsyncResult<()>
+    where
+        Sub: Subscriber,
+        Sub::Item: 'static,
+        G: Fn(Message) -> Option<Sub::Item> + 'static,
+    {
+        let mapper = Box::new(mapper) as BoxMapper<Sub::Item>;
+        self.request(|sender| {
+            Event::ClientSetMessageMapper(MapperReplacement {
+                mapper: Box::new(mapper),
+                sender,
+            })
+        })
+    }
+
+    // Sends a `SystemNotice` to every subscriber. Followers of a boss are
+    // subscribers too, so they get it once, like any other message.
+    pub fn broadcast_notice<S>(&self, notice: S)
+    where
+        S: Into<String>,
+    {
+        self.send(Event::ClientBroadcastNotice(notice.into()));
+    }
+
+    // Handles a raid as if it came from the stream, e.g., to re-announce a
+    // raid that was posted elsewhere. The tweet's source is set to
+    // `TweetSource::Manual`, and its boss doesn't need to be confirmed.
+    // Resolves once the raid has been handled.
+    pub fn inject_raid(&self, info: RaidInfo) -> AsyncResult<()> {
+        self.request(|tx| Event::ClientInjectRaid { info, sender: tx })
+    }
+
     pub fn heartbeat(&self) {
         self.send(Event::SubscriberHeartbeat);
     }
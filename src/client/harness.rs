@@ -0,0 +1,385 @@
+// Utilities for driving a `Worker` deterministically in tests
+#![allow(dead_code)]
+
+use super::{Client, ClientBuilder, Subscription, Worker};
+use broadcast::{MessageMapper, SharedPayload, Subscriber};
+use chrono::{TimeZone, Utc};
+use error::*;
+use futures::{future, Async, Future, Stream};
+use futures::future::{Either, Empty, FutureResult};
+use futures::stream::MapErr;
+use futures::unsync::mpsc;
+use hyper::Uri;
+use image_hash::{BossImageHash, FetchedImage, ImageChecker, ImageHash, ImageHasher, ImageStatus};
+use metrics::{self, Metrics, Simple, SimpleMetrics};
+use model::*;
+use raid::RaidInfo;
+use serde_json;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// An owned copy of each `Message` variant, for making assertions
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recorded {
+    Heartbeat,
+    HeartbeatWithStats { subscribers: u32, bosses: u32 },
+    Tweet(RaidTweet),
+    Gap(BossName, TweetId, TweetId),
+    TweetList(Vec<RaidTweet>),
+    BossUpdate(RaidBoss),
+    BossList(Vec<RaidBoss>),
+    BossRemove(BossName),
+    SystemNotice(String),
+    ServerClosing,
+    Sequenced(u64, Box<Recorded>),
+    Unsupported(MessageKind),
+}
+
+pub fn record(message: Message) -> Option<Recorded> {
+    Some(match message {
+        Message::Sequenced { seq, inner } => {
+            Recorded::Sequenced(seq, Box::new(record(inner.clone())?))
+        }
+        Message::Heartbeat => Recorded::Heartbeat,
+        Message::HeartbeatWithStats {
+            subscribers,
+            bosses,
+        } => Recorded::HeartbeatWithStats {
+            subscribers,
+            bosses,
+        },
+        Message::Tweet(t) => Recorded::Tweet(t.clone()),
+        Message::Gap {
+            boss_name,
+            last_tweet_id,
+            tweet_id,
+        } => Recorded::Gap(boss_name.clone(), last_tweet_id, tweet_id),
+        Message::TweetList(ts) => Recorded::TweetList(ts.iter().map(|t| (**t).clone()).collect()),
+        Message::BossUpdate(b) => Recorded::BossUpdate(b.clone()),
+        Message::BossList(bs) => Recorded::BossList(bs.iter().map(|b| b.boss.clone()).collect()),
+        Message::BossRemove(n) => Recorded::BossRemove(n.clone()),
+        Message::SystemNotice(s) => Recorded::SystemNotice(s.to_string()),
+        Message::ServerClosing => Recorded::ServerClosing,
+        Message::Unsupported(kind) => Recorded::Unsupported(kind),
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Recorder {
+    messages: Rc<RefCell<Vec<Recorded>>>,
+    closed: Rc<Cell<bool>>,
+    stalled: Rc<Cell<bool>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    pub fn take(&self) -> Vec<Recorded> {
+        ::std::mem::replace(&mut *self.messages.borrow_mut(), Vec::new())
+    }
+
+    // Fail all subsequent sends, like a disconnected client
+    pub fn close(&self) {
+        self.closed.set(true);
+    }
+
+    // Report being stalled, like a client that stopped reading
+    pub fn set_stalled(&self, stalled: bool) {
+        self.stalled.set(stalled);
+    }
+}
+
+// Copied for each recipient, which is fine for the few subscribers in tests
+impl SharedPayload for Recorded {}
+
+impl Subscriber for Recorder {
+    type Item = Recorded;
+
+    fn send(&mut self, message: &Recorded) -> ::std::result::Result<(), ()> {
+        if self.closed.get() {
+            return Err(());
+        }
+
+        self.messages.borrow_mut().push(message.clone());
+        Ok(())
+    }
+
+    fn is_stalled(&mut self) -> bool {
+        self.stalled.get()
+    }
+}
+
+// Returns the image hash registered for a boss, if any, immediately.
+// While `stalled` is set, fetches started never complete.
+#[derive(Clone, Debug, Default)]
+pub struct MockHasher {
+    pub hashes: Rc<RefCell<HashMap<BossName, ImageHash>>>,
+    pub requests: Rc<Cell<usize>>,
+    pub stalled: Rc<Cell<bool>>,
+}
+
+impl ImageHasher for MockHasher {
+    type Fetch = Either<FutureResult<FetchedImage, Error>, Empty<FetchedImage, Error>>;
+    type Hash = FutureResult<BossImageHash, Error>;
+
+    fn fetch(&self, boss_name: BossName, _uri: Uri) -> Self::Fetch {
+        self.requests.set(self.requests.get() + 1);
+
+        if self.stalled.get() {
+            return Either::B(future::empty());
+        }
+
+        Either::A(future::ok(FetchedImage {
+            boss_name,
+            bytes: Some(vec![]),
+        }))
+    }
+
+    fn hash(&self, image: FetchedImage) -> Self::Hash {
+        let image_hash = self.hashes.borrow().get(&image.boss_name).cloned();
+
+        future::ok(BossImageHash {
+            boss_name: image.boss_name,
+            image_hash,
+        })
+    }
+}
+
+// Reports images as live unless their URL is in `gone` or `failing`, and
+// keeps track of the URLs that were checked
+#[derive(Clone, Debug, Default)]
+pub struct MockChecker {
+    pub gone: Rc<RefCell<HashSet<String>>>,
+    pub failing: Rc<RefCell<HashSet<String>>>,
+    pub checked: Rc<RefCell<Vec<String>>>,
+}
+
+impl ImageChecker for MockChecker {
+    type Check = FutureResult<ImageStatus, Error>;
+
+    fn check(&self, uri: Uri) -> Self::Check {
+        let url = uri.to_string();
+        let result = if self.failing.borrow().contains(&url) {
+            Err(ErrorKind::Closed.into())
+        } else if self.gone.borrow().contains(&url) {
+            Ok(ImageStatus::Gone)
+        } else {
+            Ok(ImageStatus::Live)
+        };
+
+        self.checked.borrow_mut().push(url);
+        future::result(result)
+    }
+}
+
+pub type TestStream = MapErr<mpsc::UnboundedReceiver<RaidInfo>, fn(()) -> Error>;
+pub type TestMapper = fn(Message) -> Option<Recorded>;
+pub type TestBuilder<M, Sub = Recorder, F = TestMapper> =
+    ClientBuilder<MockHasher, TestStream, Sub, F, M>;
+pub type TestWorker<M, Sub = Recorder, F = TestMapper> =
+    Worker<MockHasher, TestStream, Sub, F, M>;
+
+pub struct Harness<M: Metrics = metrics::NoOp, Sub: Subscriber = Recorder, F = TestMapper> {
+    pub client: Client<Sub, M::Export>,
+    pub worker: TestWorker<M, Sub, F>,
+    pub hasher: MockHasher,
+    raids: mpsc::UnboundedSender<RaidInfo>,
+}
+
+fn closed(_: ()) -> Error {
+    ErrorKind::Closed.into()
+}
+
+pub fn builder() -> (TestBuilder<metrics::NoOp>, MockHasher, mpsc::UnboundedSender<RaidInfo>) {
+    let (tx, rx) = mpsc::unbounded();
+    let hasher = MockHasher::default();
+
+    let builder = ClientBuilder::new()
+        .with_stream(rx.map_err(closed as fn(()) -> Error))
+        .with_image_hasher(hasher.clone())
+        .with_subscriber::<Recorder>()
+        .filter_map_message(record as TestMapper);
+
+    (builder, hasher, tx)
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self::with_builder(|b| b)
+    }
+
+    pub fn with_builder<F>(f: F) -> Self
+    where
+        F: FnOnce(TestBuilder<metrics::NoOp>) -> TestBuilder<metrics::NoOp>,
+    {
+        let (builder, hasher, raids) = builder();
+        Harness::from_builder(f(builder), hasher, raids)
+    }
+}
+
+pub type JsonMetrics = Simple<fn(&SimpleMetrics) -> serde_json::Value>;
+
+fn to_json(metrics: &SimpleMetrics) -> serde_json::Value {
+    serde_json::to_value(metrics).unwrap()
+}
+
+impl Harness<JsonMetrics> {
+    pub fn with_json_metrics() -> Self {
+        Self::with_json_metrics_and_builder(|b| b)
+    }
+
+    pub fn with_json_metrics_and_builder<F>(f: F) -> Self
+    where
+        F: FnOnce(TestBuilder<metrics::NoOp>) -> TestBuilder<metrics::NoOp>,
+    {
+        let (builder, hasher, raids) = builder();
+        let builder = f(builder).with_metrics(metrics::simple(
+            to_json as fn(&SimpleMetrics) -> serde_json::Value,
+        ));
+
+        Harness::from_builder(builder, hasher, raids)
+    }
+
+    pub fn metrics(&mut self) -> serde_json::Value {
+        let metrics = self.client.export_metrics();
+        self.request(metrics)
+    }
+}
+
+impl<M, Sub, F> Harness<M, Sub, F>
+where
+    M: Metrics,
+    Sub: Subscriber + Clone,
+    F: MessageMapper<Sub::Item>,
+{
+    pub fn from_builder(
+        builder: TestBuilder<M, Sub, F>,
+        hasher: MockHasher,
+        raids: mpsc::UnboundedSender<RaidInfo>,
+    ) -> Self {
+        let (client, worker) = builder.build().expect("invalid builder configuration");
+
+        Harness {
+            client,
+            worker,
+            hasher,
+            raids,
+        }
+    }
+
+    // Process all pending events
+    pub fn run(&mut self) {
+        let worker = &mut self.worker;
+        let polled = future::lazy(|| Ok::<_, ()>(worker.poll())).wait().unwrap();
+        assert_eq!(polled.expect("worker failed"), Async::NotReady);
+    }
+
+    // Process pending events, then wait for the result of a client request
+    pub fn request<T, R>(&mut self, request: R) -> T
+    where
+        R: Future<Item = T, Error = Error>,
+    {
+        self.run();
+        request.wait().expect("request failed")
+    }
+
+    pub fn push(&mut self, info: RaidInfo) {
+        self.raids.unbounded_send(info).unwrap();
+        self.run();
+    }
+
+    // Ends the raid tweet stream, which makes the worker fail once polled
+    pub fn end_stream(&mut self) {
+        self.raids = mpsc::unbounded().0;
+    }
+
+    // Sends all of the raid tweets before processing any of them
+    pub fn push_all<I>(&mut self, infos: I)
+    where
+        I: IntoIterator<Item = RaidInfo>,
+    {
+        for info in infos {
+            self.raids.unbounded_send(info).unwrap();
+        }
+        self.run();
+    }
+
+    pub fn set_image_hash<B: Into<BossName>>(&self, boss_name: B, hash: u64) {
+        self.hasher
+            .hashes
+            .borrow_mut()
+            .insert(boss_name.into(), ImageHash::from(hash));
+    }
+}
+
+impl<M: Metrics, F> Harness<M, Recorder, F>
+where
+    F: MessageMapper<Recorded>,
+{
+    pub fn subscribe(&mut self) -> (Subscription<Recorder, M::Export>, Recorder) {
+        let recorder = Recorder::new();
+        let subscription = self.client.subscribe(recorder.clone());
+        (self.request(subscription), recorder)
+    }
+
+    pub fn subscribe_with_filter(
+        &mut self,
+        filter: MessageFilter,
+    ) -> (Subscription<Recorder, M::Export>, Recorder) {
+        let recorder = Recorder::new();
+        let subscription = self.client
+            .subscribe_with_filter(recorder.clone(), filter);
+        (self.request(subscription), recorder)
+    }
+}
+
+pub fn boss_name_language(boss_name: &str) -> Language {
+    if boss_name.starts_with("Lvl") {
+        Language::English
+    } else {
+        Language::Japanese
+    }
+}
+
+pub fn raid_info(boss_name: &str, tweet_id: TweetId, created_at_secs: i64) -> RaidInfo {
+    RaidInfo {
+        tweet: RaidTweet {
+            tweet_id,
+            boss_name: boss_name.into(),
+            raid_id: format!("{:08X}", tweet_id),
+            user: "walfieee".into(),
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(created_at_secs, 0),
+            language: boss_name_language(boss_name),
+            seq: 0,
+            source: TweetSource::Twitter,
+        },
+        image: None,
+    }
+}
+
+pub fn raid_info_with_image(
+    boss_name: &str,
+    tweet_id: TweetId,
+    created_at_secs: i64,
+    image: &str,
+) -> RaidInfo {
+    let mut info = raid_info(boss_name, tweet_id, created_at_secs);
+    info.image = Some(image.into());
+    info
+}
+
+pub fn tweet_ids(messages: &[Recorded]) -> Vec<TweetId> {
+    messages
+        .iter()
+        .flat_map(|m| match *m {
+            Recorded::Tweet(ref t) => vec![t.tweet_id],
+            Recorded::TweetList(ref ts) => ts.iter().map(|t| t.tweet_id).collect(),
+            _ => vec![],
+        })
+        .collect()
+}
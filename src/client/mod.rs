@@ -2,20 +2,29 @@ mod builder;
 mod client;
 mod worker;
 mod subscription;
+#[cfg(test)]
+pub(crate) mod harness;
 
-pub use self::builder::ClientBuilder;
+pub use self::builder::{BuildError, ClientBuilder, MapperCheck};
 pub use self::client::Client;
 pub use self::subscription::Subscription;
 pub use self::worker::Worker;
+use broadcast::MessageSender;
 use error::*;
 use futures::{Future, Poll};
-use futures::unsync::oneshot;
+use futures::unsync::{mpsc, oneshot};
 use id_pool::Id as SubId;
-use image_hash::ImageHash;
-use model::{BossName, RaidBoss, RaidBossMetadata, RaidTweet};
-use raid::RaidInfo;
+use image_hash::{ImageHash, PacingOptions};
+use metrics::EventKind;
+use model::{BossName, DebugRaidBoss, Language, MessageFilter, RaidBoss, RaidBossMetadata, RaidId,
+            RaidTweet, TweetId, TweetOrder};
+use raid::{RaidInfo, Replacement};
+use rand::{self, Rng};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub(crate) enum Event<Sub, M> {
@@ -24,6 +33,7 @@ pub(crate) enum Event<Sub, M> {
         boss_name: BossName,
         image_hash: ImageHash,
     },
+    ImageHashFailed(BossName),
 
     SubscriberFollow {
         id: SubId,
@@ -33,6 +43,26 @@ pub(crate) enum Event<Sub, M> {
         id: SubId,
         boss_name: BossName,
     },
+    SubscriberFollowMany {
+        id: SubId,
+        names: Vec<BossName>,
+    },
+    SubscriberUnfollowMany {
+        id: SubId,
+        names: Vec<BossName>,
+    },
+    SubscriberFollowWithCatchUp {
+        id: SubId,
+        boss_name: BossName,
+    },
+    SubscriberFollowWithBacklog {
+        id: SubId,
+        boss_name: BossName,
+    },
+    SubscriberSetMuted {
+        id: SubId,
+        muted: bool,
+    },
     SubscriberGetBosses(SubId),
     SubscriberGetTweets {
         id: SubId,
@@ -42,23 +72,264 @@ pub(crate) enum Event<Sub, M> {
 
     SubscriberSubscribe {
         subscriber: Sub,
+        filter: MessageFilter,
+        key: Option<u64>,
+        client: Client<Sub, M>,
+        sender: oneshot::Sender<Subscription<Sub, M>>,
+    },
+    SubscriberSubscribeStream {
+        message_sender: MessageSender,
+        filter: MessageFilter,
         client: Client<Sub, M>,
         sender: oneshot::Sender<Subscription<Sub, M>>,
     },
     SubscriberUnsubscribe(SubId),
+    SubscriberFollowReliable {
+        id: SubId,
+        resume_token: ResumeToken,
+        boss_name: BossName,
+    },
+    SubscriberAck {
+        resume_token: ResumeToken,
+        seq: u64,
+    },
+    SubscriberResume {
+        subscriber: Sub,
+        resume_token: ResumeToken,
+        client: Client<Sub, M>,
+        sender: oneshot::Sender<Subscription<Sub, M>>,
+    },
 
     ClientGetBosses(oneshot::Sender<Vec<RaidBoss>>),
+    ClientGetBossesVersioned(oneshot::Sender<(u64, Vec<RaidBoss>)>),
+    ClientGetBossListRevision(oneshot::Sender<u64>),
+    ClientGetDebugBosses(oneshot::Sender<Vec<DebugRaidBoss>>),
+    ClientGetBossesByLanguage {
+        language: Language,
+        sender: oneshot::Sender<Vec<RaidBoss>>,
+    },
+    ClientGetBoss {
+        boss_name: BossName,
+        sender: oneshot::Sender<Option<RaidBoss>>,
+    },
     ClientGetTweets {
         boss_name: BossName,
+        order: Option<TweetOrder>,
+        include_translations: bool,
         sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
     },
+    ClientGetTweetsMany {
+        boss_names: Vec<BossName>,
+        sender: oneshot::Sender<HashMap<BossName, Vec<Arc<RaidTweet>>>>,
+    },
+    ClientGetTweetsSinceSeq {
+        boss_name: BossName,
+        seq: u64,
+        sender: oneshot::Sender<Vec<Arc<RaidTweet>>>,
+    },
+    ClientGetRaidIds {
+        boss_name: BossName,
+        sender: oneshot::Sender<Vec<RaidId>>,
+    },
+    ClientGetPendingFollows(oneshot::Sender<Vec<(BossName, usize)>>),
+    ClientGetSubscriberFollowCount {
+        id: SubId,
+        sender: oneshot::Sender<usize>,
+    },
+    ClientGetLatestTweet(oneshot::Sender<Option<Arc<RaidTweet>>>),
+    ClientGetBossActivity {
+        boss_name: BossName,
+        sender: oneshot::Sender<Option<ActivityStats>>,
+    },
+    ClientGetStaleBosses {
+        older_than: Duration,
+        sender: oneshot::Sender<Vec<BossName>>,
+    },
     ClientExportMetadata(oneshot::Sender<Vec<RaidBossMetadata>>),
+    ClientExportMetadataStream {
+        sender: mpsc::UnboundedSender<RaidBossMetadata>,
+        client: Client<Sub, M>,
+    },
+    ContinueMetadataExport(MetadataExport<Sub, M>),
+    ClientGetStats(oneshot::Sender<WorkerStats>),
+    ClientGetConfig(oneshot::Sender<WorkerConfig>),
+    ClientGetImageHashStatus(oneshot::Sender<ImageHashStatus>),
     ClientExportMetrics(oneshot::Sender<M>),
+    ClientExportMetricsAndReset(oneshot::Sender<M>),
     ClientRemoveBosses(RemoveBossesPredicate),
+    ClientRemoveBossesDryRun {
+        predicate: RemoveBossesPredicate,
+        sender: oneshot::Sender<Vec<RaidBossMetadata>>,
+    },
+    ClientRemoveBossesPlanned(RemoveBossesPlanner),
+    ClientRehashBoss(BossName),
+    ClientSetHashThreshold(u32),
+    ClientSetBossMuted {
+        boss_name: BossName,
+        muted: bool,
+        sender: oneshot::Sender<bool>,
+    },
+    ClientGetPendingTranslations(oneshot::Sender<Vec<(BossName, BossName)>>),
+    ClientConfirmTranslation(BossName, BossName),
+    ClientRejectTranslation(BossName, BossName),
+    ClientReplaceStream(Replacement),
+    ClientSetMessageMapper(MapperReplacement),
+    ClientBroadcastNotice(String),
+    ClientInjectRaid {
+        info: RaidInfo,
+        sender: oneshot::Sender<()>,
+    },
 
     ClientReadError,
 }
 
+impl<Sub, M> Event<Sub, M> {
+    pub(crate) fn kind(&self) -> EventKind {
+        use self::Event::*;
+
+        match *self {
+            NewRaidInfo(_) | NewImageHash { .. } | ImageHashFailed(_) => EventKind::Ingest,
+
+            SubscriberFollow { .. }
+            | SubscriberUnfollow { .. }
+            | SubscriberFollowMany { .. }
+            | SubscriberUnfollowMany { .. }
+            | SubscriberFollowWithCatchUp { .. }
+            | SubscriberFollowWithBacklog { .. }
+            | SubscriberSetMuted { .. }
+            | SubscriberGetBosses(_)
+            | SubscriberGetTweets { .. }
+            | SubscriberHeartbeat
+            | SubscriberSubscribe { .. }
+            | SubscriberSubscribeStream { .. }
+            | SubscriberUnsubscribe(_)
+            | SubscriberFollowReliable { .. }
+            | SubscriberAck { .. }
+            | SubscriberResume { .. } => EventKind::Subscriber,
+
+            ClientGetBosses(_)
+            | ClientGetBossesVersioned(_)
+            | ClientGetBossListRevision(_)
+            | ClientGetDebugBosses(_)
+            | ClientGetBossesByLanguage { .. }
+            | ClientGetBoss { .. }
+            | ClientGetTweets { .. }
+            | ClientGetTweetsMany { .. }
+            | ClientGetTweetsSinceSeq { .. }
+            | ClientGetRaidIds { .. }
+            | ClientGetPendingFollows(_)
+            | ClientGetSubscriberFollowCount { .. }
+            | ClientGetLatestTweet(_)
+            | ClientGetBossActivity { .. }
+            | ClientGetStaleBosses { .. }
+            | ClientExportMetadata(_)
+            | ClientExportMetadataStream { .. }
+            | ContinueMetadataExport(_)
+            | ClientGetStats(_)
+            | ClientGetConfig(_)
+            | ClientGetImageHashStatus(_)
+            | ClientExportMetrics(_)
+            | ClientExportMetricsAndReset(_)
+            | ClientRemoveBosses(_)
+            | ClientRemoveBossesDryRun { .. }
+            | ClientRemoveBossesPlanned(_)
+            | ClientRehashBoss(_)
+            | ClientSetHashThreshold(_)
+            | ClientSetBossMuted { .. }
+            | ClientGetPendingTranslations(_)
+            | ClientConfirmTranslation(_, _)
+            | ClientRejectTranslation(_, _)
+            | ClientReplaceStream(_)
+            | ClientSetMessageMapper(_)
+            | ClientBroadcastNotice(_)
+            | ClientInjectRaid { .. }
+            | ClientReadError => EventKind::Client,
+        }
+    }
+}
+
+// A metadata export in progress. The worker sends it back to itself after
+// each batch, so that other events are handled in between batches.
+#[derive(Debug)]
+pub(crate) struct MetadataExport<Sub, M> {
+    pub(crate) remaining: VecDeque<BossName>,
+    pub(crate) sender: mpsc::UnboundedSender<RaidBossMetadata>,
+    pub(crate) client: Client<Sub, M>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WorkerStats {
+    pub bosses: usize,
+    pub subscribers: usize,
+    // Total number of tweets in the history of all bosses
+    pub buffered_tweets: usize,
+}
+
+// The effective settings a worker was built with, including defaults
+// for anything that wasn't set on the builder
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WorkerConfig {
+    pub history_size: usize,
+    pub global_history_limit: Option<usize>,
+    pub unacked_buffer_size: usize,
+    pub max_detached_sessions: usize,
+    pub image_fetch_concurrency: usize,
+    pub image_hash_concurrency: usize,
+    pub unique_user_capacity: usize,
+    pub gap_threshold: Option<TweetId>,
+    pub straggler_threshold: Duration,
+    pub max_tweet_age: Option<Duration>,
+    pub image_hash_pacing: Option<PacingOptions>,
+    pub tweet_coalescing_window: Option<Duration>,
+    pub idle_subscriber_timeout: Option<Duration>,
+    pub heartbeat_splay_window: Option<Duration>,
+    pub image_check_age: Option<Duration>,
+    pub boss_confirmation_threshold: usize,
+    pub boss_confirmation_window: Duration,
+    pub review_translations: bool,
+    pub translation_history_sharing: bool,
+    pub stats_in_heartbeat: bool,
+    pub debug_image_hashes: bool,
+    pub dynamic_stream: bool,
+    pub sequencing: bool,
+}
+
+// How often a boss has been tweeted recently, based on its tweet history
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ActivityStats {
+    // Number of tweets in the boss' history
+    pub tweets: usize,
+    // Average number of seconds between those tweets, if there are at
+    // least two of them
+    pub average_interval_secs: Option<f64>,
+    // Distinct users among the boss' most recent tweets, up to the
+    // builder's unique user capacity. This isn't limited to the tweets in
+    // the boss' history.
+    pub unique_recent_users: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImageHashStatus {
+    // Requests waiting in the image hash queue or in flight
+    pub queued: usize,
+    // Bosses that have requested an image hash, but haven't received one
+    pub pending: Vec<BossName>,
+}
+
+// Identifies a subscription's reliable delivery state, which outlives the
+// subscription itself so that it can be resumed after a disconnect. Tokens
+// are random, so one subscriber can't guess another's token and take over
+// its session.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ResumeToken(u64, u64);
+
+impl ResumeToken {
+    pub(crate) fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        ResumeToken(rng.gen(), rng.gen())
+    }
+}
+
 // This is only here because `Debug` isn't implemented for `Fn(&T)`
 pub(crate) struct RemoveBossesPredicate(Box<Fn(&RaidBossMetadata) -> bool>);
 impl fmt::Debug for RemoveBossesPredicate {
@@ -67,6 +338,26 @@ impl fmt::Debug for RemoveBossesPredicate {
     }
 }
 
+pub(crate) struct RemoveBossesPlanner(Box<Fn(&[RaidBossMetadata]) -> Vec<BossName>>);
+impl fmt::Debug for RemoveBossesPlanner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        write!(f, "function")
+    }
+}
+
+// A new mapper for a `DynamicMapper`, which should be a `BoxMapper` for the
+// subscriber's item type
+pub(crate) struct MapperReplacement {
+    pub(crate) mapper: Box<Any>,
+    // Completed once the mapper has been replaced
+    pub(crate) sender: oneshot::Sender<()>,
+}
+impl fmt::Debug for MapperReplacement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        write!(f, "MapperReplacement")
+    }
+}
+
 pub struct AsyncResult<T>(oneshot::Receiver<T>);
 impl<T> Future for AsyncResult<T> {
     type Item = T;
@@ -76,3 +367,41 @@ impl<T> Future for AsyncResult<T> {
         self.0.poll().map_err(|_| ErrorKind::Closed.into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use broadcast::NoOpSubscriber;
+    use model::{RaidBoss, RaidTweet};
+
+    fn assert_clone<T: Clone>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    // Only compiles if `T` doesn't implement `Send`, because otherwise
+    // the type parameter `A` would be ambiguous
+    trait AmbiguousIfSend<A> {
+        fn some_item() {}
+    }
+    impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+    impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+
+    #[test]
+    fn client_is_clone() {
+        assert_clone::<Client<NoOpSubscriber, ()>>();
+        assert_clone::<Client<NoOpSubscriber, String>>();
+    }
+
+    #[test]
+    fn client_is_not_send() {
+        let _ = <Client<NoOpSubscriber, ()> as AmbiguousIfSend<_>>::some_item;
+        let _ = <Client<NoOpSubscriber, String> as AmbiguousIfSend<_>>::some_item;
+        let _ = <Subscription<NoOpSubscriber, ()> as AmbiguousIfSend<_>>::some_item;
+    }
+
+    #[test]
+    fn message_payloads_are_send_sync() {
+        assert_send_sync::<RaidTweet>();
+        assert_send_sync::<RaidBoss>();
+        assert_send_sync::<Arc<RaidTweet>>();
+    }
+}
@@ -1,3 +1,4 @@
+use super::ResumeToken;
 pub use client::Client;
 use id_pool::Id as SubId;
 use model::BossName;
@@ -9,7 +10,11 @@ use std::collections::HashSet;
 pub struct Subscription<Sub, M = ()> {
     pub(crate) id: SubId,
     pub(crate) following: HashSet<BossName>,
+    pub(crate) resume_token: ResumeToken,
+    pub(crate) desynced: bool,
     pub(crate) client: Client<Sub, M>,
+    // Set if the worker has already unsubscribed it
+    pub(crate) detached: bool,
 }
 
 impl<Sub, M> Subscription<Sub, M> {
@@ -22,6 +27,79 @@ impl<Sub, M> Subscription<Sub, M> {
         self.client.subscriber_follow(self.id.clone(), name);
     }
 
+    // Like calling `follow` for each boss, but sent to the worker as a
+    // single event, e.g., for restoring a saved list of bosses
+    pub fn follow_many<I, B>(&mut self, boss_names: I)
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<BossName>,
+    {
+        let names = boss_names.into_iter().map(Into::into).collect::<Vec<_>>();
+        self.following.extend(names.iter().cloned());
+        self.client.subscriber_follow_many(self.id.clone(), names);
+    }
+
+    // Like `follow`, but first replays the boss' recent tweets as individual
+    // `Tweet` messages, oldest first. No tweets are missed or duplicated
+    // between the replayed tweets and the live ones that follow.
+    pub fn follow_with_catch_up<B>(&mut self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        let name = boss_name.into();
+        self.following.insert(name.clone());
+        self.client
+            .subscriber_follow_with_catch_up(self.id.clone(), name);
+    }
+
+    // Like `get_tweets` followed by `follow`, except that no tweets can
+    // arrive in between, so none are missed or duplicated
+    pub fn follow_with_backlog<B>(&mut self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        let name = boss_name.into();
+        self.following.insert(name.clone());
+        self.client
+            .subscriber_follow_with_backlog(self.id.clone(), name);
+    }
+
+    // Like `follow`, but each tweet is wrapped in a `Message::Sequenced`
+    // with a sequence number that should be passed to `ack` once the tweet
+    // has been handled. Unacknowledged tweets are kept after the
+    // subscription ends, and are redelivered by `Client::resume_subscription`.
+    pub fn follow_reliable<B>(&mut self, boss_name: B)
+    where
+        B: Into<BossName>,
+    {
+        self.client.subscriber_follow_reliable(
+            self.id.clone(),
+            self.resume_token,
+            boss_name.into(),
+        );
+    }
+
+    // Acknowledge all reliably delivered messages up to and including `seq`
+    pub fn ack(&self, seq: u64) {
+        self.client.subscriber_ack(self.resume_token, seq)
+    }
+
+    // Identifies this subscription in client requests, e.g.,
+    // `Client::subscriber_follow_count`
+    pub fn id(&self) -> &SubId {
+        &self.id
+    }
+
+    pub fn resume_token(&self) -> ResumeToken {
+        self.resume_token
+    }
+
+    // Whether unacknowledged messages were dropped before this subscription
+    // was resumed, because the buffer of unacknowledged messages was full
+    pub fn is_desynced(&self) -> bool {
+        self.desynced
+    }
+
     pub fn unfollow<B>(&mut self, boss_name: B)
     where
         B: Into<BossName>,
@@ -31,6 +109,25 @@ impl<Sub, M> Subscription<Sub, M> {
         self.client.subscriber_unfollow(self.id.clone(), name);
     }
 
+    // Like calling `unfollow` for each boss, but as a single event
+    pub fn unfollow_many<I, B>(&mut self, boss_names: I)
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<BossName>,
+    {
+        let names = boss_names.into_iter().map(Into::into).collect::<Vec<_>>();
+        for name in &names {
+            self.following.remove(name);
+        }
+        self.client.subscriber_unfollow_many(self.id.clone(), names);
+    }
+
+    // While muted, nothing is sent to the subscriber, but its follows are
+    // kept. Tweets sent in the meantime aren't redelivered after unmuting.
+    pub fn set_muted(&self, muted: bool) {
+        self.client.subscriber_set_muted(self.id.clone(), muted)
+    }
+
     pub fn get_bosses(&self) {
         self.client.subscriber_get_bosses(self.id.clone())
     }
@@ -48,6 +145,12 @@ impl<Sub, M> Subscription<Sub, M> {
         self.non_consuming_unsubscribe()
     }
 
+    // Drops the subscription without notifying the worker, for when the
+    // worker has already unsubscribed it
+    pub(crate) fn detach(mut self) {
+        self.detached = true;
+    }
+
     // This is needed for the Drop implementation
     fn non_consuming_unsubscribe(&self) {
         self.client.subscriber_unsubscribe(self.id.clone())
@@ -56,10 +159,14 @@ impl<Sub, M> Subscription<Sub, M> {
 
 impl<Sub, M> Drop for Subscription<Sub, M> {
     fn drop(&mut self) {
-        let mut following = ::std::mem::replace(&mut self.following, HashSet::with_capacity(0));
+        if self.detached {
+            return;
+        }
+
+        let following = ::std::mem::replace(&mut self.following, HashSet::with_capacity(0));
 
-        for boss_name in following.drain() {
-            self.unfollow(boss_name);
+        if !following.is_empty() {
+            self.unfollow_many(following);
         }
 
         self.non_consuming_unsubscribe();
@@ -1,26 +1,48 @@
-use super::{Event, Subscription};
-use broadcast::{Broadcast, Subscriber};
+use super::{ActivityStats, Event, ImageHashStatus, MetadataExport, ResumeToken, Subscription,
+            WorkerConfig, WorkerStats};
+use broadcast::{Broadcast, MessageMapper, Outgoing, Subscriber};
 use circular_buffer::CircularBuffer;
+use clock::{to_chrono, Clock, Interval};
 use error::*;
 use futures::{Async, Future, Poll, Stream};
-use futures::stream::{Chain, FilterMap, Map, Once, OrElse, Select};
-use futures::unsync::mpsc;
+use futures::stream::{Chain, FuturesUnordered, Map, Once, OrElse, Select};
+use futures::unsync::{mpsc, oneshot};
 use id_pool::{Id as SubId, IdPool};
-use image_hash::{BossImageHash, ImageHash, ImageHashReceiver, ImageHashSender, ImageHasher};
-use metrics::Metrics;
-use model::{BossLevel, BossName, Message, RaidBoss, RaidBossMetadata, RaidTweet};
-use raid::RaidInfo;
-use std::collections::{HashMap, HashSet};
+use image_hash::{BossImageHash, DynImageChecker, ImageHash, ImageHashReceiver, ImageHashSender,
+                 ImageHasher, ImageStatus, TranslationMatcher};
+use metrics::{EventKind, Metrics};
+use model::{BossImageUrl, BossLevel, BossName, DateTime, DebugRaidBoss, Message, MessageFilter,
+            MessageKind, RaidBoss, RaidBossMetadata, RaidTweet, TweetId, TweetSource};
+use raid::{RaidInfo, StreamReplacer, StreamWarnings};
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::collections::hash_map::Entry;
 use std::iter::FromIterator;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
+use tweet_store::{StoreFactory, TweetStore};
 
 const DEFAULT_BOSS_LEVEL: BossLevel = 0;
 
+// Number of bosses sent per event by `Client::export_metadata_stream`
+const METADATA_EXPORT_BATCH_SIZE: usize = 50;
+
 pub(crate) struct RaidBossEntry<Sub> {
     pub(crate) boss_data: RaidBossMetadata,
-    pub(crate) recent_tweets: CircularBuffer<Arc<RaidTweet>>,
+    pub(crate) recent_tweets: Box<TweetStore>,
+    pub(crate) recent_users: RecentUsers,
+    // The image a boss from `ClientBuilder::with_bosses` was seeded with,
+    // until a tweet with an image is seen for it
+    pub(crate) seeded_image: Option<BossImageUrl>,
+    // The highest tweet ID seen for this boss since the worker started
+    pub(crate) last_tweet_id: Option<TweetId>,
     pub(crate) broadcast: Broadcast<SubId, Sub>,
+    // When the boss' image was set, or last checked by `ImageChecks`
+    pub(crate) image_seen_at: DateTime,
+    // Translations that were linked because image hashes matched. These are
+    // unlinked if the boss' image is hashed again and no longer matches.
+    pub(crate) hash_links: HashSet<BossName>,
 }
 
 #[must_use = "futures do nothing unless polled"]
@@ -40,388 +62,5647 @@ where
                 fn(()) -> Result<Event<Sub, M::Export>>,
                 Result<Event<Sub, M::Export>>,
             >,
-            FilterMap<ImageHashReceiver<H>, fn(BossImageHash) -> Option<Event<Sub, M::Export>>>,
+            Map<ImageHashReceiver<H>, fn(BossImageHash) -> Event<Sub, M::Export>>,
         >,
     >,
     pub(crate) bosses: HashMap<BossName, RaidBossEntry<Sub>>,
     pub(crate) tweet_history_size: usize,
     pub(crate) requested_bosses: HashMap<BossName, Broadcast<SubId, Sub>>,
     pub(crate) subscribers: Broadcast<SubId, Sub>,
+    // The filters of muted subscribers, to be restored when unmuted
+    pub(crate) muted_subscribers: HashMap<SubId, MessageFilter>,
     pub(crate) filter_map_message: F,
     pub(crate) cached_boss_list: Option<Sub::Item>,
+    pub(crate) cached_boss_list_is_stale: bool,
+    pub(crate) tweet_store: StoreFactory,
+    pub(crate) boss_list_revision: u64,
     pub(crate) heartbeat: Option<Sub::Item>,
+    // If set, heartbeats are mapped on each send instead of using `heartbeat`
+    pub(crate) stats_in_heartbeat: bool,
     pub(crate) metrics: M,
+    // If unset, the system time is used
+    pub(crate) clock: Option<Rc<Clock>>,
+    pub(crate) coalescing: Option<Coalescing>,
+    pub(crate) idle_sweep: Option<IdleSweep>,
+    pub(crate) heartbeat_splay: Option<HeartbeatSplay>,
+    pub(crate) image_checks: Option<ImageChecks>,
+    pub(crate) global_history_limit: Option<usize>,
+    pub(crate) buffered_tweets: usize,
+    // Only set if there's a global history limit
+    pub(crate) history_eviction_order: Option<EvictionOrder>,
+    // Subscribers whose `send` failed while handling the current event
+    pub(crate) evicted: Vec<SubId>,
+    pub(crate) reliable_sessions: HashMap<ResumeToken, ReliableSession>,
+    // Orders detached sessions, so the oldest can be dropped first
+    pub(crate) next_detach_order: u64,
+    pub(crate) next_tweet_seq: u64,
+    pub(crate) unacked_buffer_size: usize,
+    // If set, image hash matches are added to `pending_translations`
+    // instead of being linked
+    pub(crate) review_translations: bool,
+    pub(crate) translation_matcher: Rc<TranslationMatcher>,
+    pub(crate) pending_translations: Vec<(BossName, BossName)>,
+    // Pairs rejected with `Client::reject_translation`, in both orders, so
+    // they aren't matched again
+    pub(crate) rejected_translations: HashSet<(BossName, BossName)>,
+    // Bosses with an image hash requested, but no result yet
+    pub(crate) pending_image_hashes: HashSet<BossName>,
+    pub(crate) config: WorkerConfig,
+    pub(crate) boss_confirmation_threshold: usize,
+    // Number of times each boss has been seen, and when it was first seen,
+    // until it's confirmed
+    pub(crate) unconfirmed_bosses: HashMap<BossName, (usize, DateTime)>,
+    // Subscriptions made with `Client::subscribe_keyed`
+    pub(crate) keyed_subscribers: HashMap<u64, SubId>,
+    // Lowercased screen names of users whose tweets are dropped
+    pub(crate) user_blocklist: HashSet<String>,
+    // Reported to the metrics before each export
+    pub(crate) stream_warnings: Option<StreamWarnings>,
+    // Only set if the stream is a `SwitchableStream`
+    pub(crate) stream_replacer: Option<StreamReplacer>,
 }
 
-impl<H, S, Sub, F, M> Worker<H, S, Sub, F, M>
-where
-    H: ImageHasher,
-    Sub: Subscriber + Clone,
-    F: Fn(Message) -> Option<Sub::Item>,
-    M: Metrics,
-{
-    fn handle_event(&mut self, event: Event<Sub, M::Export>) {
-        use super::Event::*;
+// Reliable delivery state for a subscription. This outlives the subscription
+// so that unacknowledged tweets can be redelivered when it is resumed.
+pub(crate) struct ReliableSession {
+    // `None` after the subscription ends, until it is resumed
+    subscriber: Option<SubId>,
+    // Set while detached, from `Worker::next_detach_order`
+    detached_order: Option<u64>,
+    // Set by the idle sweep while detached. Sessions that are still detached
+    // on the next sweep are dropped.
+    expiring: bool,
+    bosses: HashSet<BossName>,
+    next_seq: u64,
+    unacked: VecDeque<(u64, Arc<RaidTweet>)>,
+    // Whether unacknowledged tweets were dropped since the last resume
+    desynced: bool,
+}
 
-        match event {
-            SubscriberSubscribe {
-                subscriber,
-                sender,
-                client,
-            } => {
-                let id = self.subscribe(subscriber);
-                let _ = sender.send(Subscription {
-                    id,
-                    following: HashSet::new(),
-                    client,
-                });
-            }
-            SubscriberUnsubscribe(id) => {
-                self.unsubscribe(&id);
-            }
-            SubscriberFollow { id, boss_name } => {
-                self.follow(id, boss_name);
-            }
-            SubscriberUnfollow { id, boss_name } => {
-                self.unfollow(&id, boss_name);
-            }
-            SubscriberGetBosses(id) => {
-                if let Some(sub) = self.subscribers.get_mut(&id) {
-                    let _ = sub.maybe_send(self.cached_boss_list.as_ref());
-                }
-            }
-            SubscriberGetTweets { id, boss_name } => {
-                if let Some(sub) = self.subscribers.get_mut(&id) {
-                    let tweets = self.bosses
-                        .get(&boss_name)
-                        .map_or(&[][..], |e| e.recent_tweets.as_unordered_slice());
+impl ReliableSession {
+    fn new(subscriber: SubId) -> Self {
+        ReliableSession {
+            subscriber: Some(subscriber),
+            detached_order: None,
+            expiring: false,
+            bosses: HashSet::new(),
+            next_seq: 0,
+            unacked: VecDeque::new(),
+            desynced: false,
+        }
+    }
 
-                    let message = (self.filter_map_message)(Message::TweetList(tweets));
+    // Returns the sequence number assigned to the tweet. If the buffer is
+    // full, the oldest unacknowledged tweet is dropped.
+    fn push(&mut self, tweet: Arc<RaidTweet>, capacity: usize) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
 
-                    let _ = sub.maybe_send(message.as_ref());
-                }
-            }
-            SubscriberHeartbeat => self.subscribers.maybe_send(self.heartbeat.as_ref()),
+        if self.unacked.len() >= capacity {
+            self.unacked.pop_front();
+            self.desynced = true;
+        }
 
-            NewRaidInfo(r) => {
-                self.handle_raid_info(r);
-            }
-            NewImageHash {
-                boss_name,
-                image_hash,
-            } => {
-                self.handle_image_hash(boss_name, image_hash);
-            }
+        self.unacked.push_back((seq, tweet));
+        seq
+    }
 
-            ClientGetBosses(tx) => {
-                let _ = tx.send(Vec::from_iter(
-                    self.bosses.values().map(|e| e.boss_data.boss.clone()),
-                ));
-            }
-            ClientGetTweets { boss_name, sender } => {
-                let tweets = self.bosses.get(&boss_name).map_or(vec![], |e| {
-                    // Returns recent tweets, unsorted. The client is
-                    // expected to do the sorting on their end.
-                    e.recent_tweets.as_unordered_slice().to_vec()
-                });
+    fn attach(&mut self, subscriber: SubId) {
+        self.subscriber = Some(subscriber);
+        self.detached_order = None;
+        self.expiring = false;
+    }
 
-                let _ = sender.send(tweets);
-            }
-            ClientExportMetadata(tx) => {
-                let _ = tx.send(Vec::from_iter(
-                    self.bosses.values().map(|e| e.boss_data.clone()),
-                ));
-            }
-            ClientExportMetrics(tx) => {
-                let _ = tx.send(self.metrics.export());
-            }
-            ClientRemoveBosses(f) => {
-                self.remove_bosses(f.0);
-            }
-            ClientReadError => {} // This should never happen
+    fn ack(&mut self, seq: u64) {
+        while self.unacked.front().map_or(false, |&(s, _)| s <= seq) {
+            self.unacked.pop_front();
         }
     }
+}
 
-    fn remove_bosses(&mut self, f: Box<Fn(&RaidBossMetadata) -> bool>) {
-        let (filter_map, subscribers, requested_bosses, metrics) = (
-            &self.filter_map_message,
-            &mut self.subscribers,
-            &mut self.requested_bosses,
-            &mut self.metrics,
-        );
+// The result of looking up a boss by name with `resolve_boss_name`
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Resolution {
+    Exact,
+    // No boss has the exact name, but this boss has it as a translation
+    Translated(BossName),
+    // Multiple bosses of different levels have the name as a translation
+    Ambiguous,
+    Miss,
+}
 
-        self.bosses.retain(|_, entry| {
-            let should_remove = (f)(&entry.boss_data);
+// Pushes a tweet into a boss' history, keeping track of the total number
+// of tweets buffered across all bosses
+// Tweets are added to the history in the order they arrive, unless they're
+// older than the newest tweet by more than `straggler_threshold`, in which
+// case they're inserted where they belong
+// Returns whether the history was empty, since bosses without tweets
+// aren't in `Worker::history_eviction_order`
+fn push_recent_tweet(
+    history: &mut TweetStore,
+    tweet: Arc<RaidTweet>,
+    buffered_tweets: &mut usize,
+    straggler_threshold: ::chrono::Duration,
+) -> bool {
+    let was_empty = history.is_empty();
+    let is_straggler = history.newest_created_at().map_or(false, |newest| {
+        newest.signed_duration_since(tweet.created_at) > straggler_threshold
+    });
 
-            if should_remove {
-                let boss_name = &entry.boss_data.boss.name;
-                let message = (filter_map)(Message::BossRemove(boss_name));
-                subscribers.maybe_send(message.as_ref());
+    let evicted = if is_straggler {
+        history.insert_by_created_at(tweet)
+    } else {
+        history.push(tweet)
+    };
 
-                // If there are existing subscribers, move them to `requested_bosses`
-                if !entry.broadcast.is_empty() {
-                    let broadcast = ::std::mem::replace(&mut entry.broadcast, Broadcast::new());
-                    requested_bosses.insert(boss_name.clone(), broadcast);
-                }
+    if evicted.is_none() {
+        *buffered_tweets += 1;
+    }
 
-                metrics.remove_boss(boss_name);
-            }
+    was_empty
+}
 
-            !should_remove
-        });
-    }
+// Bosses with tweets, least recently seen first. Entries are checked
+// against the boss when they're popped, since bosses are seen again after
+// they're added.
+pub(crate) type EvictionOrder = BinaryHeap<Reverse<(DateTime, BossName)>>;
 
-    fn subscribe(&mut self, subscriber: Sub) -> SubId {
-        let id = self.id_pool.get();
-        self.subscribers.subscribe(id.clone(), subscriber);
-        self.metrics
-            .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
-        id
+// Bosses are only in the eviction order while they have tweets, so a boss
+// is added once a tweet is pushed into its empty history
+fn track_eviction_order(
+    order: &mut Option<EvictionOrder>,
+    was_empty: bool,
+    last_seen: DateTime,
+    boss_name: &BossName,
+) {
+    if let Some(ref mut order) = *order {
+        if was_empty {
+            order.push(Reverse((last_seen, boss_name.clone())));
+        }
     }
+}
 
-    fn unsubscribe(&mut self, id: &SubId) {
-        self.subscribers.unsubscribe(id);
-        self.metrics
-            .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
-        self.id_pool.recycle(id.clone());
+// Tweets can arrive out of order, but the average gap between consecutive
+// timestamps is the same as the total span divided by the number of gaps
+fn activity_stats<Sub>(entry: &RaidBossEntry<Sub>) -> ActivityStats {
+    let tweets = entry.recent_tweets.recent();
+    let earliest = tweets.iter().map(|t| t.created_at).min();
+    let latest = tweets.iter().map(|t| t.created_at).max();
+
+    let average_interval_secs = match (earliest, latest) {
+        (Some(earliest), Some(latest)) if tweets.len() > 1 => {
+            let span = latest.signed_duration_since(earliest);
+            let millis = span.num_milliseconds() as f64;
+            Some(millis / 1000.0 / (tweets.len() - 1) as f64)
+        }
+        _ => None,
+    };
+
+    ActivityStats {
+        tweets: tweets.len(),
+        average_interval_secs,
+        unique_recent_users: entry.recent_users.len(),
     }
+}
 
-    fn follow(&mut self, id: SubId, boss_name: BossName) {
-        if let Some(sub) = self.subscribers.get(&id) {
-            let subscriber = sub.clone();
+// The most recent users to tweet about a boss, without duplicates. Once
+// full, the user that was added the longest ago is forgotten, even if they
+// tweeted again since then.
+pub(crate) struct RecentUsers {
+    users: HashSet<String>,
+    order: CircularBuffer<String>,
+}
 
-            if let Some(entry) = self.bosses.get_mut(&boss_name) {
-                entry.broadcast.subscribe(id, subscriber);
-                self.metrics
-                    .set_follower_count(&boss_name, entry.broadcast.subscriber_count() as u32);
-            } else {
-                match self.requested_bosses.entry(boss_name) {
-                    Entry::Occupied(mut entry) => {
-                        entry.get_mut().subscribe(id, subscriber);
-                    }
-                    Entry::Vacant(entry) => {
-                        let mut broadcast = Broadcast::new();
-                        broadcast.subscribe(id, subscriber);
-                        entry.insert(broadcast);
-                    }
-                }
-            }
+impl RecentUsers {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        RecentUsers {
+            users: HashSet::with_capacity(capacity),
+            order: CircularBuffer::with_capacity(capacity),
         }
     }
 
-    fn unfollow(&mut self, id: &SubId, boss_name: BossName) {
-        if let Some(entry) = self.bosses.get_mut(&boss_name) {
-            entry.broadcast.unsubscribe(&id);
-            self.metrics
-                .set_follower_count(&boss_name, entry.broadcast.subscriber_count() as u32);
-        } else if let Entry::Occupied(mut entry) = self.requested_bosses.entry(boss_name) {
-            let is_empty = {
-                let broadcast = entry.get_mut();
-                broadcast.unsubscribe(&id);
-                broadcast.is_empty()
-            };
-
-            if is_empty {
-                entry.remove();
+    // Returns the number of unique users, including this one
+    fn insert(&mut self, user: &str) -> usize {
+        if !self.users.contains(user) {
+            if let Some(evicted) = self.order.push(user.to_string()) {
+                self.users.remove(&evicted);
             }
+            self.users.insert(user.to_string());
         }
+
+        self.users.len()
     }
 
-    fn handle_image_hash(&mut self, boss_name: BossName, image_hash: ImageHash) {
-        // TODO: Is it possible to avoid finding the same boss twice?
-        let (level, language) = match self.bosses.get_mut(&boss_name) {
-            Some(entry) => {
-                entry.boss_data.image_hash = Some(image_hash);
+    fn len(&self) -> usize {
+        self.users.len()
+    }
+}
 
-                (entry.boss_data.boss.level, entry.boss_data.boss.language)
-            }
-            None => return,
-        };
+// Requests an image hash, keeping track of the bosses that are waiting for one
+fn request_image_hash(
+    requester: &ImageHashSender,
+    boss_name: &BossName,
+    image_url: &str,
+    pending: &mut HashSet<BossName>,
+) {
+    if requester.request(boss_name.clone(), image_url) {
+        pending.insert(boss_name.clone());
+    }
+}
 
-        let mut matches = Vec::new();
+type PendingTweets = HashMap<BossName, Vec<Arc<RaidTweet>>>;
 
-        for entry in self.bosses.values_mut() {
-            if entry.boss_data.boss.level == level && entry.boss_data.boss.language != language
-                && entry.boss_data.image_hash == Some(image_hash)
-            {
-                entry.boss_data.boss.translations.insert(boss_name.clone());
+// The number of subscribers each pending tweet was sent to so far, keyed by
+// `seq`. Tweets are only counted as delivered once they're flushed.
+type PendingDeliveries = HashMap<u64, (BossName, usize)>;
 
-                let message = (self.filter_map_message)(Message::BossUpdate(&entry.boss_data.boss));
-                self.subscribers.maybe_send(message.as_ref());
-                matches.push(entry.boss_data.boss.name.clone());
-            }
+// Tweets waiting to be broadcast as a `TweetList` at the end of the current window
+pub(crate) struct Coalescing {
+    ticks: Interval,
+    pending: PendingTweets,
+    delivered: PendingDeliveries,
+}
+
+impl Coalescing {
+    pub(crate) fn new(clock: &Clock, window: Duration) -> Self {
+        Coalescing {
+            ticks: clock.interval(window),
+            pending: HashMap::new(),
+            delivered: HashMap::new(),
         }
+    }
 
-        if !matches.is_empty() {
-            if let Some(entry) = self.bosses.get_mut(&boss_name) {
-                entry.boss_data.boss.translations.extend(matches);
+    fn push(&mut self, boss_name: &BossName, tweet: &Arc<RaidTweet>) {
+        self.pending
+            .entry(boss_name.clone())
+            .or_insert_with(Vec::new)
+            .push(tweet.clone());
+        self.delivered
+            .entry(tweet.seq)
+            .or_insert_with(|| (tweet.boss_name.clone(), 0));
+    }
 
-                let message = (self.filter_map_message)(Message::BossUpdate(&entry.boss_data.boss));
-                self.subscribers.maybe_send(message.as_ref());
+    // Returns false if the tweet isn't pending
+    fn add_delivered(&mut self, tweet: &RaidTweet, count: usize) -> bool {
+        match self.delivered.get_mut(&tweet.seq) {
+            Some(&mut (_, ref mut delivered)) => {
+                *delivered += count;
+                true
             }
-
-            self.update_cached_boss_list();
+            None => false,
         }
     }
 
-    pub(crate) fn update_cached_boss_list(&mut self) {
-        let updated = self.bosses
-            .values()
-            .map(|entry| &entry.boss_data.boss)
-            .collect::<Vec<_>>();
-
-        self.cached_boss_list = (self.filter_map_message)(Message::BossList(&updated))
+    fn take_pending(&mut self) -> (PendingTweets, PendingDeliveries) {
+        (
+            ::std::mem::replace(&mut self.pending, HashMap::new()),
+            ::std::mem::replace(&mut self.delivered, HashMap::new()),
+        )
     }
+}
 
-    fn handle_raid_info(&mut self, info: RaidInfo) {
-        self.metrics.inc_tweet_count(&info.tweet.boss_name);
+// The number of groups of subscribers that splayed heartbeats are sent to,
+// one group per tick. Subscribers are grouped by ID.
+pub(crate) const HEARTBEAT_SPLAY_SLOTS: u32 = 8;
 
-        let mapped_tweet_message = (self.filter_map_message)(Message::Tweet(&info.tweet));
+// Spreads each heartbeat across a window, instead of sending it to every
+// subscriber at once
+pub(crate) struct HeartbeatSplay {
+    ticks: Interval,
+    // The next slot to send the current heartbeat to, until it has been
+    // sent to every slot
+    next_slot: Option<u32>,
+}
 
-        // Currently, only one translated boss should exist at most, but in
-        // case the game gets translated to another language, this should still
-        // handle that case. This enum exists because we don't want to allocate
-        // a Vec in the cases where only one translation exists.
-        enum TranslationsExist {
-            One {
-                boss_name: BossName,
-                tweet: Arc<RaidTweet>,
-            },
-            Multiple {
-                boss_names: Vec<BossName>,
-                tweet: Arc<RaidTweet>,
-            },
+impl HeartbeatSplay {
+    pub(crate) fn new(clock: &Clock, window: Duration) -> Self {
+        HeartbeatSplay {
+            ticks: clock.interval(window / HEARTBEAT_SPLAY_SLOTS),
+            next_slot: None,
         }
+    }
+}
 
-        let mut translations: Option<TranslationsExist> = None;
-
-        let is_new_boss = match self.bosses.entry(info.tweet.boss_name.clone()) {
-            Entry::Occupied(mut entry) => {
-                let value = entry.get_mut();
-
-                value.boss_data.last_seen = info.tweet.created_at;
-
-                value.broadcast.maybe_send(mapped_tweet_message.as_ref());
-
-                if value.boss_data.boss.image.is_none() {
-                    if let Some(image_url) = info.image {
-                        self.hash_requester
-                            .request(value.boss_data.boss.name.clone(), &image_url);
-                        value.boss_data.boss.image = Some(image_url);
-                    }
-                }
-
-                let arc_tweet = Arc::new(info.tweet);
-
-                // If this boss has translations, send the tweet to that boss' subscribers too
-                let boss_translations = &value.boss_data.boss.translations;
-                match boss_translations.len() {
-                    1 => {
-                        translations = Some(TranslationsExist::One {
-                            boss_name: boss_translations.iter().cloned().next().unwrap(),
-                            tweet: arc_tweet.clone(),
-                        });
-                    }
-                    0 => {}
-                    _ => {
-                        translations = Some(TranslationsExist::Multiple {
-                            boss_names: boss_translations.iter().cloned().collect(),
-                            tweet: arc_tweet.clone(),
-                        });
-                    }
-                }
-
-                value.recent_tweets.push(arc_tweet);
-                false
-            }
-            Entry::Vacant(entry) => {
-                let name = entry.key().clone();
-
-                let mut broadcast = self.requested_bosses
-                    .remove(&name)
-                    .unwrap_or(Broadcast::new());
+// Subscribers that are stalled on two consecutive ticks are evicted, so
+// they're evicted after being stalled for between one and two intervals
+pub(crate) struct IdleSweep {
+    ticks: Interval,
+    stalled: HashSet<SubId>,
+}
 
-                let last_seen = info.tweet.created_at.clone();
-                let boss = RaidBoss {
-                    level: name.parse_level().unwrap_or(DEFAULT_BOSS_LEVEL),
-                    name: name,
-                    image: info.image,
-                    language: info.tweet.language,
-                    translations: HashSet::with_capacity(1),
-                };
+impl IdleSweep {
+    pub(crate) fn new(clock: &Clock, timeout: Duration) -> Self {
+        IdleSweep {
+            ticks: clock.interval(timeout),
+            stalled: HashSet::new(),
+        }
+    }
+}
 
-                {
-                    let boss_message = Message::BossUpdate(&boss);
-                    self.subscribers
-                        .maybe_send((self.filter_map_message)(boss_message).as_ref());
+struct CheckedImage {
+    boss_name: BossName,
+    image_url: BossImageUrl,
+    status: ImageStatus,
+}
 
-                    broadcast.maybe_send(mapped_tweet_message.as_ref());
-                }
+// Checks whether a boss' image still exists when a tweet without an image
+// arrives for it, if the image is older than `max_age`
+pub(crate) struct ImageChecks {
+    checker: DynImageChecker,
+    max_age: ::chrono::Duration,
+    in_flight: FuturesUnordered<Box<Future<Item = CheckedImage, Error = Error>>>,
+}
 
-                if let Some(ref image_url) = boss.image {
-                    self.hash_requester.request(boss.name.clone(), &image_url);
-                }
+impl ImageChecks {
+    pub(crate) fn new(checker: DynImageChecker, max_age: Duration) -> Self {
+        ImageChecks {
+            checker,
+            max_age: to_chrono(max_age),
+            in_flight: FuturesUnordered::new(),
+        }
+    }
 
-                let mut recent_tweets = CircularBuffer::with_capacity(self.tweet_history_size);
-                recent_tweets.push(Arc::new(info.tweet));
+    fn check_if_stale<Sub>(&mut self, entry: &mut RaidBossEntry<Sub>, now: DateTime) {
+        if now.signed_duration_since(entry.image_seen_at) < self.max_age {
+            return;
+        }
 
-                entry.insert(RaidBossEntry {
-                    boss_data: RaidBossMetadata {
-                        boss,
-                        last_seen,
-                        image_hash: None,
-                    },
-                    broadcast,
-                    recent_tweets,
+        if let Some(ref image_url) = entry.boss_data.boss.image {
+            if let Ok(uri) = image_url.parse() {
+                let boss_name = entry.boss_data.boss.name.clone();
+                let image_url = image_url.clone();
+                // A failed check is as inconclusive as an unknown status
+                let check = (self.checker)(uri).then(|result| {
+                    Ok(CheckedImage {
+                        boss_name,
+                        image_url,
+                        status: result.unwrap_or(ImageStatus::Unknown),
+                    })
                 });
 
-                true
-            }
-        };
-
-        // Broadcast the tweet to the equivalent translated bosses
-        match translations {
-            Some(TranslationsExist::One { boss_name, tweet }) => {
-                if let Some(value) = self.bosses.get_mut(&boss_name) {
-                    value.broadcast.maybe_send(mapped_tweet_message.as_ref());
-                    value.recent_tweets.push(tweet);
-                }
-            }
-            None => {}
-            Some(TranslationsExist::Multiple { boss_names, tweet }) => {
-                for boss_name in boss_names {
-                    if let Some(value) = self.bosses.get_mut(&boss_name) {
-                        value.broadcast.maybe_send(mapped_tweet_message.as_ref());
-                        value.recent_tweets.push(tweet.clone());
-                    }
-                }
+                self.in_flight.push(Box::new(check));
             }
         }
 
-        if is_new_boss {
-            self.update_cached_boss_list();
-        }
+        // Whatever the result, the image isn't checked again until it's
+        // stale again
+        entry.image_seen_at = now;
     }
 }
 
-impl<H, S, Sub, F, M> Future for Worker<H, S, Sub, F, M>
+impl<H, S, Sub, F, M> Worker<H, S, Sub, F, M>
 where
     H: ImageHasher,
-    S: Stream<Item = RaidInfo, Error = Error>,
     Sub: Subscriber + Clone,
-    F: Fn(Message) -> Option<Sub::Item>,
+    F: MessageMapper<Sub::Item>,
     M: Metrics,
 {
-    type Item = ();
-    type Error = Error;
+    // Events are handled by category, and each is timed for
+    // `Metrics::observe_event_duration`
+    fn handle_event(&mut self, event: Event<Sub, M::Export>) {
+        let kind = event.kind();
+        let started_at = self.now();
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        loop {
-            if let Some(event) = try_ready!(self.events.poll()) {
-                self.handle_event(event)
-            } else {
-                return Ok(Async::Ready(()));
+        match kind {
+            EventKind::Ingest => self.handle_ingest_event(event),
+            EventKind::Subscriber => self.handle_subscriber_event(event),
+            EventKind::Client => self.handle_client_event(event),
+        }
+
+        let elapsed = self.now().signed_duration_since(started_at);
+        let micros = elapsed
+            .num_microseconds()
+            .map_or(0, |micros| max(micros, 0) as u64);
+        self.metrics.observe_event_duration(kind, micros);
+    }
+
+    fn handle_ingest_event(&mut self, event: Event<Sub, M::Export>) {
+        use super::Event::*;
+
+        match event {
+            NewRaidInfo(r) => {
+                self.handle_raid_info(r);
+            }
+            NewImageHash {
+                boss_name,
+                image_hash,
+            } => {
+                self.pending_image_hashes.remove(&boss_name);
+                if !image_hash.is_degenerate() {
+                    self.metrics.inc_image_hashed(&boss_name);
+                }
+                self.handle_image_hash(boss_name, image_hash);
             }
+            ImageHashFailed(boss_name) => {
+                self.pending_image_hashes.remove(&boss_name);
+            }
+            _ => unreachable!("not an ingest event"),
         }
     }
+
+    fn handle_subscriber_event(&mut self, event: Event<Sub, M::Export>) {
+        use super::Event::*;
+
+        match event {
+            SubscriberSubscribe {
+                subscriber,
+                filter,
+                key,
+                sender,
+                client,
+            } => {
+                let id = match key {
+                    Some(key) => self.subscribe_keyed(key, subscriber, filter),
+                    None => self.subscribe(subscriber, filter),
+                };
+                let subscription = Subscription {
+                    id,
+                    following: HashSet::new(),
+                    resume_token: self.new_resume_token(),
+                    desynced: false,
+                    client,
+                    detached: false,
+                };
+
+                self.deliver_subscription(sender, subscription);
+            }
+            SubscriberSubscribeStream {
+                message_sender,
+                filter,
+                sender,
+                client,
+            } => {
+                let id = self.id_pool.get();
+                self.subscribers
+                    .subscribe_stream(id.clone(), message_sender, filter);
+                self.metrics
+                    .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
+
+                let subscription = Subscription {
+                    id,
+                    following: HashSet::new(),
+                    resume_token: self.new_resume_token(),
+                    desynced: false,
+                    client,
+                    detached: false,
+                };
+
+                self.deliver_subscription(sender, subscription);
+            }
+            SubscriberResume {
+                subscriber,
+                resume_token,
+                client,
+                sender,
+            } => {
+                let id = self.subscribe(subscriber, MessageFilter::all());
+
+                let (resume_token, desynced) = match self.resume(&id, resume_token) {
+                    Some(desynced) => (resume_token, desynced),
+                    None => (self.new_resume_token(), false),
+                };
+
+                let subscription = Subscription {
+                    id,
+                    following: HashSet::new(),
+                    resume_token,
+                    desynced,
+                    client,
+                    detached: false,
+                };
+
+                self.deliver_subscription(sender, subscription);
+            }
+            SubscriberFollowReliable {
+                id,
+                resume_token,
+                boss_name,
+            } => {
+                if self.subscribers.contains(&id) {
+                    let session = self.reliable_sessions
+                        .entry(resume_token)
+                        .or_insert_with(|| ReliableSession::new(id.clone()));
+
+                    if session.subscriber.is_none() {
+                        session.attach(id);
+                    }
+                    session.bosses.insert(boss_name);
+                }
+            }
+            SubscriberAck { resume_token, seq } => {
+                if let Some(session) = self.reliable_sessions.get_mut(&resume_token) {
+                    session.ack(seq);
+                }
+            }
+            SubscriberUnsubscribe(id) => {
+                self.unsubscribe(&id);
+            }
+            SubscriberFollow { id, boss_name } => {
+                self.follow(id, boss_name);
+            }
+            SubscriberUnfollow { id, boss_name } => {
+                self.unfollow(&id, boss_name);
+            }
+            SubscriberFollowMany { id, names } => {
+                self.follow_many(id, names);
+            }
+            SubscriberUnfollowMany { id, names } => {
+                self.unfollow_many(&id, names);
+            }
+            SubscriberSetMuted { id, muted } => {
+                self.set_subscriber_muted(&id, muted);
+            }
+            SubscriberFollowWithCatchUp { id, boss_name } => {
+                // Since events are handled one at a time, no tweets can
+                // arrive between catching up and following
+                self.catch_up(&id, &boss_name);
+                self.follow(id, boss_name);
+            }
+            SubscriberFollowWithBacklog { id, boss_name } => {
+                // Like catching up, no tweets can arrive in between
+                self.send_backlog(&id, &boss_name);
+                self.follow(id, boss_name);
+            }
+            SubscriberGetBosses(id) => {
+                // The cached boss list can't be used if each subscriber's
+                // copy is mapped separately, or for stream subscribers,
+                // which aren't sent mapped messages
+                let result = if self.filter_map_message.is_sequenced()
+                    || self.subscribers.is_stream(&id)
+                {
+                    let bosses = self.bosses
+                        .values()
+                        .map(|entry| &entry.boss_data)
+                        .collect::<Vec<_>>();
+
+                    self.subscribers.send_message_to(
+                        &id,
+                        Message::BossList(&bosses),
+                        &self.filter_map_message,
+                    )
+                } else {
+                    // The message is only used for its kind, since stream
+                    // subscribers are handled above
+                    self.update_cached_boss_list();
+                    self.subscribers.send_mapped_to(
+                        &id,
+                        &Message::BossList(&[]),
+                        self.cached_boss_list.as_ref(),
+                    )
+                };
+
+                let result = match result {
+                    Ok(false) => self.send_unsupported(&id, MessageKind::BossList),
+                    result => result,
+                };
+
+                if result.is_err() {
+                    self.evicted.push(id);
+                }
+            }
+            SubscriberGetTweets { id, boss_name } => {
+                self.send_backlog(&id, &boss_name);
+            }
+            SubscriberHeartbeat => {
+                let unfinished = match self.heartbeat_splay {
+                    Some(ref mut splay) => Some(::std::mem::replace(&mut splay.next_slot, Some(1))),
+                    None => None,
+                };
+
+                match unfinished {
+                    None => self.send_heartbeat(None),
+                    Some(unfinished) => {
+                        // Slots that haven't been sent the previous
+                        // heartbeat yet are sent it right away
+                        if let Some(first) = unfinished {
+                            for slot in first..HEARTBEAT_SPLAY_SLOTS {
+                                self.send_heartbeat(Some(slot));
+                            }
+                        }
+
+                        self.send_heartbeat(Some(0));
+                    }
+                }
+            }
+            _ => unreachable!("not a subscriber event"),
+        }
+    }
+
+    fn handle_client_event(&mut self, event: Event<Sub, M::Export>) {
+        use super::Event::*;
+
+        match event {
+            ClientGetBosses(tx) => {
+                let _ = tx.send(Vec::from_iter(
+                    self.bosses.values().map(|e| e.boss_data.boss.clone()),
+                ));
+            }
+            ClientGetBossesVersioned(tx) => {
+                let bosses = Vec::from_iter(self.bosses.values().map(|e| e.boss_data.boss.clone()));
+                let _ = tx.send((self.boss_list_revision, bosses));
+            }
+            ClientGetBossListRevision(tx) => {
+                let _ = tx.send(self.boss_list_revision);
+            }
+            ClientGetDebugBosses(tx) => {
+                let include_image_hash = self.config.debug_image_hashes;
+                let _ = tx.send(Vec::from_iter(self.bosses.values().map(|e| {
+                    DebugRaidBoss::new(&e.boss_data, include_image_hash)
+                })));
+            }
+            ClientGetBossesByLanguage { language, sender } => {
+                let _ = sender.send(Vec::from_iter(
+                    self.bosses
+                        .values()
+                        .map(|e| &e.boss_data.boss)
+                        .filter(|boss| boss.language == language)
+                        .cloned(),
+                ));
+            }
+            ClientGetBoss { boss_name, sender } => {
+                let boss = self.lookup_boss_name(&boss_name)
+                    .and_then(|name| self.bosses.get(&name))
+                    .map(|e| e.boss_data.boss.clone());
+
+                let _ = sender.send(boss);
+            }
+            ClientGetTweets {
+                boss_name,
+                order,
+                include_translations,
+                sender,
+            } => {
+                let boss_name = self.lookup_boss_name(&boss_name);
+                let bosses = &self.bosses;
+                let entry = boss_name.and_then(|name| bosses.get(&name));
+
+                // Without an order, recent tweets are returned unsorted,
+                // and the client is expected to sort them on their end
+                let mut tweets = entry.map_or(vec![], |e| {
+                    let mut tweets = e.recent_tweets.recent();
+
+                    if include_translations {
+                        let translated = e.boss_data
+                            .boss
+                            .translations
+                            .iter()
+                            .filter_map(|name| bosses.get(name))
+                            .flat_map(|t| t.recent_tweets.recent());
+                        tweets.extend(translated);
+
+                        // Shared history would otherwise be duplicated
+                        tweets.sort_by_key(|tweet| tweet.tweet_id);
+                        tweets.dedup_by_key(|tweet| tweet.tweet_id);
+                    }
+
+                    tweets
+                });
+
+                if let Some(order) = order {
+                    order.sort(&mut tweets);
+                }
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsMany { boss_names, sender } => {
+                let tweets = boss_names
+                    .into_iter()
+                    .map(|boss_name| {
+                        let tweets = self.lookup_boss_name(&boss_name)
+                            .and_then(|name| self.bosses.get(&name))
+                            .map_or(vec![], |e| e.recent_tweets.recent());
+
+                        (boss_name, tweets)
+                    })
+                    .collect();
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetTweetsSinceSeq {
+                boss_name,
+                seq,
+                sender,
+            } => {
+                let boss_name = self.lookup_boss_name(&boss_name);
+                let entry = boss_name.and_then(|name| self.bosses.get(&name));
+
+                let tweets = entry.map_or(vec![], |e| {
+                    let mut tweets = e.recent_tweets.recent();
+                    tweets.retain(|tweet| tweet.seq > seq);
+                    tweets
+                });
+
+                let _ = sender.send(tweets);
+            }
+            ClientGetRaidIds { boss_name, sender } => {
+                let boss_name = self.lookup_boss_name(&boss_name);
+                let entry = boss_name.and_then(|name| self.bosses.get(&name));
+
+                let raid_ids = entry.map_or(vec![], |e| {
+                    e.recent_tweets
+                        .recent()
+                        .iter()
+                        .rev()
+                        .map(|tweet| tweet.raid_id.clone())
+                        .collect()
+                });
+
+                let _ = sender.send(raid_ids);
+            }
+            ClientGetPendingFollows(tx) => {
+                self.requested_bosses
+                    .retain(|_, broadcast| !broadcast.is_empty());
+
+                let _ = tx.send(Vec::from_iter(
+                    self.requested_bosses
+                        .iter()
+                        .map(|(name, broadcast)| (name.clone(), broadcast.subscriber_count())),
+                ));
+            }
+            ClientGetSubscriberFollowCount { id, sender } => {
+                let _ = sender.send(self.follow_count(&id));
+            }
+            ClientGetLatestTweet(tx) => {
+                let latest = self.bosses
+                    .values()
+                    .flat_map(|e| e.recent_tweets.recent())
+                    .max_by_key(|tweet| (tweet.created_at, tweet.tweet_id));
+
+                let _ = tx.send(latest);
+            }
+            ClientGetBossActivity { boss_name, sender } => {
+                let boss_name = self.lookup_boss_name(&boss_name);
+                let entry = boss_name.and_then(|name| self.bosses.get(&name));
+
+                let _ = sender.send(entry.map(activity_stats));
+            }
+            ClientGetStaleBosses { older_than, sender } => {
+                let now = self.now();
+                let older_than = to_chrono(older_than);
+
+                let _ = sender.send(Vec::from_iter(
+                    self.bosses
+                        .values()
+                        .filter(|e| now.signed_duration_since(e.boss_data.last_seen) > older_than)
+                        .map(|e| e.boss_data.boss.name.clone()),
+                ));
+            }
+            ClientExportMetadata(tx) => {
+                let _ = tx.send(Vec::from_iter(
+                    self.bosses.values().map(|e| e.boss_data.clone()),
+                ));
+            }
+            ClientExportMetadataStream { sender, client } => {
+                // Only the names are copied up front, and each boss is
+                // looked up when its batch is sent
+                let export = MetadataExport {
+                    remaining: self.bosses.keys().cloned().collect(),
+                    sender,
+                    client,
+                };
+
+                self.export_metadata_batch(export);
+            }
+            ContinueMetadataExport(export) => {
+                self.export_metadata_batch(export);
+            }
+            ClientGetConfig(tx) => {
+                let _ = tx.send(self.config.clone());
+            }
+            ClientGetImageHashStatus(tx) => {
+                let _ = tx.send(ImageHashStatus {
+                    queued: self.hash_requester.pending_estimate(),
+                    pending: self.pending_image_hashes.iter().cloned().collect(),
+                });
+            }
+            ClientGetStats(tx) => {
+                let _ = tx.send(WorkerStats {
+                    bosses: self.bosses.len(),
+                    subscribers: self.subscribers.subscriber_count(),
+                    buffered_tweets: self.buffered_tweets,
+                });
+            }
+            ClientExportMetrics(tx) => {
+                self.report_stream_warnings();
+                let _ = tx.send(self.metrics.export());
+            }
+            ClientExportMetricsAndReset(tx) => {
+                self.report_stream_warnings();
+                let _ = tx.send(self.metrics.export_and_reset());
+            }
+            ClientRemoveBosses(f) => {
+                self.remove_bosses(f.0);
+            }
+            ClientRemoveBossesDryRun { predicate, sender } => {
+                let _ = sender.send(Vec::from_iter(
+                    self.bosses
+                        .values()
+                        .map(|e| &e.boss_data)
+                        .filter(|boss_data| (predicate.0)(boss_data))
+                        .cloned(),
+                ));
+            }
+            ClientRemoveBossesPlanned(planner) => {
+                let snapshot = self.bosses
+                    .values()
+                    .map(|e| e.boss_data.clone())
+                    .collect::<Vec<_>>();
+
+                for boss_name in (planner.0)(&snapshot) {
+                    self.remove_boss(&boss_name);
+                }
+            }
+            ClientReplaceStream(replacement) => {
+                // If the stream can't be replaced, dropping the replacement
+                // makes the request fail
+                if let Some(ref replacer) = self.stream_replacer {
+                    replacer.replace(replacement);
+                }
+            }
+            ClientSetMessageMapper(replacement) => {
+                // As with streams, dropping the sender makes the request
+                // fail if the mapper can't be replaced
+                if self.filter_map_message.replace(replacement.mapper) {
+                    self.remap_cached_messages();
+                    let _ = replacement.sender.send(());
+                }
+            }
+            ClientGetPendingTranslations(tx) => {
+                let _ = tx.send(self.pending_translations.clone());
+            }
+            ClientConfirmTranslation(a, b) => {
+                self.confirm_translation(a, b);
+            }
+            ClientRejectTranslation(a, b) => {
+                self.reject_translation(a, b);
+            }
+            ClientSetHashThreshold(threshold) => {
+                self.set_hash_threshold(threshold);
+            }
+            ClientRehashBoss(boss_name) => {
+                let boss_name = self.lookup_boss_name(&boss_name);
+                let bosses = &self.bosses;
+                let boss = boss_name
+                    .and_then(|name| bosses.get(&name))
+                    .map(|e| &e.boss_data.boss);
+
+                // The image is fetched again, even if its URL was hashed recently
+                if let Some(boss) = boss {
+                    if let Some(ref image_url) = boss.image {
+                        if self.hash_requester
+                            .request_uncached(boss.name.clone(), image_url)
+                        {
+                            self.pending_image_hashes.insert(boss.name.clone());
+                        }
+                    }
+                }
+            }
+            ClientSetBossMuted {
+                boss_name,
+                muted,
+                sender,
+            } => {
+                let exists = self.set_boss_muted(&boss_name, muted);
+                let _ = sender.send(exists);
+            }
+            ClientInjectRaid { mut info, sender } => {
+                info.tweet.source = TweetSource::Manual;
+                self.handle_raid_info(info);
+                let _ = sender.send(());
+            }
+            ClientBroadcastNotice(notice) => {
+                self.subscribers.send_message(
+                    Message::SystemNotice(&notice),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+            ClientReadError => {} // This should never happen
+            _ => unreachable!("not a client event"),
+        }
+    }
+
+    fn remove_bosses(&mut self, f: Box<Fn(&RaidBossMetadata) -> bool>) {
+        let boss_names = self.bosses
+            .values()
+            .filter(|e| (f)(&e.boss_data))
+            .map(|e| e.boss_data.boss.name.clone())
+            .collect::<Vec<_>>();
+
+        for boss_name in boss_names {
+            self.remove_boss(&boss_name);
+        }
+    }
+
+    fn remove_boss(&mut self, boss_name: &BossName) {
+        let entry = match self.bosses.remove(boss_name) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        // Otherwise, its translations would keep listing a boss that no
+        // longer exists
+        self.strip_translation_links(boss_name, &entry.boss_data.boss.translations);
+
+        // Pairs with a boss that no longer exists can't be confirmed, and a
+        // boss that comes back has to be confirmed and reviewed from scratch
+        self.pending_translations
+            .retain(|&(ref x, ref y)| x != boss_name && y != boss_name);
+        self.rejected_translations
+            .retain(|&(ref x, ref y)| x != boss_name && y != boss_name);
+        self.unconfirmed_bosses.remove(boss_name);
+
+        self.subscribers.send_message(
+            Message::BossRemove(boss_name),
+            &self.filter_map_message,
+            &mut self.evicted,
+        );
+
+        // If there are existing subscribers, move them to `requested_bosses`
+        if !entry.broadcast.is_empty() {
+            self.requested_bosses
+                .insert(boss_name.clone(), entry.broadcast);
+        }
+
+        self.metrics.remove_boss(boss_name);
+        self.buffered_tweets -= entry.recent_tweets.len();
+        if let Some(ref mut order) = self.history_eviction_order {
+            if !entry.recent_tweets.is_empty() {
+                let entries = ::std::mem::replace(order, BinaryHeap::new());
+                *order = entries
+                    .into_iter()
+                    .filter(|&Reverse((_, ref name))| name != boss_name)
+                    .collect();
+            }
+        }
+        self.bump_revision();
+    }
+
+    // Removes `boss_name` from the translations of each of `partners`, and
+    // sends an update for each partner that changed
+    fn strip_translation_links(&mut self, boss_name: &BossName, partners: &HashSet<BossName>) {
+        for partner in partners {
+            if let Some(entry) = self.bosses.get_mut(partner) {
+                entry.hash_links.remove(boss_name);
+                if entry.boss_data.boss.translations.remove(boss_name) {
+                    self.subscribers.send_message(
+                        Message::BossUpdate(&entry.boss_data.boss),
+                        &self.filter_map_message,
+                        &mut self.evicted,
+                    );
+                }
+            }
+        }
+    }
+
+    fn subscribe(&mut self, subscriber: Sub, filter: MessageFilter) -> SubId {
+        let id = self.id_pool.get();
+        self.subscribers.subscribe(id.clone(), subscriber, filter);
+        self.metrics
+            .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
+        id
+    }
+
+    // A previous subscription with the same key is removed the same way as
+    // an evicted subscriber, so it stops receiving messages from any boss
+    fn subscribe_keyed(&mut self, key: u64, subscriber: Sub, filter: MessageFilter) -> SubId {
+        if let Some(previous) = self.keyed_subscribers.remove(&key) {
+            self.evicted.push(previous);
+            self.remove_evicted_subscribers();
+        }
+
+        let id = self.subscribe(subscriber, filter);
+        self.keyed_subscribers.insert(key, id.clone());
+        id
+    }
+
+    // Sends a heartbeat to every subscriber, or only the ones in a slot if
+    // heartbeats are splayed
+    fn send_heartbeat(&mut self, slot: Option<u32>) {
+        let in_slot =
+            |id: &SubId| slot.map_or(true, |slot| id.value() % HEARTBEAT_SPLAY_SLOTS == slot);
+
+        let message = if self.stats_in_heartbeat {
+            Message::HeartbeatWithStats {
+                subscribers: self.subscribers.subscriber_count() as u32,
+                bosses: self.bosses.len() as u32,
+            }
+        } else if self.filter_map_message.is_sequenced() {
+            Message::Heartbeat
+        } else {
+            self.subscribers.send_mapped_where(
+                &Message::Heartbeat,
+                self.heartbeat.as_ref(),
+                &mut self.evicted,
+                in_slot,
+            );
+            return;
+        };
+
+        self.subscribers.send_outgoing_where(
+            &Outgoing::new(message, &self.filter_map_message),
+            &self.filter_map_message,
+            &mut self.evicted,
+            in_slot,
+        );
+    }
+
+    fn forget_subscriber_key(&mut self, id: &SubId) {
+        if !self.keyed_subscribers.is_empty() {
+            self.keyed_subscribers.retain(|_, keyed_id| keyed_id != id);
+        }
+    }
+
+    // If the requester is gone, the subscription is rolled back right away.
+    // Otherwise it would keep receiving messages until its `Drop` is handled.
+    fn deliver_subscription(
+        &mut self,
+        sender: oneshot::Sender<Subscription<Sub, M::Export>>,
+        subscription: Subscription<Sub, M::Export>,
+    ) {
+        if let Err(subscription) = sender.send(subscription) {
+            self.unsubscribe(&subscription.id);
+            subscription.detach();
+        }
+    }
+
+    fn unsubscribe(&mut self, id: &SubId) {
+        // The subscriber may have already been evicted, in which case
+        // its ID has already been recycled
+        if self.subscribers.unsubscribe(id) {
+            self.metrics
+                .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
+
+            // Follows of bosses that were never seen would otherwise linger
+            // if the subscriber didn't unfollow them first
+            self.requested_bosses.retain(|_, broadcast| {
+                broadcast.unsubscribe(id);
+                !broadcast.is_empty()
+            });
+
+            self.muted_subscribers.remove(id);
+            self.forget_subscriber_key(id);
+            self.detach_reliable_sessions(id);
+            self.id_pool.recycle(id.clone());
+        }
+    }
+
+    // Muting replaces the subscriber's filter in every broadcast with one that
+    // accepts nothing, so follows made while muted are also muted
+    fn set_subscriber_muted(&mut self, id: &SubId, muted: bool) {
+        let filter = if muted {
+            if self.muted_subscribers.contains_key(id) {
+                return;
+            }
+
+            match self.subscribers.set_filter(id, MessageFilter::none()) {
+                Some(filter) => {
+                    self.muted_subscribers.insert(id.clone(), filter);
+                }
+                None => return,
+            }
+            MessageFilter::none()
+        } else {
+            match self.muted_subscribers.remove(id) {
+                Some(filter) => {
+                    self.subscribers.set_filter(id, filter);
+                    filter
+                }
+                None => return,
+            }
+        };
+
+        for entry in self.bosses.values_mut() {
+            entry.broadcast.set_filter(id, filter);
+        }
+        for broadcast in self.requested_bosses.values_mut() {
+            broadcast.set_filter(id, filter);
+        }
+    }
+
+    fn new_resume_token(&mut self) -> ResumeToken {
+        loop {
+            let token = ResumeToken::random();
+            if !self.reliable_sessions.contains_key(&token) {
+                return token;
+            }
+        }
+    }
+
+    // Keep reliable sessions around after their subscriber is gone, unless
+    // there's nothing to resume
+    fn detach_reliable_sessions(&mut self, id: &SubId) {
+        let order = self.next_detach_order;
+        self.next_detach_order += 1;
+
+        self.reliable_sessions.retain(|_, session| {
+            if session.subscriber.as_ref() == Some(id) {
+                session.subscriber = None;
+                session.detached_order = Some(order);
+                !session.bosses.is_empty()
+            } else {
+                true
+            }
+        });
+
+        self.limit_detached_sessions();
+    }
+
+    // Drops the sessions that were detached first, if there are more
+    // detached sessions than the builder allows
+    fn limit_detached_sessions(&mut self) {
+        let mut detached = self.reliable_sessions
+            .iter()
+            .filter_map(|(token, session)| session.detached_order.map(|order| (order, *token)))
+            .collect::<Vec<_>>();
+
+        let max = self.config.max_detached_sessions;
+        if detached.len() <= max {
+            return;
+        }
+
+        detached.sort_by_key(|&(order, _)| order);
+        for &(_, ref token) in &detached[..detached.len() - max] {
+            self.reliable_sessions.remove(token);
+        }
+    }
+
+    // Drops sessions that were already detached on the previous sweep
+    fn sweep_detached_sessions(&mut self) {
+        self.reliable_sessions.retain(|_, session| {
+            if session.subscriber.is_some() {
+                return true;
+            }
+
+            let expired = session.expiring;
+            session.expiring = true;
+            !expired
+        });
+    }
+
+    // Attaches a reliable session to a subscriber, and redelivers any
+    // unacknowledged tweets. Returns whether tweets were dropped since the
+    // session was last resumed, or `None` if the session doesn't exist.
+    fn resume(&mut self, id: &SubId, resume_token: ResumeToken) -> Option<bool> {
+        let session = match self.reliable_sessions.get_mut(&resume_token) {
+            Some(session) => session,
+            None => return None,
+        };
+
+        // If the session is still attached to another subscriber, take it over
+        session.attach(id.clone());
+
+        for &(seq, ref tweet) in session.unacked.iter() {
+            let inner = Message::Tweet(tweet);
+            let message = Message::Sequenced {
+                seq,
+                inner: &inner,
+            };
+
+            let result = self.subscribers
+                .send_message_to(id, message, &self.filter_map_message);
+
+            if result.is_err() {
+                self.evicted.push(id.clone());
+                break;
+            }
+        }
+
+        Some(::std::mem::replace(&mut session.desynced, false))
+    }
+
+    // Returns the number of reliable followers the tweet was sent to
+    fn deliver_reliable(&mut self, tweet: &Arc<RaidTweet>) -> usize {
+        if self.reliable_sessions.is_empty() {
+            return 0;
+        }
+
+        let translations = self.bosses
+            .get(&tweet.boss_name)
+            .map(|entry| &entry.boss_data.boss.translations);
+
+        let mut delivered = 0;
+
+        for session in self.reliable_sessions.values_mut() {
+            let is_following = session.bosses.contains(&tweet.boss_name)
+                || translations.map_or(false, |names| {
+                    names.iter().any(|name| session.bosses.contains(name))
+                });
+
+            if !is_following {
+                continue;
+            }
+
+            // Tweets are buffered even while detached, so they can be
+            // redelivered on resume
+            let seq = session.push(tweet.clone(), self.unacked_buffer_size);
+
+            let id = match session.subscriber {
+                Some(ref id) => id,
+                None => continue,
+            };
+
+            let inner = Message::Tweet(tweet);
+            let message = Message::Sequenced {
+                seq,
+                inner: &inner,
+            };
+
+            match self.subscribers
+                .send_message_to(id, message, &self.filter_map_message)
+            {
+                Ok(true) => delivered += 1,
+                Ok(false) => {}
+                Err(()) => self.evicted.push(id.clone()),
+            }
+        }
+
+        delivered
+    }
+
+    // Removes subscribers that failed to receive a message from every
+    // boss they follow, and recycles their IDs
+    fn remove_evicted_subscribers(&mut self) {
+        if self.evicted.is_empty() {
+            return;
+        }
+
+        let evicted = self.evicted.drain(..).collect::<HashSet<_>>();
+        self.remove_follows(&evicted);
+
+        for id in evicted {
+            self.subscribers.unsubscribe(&id);
+            self.muted_subscribers.remove(&id);
+            self.forget_subscriber_key(&id);
+            self.detach_reliable_sessions(&id);
+            self.id_pool.recycle(id);
+        }
+
+        self.metrics
+            .set_total_subscriber_count(self.subscribers.subscriber_count() as u32);
+    }
+
+    // A failed send on the master `subscribers` broadcast (e.g., a
+    // heartbeat) doesn't touch the per-boss broadcasts, so the subscriber
+    // would otherwise keep following bosses under an ID that's about to be
+    // recycled. Treat it as a full unsubscribe by dropping every follow.
+    fn remove_follows(&mut self, ids: &HashSet<SubId>) {
+        for (boss_name, entry) in self.bosses.iter_mut() {
+            for id in ids.iter() {
+                entry.broadcast.unsubscribe(id);
+            }
+
+            // The broadcast may have already evicted some of these, so
+            // the follower count could have changed either way
+            self.metrics
+                .set_follower_count(boss_name, entry.broadcast.subscriber_count() as u32);
+        }
+
+        self.requested_bosses.retain(|_, broadcast| {
+            for id in ids.iter() {
+                broadcast.unsubscribe(id);
+            }
+            !broadcast.is_empty()
+        });
+    }
+
+    // Looks up a boss by name, falling back to the boss that has the name
+    // as a translation. Translations can exist without a boss of that name,
+    // e.g., if only one language's tweets have been seen since the
+    // translated boss was removed.
+    pub(crate) fn resolve_boss_name(&self, boss_name: &BossName) -> Resolution {
+        if self.bosses.contains_key(boss_name) {
+            return Resolution::Exact;
+        }
+
+        let candidates = self.bosses
+            .values()
+            .map(|entry| &entry.boss_data.boss)
+            .filter(|boss| boss.translations.contains(boss_name))
+            .collect::<Vec<_>>();
+
+        match candidates.len() {
+            0 => Resolution::Miss,
+            1 => Resolution::Translated(candidates[0].name.clone()),
+            _ => {
+                // Prefer the boss with the same level as the requested name
+                let level = boss_name.parse_level();
+                let mut same_level = candidates.iter().filter(|boss| Some(boss.level) == level);
+
+                match (same_level.next(), same_level.next()) {
+                    (Some(boss), None) => Resolution::Translated(boss.name.clone()),
+                    _ => Resolution::Ambiguous,
+                }
+            }
+        }
+    }
+
+    // Returns the name of the boss that should be used for the given name,
+    // if any, counting translated resolutions
+    fn lookup_boss_name(&mut self, boss_name: &BossName) -> Option<BossName> {
+        match self.resolve_boss_name(boss_name) {
+            Resolution::Exact => Some(boss_name.clone()),
+            Resolution::Translated(name) => {
+                self.metrics.inc_translated_resolution(&name);
+                Some(name)
+            }
+            Resolution::Ambiguous | Resolution::Miss => None,
+        }
+    }
+
+    fn follow(&mut self, id: SubId, boss_name: BossName) {
+        // Follow the translated boss, but also register interest in the
+        // requested name, in case a boss with that name shows up later
+        if let Resolution::Translated(name) = self.resolve_boss_name(&boss_name) {
+            self.metrics.inc_translated_resolution(&name);
+            self.follow_boss(id.clone(), name);
+        }
+
+        self.follow_boss(id, boss_name);
+    }
+
+    // Like `follow` for each boss, but each boss' follower count is only
+    // updated once
+    fn follow_many(&mut self, id: SubId, names: Vec<BossName>) {
+        if !self.subscribers.contains(&id) {
+            return;
+        }
+
+        let mut changed = HashSet::new();
+        for boss_name in names {
+            if let Resolution::Translated(name) = self.resolve_boss_name(&boss_name) {
+                self.metrics.inc_translated_resolution(&name);
+                changed.extend(self.join_boss(&id, name));
+            }
+
+            changed.extend(self.join_boss(&id, boss_name));
+        }
+
+        for boss_name in &changed {
+            self.update_follower_count(boss_name);
+        }
+    }
+
+    fn follow_boss(&mut self, id: SubId, boss_name: BossName) {
+        if !self.subscribers.contains(&id) {
+            return;
+        }
+
+        if let Some(boss_name) = self.join_boss(&id, boss_name) {
+            self.update_follower_count(&boss_name);
+        }
+    }
+
+    // Adds the subscriber to the boss' broadcast, or to its pending follows
+    // if the boss doesn't exist. Returns the boss' name if it exists.
+    fn join_boss(&mut self, id: &SubId, boss_name: BossName) -> Option<BossName> {
+        if let Some(entry) = self.bosses.get_mut(&boss_name) {
+            self.subscribers.share_with(id, &mut entry.broadcast);
+            return Some(boss_name);
+        }
+
+        let broadcast = self.requested_bosses
+            .entry(boss_name)
+            .or_insert_with(Broadcast::new);
+        self.subscribers.share_with(id, broadcast);
+        None
+    }
+
+    fn update_follower_count(&mut self, boss_name: &BossName) {
+        if let Some(entry) = self.bosses.get(boss_name) {
+            self.metrics
+                .set_follower_count(boss_name, entry.broadcast.subscriber_count() as u32);
+        }
+    }
+
+    // Sends a boss' recent tweets as a single `TweetList`
+    fn send_backlog(&mut self, id: &SubId, boss_name: &BossName) {
+        let tweets = self.lookup_boss_name(boss_name)
+            .and_then(|name| self.bosses.get(&name))
+            .map_or(vec![], |e| e.recent_tweets.recent());
+
+        let result = self.subscribers.send_message_to(
+            id,
+            Message::TweetList(&tweets),
+            &self.filter_map_message,
+        );
+
+        let result = match result {
+            Ok(false) => self.send_unsupported(id, MessageKind::TweetList),
+            result => result,
+        };
+
+        if result.is_err() {
+            self.evicted.push(id.clone());
+        }
+    }
+
+    // Called when a message that a subscriber asked for wasn't sent to it.
+    // If that's because the mapper returned `None`, the subscriber is sent
+    // `Message::Unsupported`, or a heartbeat if that can't be mapped either,
+    // so that it isn't left waiting for a response.
+    fn send_unsupported(
+        &mut self,
+        id: &SubId,
+        kind: MessageKind,
+    ) -> ::std::result::Result<bool, ()> {
+        if !self.subscribers.accepts_mapped(id, kind) {
+            return Ok(false);
+        }
+
+        self.metrics.inc_unmappable_message(kind);
+
+        let unsupported = Message::Unsupported(kind);
+        if self.subscribers
+            .send_message_to(id, unsupported, &self.filter_map_message)?
+        {
+            return Ok(true);
+        }
+
+        if self.stats_in_heartbeat {
+            let heartbeat = Message::HeartbeatWithStats {
+                subscribers: self.subscribers.subscriber_count() as u32,
+                bosses: self.bosses.len() as u32,
+            };
+            self.subscribers
+                .send_message_to(id, heartbeat, &self.filter_map_message)
+        } else if self.filter_map_message.is_sequenced() {
+            self.subscribers
+                .send_message_to(id, Message::Heartbeat, &self.filter_map_message)
+        } else {
+            self.subscribers
+                .send_mapped_to(id, &Message::Heartbeat, self.heartbeat.as_ref())
+        }
+    }
+
+    fn catch_up(&mut self, id: &SubId, boss_name: &BossName) {
+        let boss_name = match self.resolve_boss_name(boss_name) {
+            Resolution::Translated(name) => name,
+            _ => boss_name.clone(),
+        };
+
+        let entry = match self.bosses.get(&boss_name) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        for tweet in entry.recent_tweets.recent() {
+            let result = self.subscribers.send_message_to(
+                id,
+                Message::Tweet(&tweet),
+                &self.filter_map_message,
+            );
+
+            if result.is_err() {
+                self.evicted.push(id.clone());
+                return;
+            }
+        }
+    }
+
+    fn unfollow(&mut self, id: &SubId, boss_name: BossName) {
+        if let Resolution::Translated(name) = self.resolve_boss_name(&boss_name) {
+            self.unfollow_boss(id, name);
+        }
+
+        self.unfollow_boss(id, boss_name);
+    }
+
+    // Like `unfollow` for each boss, but each boss' follower count is only
+    // updated once
+    fn unfollow_many(&mut self, id: &SubId, names: Vec<BossName>) {
+        let mut changed = HashSet::new();
+        for boss_name in names {
+            if let Resolution::Translated(name) = self.resolve_boss_name(&boss_name) {
+                changed.extend(self.leave_boss(id, name));
+            }
+
+            changed.extend(self.leave_boss(id, boss_name));
+        }
+
+        for boss_name in &changed {
+            self.update_follower_count(boss_name);
+        }
+    }
+
+    fn unfollow_boss(&mut self, id: &SubId, boss_name: BossName) {
+        if let Some(boss_name) = self.leave_boss(id, boss_name) {
+            self.update_follower_count(&boss_name);
+        }
+    }
+
+    // Removes the subscriber from the boss' broadcast, or from its pending
+    // follows. Returns the boss' name if it exists.
+    fn leave_boss(&mut self, id: &SubId, boss_name: BossName) -> Option<BossName> {
+        for session in self.reliable_sessions.values_mut() {
+            if session.subscriber.as_ref() == Some(id) {
+                session.bosses.remove(&boss_name);
+            }
+        }
+
+        if let Some(entry) = self.bosses.get_mut(&boss_name) {
+            entry.broadcast.unsubscribe(id);
+            return Some(boss_name);
+        }
+
+        if let Entry::Occupied(mut entry) = self.requested_bosses.entry(boss_name) {
+            let is_empty = {
+                let broadcast = entry.get_mut();
+                broadcast.unsubscribe(id);
+                broadcast.is_empty()
+            };
+
+            if is_empty {
+                entry.remove();
+            }
+        }
+
+        None
+    }
+
+    fn handle_image_hash(&mut self, boss_name: BossName, image_hash: ImageHash) {
+        // Degenerate hashes would match unrelated bosses, so treat them as
+        // if the boss had no hash at all
+        let is_degenerate = image_hash.is_degenerate();
+
+        // TODO: Is it possible to avoid finding the same boss twice?
+        let (boss_data, previous_links) = match self.bosses.get_mut(&boss_name) {
+            Some(entry) => {
+                // If the hash changed, links made with the old one are
+                // checked against the new one below
+                let previous_links = if entry.boss_data.image_hash == Some(image_hash) {
+                    None
+                } else {
+                    Some(::std::mem::replace(&mut entry.hash_links, HashSet::new()))
+                };
+
+                entry.boss_data.image_hash = if is_degenerate {
+                    None
+                } else {
+                    Some(image_hash)
+                };
+                (entry.boss_data.clone(), previous_links)
+            }
+            None => return,
+        };
+
+        let mut matches = Vec::new();
+        let mut still_matching = HashSet::new();
+
+        for entry in self.bosses.values_mut() {
+            if !is_degenerate && entry.boss_data.boss.name != boss_name
+                && self.translation_matcher.matches(&entry.boss_data, &boss_data)
+            {
+                // Bosses are matched again when the hash threshold changes
+                // or an image is re-hashed, so this pair may already be
+                // linked or awaiting review
+                let other_name = &entry.boss_data.boss.name;
+                still_matching.insert(other_name.clone());
+                let is_pending = self.review_translations
+                    && self.pending_translations.iter().any(|&(ref x, ref y)| {
+                        (x == other_name && y == &boss_name) || (x == &boss_name && y == other_name)
+                    });
+                let is_rejected = self.rejected_translations
+                    .contains(&(other_name.clone(), boss_name.clone()));
+                if is_pending || is_rejected
+                    || entry.boss_data.boss.translations.contains(&boss_name)
+                {
+                    continue;
+                }
+
+                self.metrics.inc_hash_match(&boss_name);
+
+                if self.review_translations {
+                    self.pending_translations
+                        .push((other_name.clone(), boss_name.clone()));
+                    continue;
+                }
+
+                entry.boss_data.boss.translations.insert(boss_name.clone());
+                entry.hash_links.insert(boss_name.clone());
+
+                // Every subscriber gets boss updates through the master
+                // broadcast, so followers of this boss know about the link
+                // before they get the other boss' tweets
+                self.subscribers.send_message(
+                    Message::BossUpdate(&entry.boss_data.boss),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+                self.metrics.inc_translation_created(&boss_name);
+                matches.push(entry.boss_data.boss.name.clone());
+            }
+        }
+
+        let unlinked = match previous_links {
+            Some(previous_links) => {
+                self.unlink_stale_hash_matches(&boss_name, previous_links, &still_matching)
+            }
+            None => false,
+        };
+
+        if !matches.is_empty() || unlinked {
+            if let Some(entry) = self.bosses.get_mut(&boss_name) {
+                entry.hash_links.extend(matches.iter().cloned());
+                entry.boss_data.boss.translations.extend(matches);
+
+                self.subscribers.send_message(
+                    Message::BossUpdate(&entry.boss_data.boss),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+
+            self.bump_revision();
+        }
+    }
+
+    // Unlinks bosses that were linked through an earlier image hash of
+    // `boss_name`, but don't match its current one. Returns whether any of
+    // its translations were removed.
+    fn unlink_stale_hash_matches(
+        &mut self,
+        boss_name: &BossName,
+        previous_links: HashSet<BossName>,
+        still_matching: &HashSet<BossName>,
+    ) -> bool {
+        self.pending_translations.retain(|&(ref x, ref y)| {
+            (x != boss_name || still_matching.contains(y))
+                && (y != boss_name || still_matching.contains(x))
+        });
+
+        let (kept, stale): (HashSet<_>, HashSet<_>) = previous_links
+            .into_iter()
+            .partition(|name| still_matching.contains(name));
+
+        if let Some(entry) = self.bosses.get_mut(boss_name) {
+            entry.hash_links.extend(kept);
+            for name in &stale {
+                entry.boss_data.boss.translations.remove(name);
+            }
+        }
+
+        self.strip_translation_links(boss_name, &stale);
+        !stale.is_empty()
+    }
+
+    fn set_hash_threshold(&mut self, threshold: u32) {
+        match self.translation_matcher.with_hash_threshold(threshold) {
+            Some(matcher) => self.translation_matcher = Rc::from(matcher),
+            None => return,
+        }
+
+        let hashed_bosses = self.bosses
+            .iter()
+            .filter_map(|(boss_name, entry)| {
+                entry
+                    .boss_data
+                    .image_hash
+                    .map(|image_hash| (boss_name.clone(), image_hash))
+            })
+            .collect::<Vec<_>>();
+
+        for (boss_name, image_hash) in hashed_bosses {
+            self.handle_image_hash(boss_name, image_hash);
+        }
+    }
+
+    fn now(&self) -> DateTime {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => ::chrono::Utc::now(),
+        }
+    }
+
+    fn export_metadata_batch(&mut self, mut export: MetadataExport<Sub, M::Export>) {
+        for _ in 0..METADATA_EXPORT_BATCH_SIZE {
+            let boss_name = match export.remaining.pop_front() {
+                Some(boss_name) => boss_name,
+                None => return,
+            };
+
+            if let Some(entry) = self.bosses.get(&boss_name) {
+                // The stream was dropped, so there's no point continuing
+                if export.sender.unbounded_send(entry.boss_data.clone()).is_err() {
+                    return;
+                }
+            }
+        }
+
+        if !export.remaining.is_empty() {
+            let client = export.client.clone();
+            client.send(Event::ContinueMetadataExport(export));
+        }
+    }
+
+    fn report_stream_warnings(&mut self) {
+        if let Some(ref warnings) = self.stream_warnings {
+            self.metrics.set_stream_warnings(warnings);
+        }
+    }
+
+    fn handle_checked_image(&mut self, checked: CheckedImage) {
+        match checked.status {
+            ImageStatus::Gone => {}
+            ImageStatus::Live => return,
+            ImageStatus::Unknown => {
+                self.metrics.inc_inconclusive_image_check(&checked.boss_name);
+                return;
+            }
+        }
+
+        let checked_url = Some(&checked.image_url);
+
+        match self.bosses.get_mut(&checked.boss_name) {
+            // The boss may have taken a new image while the check was in flight
+            Some(ref mut entry) if entry.boss_data.boss.image.as_ref() == checked_url => {
+                // The image hash is kept, since it still identifies the boss.
+                // Without an image, the boss takes the next one that's tweeted.
+                entry.boss_data.boss.image = None;
+
+                self.subscribers.send_message(
+                    Message::BossUpdate(&entry.boss_data.boss),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+            _ => return,
+        }
+
+        self.bump_revision();
+    }
+
+    // Links two bosses as translations of each other, regardless of
+    // whether their image hashes match
+    fn confirm_translation(&mut self, a: BossName, b: BossName) {
+        self.pending_translations.retain(|&(ref x, ref y)| {
+            !((x == &a && y == &b) || (x == &b && y == &a))
+        });
+
+        if a == b || !self.bosses.contains_key(&a) || !self.bosses.contains_key(&b) {
+            return;
+        }
+
+        for &(boss_name, translation) in [(&a, &b), (&b, &a)].iter() {
+            if let Some(entry) = self.bosses.get_mut(boss_name) {
+                entry.boss_data.boss.translations.insert(translation.clone());
+
+                self.subscribers.send_message(
+                    Message::BossUpdate(&entry.boss_data.boss),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+        }
+
+        self.bump_revision();
+    }
+
+    // Drops a pending translation, and keeps the pair from being matched
+    // again. Pairs that are already linked are left as they are.
+    fn reject_translation(&mut self, a: BossName, b: BossName) {
+        self.pending_translations.retain(|&(ref x, ref y)| {
+            !((x == &a && y == &b) || (x == &b && y == &a))
+        });
+
+        if a == b || !self.bosses.contains_key(&a) || !self.bosses.contains_key(&b) {
+            return;
+        }
+
+        self.rejected_translations.insert((a.clone(), b.clone()));
+        self.rejected_translations.insert((b, a));
+    }
+
+    fn set_boss_muted(&mut self, boss_name: &BossName, muted: bool) -> bool {
+        let name = match self.lookup_boss_name(boss_name) {
+            Some(name) => name,
+            None => return false,
+        };
+
+        match self.bosses.get_mut(&name) {
+            Some(ref mut entry) if entry.boss_data.boss.muted != muted => {
+                entry.boss_data.boss.muted = muted;
+                self.subscribers.send_message(
+                    Message::BossUpdate(&entry.boss_data.boss),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+            Some(_) => return true,
+            None => return false,
+        }
+
+        self.bump_revision();
+        true
+    }
+
+    // Every change to a boss that's visible in the boss list goes through
+    // here. The boss list is only mapped again when it's requested, so that
+    // changes to many bosses at once don't map it each time.
+    fn bump_revision(&mut self) {
+        self.boss_list_revision += 1;
+        self.cached_boss_list_is_stale = true;
+    }
+
+    // Called after the mapper is replaced
+    fn remap_cached_messages(&mut self) {
+        if !self.config.sequencing {
+            self.heartbeat = self.filter_map_message.map(0, Message::Heartbeat);
+        }
+        self.cached_boss_list_is_stale = true;
+    }
+
+    fn update_cached_boss_list(&mut self) {
+        if !self.cached_boss_list_is_stale {
+            return;
+        }
+
+        let updated = self.bosses
+            .values()
+            .map(|entry| &entry.boss_data)
+            .collect::<Vec<_>>();
+
+        self.cached_boss_list = self.filter_map_message
+            .map(0, Message::BossList(&updated));
+        self.cached_boss_list_is_stale = false;
+    }
+
+    // Returns whether a boss has been seen enough times to exist
+    fn confirm_boss(&mut self, boss_name: &BossName) -> bool {
+        if self.boss_confirmation_threshold <= 1 || self.bosses.contains_key(boss_name) {
+            return true;
+        }
+
+        let now = self.now();
+        let window = to_chrono(self.config.boss_confirmation_window);
+
+        let is_confirmed = {
+            let sightings = self.unconfirmed_bosses
+                .entry(boss_name.clone())
+                .or_insert((0, now));
+
+            // Sightings from before the window start over
+            if now.signed_duration_since(sightings.1) > window {
+                *sightings = (0, now);
+            }
+
+            sightings.0 += 1;
+            sightings.0 >= self.boss_confirmation_threshold
+        };
+
+        if is_confirmed {
+            self.unconfirmed_bosses.remove(boss_name);
+        }
+
+        is_confirmed
+    }
+
+    // Forgets bosses that weren't confirmed within the confirmation window
+    fn expire_unconfirmed_bosses(&mut self) {
+        if self.unconfirmed_bosses.is_empty() {
+            return;
+        }
+
+        let now = self.now();
+        let window = to_chrono(self.config.boss_confirmation_window);
+
+        self.unconfirmed_bosses
+            .retain(|_, &mut (_, first_seen)| now.signed_duration_since(first_seen) <= window);
+    }
+
+    fn handle_raid_info(&mut self, mut info: RaidInfo) {
+        // Tweets from blocked users don't count towards anything, including
+        // metrics and boss confirmation
+        let blocked = !self.user_blocklist.is_empty()
+            && self.user_blocklist.contains(&info.tweet.user.to_lowercase());
+        if blocked {
+            return;
+        }
+
+        // Tweets for unconfirmed bosses are dropped. They aren't counted in
+        // metrics either, since nothing would remove the boss' metrics if it
+        // never gets confirmed.
+        let injected = info.tweet.source == TweetSource::Manual;
+        if !injected && !self.confirm_boss(&info.tweet.boss_name) {
+            return;
+        }
+
+        self.metrics
+            .inc_tweet_count(&info.tweet.boss_name, info.tweet.language);
+        if injected {
+            self.metrics.inc_injected_tweet(&info.tweet.boss_name);
+        }
+
+        info.tweet.seq = self.next_tweet_seq;
+        self.next_tweet_seq += 1;
+
+        let tweet = Arc::new(info.tweet);
+        let straggler_threshold = to_chrono(self.config.straggler_threshold);
+
+        // Tweets that arrive too late aren't sent to followers at all, but
+        // they're still added to the boss' history
+        let live = match self.config.max_tweet_age {
+            Some(max_age) => self.bosses
+                .get(&tweet.boss_name)
+                .and_then(|entry| entry.recent_tweets.newest_created_at())
+                .map_or(true, |newest| {
+                    newest.signed_duration_since(tweet.created_at) <= to_chrono(max_age)
+                }),
+            None => true,
+        };
+
+        // When coalescing, tweets are broadcast later as part of a `TweetList`.
+        // If nobody is following this boss, avoid mapping the message at all.
+        let has_followers = self.follower_count(&tweet.boss_name) > 0;
+        let tweet_message = if self.coalescing.is_some() || !has_followers || !live {
+            Outgoing::none()
+        } else {
+            Outgoing::new(Message::Tweet(&tweet), &self.filter_map_message)
+        };
+
+        let mut delivered = 0;
+
+        // Currently, only one translated boss should exist at most, but in
+        // case the game gets translated to another language, this should still
+        // handle that case. This enum exists because we don't want to allocate
+        // a Vec in the cases where only one translation exists.
+        enum TranslationsExist {
+            One(BossName),
+            Multiple(Vec<BossName>),
+        }
+
+        let mut translations: Option<TranslationsExist> = None;
+
+        // Whether the boss was created, or its image was set
+        let boss_changed = match self.bosses.entry(tweet.boss_name.clone()) {
+            Entry::Occupied(mut entry) => {
+                let value = entry.get_mut();
+
+                value.boss_data.last_seen = tweet.created_at;
+                let muted = value.boss_data.boss.muted;
+                let mut image_set = false;
+
+                let unique_users = value.recent_users.insert(&tweet.user);
+                self.metrics
+                    .set_unique_users(&tweet.boss_name, unique_users as u32);
+
+                let last_tweet_id = value.last_tweet_id;
+                value.last_tweet_id = Some(max(tweet.tweet_id, last_tweet_id.unwrap_or(0)));
+
+                if !muted && live {
+                    if let (Some(threshold), Some(last_tweet_id)) =
+                        (self.config.gap_threshold, last_tweet_id)
+                    {
+                        let is_gap = tweet.tweet_id < last_tweet_id
+                            || tweet.tweet_id - last_tweet_id > threshold;
+                        if is_gap {
+                            value.broadcast.send_message(
+                                Message::Gap {
+                                    boss_name: &tweet.boss_name,
+                                    last_tweet_id,
+                                    tweet_id: tweet.tweet_id,
+                                },
+                                &self.filter_map_message,
+                                &mut self.evicted,
+                            );
+                        }
+                    }
+
+                    delivered += value.broadcast.send_outgoing(
+                        &tweet_message,
+                        &self.filter_map_message,
+                        &mut self.evicted,
+                    );
+                    if let Some(ref mut coalescing) = self.coalescing {
+                        coalescing.push(&tweet.boss_name, &tweet);
+                    }
+                }
+
+                // A boss from `ClientBuilder::with_bosses` keeps its image
+                // hash if the first image seen for it is the same one.
+                // Otherwise, the image is treated as if it were new.
+                let reseed = match info.image {
+                    Some(ref image_url) if value.seeded_image.is_some() => {
+                        let seeded_image = value.seeded_image.take();
+                        if seeded_image.as_ref() == Some(image_url)
+                            && value.boss_data.image_hash.is_some()
+                        {
+                            self.metrics.inc_seeded_hash_reused(&tweet.boss_name);
+                            false
+                        } else {
+                            true
+                        }
+                    }
+                    _ => false,
+                };
+
+                if value.boss_data.boss.image.is_none() || reseed {
+                    if let Some(image_url) = info.image {
+                        request_image_hash(
+                            &self.hash_requester,
+                            &value.boss_data.boss.name,
+                            &image_url,
+                            &mut self.pending_image_hashes,
+                        );
+                        value.boss_data.boss.image = Some(image_url);
+                        value.image_seen_at = tweet.created_at;
+                        image_set = true;
+                    }
+                } else if info.image.is_none() {
+                    if let Some(ref mut checks) = self.image_checks {
+                        checks.check_if_stale(value, tweet.created_at);
+                    }
+                }
+
+                // If this boss has translations, send the tweet to that boss' subscribers too
+                let boss_translations = &value.boss_data.boss.translations;
+                match boss_translations.len() {
+                    _ if muted => {}
+                    1 => {
+                        translations = Some(TranslationsExist::One(
+                            boss_translations.iter().cloned().next().unwrap(),
+                        ));
+                    }
+                    0 => {}
+                    _ => {
+                        translations = Some(TranslationsExist::Multiple(
+                            boss_translations.iter().cloned().collect(),
+                        ));
+                    }
+                }
+
+                let was_empty = push_recent_tweet(
+                    &mut *value.recent_tweets,
+                    tweet.clone(),
+                    &mut self.buffered_tweets,
+                    straggler_threshold,
+                );
+                track_eviction_order(
+                    &mut self.history_eviction_order,
+                    was_empty,
+                    value.boss_data.last_seen,
+                    &tweet.boss_name,
+                );
+                image_set
+            }
+            Entry::Vacant(entry) => {
+                let name = entry.key().clone();
+
+                let mut broadcast = self.requested_bosses
+                    .remove(&name)
+                    .unwrap_or(Broadcast::new());
+
+                let last_seen = tweet.created_at;
+                let boss = RaidBoss {
+                    level: name.parse_level().unwrap_or(DEFAULT_BOSS_LEVEL),
+                    name: name,
+                    image: info.image,
+                    language: tweet.language,
+                    translations: HashSet::with_capacity(1),
+                    muted: false,
+                };
+
+                {
+                    self.subscribers.send_message(
+                        Message::BossUpdate(&boss),
+                        &self.filter_map_message,
+                        &mut self.evicted,
+                    );
+
+                    delivered += broadcast.send_outgoing(
+                        &tweet_message,
+                        &self.filter_map_message,
+                        &mut self.evicted,
+                    );
+                    if let Some(ref mut coalescing) = self.coalescing {
+                        coalescing.push(&boss.name, &tweet);
+                    }
+                }
+
+                if let Some(ref image_url) = boss.image {
+                    request_image_hash(
+                        &self.hash_requester,
+                        &boss.name,
+                        image_url,
+                        &mut self.pending_image_hashes,
+                    );
+                }
+
+                if !broadcast.is_empty() {
+                    self.metrics
+                        .set_follower_count(&boss.name, broadcast.subscriber_count() as u32);
+                }
+
+                let mut recent_tweets =
+                    self.tweet_store.create(&boss.name, self.tweet_history_size);
+                let was_empty = push_recent_tweet(
+                    &mut *recent_tweets,
+                    tweet.clone(),
+                    &mut self.buffered_tweets,
+                    straggler_threshold,
+                );
+                track_eviction_order(
+                    &mut self.history_eviction_order,
+                    was_empty,
+                    last_seen,
+                    &boss.name,
+                );
+
+                let mut recent_users = RecentUsers::with_capacity(self.config.unique_user_capacity);
+                recent_users.insert(&tweet.user);
+                self.metrics.set_unique_users(&boss.name, 1);
+
+                entry.insert(RaidBossEntry {
+                    boss_data: RaidBossMetadata {
+                        boss,
+                        first_seen: last_seen,
+                        last_seen,
+                        image_hash: None,
+                    },
+                    broadcast,
+                    recent_tweets,
+                    recent_users,
+                    seeded_image: None,
+                    last_tweet_id: Some(tweet.tweet_id),
+                    image_seen_at: last_seen,
+                    hash_links: HashSet::new(),
+                });
+
+                true
+            }
+        };
+
+        // Broadcast the tweet to the equivalent translated bosses
+        match translations {
+            Some(TranslationsExist::One(boss_name)) => {
+                delivered += self.broadcast_translated_tweet(
+                    &boss_name,
+                    &tweet,
+                    &tweet_message,
+                    live,
+                );
+            }
+            None => {}
+            Some(TranslationsExist::Multiple(boss_names)) => {
+                for boss_name in boss_names {
+                    delivered += self.broadcast_translated_tweet(
+                        &boss_name,
+                        &tweet,
+                        &tweet_message,
+                        live,
+                    );
+                }
+            }
+        }
+
+        // Reliable followers don't get tweets that nobody else would get
+        let muted = self.bosses
+            .get(&tweet.boss_name)
+            .map_or(false, |entry| entry.boss_data.boss.muted);
+        if live && !muted {
+            delivered += self.deliver_reliable(&tweet);
+        }
+
+        self.count_delivered(&tweet, delivered);
+
+        self.enforce_global_history_limit();
+
+        if boss_changed {
+            self.bump_revision();
+        }
+    }
+
+    // Remove tweets from the least recently seen bosses until the total
+    // number of buffered tweets is within the global limit
+    fn enforce_global_history_limit(&mut self) {
+        let (limit, order) = match (self.global_history_limit, &mut self.history_eviction_order) {
+            (Some(limit), &mut Some(ref mut order)) if self.buffered_tweets > limit => {
+                (limit, order)
+            }
+            _ => return,
+        };
+
+        while self.buffered_tweets > limit {
+            let Reverse((last_seen, boss_name)) = match order.pop() {
+                Some(next) => next,
+                None => break,
+            };
+
+            if let Some(entry) = self.bosses.get_mut(&boss_name) {
+                if entry.recent_tweets.is_empty() {
+                    continue;
+                }
+
+                // The boss was seen again since it was added
+                if entry.boss_data.last_seen != last_seen {
+                    order.push(Reverse((entry.boss_data.last_seen, boss_name.clone())));
+                    continue;
+                }
+
+                let excess = self.buffered_tweets - limit;
+                let removed = entry.recent_tweets.remove_oldest(excess);
+                self.buffered_tweets -= removed;
+
+                if removed > 0 && !entry.recent_tweets.is_empty() {
+                    order.push(Reverse((last_seen, boss_name.clone())));
+                }
+            }
+        }
+    }
+
+    // Returns the number of followers the tweet was sent to
+    fn broadcast_translated_tweet(
+        &mut self,
+        boss_name: &BossName,
+        tweet: &Arc<RaidTweet>,
+        message: &Outgoing<Sub::Item>,
+        live: bool,
+    ) -> usize {
+        if let Some(value) = self.bosses.get_mut(boss_name) {
+            let delivered = value.broadcast.send_outgoing(
+                message,
+                &self.filter_map_message,
+                &mut self.evicted,
+            );
+            match self.coalescing {
+                Some(ref mut coalescing) if live => coalescing.push(boss_name, tweet),
+                _ => {}
+            }
+            if self.config.translation_history_sharing {
+                let was_empty = push_recent_tweet(
+                    &mut *value.recent_tweets,
+                    tweet.clone(),
+                    &mut self.buffered_tweets,
+                    to_chrono(self.config.straggler_threshold),
+                );
+                track_eviction_order(
+                    &mut self.history_eviction_order,
+                    was_empty,
+                    value.boss_data.last_seen,
+                    boss_name,
+                );
+            }
+            delivered
+        } else {
+            0
+        }
+    }
+
+    // Followers of a boss, including followers of its translations,
+    // and followers waiting for the boss to appear
+    // A name that was followed as the translation of an existing boss is
+    // also a pending follow, but both only count as one boss
+    fn follow_count(&self, id: &SubId) -> usize {
+        let is_following = |boss_name: &BossName| {
+            self.bosses
+                .get(boss_name)
+                .map_or(false, |entry| entry.broadcast.contains(id))
+        };
+
+        let following = self.bosses
+            .values()
+            .filter(|entry| entry.broadcast.contains(id))
+            .count();
+
+        let pending = self.requested_bosses
+            .iter()
+            .filter(|&(boss_name, broadcast)| {
+                broadcast.contains(id) && match self.resolve_boss_name(boss_name) {
+                    Resolution::Translated(ref name) => !is_following(name),
+                    _ => true,
+                }
+            })
+            .count();
+
+        following + pending
+    }
+
+    fn follower_count(&self, boss_name: &BossName) -> usize {
+        match self.bosses.get(boss_name) {
+            Some(entry) => {
+                let translated = entry
+                    .boss_data
+                    .boss
+                    .translations
+                    .iter()
+                    .filter_map(|name| self.bosses.get(name))
+                    .map(|e| e.broadcast.subscriber_count())
+                    .sum::<usize>();
+
+                entry.broadcast.subscriber_count() + translated
+            }
+            None => self.requested_bosses
+                .get(boss_name)
+                .map_or(0, |b| b.subscriber_count()),
+        }
+    }
+
+    // Coalesced tweets are counted once they're flushed
+    fn count_delivered(&mut self, tweet: &RaidTweet, delivered: usize) {
+        let is_pending = match self.coalescing {
+            Some(ref mut coalescing) => coalescing.add_delivered(tweet, delivered),
+            None => false,
+        };
+
+        if !is_pending {
+            self.metrics
+                .inc_tweet_delivered(&tweet.boss_name, delivered as u32);
+        }
+    }
+
+    fn flush_coalesced_tweets(&mut self) {
+        let (pending, mut delivered) = match self.coalescing {
+            Some(ref mut coalescing) => coalescing.take_pending(),
+            None => return,
+        };
+
+        for (boss_name, tweets) in pending {
+            if let Some(entry) = self.bosses.get_mut(&boss_name) {
+                let sent = entry.broadcast.send_message(
+                    Message::TweetList(&tweets),
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+
+                for tweet in tweets.iter() {
+                    if let Some(&mut (_, ref mut count)) = delivered.get_mut(&tweet.seq) {
+                        *count += sent;
+                    }
+                }
+            }
+        }
+
+        for (_, (boss_name, count)) in delivered {
+            self.metrics.inc_tweet_delivered(&boss_name, count as u32);
+        }
+    }
+
+    fn sweep_idle_subscribers(&mut self) {
+        let stalled = self.subscribers.stalled();
+
+        if let Some(ref mut sweep) = self.idle_sweep {
+            let (still_stalled, newly_stalled) = stalled
+                .into_iter()
+                .partition::<Vec<_>, _>(|id| sweep.stalled.contains(id));
+
+            self.evicted.extend(still_stalled);
+            sweep.stalled = newly_stalled.into_iter().collect();
+        }
+
+        self.sweep_detached_sessions();
+        self.expire_unconfirmed_bosses();
+    }
+
+    fn poll_idle_sweep(&mut self) -> Result<()> {
+        loop {
+            let tick = match self.idle_sweep {
+                Some(ref mut sweep) => sweep.ticks.poll()?,
+                None => return Ok(()),
+            };
+
+            match tick {
+                Async::Ready(Some(())) => self.sweep_idle_subscribers(),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn poll_heartbeat_splay(&mut self) -> Result<()> {
+        loop {
+            let slot = match self.heartbeat_splay {
+                Some(ref mut splay) => match splay.ticks.poll()? {
+                    Async::Ready(Some(())) => {
+                        let slot = splay.next_slot;
+                        splay.next_slot = slot
+                            .map(|slot| slot + 1)
+                            .filter(|&slot| slot < HEARTBEAT_SPLAY_SLOTS);
+                        slot
+                    }
+                    _ => return Ok(()),
+                },
+                None => return Ok(()),
+            };
+
+            if let Some(slot) = slot {
+                self.send_heartbeat(Some(slot));
+            }
+        }
+    }
+
+    fn poll_image_checks(&mut self) {
+        loop {
+            let polled = match self.image_checks {
+                Some(ref mut checks) => checks.in_flight.poll(),
+                None => return,
+            };
+
+            match polled {
+                Ok(Async::Ready(Some(checked))) => self.handle_checked_image(checked),
+                // Failed checks are mapped to `ImageStatus::Unknown`
+                Err(_) => {}
+                Ok(_) => return,
+            }
+        }
+    }
+
+    fn poll_coalescing(&mut self) -> Result<()> {
+        let mut should_flush = false;
+
+        if let Some(ref mut coalescing) = self.coalescing {
+            while let Async::Ready(Some(())) = coalescing.ticks.poll()? {
+                should_flush = true;
+            }
+        }
+
+        if should_flush {
+            self.flush_coalesced_tweets();
+        }
+
+        Ok(())
+    }
+}
+
+impl<H, S, Sub, F, M> Worker<H, S, Sub, F, M>
+where
+    H: ImageHasher,
+    S: Stream<Item = RaidInfo, Error = Error>,
+    Sub: Subscriber + Clone,
+    F: MessageMapper<Sub::Item>,
+    M: Metrics,
+{
+    fn poll_events(&mut self) -> Poll<(), Error> {
+        loop {
+            self.poll_coalescing()?;
+            self.poll_idle_sweep()?;
+            self.poll_heartbeat_splay()?;
+            self.poll_image_checks();
+            self.remove_evicted_subscribers();
+
+            if let Some(event) = try_ready!(self.events.poll()) {
+                self.handle_event(event)
+            } else {
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
+
+impl<H, S, Sub, F, M> Future for Worker<H, S, Sub, F, M>
+where
+    H: ImageHasher,
+    S: Stream<Item = RaidInfo, Error = Error>,
+    Sub: Subscriber + Clone,
+    F: MessageMapper<Sub::Item>,
+    M: Metrics,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let polled = self.poll_events();
+
+        match polled {
+            Ok(Async::NotReady) => {}
+            // Let subscribers know why their stream is about to end, after
+            // sending any tweets that are waiting for the coalescing window
+            _ => {
+                self.flush_coalesced_tweets();
+                self.subscribers.send_message(
+                    Message::ServerClosing,
+                    &self.filter_map_message,
+                    &mut self.evicted,
+                );
+            }
+        }
+
+        polled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RecentUsers, Resolution, HEARTBEAT_SPLAY_SLOTS, METADATA_EXPORT_BATCH_SIZE};
+    use broadcast::{MessageStream, Subscriber, MESSAGE_STREAM_CAPACITY};
+    use chrono::{TimeZone, Utc};
+    use client::harness::*;
+    use clock::ManualClock;
+    use futures::{future, Async, Future, Stream};
+    use id_pool::{Id as SubId, IdPool};
+    use image_hash::{ExactHashMatcher, HammingMatcher, ImageHash, TranslationMatcher};
+    use client::{ActivityStats, MapperCheck, ResumeToken};
+    use metrics::{EventKind, Metrics};
+    use model::{BossName, Language, Message, MessageFilter, MessageKind, RaidBossMetadata,
+                RaidTweet, TweetId, TweetOrder, TweetSource};
+    use protocol::{self, OwnedMessage};
+    use raid::RaidInfo;
+    use serde_json;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tweet_store::{self, TweetStore};
+
+    #[test]
+    fn coalesce_tweets_within_window() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_tweet_coalescing(Duration::from_secs(1))
+        });
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        for id in 0..5 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+
+        // No tweets are sent until the window ends
+        assert!(tweet_ids(&recorder.take()).is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        h.run();
+
+        let messages = recorder.take();
+        assert_eq!(messages.len(), 1);
+        match messages[0] {
+            Recorded::TweetList(ref tweets) => assert_eq!(tweets.len(), 5),
+            ref other => panic!("expected TweetList, got {:?}", other),
+        }
+        assert_eq!(tweet_ids(&messages), vec![0, 1, 2, 3, 4]);
+
+        // Nothing left to flush in the next window
+        clock.advance(Duration::from_secs(1));
+        h.run();
+        assert_eq!(recorder.take(), vec![]);
+    }
+
+    #[test]
+    fn flush_coalesced_tweets_before_closing() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_tweet_coalescing(Duration::from_secs(1))
+        });
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        assert!(tweet_ids(&recorder.take()).is_empty());
+
+        h.end_stream();
+        let worker = &mut h.worker;
+        let polled = future::lazy(|| Ok::<_, ()>(worker.poll())).wait().unwrap();
+        assert!(polled.is_err());
+
+        let messages = recorder.take();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(tweet_ids(&messages), vec![1, 2]);
+        assert_eq!(messages[1], Recorded::ServerClosing);
+    }
+
+    // Records the number of followers each tweet was delivered to
+    #[derive(Clone, Debug, Default)]
+    struct Deliveries(Rc<RefCell<Vec<(BossName, u32)>>>);
+
+    impl Metrics for Deliveries {
+        type Export = ();
+
+        fn set_total_subscriber_count(&mut self, _count: u32) {}
+        fn set_follower_count(&mut self, _boss_name: &BossName, _count: u32) {}
+        fn inc_tweet_count(&mut self, _boss_name: &BossName, _language: Language) {}
+        fn inc_tweet_delivered(&mut self, boss_name: &BossName, followers: u32) {
+            self.0.borrow_mut().push((boss_name.clone(), followers));
+        }
+        fn remove_boss(&mut self, _boss_name: &BossName) {}
+        fn export(&self) {}
+    }
+
+    #[test]
+    fn count_coalesced_deliveries_when_flushed() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let deliveries = Deliveries::default();
+        let (builder, hasher, raids) = builder();
+        let builder = builder
+            .with_clock(clock.clone())
+            .with_tweet_coalescing(Duration::from_secs(1))
+            .with_metrics(deliveries.clone());
+        let mut h = Harness::from_builder(builder, hasher, raids);
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        let (mut filtered, _filtered_recorder) =
+            h.subscribe_with_filter(MessageFilter::only(&[MessageKind::Tweet]));
+        filtered.follow("Lv60 オオゾラッコ");
+        let (mut muted, _muted_recorder) = h.subscribe();
+        muted.follow("Lv60 オオゾラッコ");
+        muted.set_muted(true);
+        h.run();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        assert!(deliveries.0.borrow().is_empty());
+
+        // Only the subscriber that accepts tweet lists receives them
+        clock.advance(Duration::from_secs(1));
+        h.run();
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+        assert_eq!(
+            deliveries.0.replace(Vec::new()),
+            vec![(boss_name.clone(), 1), (boss_name, 1)]
+        );
+    }
+
+    #[test]
+    fn broadcast_tweets_individually_by_default() {
+        let mut h = Harness::new();
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        for id in 0..5 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+
+        let tweets = recorder
+            .take()
+            .into_iter()
+            .filter(|m| match *m {
+                Recorded::Tweet(_) => true,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(tweet_ids(&tweets), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn follow_with_catch_up() {
+        let mut h = Harness::new();
+
+        for id in 1..4 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow_with_catch_up("Lv60 オオゾラッコ");
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 0));
+
+        let messages = recorder.take();
+        assert!(messages.iter().all(|m| match *m {
+            Recorded::Tweet(_) => true,
+            _ => false,
+        }));
+        assert_eq!(tweet_ids(&messages), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn follow_with_backlog() {
+        let mut h = Harness::new();
+        h.push_all(vec![
+            raid_info("Lv60 オオゾラッコ", 1, 0),
+            raid_info("Lv60 オオゾラッコ", 2, 1),
+        ]);
+
+        // A tweet that arrives between `get_tweets` and `follow` is missed
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.get_tweets("Lv60 オオゾラッコ");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 2));
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 3));
+        assert_eq!(tweet_ids(&recorder.take()), vec![1, 2, 4]);
+
+        // Tweets queued at the same time as the follow are delivered once,
+        // either in the backlog or on their own
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow_with_backlog("Lv60 オオゾラッコ");
+        h.push(raid_info("Lv60 オオゾラッコ", 5, 4));
+        h.push(raid_info("Lv60 オオゾラッコ", 6, 5));
+
+        let messages = recorder.take();
+        match messages.first() {
+            Some(&Recorded::TweetList(_)) => {}
+            other => panic!("expected tweet list, got {:?}", other),
+        }
+        assert_eq!(tweet_ids(&messages), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn global_history_limit() {
+        let mut h = Harness::with_builder(|b| b.with_global_history_limit(3));
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 1));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 5));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 4, 6));
+
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).buffered_tweets, 3);
+
+        // The oldest tweet is removed from the least recently seen boss
+        let tweets = h.client.tweets("Lv60 オオゾラッコ");
+        let ids = h.request(tweets).iter().map(|t| t.tweet_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2]);
+
+        h.push(raid_info("Lv75 スーペルヒガンテ", 5, 7));
+
+        let tweets = h.client.tweets("Lv60 オオゾラッコ");
+        assert!(h.request(tweets).is_empty());
+
+        let tweets = h.client.tweets("Lv75 スーペルヒガンテ");
+        let mut ids = h.request(tweets).iter().map(|t| t.tweet_id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn global_history_limit_follows_last_seen() {
+        let mut h = Harness::with_builder(|b| b.with_global_history_limit(3));
+        let ids = |h: &mut Harness<_>, boss_name: &str| {
+            let tweets = h.client.tweets(boss_name);
+            h.request(tweets).iter().map(|t| t.tweet_id).collect::<Vec<_>>()
+        };
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 2, 1));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 2));
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 3));
+
+        // The Lv75 boss was seen less recently, even though the Lv60 boss
+        // has the oldest tweet
+        assert_eq!(ids(&mut h, "Lv60 オオゾラッコ"), vec![1, 3, 4]);
+        assert!(ids(&mut h, "Lv75 スーペルヒガンテ").is_empty());
+
+        // Once the Lv75 boss has tweets again, it's seen more recently
+        h.push(raid_info("Lv75 スーペルヒガンテ", 5, 4));
+        assert_eq!(ids(&mut h, "Lv60 オオゾラッコ"), vec![3, 4]);
+        assert_eq!(ids(&mut h, "Lv75 スーペルヒガンテ"), vec![5]);
+        assert_eq!(h.worker.history_eviction_order.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn buffered_tweets_ignore_overwrites() {
+        let mut h = Harness::with_builder(|b| b.with_history_size(2));
+
+        for id in 0..5 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+        h.push(raid_info("Lv75 スーペルヒガンテ", 5, 0));
+
+        let stats = h.client.stats();
+        let stats = h.request(stats);
+        assert_eq!(stats.buffered_tweets, 3);
+        assert_eq!(stats.bosses, 2);
+
+        h.client.remove_bosses(|m| m.boss.level == 60);
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).buffered_tweets, 1);
+    }
+
+    #[test]
+    fn latest_tweet() {
+        let mut h = Harness::new();
+        let latest = h.client.latest_tweet();
+        assert_eq!(h.request(latest), None);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 10));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 4, 30));
+        h.push(raid_info("Lvl 60 Ozorotter", 3, 30));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 20));
+
+        // Ties in creation time are broken by tweet ID
+        let latest = h.client.latest_tweet();
+        assert_eq!(h.request(latest).map(|t| t.tweet_id), Some(4));
+
+        h.push(raid_info("Lvl 60 Ozorotter", 5, 40));
+        let latest = h.client.latest_tweet();
+        assert_eq!(h.request(latest).map(|t| t.tweet_id), Some(5));
+    }
+
+    #[test]
+    fn remove_bosses_dry_run() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+
+        let boss_names = |h: &mut Harness| {
+            let bosses = h.client.bosses();
+            let mut names = h.request(bosses)
+                .into_iter()
+                .map(|b| b.name.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let before = boss_names(&mut h);
+
+        let dry_run = h.client.remove_bosses_dry_run(|m| m.boss.level == 60);
+        let mut matched = h.request(dry_run)
+            .into_iter()
+            .map(|m| m.boss.name.to_string())
+            .collect::<Vec<_>>();
+        matched.sort();
+        assert_eq!(matched, vec!["Lv60 オオゾラッコ", "Lvl 60 Ozorotter"]);
+        assert_eq!(boss_names(&mut h), before);
+
+        // The real removal removes the same bosses
+        h.client.remove_bosses(|m| m.boss.level == 60);
+        let remaining = boss_names(&mut h);
+        let removed = before
+            .into_iter()
+            .filter(|name| !remaining.contains(name))
+            .collect::<Vec<_>>();
+        assert_eq!(removed, matched);
+    }
+
+    // Every subscription receives boss updates, including ones that only
+    // follow a single boss, so followers learn about a new translation
+    // before receiving its tweets
+    #[test]
+    fn boss_update_before_translated_tweets() {
+        let mut h = Harness::new();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.push(raid_info("Lvl 60 Ozorotter", 3, 0));
+
+        let messages = recorder.take();
+        let update_index = messages
+            .iter()
+            .position(|m| match *m {
+                Recorded::BossUpdate(ref boss) => {
+                    boss.name == ja && boss.translations.contains(&en)
+                }
+                _ => false,
+            })
+            .expect("missing boss update");
+        let tweet_index = messages
+            .iter()
+            .position(|m| match *m {
+                Recorded::Tweet(ref tweet) => tweet.boss_name == en,
+                _ => false,
+            })
+            .expect("missing translated tweet");
+
+        assert!(update_index < tweet_index);
+        assert_eq!(tweet_ids(&messages), vec![3]);
+    }
+
+    #[test]
+    fn translation_matchers() {
+        let translations = |h: &mut Harness| {
+            h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+            h.set_image_hash("Lvl 60 Ozorotter", 0b110);
+            h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+            h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+            let boss = h.client.boss("Lv60 オオゾラッコ");
+            h.request(boss).unwrap().translations
+        };
+
+        // The hashes differ by one bit, so they're only linked if the
+        // matcher allows it
+        assert!(translations(&mut Harness::new()).is_empty());
+
+        let mut h = Harness::with_builder(|b| b.with_translation_matcher(HammingMatcher(1)));
+        assert!(translations(&mut h).contains(&BossName::from("Lvl 60 Ozorotter")));
+
+        let mut h = Harness::with_builder(|b| b.with_translation_matcher(ExactHashMatcher));
+        assert!(translations(&mut h).is_empty());
+    }
+
+    #[test]
+    fn set_hash_threshold() {
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+        let translations = |h: &mut Harness, boss_name: &BossName| {
+            let boss = h.client.boss(boss_name.clone());
+            h.request(boss).unwrap().translations
+        };
+
+        // The hashes differ by two bits
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.set_image_hash("Lvl 60 Ozorotter", 0b1110);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        assert!(translations(&mut h, &ja).is_empty());
+
+        h.client.set_hash_threshold(1);
+        h.run();
+        assert!(translations(&mut h, &ja).is_empty());
+
+        // Raising the threshold links the existing bosses
+        let (_subscription, recorder) = h.subscribe();
+        h.client.set_hash_threshold(2);
+        h.run();
+        assert!(translations(&mut h, &ja).contains(&en));
+        assert!(translations(&mut h, &en).contains(&ja));
+
+        let updates = recorder
+            .take()
+            .into_iter()
+            .filter(|m| match *m {
+                Recorded::BossUpdate(_) => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(updates, 2);
+
+        // Lowering it only affects new hashes
+        h.client.set_hash_threshold(0);
+        h.set_image_hash("Lv75 シュヴァリエ・マグナ", 0b10);
+        h.set_image_hash("Lvl 75 Luminiera Omega", 0b110);
+        h.push(raid_info_with_image("Lv75 シュヴァリエ・マグナ", 3, 0, "http://example.com/c.png"));
+        h.push(raid_info_with_image("Lvl 75 Luminiera Omega", 4, 0, "http://example.com/d.png"));
+        assert!(translations(&mut h, &ja).contains(&en));
+        assert!(translations(&mut h, &"Lv75 シュヴァリエ・マグナ".into()).is_empty());
+    }
+
+    #[test]
+    fn keep_custom_matcher_on_threshold_change() {
+        // Ignores image hashes entirely
+        #[derive(Debug)]
+        struct LevelMatcher;
+        impl TranslationMatcher for LevelMatcher {
+            fn matches(&self, a: &RaidBossMetadata, b: &RaidBossMetadata) -> bool {
+                a.boss.level == b.boss.level && a.boss.language != b.boss.language
+            }
+        }
+
+        let mut h = Harness::with_builder(|b| b.with_translation_matcher(LevelMatcher));
+        h.client.set_hash_threshold(0);
+        h.run();
+
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.set_image_hash("Lvl 60 Ozorotter", 0b1111_0000);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let boss = h.client.boss("Lv60 オオゾラッコ");
+        let translations = h.request(boss).unwrap().translations;
+        assert!(translations.contains(&BossName::from("Lvl 60 Ozorotter")));
+    }
+
+    #[test]
+    fn count_hash_matches() {
+        let mut h = Harness::with_json_metrics();
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.set_image_hash("Lvl 60 Ozorotter", 0b10);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let counters = |h: &mut Harness<JsonMetrics>, boss_name: &str| {
+            let counters = &h.metrics()["boss_counts"][boss_name]["counters"];
+            (
+                counters["images_hashed"].as_u64().unwrap(),
+                counters["hash_matches"].as_u64().unwrap(),
+                counters["translations_created"].as_u64().unwrap(),
+            )
+        };
+
+        // Only the second boss to be hashed finds a match
+        assert_eq!(counters(&mut h, "Lv60 オオゾラッコ"), (1, 0, 0));
+        assert_eq!(counters(&mut h, "Lvl 60 Ozorotter"), (1, 1, 1));
+
+        // Matching existing translations again doesn't count them again
+        h.client.set_hash_threshold(5);
+        h.run();
+        assert_eq!(counters(&mut h, "Lv60 オオゾラッコ"), (1, 0, 0));
+        assert_eq!(counters(&mut h, "Lvl 60 Ozorotter"), (1, 1, 1));
+    }
+
+    #[test]
+    fn count_pending_hash_matches_once() {
+        let mut h = Harness::with_json_metrics_and_builder(|b| b.with_translation_review(true));
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.set_image_hash("Lvl 60 Ozorotter", 0b10);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let hash_matches = |h: &mut Harness<JsonMetrics>, boss_name: &str| {
+            h.metrics()["boss_counts"][boss_name]["counters"]["hash_matches"].as_u64()
+        };
+
+        assert_eq!(hash_matches(&mut h, "Lv60 オオゾラッコ"), Some(0));
+        assert_eq!(hash_matches(&mut h, "Lvl 60 Ozorotter"), Some(1));
+
+        // The pair is still awaiting review, from either direction
+        h.client.set_hash_threshold(5);
+        h.run();
+        assert_eq!(hash_matches(&mut h, "Lv60 オオゾラッコ"), Some(0));
+        assert_eq!(hash_matches(&mut h, "Lvl 60 Ozorotter"), Some(1));
+
+        let pending = h.client.pending_translations();
+        assert_eq!(h.request(pending).len(), 1);
+    }
+
+    #[test]
+    fn reuse_seeded_image_hashes() {
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        let metadata = h.client.export_metadata();
+        let metadata = h.request(metadata);
+        assert!(metadata[0].image_hash.is_some());
+
+        let seeded = |metadata: Vec<RaidBossMetadata>, image: &str| {
+            let mut h = Harness::with_json_metrics_and_builder(|b| b.with_bosses(metadata));
+            h.push(raid_info_with_image("Lv60 オオゾラッコ", 2, 10, image));
+            h.push(raid_info_with_image("Lv60 オオゾラッコ", 3, 20, image));
+            h
+        };
+        let reused = |h: &mut Harness<JsonMetrics>| {
+            h.metrics()["boss_counts"][&*boss_name]["counters"]["seeded_hashes_reused"].clone()
+        };
+
+        // Unchanged image
+        let mut h = seeded(metadata.clone(), "http://example.com/a.png");
+        assert_eq!(h.hasher.requests.get(), 0);
+        assert_eq!(reused(&mut h), 1);
+
+        // Changed image
+        let mut h = seeded(metadata.clone(), "http://example.com/b.png");
+        assert_eq!(h.hasher.requests.get(), 1);
+        assert_eq!(reused(&mut h), 0);
+        let bosses = h.client.bosses();
+        assert_eq!(
+            h.request(bosses)[0].image,
+            Some("http://example.com/b.png".into())
+        );
+
+        // Seeded without a hash
+        let mut unhashed = metadata.clone();
+        unhashed[0].image_hash = None;
+        let mut h = seeded(unhashed, "http://example.com/a.png");
+        assert_eq!(h.hasher.requests.get(), 1);
+        assert_eq!(reused(&mut h), 0);
+    }
+
+    #[test]
+    fn ignore_degenerate_image_hashes() {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 0);
+        h.set_image_hash("Lvl 60 Ozorotter", 0);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let bosses = h.client.bosses();
+        for boss in h.request(bosses) {
+            assert!(boss.translations.is_empty());
+        }
+
+        let metadata = h.client.export_metadata();
+        for metadata in h.request(metadata) {
+            assert_eq!(metadata.image_hash, None);
+        }
+    }
+
+    #[test]
+    fn debug_image_hashes() {
+        let boss_name = "Lvl 60 Ozorotter";
+        let debug_bosses = |enabled: bool| {
+            let mut h = Harness::with_builder(|b| b.with_debug_image_hashes(enabled));
+            h.set_image_hash(boss_name, 0xabc);
+            h.push(raid_info_with_image(boss_name, 1, 0, "http://example.com/a.png"));
+
+            let bosses = h.client.bosses();
+            let json = serde_json::to_value(h.request(bosses)).unwrap();
+            assert!(json[0].get("image_hash").is_none());
+
+            let bosses = h.client.debug_bosses();
+            serde_json::to_value(h.request(bosses)).unwrap()
+        };
+
+        let json = debug_bosses(true);
+        assert_eq!(json[0]["boss"]["name"], boss_name);
+        assert_eq!(json[0]["image_hash"], "0000000000000abc");
+
+        let json = debug_bosses(false);
+        assert_eq!(json[0]["boss"]["name"], boss_name);
+        assert!(json[0].get("image_hash").is_none());
+    }
+
+    // Links the Japanese and English Ozorotter bosses, then sends a tweet for
+    // the Japanese one. Returns the tweet IDs received by an English follower,
+    // and the recent tweets of the English boss with and without merging.
+    fn translation_history(sharing: bool) -> (Vec<TweetId>, Vec<TweetId>, Vec<TweetId>) {
+        let mut h = Harness::with_builder(|b| b.with_translation_history_sharing(sharing));
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        let received = tweet_ids(&recorder.take());
+
+        let ids = |tweets: Vec<Arc<RaidTweet>>| tweets.iter().map(|t| t.tweet_id).collect();
+        let tweets = h.client
+            .sorted_tweets("Lvl 60 Ozorotter", TweetOrder::TweetId);
+        let history = ids(h.request(tweets));
+        let tweets = h.client
+            .sorted_tweets_with_translations("Lvl 60 Ozorotter", TweetOrder::TweetId);
+        let merged = ids(h.request(tweets));
+
+        (received, history, merged)
+    }
+
+    #[test]
+    fn translation_history_sharing() {
+        let (received, history, merged) = translation_history(true);
+        assert_eq!(received, vec![3]);
+        assert_eq!(history, vec![2, 3]);
+        assert_eq!(merged, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn no_translation_history_sharing() {
+        let (received, history, merged) = translation_history(false);
+        assert_eq!(received, vec![3]);
+        assert_eq!(history, vec![2]);
+        assert_eq!(merged, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn review_translations() {
+        let mut h = Harness::with_builder(|b| b.with_translation_review(true));
+        let (_subscription, recorder) = h.subscribe();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+        recorder.take();
+
+        // Matches are only recorded as candidates
+        assert!(h.worker.bosses[&ja].boss_data.boss.translations.is_empty());
+        assert!(h.worker.bosses[&en].boss_data.boss.translations.is_empty());
+
+        let pending = h.client.pending_translations();
+        assert_eq!(h.request(pending), vec![(ja.clone(), en.clone())]);
+
+        // Hashing again doesn't add a duplicate candidate
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.client.confirm_translation("Lvl 60 Ozorotter", "Lvl 100 Unknown");
+        let pending = h.client.pending_translations();
+        assert_eq!(h.request(pending).len(), 1);
+        assert!(recorder.take().is_empty());
+
+        h.client.confirm_translation("Lvl 60 Ozorotter", "Lv60 オオゾラッコ");
+        let pending = h.client.pending_translations();
+        assert!(h.request(pending).is_empty());
+
+        assert!(h.worker.bosses[&ja].boss_data.boss.translations.contains(&en));
+        assert!(h.worker.bosses[&en].boss_data.boss.translations.contains(&ja));
+
+        let updates = recorder
+            .take()
+            .into_iter()
+            .filter_map(|m| match m {
+                Recorded::BossUpdate(boss) => Some(boss.name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(updates, vec![en, ja]);
+    }
+
+    #[test]
+    fn reject_translations() {
+        let mut h = Harness::with_builder(|b| b.with_translation_review(true));
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+
+        h.client.reject_translation("Lvl 60 Ozorotter", "Lv60 オオゾラッコ");
+        let pending = h.client.pending_translations();
+        assert!(h.request(pending).is_empty());
+
+        // Rejected pairs aren't proposed again
+        h.client.rehash_boss("Lv60 オオゾラッコ");
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        let pending = h.client.pending_translations();
+        assert!(h.request(pending).is_empty());
+        assert!(h.worker.bosses[&ja].boss_data.boss.translations.is_empty());
+        assert!(h.worker.bosses[&en].boss_data.boss.translations.is_empty());
+
+        // Until one of the bosses is removed
+        h.client
+            .remove_bosses(|meta| meta.boss.name.as_str() == "Lvl 60 Ozorotter");
+        h.run();
+        assert!(h.worker.rejected_translations.is_empty());
+
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 3, 0, "http://example.com/b.png"));
+        h.run();
+        let pending = h.client.pending_translations();
+        assert_eq!(h.request(pending), vec![(ja.clone(), en.clone())]);
+
+        // Removing a boss also drops its pending translations
+        h.client
+            .remove_bosses(|meta| meta.boss.name.as_str() == "Lv60 オオゾラッコ");
+        let pending = h.client.pending_translations();
+        assert!(h.request(pending).is_empty());
+    }
+
+    #[test]
+    fn image_hash_status() {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.hasher.stalled.set(true);
+
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let status = h.client.image_hash_status();
+        let mut status = h.request(status);
+        status.pending.sort_by_key(|name| name.to_string());
+        assert_eq!(status.queued, 2);
+        assert_eq!(
+            status.pending,
+            vec![
+                BossName::from("Lv60 オオゾラッコ"),
+                BossName::from("Lvl 60 Ozorotter"),
+            ]
+        );
+
+        h.push(raid_info_with_image("Lv75 スーペルヒガンテ", 3, 0, "http://example.com/c.png"));
+        let status = h.client.image_hash_status();
+        let status = h.request(status);
+        assert_eq!(status.queued, 3);
+        assert_eq!(status.pending.len(), 3);
+
+        // Duplicate requests for bosses that are already in flight are dropped
+        h.client.rehash_boss("Lv60 オオゾラッコ");
+        let status = h.client.image_hash_status();
+        assert_eq!(h.request(status).queued, 3);
+    }
+
+    #[test]
+    fn image_hash_status_cleared() {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+
+        // Only one of these has a hash, so the other fails
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let status = h.client.image_hash_status();
+        let status = h.request(status);
+        assert_eq!(status.queued, 0);
+        assert!(status.pending.is_empty());
+    }
+
+    #[test]
+    fn clear_deleted_boss_images() {
+        let checker = MockChecker::default();
+        let mut h = Harness::with_builder({
+            let checker = checker.clone();
+            move |b| b.with_image_checker(checker, Duration::from_secs(3600))
+        });
+        let boss_name = "Lvl 60 Ozorotter";
+        let (a, b) = ("http://example.com/a.png", "http://example.com/b.png");
+        h.set_image_hash(boss_name, 1);
+
+        let (_subscription, recorder) = h.subscribe();
+        h.push(raid_info_with_image(boss_name, 1, 0, a));
+
+        // Images aren't checked until they're stale, and only by tweets
+        // without an image
+        h.push(raid_info(boss_name, 2, 1800));
+        h.push(raid_info_with_image(boss_name, 3, 3600, b));
+        assert!(checker.checked.borrow().is_empty());
+
+        // Once checked, the image isn't checked again until it's stale again
+        h.push(raid_info(boss_name, 4, 3600));
+        h.push(raid_info(boss_name, 5, 5400));
+        assert_eq!(*checker.checked.borrow(), vec![a]);
+        recorder.take();
+
+        checker.gone.borrow_mut().insert(a.into());
+        h.push(raid_info(boss_name, 6, 7200));
+        assert_eq!(checker.checked.borrow().len(), 2);
+        match recorder.take().as_slice() {
+            &[Recorded::BossUpdate(ref boss)] => assert_eq!(boss.image, None),
+            other => panic!("expected boss update, got {:?}", other),
+        }
+
+        // The image hash is kept, and the next tweeted image is taken
+        let metadata = h.client.export_metadata();
+        assert_eq!(h.request(metadata)[0].image_hash, Some(ImageHash::from(1)));
+
+        h.push(raid_info_with_image(boss_name, 7, 7200, b));
+        let bosses = h.client.bosses();
+        assert_eq!(h.request(bosses)[0].image, Some(b.into()));
+        assert_eq!(h.hasher.requests.get(), 2);
+    }
+
+    #[test]
+    fn count_inconclusive_image_checks() {
+        let checker = MockChecker::default();
+        let mut h = Harness::with_json_metrics_and_builder({
+            let checker = checker.clone();
+            move |b| b.with_image_checker(checker, Duration::from_secs(3600))
+        });
+        let boss_name = "Lvl 60 Ozorotter";
+        let image_url = "http://example.com/a.png";
+        h.set_image_hash(boss_name, 1);
+        checker.failing.borrow_mut().insert(image_url.into());
+
+        let (_subscription, recorder) = h.subscribe();
+        h.push(raid_info_with_image(boss_name, 1, 0, image_url));
+        recorder.take();
+
+        // The image is kept if the check fails
+        h.push(raid_info(boss_name, 2, 3600));
+        assert_eq!(checker.checked.borrow().len(), 1);
+        assert_eq!(recorder.take(), vec![]);
+
+        let metrics = h.metrics();
+        let counters = &metrics["boss_counts"][boss_name]["counters"];
+        assert_eq!(counters["inconclusive_image_checks"], 1);
+    }
+
+    #[test]
+    fn bump_boss_list_revision() {
+        let checker = MockChecker::default();
+        let mut h = Harness::with_builder({
+            let checker = checker.clone();
+            move |b| b.with_image_checker(checker, Duration::from_secs(3600))
+        });
+        let (ja, en) = ("Lv60 オオゾラッコ", "Lvl 60 Ozorotter");
+        let image_url = "http://example.com/a.png";
+        h.set_image_hash(ja, 1);
+        h.set_image_hash(en, 2);
+
+        let revision = |h: &mut Harness| {
+            let revision = h.client.boss_list_revision();
+            h.request(revision)
+        };
+        assert_eq!(revision(&mut h), 0);
+
+        // Created
+        h.push(raid_info(ja, 1, 0));
+        assert_eq!(revision(&mut h), 1);
+
+        // Image set, and the image hash doesn't match any other boss
+        h.push(raid_info_with_image(ja, 2, 0, image_url));
+        assert_eq!(revision(&mut h), 2);
+
+        // Tweets that don't change the boss
+        h.push_all(vec![raid_info(ja, 3, 1), raid_info_with_image(ja, 4, 1, image_url)]);
+        assert_eq!(revision(&mut h), 2);
+
+        // Image cleared after it was deleted
+        checker.gone.borrow_mut().insert(image_url.into());
+        h.push(raid_info(ja, 5, 3600));
+        assert_eq!(revision(&mut h), 3);
+
+        // Translation linked
+        h.push(raid_info(en, 6, 3600));
+        assert_eq!(revision(&mut h), 4);
+        h.client.confirm_translation(ja, en);
+        assert_eq!(revision(&mut h), 5);
+
+        // Muted, but only if it wasn't already
+        for _ in 0..2 {
+            let muted = h.client.set_boss_muted(en, true);
+            assert!(h.request(muted));
+        }
+        assert_eq!(revision(&mut h), 6);
+
+        // Removed
+        h.client.remove_bosses(move |metadata| metadata.boss.name == BossName::from(en));
+        assert_eq!(revision(&mut h), 7);
+
+        let bosses = h.client.bosses_versioned();
+        let (revision, bosses) = h.request(bosses);
+        assert_eq!(revision, 7);
+        assert_eq!(
+            bosses.iter().map(|b| b.name.clone()).collect::<Vec<_>>(),
+            vec![BossName::from(ja)]
+        );
+    }
+
+    #[test]
+    fn boss_activity() {
+        let mut h = Harness::with_builder(|b| b.with_history_size(3));
+
+        let activity = |h: &mut Harness, name: &str| {
+            let activity = h.client.boss_activity(name);
+            h.request(activity)
+        };
+
+        assert_eq!(activity(&mut h, "Lv60 オオゾラッコ"), None);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        assert_eq!(
+            activity(&mut h, "Lv60 オオゾラッコ"),
+            Some(ActivityStats {
+                tweets: 1,
+                average_interval_secs: None,
+                unique_recent_users: 1,
+            })
+        );
+
+        // Out of order, with gaps of 10 and 20 seconds
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 30));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 10));
+        assert_eq!(
+            activity(&mut h, "Lv60 オオゾラッコ"),
+            Some(ActivityStats {
+                tweets: 3,
+                average_interval_secs: Some(15.0),
+                unique_recent_users: 1,
+            })
+        );
+
+        // The oldest tweet falls out of the history
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 35));
+        assert_eq!(
+            activity(&mut h, "Lv60 オオゾラッコ"),
+            Some(ActivityStats {
+                tweets: 3,
+                average_interval_secs: Some(12.5),
+                unique_recent_users: 1,
+            })
+        );
+    }
+
+    fn from_user(user: &str, tweet_id: TweetId) -> RaidInfo {
+        let mut info = raid_info("Lv60 オオゾラッコ", tweet_id, 0);
+        info.tweet.user = user.into();
+        info
+    }
+
+    #[test]
+    fn unique_recent_users() {
+        let mut h = Harness::with_json_metrics();
+
+        h.push_all(vec![
+            from_user("walfieee", 1),
+            from_user("spammer", 2),
+            from_user("spammer", 3),
+            from_user("walfieee", 4),
+            from_user("spammer", 5),
+        ]);
+
+        let activity = h.client.boss_activity("Lv60 オオゾラッコ");
+        let activity = h.request(activity).expect("boss not found");
+        assert_eq!(activity.tweets, 5);
+        assert_eq!(activity.unique_recent_users, 2);
+
+        let metrics = h.metrics();
+        let gauges = &metrics["boss_counts"]["Lv60 オオゾラッコ"]["gauges"];
+        assert_eq!(gauges["unique_users"], 2);
+    }
+
+    #[test]
+    fn unique_recent_users_eviction() {
+        let mut h = Harness::with_builder(|b| b.with_unique_user_capacity(1));
+
+        let unique_users = |h: &mut Harness| {
+            let activity = h.client.boss_activity("Lv60 オオゾラッコ");
+            h.request(activity).unwrap().unique_recent_users
+        };
+
+        h.push(from_user("walfieee", 1));
+        assert_eq!(unique_users(&mut h), 1);
+        h.push(from_user("spammer", 2));
+        assert_eq!(unique_users(&mut h), 1);
+        h.push(from_user("walfieee", 3));
+        assert_eq!(unique_users(&mut h), 1);
+
+        let mut users = RecentUsers::with_capacity(2);
+        assert_eq!(users.insert("a"), 1);
+        assert_eq!(users.insert("b"), 2);
+        assert_eq!(users.insert("a"), 2);
+        // "a" was added first, so it's evicted despite tweeting again
+        assert_eq!(users.insert("c"), 2);
+        assert_eq!(users.insert("a"), 2);
+        assert!(!users.users.contains("b"));
+    }
+
+    #[test]
+    fn stale_bosses() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| b.with_clock(clock.clone()));
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 600));
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 900));
+        clock.advance(Duration::from_secs(900));
+
+        let stale = |h: &mut Harness, secs| {
+            let stale = h.client.stale_bosses(Duration::from_secs(secs));
+            let mut names = h.request(stale);
+            names.sort_by_key(|name| name.to_string());
+            names
+        };
+
+        assert_eq!(
+            stale(&mut h, 60),
+            vec![
+                BossName::from("Lv75 スーペルヒガンテ"),
+                BossName::from("Lvl 60 Ozorotter"),
+            ]
+        );
+        assert_eq!(stale(&mut h, 300), vec![BossName::from("Lvl 60 Ozorotter")]);
+        assert!(stale(&mut h, 900).is_empty());
+    }
+
+    // Handles exactly one event, unlike `Harness::run`
+    fn handle_next_event(h: &mut Harness) {
+        let worker = &mut h.worker;
+        let polled = future::lazy(|| Ok::<_, ()>(worker.events.poll())).wait().unwrap();
+
+        match polled {
+            Ok(Async::Ready(Some(event))) => worker.handle_event(event),
+            _ => panic!("expected an event"),
+        }
+    }
+
+    #[test]
+    fn export_metadata_stream() {
+        let boss_count = METADATA_EXPORT_BATCH_SIZE * 2 + 1;
+        let boss_names = (0..boss_count)
+            .map(|i| BossName::from(format!("Lv{} Boss", i)))
+            .collect::<Vec<_>>();
+
+        let mut h = Harness::new();
+        h.push_all(
+            boss_names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| raid_info(name.as_str(), i as TweetId, 0)),
+        );
+
+        let (mut subscription, _recorder) = h.subscribe();
+        let mut export = h.client.export_metadata_stream().map(|metadata| metadata.boss.name);
+        subscription.follow(boss_names[0].clone());
+
+        // The follow is handled after the first batch, instead of waiting
+        // for the whole export
+        handle_next_event(&mut h);
+        handle_next_event(&mut h);
+        assert_eq!(h.worker.bosses[&boss_names[0]].broadcast.subscriber_count(), 1);
+
+        let mut exported = future::lazy(|| {
+            let mut names = Vec::new();
+            while let Ok(Async::Ready(Some(name))) = export.poll() {
+                names.push(name);
+            }
+            Ok::<_, ()>(names)
+        }).wait()
+            .unwrap();
+        assert_eq!(exported.len(), METADATA_EXPORT_BATCH_SIZE);
+
+        h.run();
+        exported.extend(export.wait().map(Result::unwrap));
+        exported.sort_by_key(|name| name.to_string());
+
+        let mut expected = boss_names.clone();
+        expected.sort_by_key(|name| name.to_string());
+        assert_eq!(exported, expected);
+    }
+
+    #[test]
+    fn mute_boss() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        let (mut reliable, reliable_recorder) = h.subscribe();
+        reliable.follow_reliable("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+        reliable_recorder.take();
+
+        let muted = h.client.set_boss_muted("Lv60 オオゾラッコ", true);
+        assert!(h.request(muted));
+        match recorder.take().as_slice() {
+            &[Recorded::BossUpdate(ref boss)] => assert!(boss.muted),
+            other => panic!("expected boss update, got {:?}", other),
+        }
+
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 10));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 20));
+        assert!(recorder.take().is_empty());
+        assert!(sequenced(&reliable_recorder.take()).is_empty());
+
+        let tweets = h.client.tweets("Lv60 オオゾラッコ");
+        assert_eq!(h.request(tweets).len(), 3);
+
+        // Muted state is kept in exported metadata
+        let metadata = h.client.export_metadata();
+        let metadata = h.request(metadata);
+        assert!(metadata[0].boss.muted);
+        assert_eq!(metadata[0].last_seen, Utc.timestamp(20, 0));
+
+        let (_, imported) = Harness::with_builder(|b| b.with_bosses(metadata))
+            .worker
+            .bosses
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(imported.boss_data.boss.muted);
+
+        let unmuted = h.client.set_boss_muted("Lv60 オオゾラッコ", false);
+        assert!(h.request(unmuted));
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 30));
+        assert_eq!(tweet_ids(&recorder.take()), vec![4]);
+        assert_eq!(sequenced(&reliable_recorder.take()), vec![(0, 4)]);
+
+        let missing = h.client.set_boss_muted("Lv100 Unknown", true);
+        assert!(!h.request(missing));
+    }
+
+    #[test]
+    fn subscribe_with_filter() {
+        let mut h = Harness::new();
+        let (mut tweet_subscription, tweet_recorder) =
+            h.subscribe_with_filter(MessageFilter::only(&[MessageKind::Tweet]));
+        let (mut boss_subscription, boss_recorder) =
+            h.subscribe_with_filter(MessageFilter::only(&[MessageKind::BossUpdate]));
+        tweet_subscription.follow("Lv60 オオゾラッコ");
+        boss_subscription.follow("Lv60 オオゾラッコ");
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        match tweet_recorder.take().as_slice() {
+            &[Recorded::Tweet(ref tweet)] => assert_eq!(tweet.tweet_id, 1),
+            other => panic!("expected tweet, got {:?}", other),
+        }
+        match boss_recorder.take().as_slice() {
+            &[Recorded::BossUpdate(ref boss)] => assert_eq!(boss.name, "Lv60 オオゾラッコ".into()),
+            other => panic!("expected boss update, got {:?}", other),
+        }
+
+        // Responses to the subscriber's own requests are filtered too
+        tweet_subscription.get_bosses();
+        boss_subscription.get_bosses();
+        h.run();
+        assert!(tweet_recorder.take().is_empty());
+        assert!(boss_recorder.take().is_empty());
+    }
+
+    fn drain(stream: &mut MessageStream) -> Option<Vec<OwnedMessage>> {
+        let mut messages = Vec::new();
+
+        let ended = future::lazy(|| loop {
+            match stream.poll()? {
+                Async::Ready(Some(message)) => messages.push(message),
+                Async::Ready(None) => return Ok(true),
+                Async::NotReady => return Ok::<_, ::error::Error>(false),
+            }
+        }).wait()
+            .unwrap();
+
+        if ended && messages.is_empty() {
+            None
+        } else {
+            Some(messages)
+        }
+    }
+
+    #[test]
+    fn message_stream() {
+        let mut h = Harness::new();
+        let (subscription, mut stream) = h.client.message_stream(MessageFilter::all());
+        let mut subscription = h.request(subscription);
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        h.push_all(vec![
+            raid_info("Lvl 60 Ozorotter", 1, 0),
+            raid_info("Lvl 60 Ozorotter", 2, 0),
+            raid_info("Lv75 スーペルヒガンテ", 3, 0),
+            raid_info("Lvl 60 Ozorotter", 4, 0),
+        ]);
+
+        let messages = drain(&mut stream).unwrap();
+        let summary = messages
+            .iter()
+            .map(|message| match *message {
+                OwnedMessage::Tweet(ref t) => format!("tweet {}", t.tweet_id),
+                OwnedMessage::BossUpdate(ref b) => format!("boss {}", b.name),
+                ref other => panic!("unexpected message {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                "boss Lvl 60 Ozorotter",
+                "tweet 1",
+                "tweet 2",
+                "boss Lv75 スーペルヒガンテ",
+                "tweet 4",
+            ]
+        );
+
+        // The mapper is bypassed, including for cached messages
+        h.client.heartbeat();
+        subscription.get_bosses();
+        h.run();
+        match drain(&mut stream).unwrap().as_slice() {
+            &[OwnedMessage::Heartbeat, OwnedMessage::BossList(ref bosses)] => {
+                assert_eq!(bosses.len(), 2);
+            }
+            other => panic!("unexpected messages {:?}", other),
+        }
+        assert!(!stream.is_desynced());
+
+        // The stream ends once the subscription is dropped
+        drop(subscription);
+        h.run();
+        assert_eq!(drain(&mut stream), None);
+    }
+
+    #[test]
+    fn message_stream_drops_oldest() {
+        let mut h = Harness::new();
+        let (subscription, mut stream) =
+            h.client.message_stream(MessageFilter::only(&[MessageKind::Tweet]));
+        let mut subscription = h.request(subscription);
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        let count = MESSAGE_STREAM_CAPACITY as TweetId + 10;
+        h.push_all((1..=count).map(|id| raid_info("Lvl 60 Ozorotter", id, 0)));
+
+        let ids = tweet_ids_from(&drain(&mut stream).unwrap());
+        assert_eq!(ids.len(), MESSAGE_STREAM_CAPACITY);
+        assert_eq!(ids.first(), Some(&11));
+        assert_eq!(ids.last(), Some(&count));
+        assert!(stream.is_desynced());
+
+        // Once the stream is dropped, the subscriber is removed
+        drop(stream);
+        h.push(raid_info("Lvl 60 Ozorotter", count + 1, 0));
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 0);
+    }
+
+    fn tweet_ids_from(messages: &[OwnedMessage]) -> Vec<TweetId> {
+        messages
+            .iter()
+            .filter_map(|message| match *message {
+                OwnedMessage::Tweet(ref t) => Some(t.tweet_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn broadcast_notice() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lvl 60 Ozorotter", 1, 0));
+
+        let (_global, global_recorder) = h.subscribe();
+        let (mut follower, follower_recorder) = h.subscribe();
+        follower.follow("Lvl 60 Ozorotter");
+        follower.follow("Lv60 オオゾラッコ");
+        h.run();
+        global_recorder.take();
+        follower_recorder.take();
+
+        h.client.broadcast_notice("Maintenance in 10 minutes");
+        h.run();
+
+        let notice = Recorded::SystemNotice("Maintenance in 10 minutes".into());
+        assert_eq!(global_recorder.take(), vec![notice.clone()]);
+        assert_eq!(follower_recorder.take(), vec![notice]);
+
+        // Muted subscribers don't get notices either
+        follower.set_muted(true);
+        h.client.broadcast_notice("Maintenance starting");
+        h.run();
+        assert_eq!(global_recorder.take().len(), 1);
+        assert!(follower_recorder.take().is_empty());
+    }
+
+    #[test]
+    fn server_closing() {
+        let mut h = Harness::new();
+        let (_subscription, recorder) = h.subscribe();
+        recorder.take();
+
+        h.end_stream();
+        let worker = &mut h.worker;
+        let polled = future::lazy(|| Ok::<_, ()>(worker.poll())).wait().unwrap();
+        assert!(polled.is_err());
+        assert_eq!(recorder.take(), vec![Recorded::ServerClosing]);
+    }
+
+    #[test]
+    fn mute_subscriber() {
+        let mut h = Harness::new();
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        recorder.take();
+
+        subscription.set_muted(true);
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 10));
+        h.push(raid_info("Lvl 60 Ozorotter", 3, 10));
+        subscription.get_bosses();
+        h.client.heartbeat();
+        h.run();
+        assert!(recorder.take().is_empty());
+
+        subscription.set_muted(false);
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 20));
+        h.push(raid_info("Lvl 60 Ozorotter", 5, 20));
+        assert_eq!(tweet_ids(&recorder.take()), vec![4, 5]);
+    }
+
+    #[test]
+    fn mute_boss_with_translations() {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        let muted = h.client.set_boss_muted("Lv60 オオゾラッコ", true);
+        assert!(h.request(muted));
+        recorder.take();
+
+        // Tweets for the muted boss aren't sent to followers of its translation
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 4, 0));
+        assert_eq!(tweet_ids(&recorder.take()), vec![4]);
+    }
+
+    #[test]
+    fn first_seen() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 10));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 20));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 30));
+
+        let metadata = h.client.export_metadata();
+        let metadata = h.request(metadata);
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].first_seen, Utc.timestamp(10, 0));
+        assert_eq!(metadata[0].last_seen, Utc.timestamp(30, 0));
+    }
+
+    #[test]
+    fn keyed_subscriptions() {
+        let mut h = Harness::new();
+        let recorder1 = Recorder::new();
+        let recorder2 = Recorder::new();
+
+        let subscription = h.client.subscribe_keyed(7, recorder1.clone());
+        let mut subscription1 = h.request(subscription);
+        subscription1.follow("Lv60 オオゾラッコ");
+        h.run();
+
+        let subscription = h.client.subscribe_keyed(7, recorder2.clone());
+        let mut subscription2 = h.request(subscription);
+        subscription2.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder1.take();
+        recorder2.take();
+
+        // The first subscription was removed, and its ID recycled
+        assert_eq!(subscription2.id.value(), subscription1.id.value());
+        assert_ne!(subscription2.id, subscription1.id);
+        assert_eq!(h.worker.subscribers.subscriber_count(), 1);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        assert!(recorder1.take().is_empty());
+        assert_eq!(tweet_ids(&recorder2.take()), vec![1]);
+
+        // Dropping the replaced subscription doesn't affect the new one
+        drop(subscription1);
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 1));
+        assert_eq!(tweet_ids(&recorder2.take()), vec![2]);
+
+        drop(subscription2);
+        h.run();
+        assert!(h.worker.keyed_subscribers.is_empty());
+    }
+
+    #[test]
+    fn user_blocklist() {
+        let blocklist = vec!["SpamBot".to_string()].into_iter().collect();
+        let mut h = Harness::with_builder(|b| b.with_user_blocklist(blocklist));
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push(from_user("spambot", 1));
+        h.push(from_user("SPAMBOT", 2));
+        assert!(recorder.take().is_empty());
+        assert!(h.worker.bosses.is_empty());
+
+        h.push(from_user("walfieee", 3));
+        h.push(from_user("SpamBot", 4));
+        h.push(from_user("spambot2", 5));
+        assert_eq!(tweet_ids(&recorder.take()), vec![3, 5]);
+
+        let tweets = h.client.tweets("Lv60 オオゾラッコ");
+        let ids = h.request(tweets)
+            .iter()
+            .map(|t| t.tweet_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 5]);
+    }
+
+    #[test]
+    fn gap_detection() {
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+        let mut h = Harness::with_builder(|b| b.with_gap_detection(10));
+        h.push(raid_info("Lv60 オオゾラッコ", 100, 0));
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push_all(vec![
+            raid_info("Lv60 オオゾラッコ", 105, 1),
+            raid_info("Lv60 オオゾラッコ", 103, 2),
+            raid_info("Lv60 オオゾラッコ", 115, 3),
+            raid_info("Lv60 オオゾラッコ", 200, 4),
+        ]);
+
+        let tweet = |tweet_id, secs| {
+            Recorded::Tweet(raid_info("Lv60 オオゾラッコ", tweet_id, secs).tweet)
+        };
+        let gap = |last_tweet_id, tweet_id| {
+            Recorded::Gap(boss_name.clone(), last_tweet_id, tweet_id)
+        };
+
+        let mut messages = recorder.take();
+        for message in messages.iter_mut() {
+            if let Recorded::Tweet(ref mut t) = *message {
+                t.seq = 0;
+            }
+        }
+
+        // Out of order IDs are flagged, and don't lower the last seen ID
+        assert_eq!(
+            messages,
+            vec![
+                tweet(105, 1),
+                gap(105, 103),
+                tweet(103, 2),
+                tweet(115, 3),
+                gap(115, 200),
+                tweet(200, 4),
+            ]
+        );
+    }
+
+    fn history_ids(history: &TweetStore) -> Vec<TweetId> {
+        history.recent().iter().map(|t| t.tweet_id).collect()
+    }
+
+    #[test]
+    fn custom_tweet_store() {
+        let created = Rc::new(RefCell::new(Vec::new()));
+        let mut h = Harness::with_builder({
+            let created = created.clone();
+            move |b| {
+                b.with_history_size(2).with_tweet_store(move |boss_name, capacity| {
+                    created.borrow_mut().push((boss_name.clone(), capacity));
+                    tweet_store::in_memory(boss_name, capacity)
+                })
+            }
+        });
+
+        h.push_all(vec![
+            raid_info("Lvl 60 Ozorotter", 1, 0),
+            raid_info("Lv60 オオゾラッコ", 2, 1),
+            raid_info("Lvl 60 Ozorotter", 3, 2),
+            raid_info("Lvl 60 Ozorotter", 4, 3),
+        ]);
+
+        assert_eq!(
+            *created.borrow(),
+            vec![
+                (BossName::from("Lvl 60 Ozorotter"), 2),
+                (BossName::from("Lv60 オオゾラッコ"), 2),
+            ]
+        );
+
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        assert_eq!(history_ids(&*h.worker.bosses[&boss_name].recent_tweets), vec![3, 4]);
+    }
+
+    #[test]
+    fn insert_stragglers_into_history() {
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let mut h = Harness::new();
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lvl 60 Ozorotter");
+
+        h.push_all(vec![
+            raid_info("Lvl 60 Ozorotter", 1, 100),
+            raid_info("Lvl 60 Ozorotter", 2, 110),
+            // Within the default threshold of the newest tweet
+            raid_info("Lvl 60 Ozorotter", 3, 105),
+            // Older than the newest tweet by more than the threshold
+            raid_info("Lvl 60 Ozorotter", 4, 90),
+            raid_info("Lvl 60 Ozorotter", 5, 98),
+        ]);
+
+        assert_eq!(history_ids(&*h.worker.bosses[&boss_name].recent_tweets), vec![4, 5, 1, 2, 3]);
+
+        // Without a max tweet age, every tweet is sent as it arrives
+        assert_eq!(tweet_ids(&recorder.take()), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn skip_broadcasting_old_stragglers() {
+        let mut h = Harness::with_builder(|b| {
+            b.with_straggler_threshold(Duration::from_secs(5))
+                .with_max_tweet_age(Duration::from_secs(60))
+        });
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lvl 60 Ozorotter");
+        let (mut reliable, reliable_recorder) = h.subscribe();
+        reliable.follow_reliable("Lvl 60 Ozorotter");
+
+        h.push_all(vec![
+            raid_info("Lvl 60 Ozorotter", 1, 100),
+            raid_info("Lvl 60 Ozorotter", 2, 200),
+            // Inserted into the history, and sent since it's within the max age
+            raid_info("Lvl 60 Ozorotter", 3, 150),
+            // Only inserted into the history
+            raid_info("Lvl 60 Ozorotter", 4, 120),
+            raid_info("Lvl 60 Ozorotter", 5, 50),
+        ]);
+
+        assert_eq!(history_ids(&*h.worker.bosses[&boss_name].recent_tweets), vec![5, 1, 4, 3, 2]);
+        assert_eq!(tweet_ids(&recorder.take()), vec![1, 2, 3]);
+        assert_eq!(
+            sequenced(&reliable_recorder.take()),
+            vec![(0, 1), (1, 2), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn gap_detection_disabled() {
+        let mut h = Harness::new();
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push_all(vec![
+            raid_info("Lv60 オオゾラッコ", 105, 1),
+            raid_info("Lv60 オオゾラッコ", 103, 2),
+            raid_info("Lv60 オオゾラッコ", 1000, 3),
+        ]);
+        assert_eq!(tweet_ids(&recorder.take()), vec![105, 103, 1000]);
+    }
+
+    #[test]
+    fn inject_raid() {
+        let mut h = Harness::with_json_metrics_and_builder(|b| {
+            b.with_boss_confirmation_threshold(3)
+        });
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        // Injected raids don't need their boss to be confirmed
+        let injected = h.client.inject_raid(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.request(injected);
+        let messages = recorder.take();
+        assert_eq!(tweet_ids(&messages), vec![1]);
+
+        let bosses = h.client.bosses();
+        let bosses = h.request(bosses);
+        assert_eq!(bosses.len(), 1);
+        assert_eq!(bosses[0].name, BossName::from("Lv60 オオゾラッコ"));
+
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 1));
+        assert_eq!(tweet_ids(&recorder.take()), vec![2]);
+
+        let tweets = h.client.tweets("Lv60 オオゾラッコ");
+        let sources = h.request(tweets)
+            .iter()
+            .map(|t| (t.tweet_id, t.source))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![(1, TweetSource::Manual), (2, TweetSource::Twitter)]
+        );
+
+        let counters = &h.metrics()["boss_counts"]["Lv60 オオゾラッコ"]["counters"];
+        assert_eq!(counters["tweets"], 2);
+        assert_eq!(counters["injected_tweets"], 1);
+    }
+
+    #[test]
+    fn boss_confirmation_threshold() {
+        let mut h = Harness::with_builder(|b| b.with_boss_confirmation_threshold(3));
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+        recorder.take();
+
+        h.push(raid_info("Lv75 スーペルヒガンテ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        assert!(recorder.take().is_empty());
+        assert!(h.worker.bosses.is_empty());
+
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 5, 0));
+        let messages = recorder.take();
+        assert_eq!(tweet_ids(&messages), vec![4, 5]);
+
+        let updates = messages
+            .iter()
+            .filter(|m| match **m {
+                Recorded::BossUpdate(_) => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(updates, 1);
+
+        let boss_names = h.worker.bosses.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(boss_names, vec![BossName::from("Lv60 オオゾラッコ")]);
+        assert!(
+            h.worker
+                .unconfirmed_bosses
+                .contains_key(&BossName::from("Lv75 スーペルヒガンテ"))
+        );
+    }
+
+    #[test]
+    fn expire_unconfirmed_bosses() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_json_metrics_and_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_boss_confirmation_threshold(2)
+                .with_boss_confirmation_window(Duration::from_secs(30))
+                .with_idle_subscriber_timeout(Duration::from_secs(10))
+        });
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        // Unconfirmed bosses aren't counted in metrics
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        assert!(h.worker.unconfirmed_bosses.contains_key(&boss_name));
+        assert_eq!(h.metrics()["boss_counts"]["Lv60 オオゾラッコ"], serde_json::Value::Null);
+
+        clock.advance(Duration::from_secs(40));
+        h.run();
+        assert!(h.worker.unconfirmed_bosses.is_empty());
+
+        // Sightings start over after the window
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 40));
+        assert!(h.worker.bosses.is_empty());
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 40));
+        assert!(h.worker.bosses.contains_key(&boss_name));
+        assert!(h.worker.unconfirmed_bosses.is_empty());
+
+        let counters = &h.metrics()["boss_counts"]["Lv60 オオゾラッコ"]["counters"];
+        assert_eq!(counters["tweets"], 1);
+    }
+
+    #[test]
+    fn stats_in_heartbeat() {
+        let mut h = Harness::with_builder(|b| b.with_stats_in_heartbeat(true));
+        let (sub1, recorder1) = h.subscribe();
+        let (_sub2, recorder2) = h.subscribe();
+        recorder1.take();
+        recorder2.take();
+
+        h.client.heartbeat();
+        h.run();
+        let expected = vec![
+            Recorded::HeartbeatWithStats {
+                subscribers: 2,
+                bosses: 0,
+            },
+        ];
+        assert_eq!(recorder1.take(), expected);
+        assert_eq!(recorder2.take(), expected);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        sub1.unsubscribe();
+        h.run();
+        recorder1.take();
+        recorder2.take();
+
+        h.client.heartbeat();
+        h.run();
+        assert!(recorder1.take().is_empty());
+        assert_eq!(
+            recorder2.take(),
+            vec![
+                Recorded::HeartbeatWithStats {
+                    subscribers: 1,
+                    bosses: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn heartbeat_splay() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_heartbeat_splay(Duration::from_secs(8))
+        });
+
+        let (subscriptions, recorders): (Vec<_>, Vec<_>) = (0..HEARTBEAT_SPLAY_SLOTS)
+            .map(|_| h.subscribe())
+            .unzip();
+        let heartbeats = |h: &mut Harness| {
+            h.run();
+            recorders
+                .iter()
+                .map(|recorder| recorder.take().len())
+                .collect::<Vec<_>>()
+        };
+
+        // Each subscriber gets the heartbeat on a different tick
+        h.client.heartbeat();
+        assert_eq!(heartbeats(&mut h), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+
+        for slot in 1..HEARTBEAT_SPLAY_SLOTS as usize {
+            clock.advance(Duration::from_secs(1));
+            let mut expected = vec![0; HEARTBEAT_SPLAY_SLOTS as usize];
+            expected[slot] = 1;
+            assert_eq!(heartbeats(&mut h), expected);
+        }
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(heartbeats(&mut h), vec![0; HEARTBEAT_SPLAY_SLOTS as usize]);
+
+        // A new heartbeat finishes sending the previous one first
+        h.client.heartbeat();
+        assert_eq!(heartbeats(&mut h), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(heartbeats(&mut h), vec![0, 1, 1, 0, 0, 0, 0, 0]);
+        h.client.heartbeat();
+        assert_eq!(heartbeats(&mut h), vec![1, 0, 0, 1, 1, 1, 1, 1]);
+
+        drop(subscriptions);
+    }
+
+    #[test]
+    fn stable_subscriber_ids() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_heartbeat_splay(Duration::from_secs(8))
+                .with_id_pool(IdPool::with_start(5).deterministic())
+        });
+
+        let (first, _) = h.subscribe();
+        drop(first);
+        h.run();
+
+        // The first subscriber's ID isn't recycled
+        let (second, recorder) = h.subscribe();
+        assert_eq!(second.id.value(), 6);
+        assert_eq!(serde_json::to_string(&second.id).unwrap(), "6");
+
+        // So which heartbeat slot the subscriber is in is known ahead of time
+        h.client.heartbeat();
+        for _ in 0..6 {
+            h.run();
+            assert!(recorder.take().is_empty());
+            clock.advance(Duration::from_secs(1));
+        }
+
+        h.run();
+        assert_eq!(recorder.take(), vec![Recorded::Heartbeat]);
+    }
+
+    #[test]
+    fn bosses_by_language() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+
+        let mut boss_names = |language| {
+            let bosses = h.client.bosses_by_language(language);
+            let mut names = h.request(bosses)
+                .into_iter()
+                .map(|b| b.name.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(
+            boss_names(Language::Japanese),
+            vec!["Lv60 オオゾラッコ", "Lv75 スーペルヒガンテ"]
+        );
+        assert_eq!(boss_names(Language::English), vec!["Lvl 60 Ozorotter"]);
+        assert!(boss_names(Language::Other).is_empty());
+    }
+
+    #[test]
+    fn pending_follows() {
+        let mut h = Harness::new();
+        let (mut sub1, _recorder1) = h.subscribe();
+        let (mut sub2, _recorder2) = h.subscribe();
+
+        sub1.follow("Lvl 100 Typo");
+        sub2.follow("Lvl 100 Typo");
+        sub2.follow("Lv60 オオゾラッコ");
+        let pending = h.client.pending_follows();
+        let mut pending = h.request(pending);
+        pending.sort_by_key(|&(ref name, _)| name.to_string());
+        assert_eq!(
+            pending,
+            vec![
+                (BossName::from("Lv60 オオゾラッコ"), 1),
+                (BossName::from("Lvl 100 Typo"), 2),
+            ]
+        );
+
+        // Unsubscribing without unfollowing first
+        h.client.subscriber_unsubscribe(sub2.id.clone());
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        let pending = h.client.pending_follows();
+        assert_eq!(
+            h.request(pending),
+            vec![(BossName::from("Lvl 100 Typo"), 1)]
+        );
+
+        sub1.unfollow("Lvl 100 Typo");
+        let pending = h.client.pending_follows();
+        assert!(h.request(pending).is_empty());
+    }
+
+    fn follow_count<M: Metrics>(h: &mut Harness<M>, id: &SubId) -> usize {
+        let count = h.client.subscriber_follow_count(id);
+        h.request(count)
+    }
+
+    #[test]
+    fn subscriber_follow_count() {
+        let mut h = harness_with_removed_translation();
+        let (mut subscription, _recorder) = h.subscribe();
+        let (other, _other_recorder) = h.subscribe();
+        assert_eq!(follow_count(&mut h, subscription.id()), 0);
+
+        // The translated name is followed as a pending follow too, but it's
+        // the same boss
+        subscription.follow("Lvl 60 Ozorotter");
+        subscription.follow("Lvl 100 Typo");
+        assert_eq!(follow_count(&mut h, subscription.id()), 2);
+        assert_eq!(follow_count(&mut h, other.id()), 0);
+
+        subscription.unfollow("Lvl 100 Typo");
+        assert_eq!(follow_count(&mut h, subscription.id()), 1);
+
+        let id = subscription.id().clone();
+        subscription.unsubscribe();
+        assert_eq!(follow_count(&mut h, &id), 0);
+    }
+
+    // Records each follower count update
+    #[derive(Clone, Debug, Default)]
+    struct FollowerCounts(Rc<RefCell<Vec<(BossName, u32)>>>);
+
+    impl FollowerCounts {
+        fn take(&self) -> Vec<(BossName, u32)> {
+            let mut counts = self.0.replace(Vec::new());
+            counts.sort_by_key(|&(ref name, _)| name.to_string());
+            counts
+        }
+    }
+
+    impl Metrics for FollowerCounts {
+        type Export = ();
+
+        fn set_total_subscriber_count(&mut self, _count: u32) {}
+        fn set_follower_count(&mut self, boss_name: &BossName, count: u32) {
+            self.0.borrow_mut().push((boss_name.clone(), count));
+        }
+        fn inc_tweet_count(&mut self, _boss_name: &BossName, _language: Language) {}
+        fn remove_boss(&mut self, _boss_name: &BossName) {}
+        fn export(&self) {}
+    }
+
+    #[test]
+    fn follow_many() {
+        let counts = FollowerCounts::default();
+        let (builder, hasher, raids) = builder();
+        let mut h = Harness::from_builder(builder.with_metrics(counts.clone()), hasher, raids);
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 2, 0));
+        let (mut subscription, recorder) = h.subscribe();
+        let id = subscription.id().clone();
+
+        let existing = vec![
+            BossName::from("Lv60 オオゾラッコ"),
+            BossName::from("Lv75 スーペルヒガンテ"),
+        ];
+        let pending = vec![
+            BossName::from("Lvl 100 A"),
+            BossName::from("Lvl 100 B"),
+            BossName::from("Lvl 100 C"),
+        ];
+        let requested = |h: &Harness<FollowerCounts>| {
+            let mut names = h.worker.requested_bosses.keys().cloned().collect::<Vec<_>>();
+            names.sort_by_key(|name| name.to_string());
+            names
+        };
+
+        // The repeated name doesn't update the follower count again
+        let mut names = existing.iter().chain(&pending).cloned().collect::<Vec<_>>();
+        names.push(existing[0].clone());
+        subscription.follow_many(names);
+        h.run();
+
+        for name in &existing {
+            assert!(h.worker.bosses[name].broadcast.contains(&id));
+        }
+        assert_eq!(requested(&h), pending);
+        assert_eq!(
+            counts.take(),
+            vec![(existing[0].clone(), 1), (existing[1].clone(), 1)]
+        );
+
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+        assert_eq!(tweet_ids(&recorder.take()), vec![3]);
+
+        subscription.unfollow_many(existing.iter().chain(&pending).cloned());
+        h.run();
+
+        for name in &existing {
+            assert!(!h.worker.bosses[name].broadcast.contains(&id));
+        }
+        assert!(requested(&h).is_empty());
+        assert_eq!(
+            counts.take(),
+            vec![(existing[0].clone(), 0), (existing[1].clone(), 0)]
+        );
+    }
+
+    #[test]
+    fn sorted_tweets() {
+        let mut h = Harness::new();
+
+        // Tweet IDs disagree with timestamps
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 30));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 10));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 20));
+
+        let mut sorted_ids = |order| {
+            let tweets = h.client.sorted_tweets("Lv60 オオゾラッコ", order);
+            h.request(tweets)
+                .iter()
+                .map(|t| t.tweet_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted_ids(TweetOrder::CreatedAt), vec![2, 3, 1]);
+        assert_eq!(sorted_ids(TweetOrder::TweetId), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn tweets_many() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+
+        let (ja, en) = (BossName::from("Lv60 オオゾラッコ"), BossName::from("Lvl 60 Ozorotter"));
+        let unknown = BossName::from("Lv75 スーペルヒガンテ");
+        let tweets = h.client
+            .tweets_many(vec![ja.clone(), en.clone(), unknown.clone()]);
+        let tweets = h.request(tweets);
+
+        let ids = |name| {
+            let mut ids = tweets[name].iter().map(|t| t.tweet_id).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(tweets.len(), 3);
+        assert_eq!(ids(&ja), vec![1, 3]);
+        assert_eq!(ids(&en), vec![2]);
+        assert!(tweets[&unknown].is_empty());
+    }
+
+    #[test]
+    fn tweets_since_seq() {
+        let mut h = Harness::new();
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+
+        // Tweet IDs are out of order, but sequence numbers aren't
+        for &(boss_name, id) in &[
+            ("Lv60 オオゾラッコ", 5),
+            ("Lvl 60 Ozorotter", 9),
+            ("Lv60 オオゾラッコ", 2),
+            ("Lv60 オオゾラッコ", 7),
+        ] {
+            h.push(raid_info(boss_name, id, 0));
+        }
+
+        let seqs = recorder
+            .take()
+            .into_iter()
+            .filter_map(|m| match m {
+                Recorded::Tweet(tweet) => Some(tweet.seq),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 3, 4]);
+
+        let tweets = h.client.tweets_since_seq("Lv60 オオゾラッコ", 1);
+        let tweets = h.request(tweets);
+        let ids = tweets.iter().map(|t| (t.seq, t.tweet_id)).collect::<Vec<_>>();
+        assert_eq!(ids, vec![(3, 2), (4, 7)]);
+
+        let tweets = h.client.tweets_since_seq("Lv60 オオゾラッコ", 4);
+        assert!(h.request(tweets).is_empty());
+
+        let tweets = h.client.tweets_since_seq("Lvl 60 Ozorotter", 0);
+        assert_eq!(h.request(tweets)[0].seq, 2);
+    }
+
+    #[test]
+    fn raid_ids() {
+        let mut h = Harness::with_builder(|b| b.with_history_size(3));
+
+        for id in 1..5 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, id as i64));
+        }
+
+        let raid_ids = h.client.raid_ids("Lv60 オオゾラッコ");
+        assert_eq!(
+            h.request(raid_ids),
+            vec!["00000004", "00000003", "00000002"]
+        );
+
+        let tweets = h.client.sorted_tweets("Lv60 オオゾラッコ", TweetOrder::TweetId);
+        let mut expected = h.request(tweets)
+            .iter()
+            .map(|t| t.raid_id.clone())
+            .collect::<Vec<_>>();
+        expected.reverse();
+
+        let raid_ids = h.client.raid_ids("Lv60 オオゾラッコ");
+        assert_eq!(h.request(raid_ids), expected);
+
+        let raid_ids = h.client.raid_ids("Lv100 Unknown");
+        assert!(h.request(raid_ids).is_empty());
+    }
+
+    // Removes bosses last seen before `cutoff`, but only if all of their
+    // translations were also last seen before then
+    fn remove_stale_with_translations(
+        cutoff: i64,
+    ) -> impl Fn(&[RaidBossMetadata]) -> Vec<BossName> {
+        move |bosses| {
+            let is_stale = |name: &BossName| {
+                bosses
+                    .iter()
+                    .find(|meta| &meta.boss.name == name)
+                    .map_or(true, |meta| meta.last_seen.timestamp() < cutoff)
+            };
+
+            bosses
+                .iter()
+                .filter(|meta| {
+                    is_stale(&meta.boss.name) && meta.boss.translations.iter().all(&is_stale)
+                })
+                .map(|meta| meta.boss.name.clone())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn remove_bosses_planned() {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 100, "http://example.com/b.png"));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+        h.run();
+
+        let boss_names = |h: &mut Harness| {
+            let bosses = h.client.bosses();
+            let mut names = h.request(bosses)
+                .into_iter()
+                .map(|b| b.name.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        // The Japanese boss is stale, but its translation isn't
+        h.client.remove_bosses_planned(remove_stale_with_translations(50));
+        assert_eq!(
+            boss_names(&mut h),
+            vec!["Lv60 オオゾラッコ", "Lvl 60 Ozorotter"]
+        );
+
+        h.client.remove_bosses_planned(remove_stale_with_translations(200));
+        assert!(boss_names(&mut h).is_empty());
+
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).buffered_tweets, 0);
+    }
+
+    #[test]
+    fn strip_translation_links_of_removed_bosses() {
+        let mut h = Harness::new();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+        assert!(h.worker.bosses[&ja].boss_data.boss.translations.contains(&en));
+
+        let (_subscription, recorder) = h.subscribe();
+        recorder.take();
+
+        h.client
+            .remove_bosses(|metadata| metadata.boss.name == BossName::from("Lvl 60 Ozorotter"));
+        h.run();
+
+        let bosses = h.client.bosses();
+        let bosses = h.request(bosses);
+        assert_eq!(bosses.len(), 1);
+        assert!(bosses[0].translations.is_empty());
+
+        match recorder.take().as_slice() {
+            [Recorded::BossUpdate(boss), Recorded::BossRemove(removed)] => {
+                assert_eq!(boss.name, ja);
+                assert!(boss.translations.is_empty());
+                assert_eq!(*removed, en);
+            }
+            messages => panic!("unexpected messages: {:?}", messages),
+        }
+    }
+
+    thread_local! {
+        static MAPPED_TWEETS: Cell<usize> = Cell::new(0);
+    }
+
+    fn count_mapped_tweets(message: Message) -> Option<Recorded> {
+        if let Message::Tweet(_) = message {
+            MAPPED_TWEETS.with(|c| c.set(c.get() + 1));
+        }
+        record(message)
+    }
+
+    fn without_lists(message: Message) -> Option<Recorded> {
+        match message {
+            Message::BossList(_) | Message::TweetList(_) => None,
+            message => record(message),
+        }
+    }
+
+    fn without_lists_or_unsupported(message: Message) -> Option<Recorded> {
+        match message {
+            Message::Unsupported(_) => None,
+            message => without_lists(message),
+        }
+    }
+
+    #[test]
+    fn fall_back_for_unmappable_requests() {
+        let mut h = Harness::with_json_metrics_and_builder(|b| {
+            b.filter_map_message(without_lists as TestMapper)
+                .with_mapper_check(MapperCheck::Ignore)
+        });
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        let (subscription, recorder) = h.subscribe();
+
+        subscription.get_bosses();
+        subscription.get_tweets("Lv60 オオゾラッコ");
+        h.run();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Recorded::Unsupported(MessageKind::BossList),
+                Recorded::Unsupported(MessageKind::TweetList),
+            ]
+        );
+
+        let metrics = h.metrics();
+        assert_eq!(metrics["unmappable_messages"]["BossList"], 1);
+        assert_eq!(metrics["unmappable_messages"]["TweetList"], 1);
+
+        // If `Unsupported` can't be mapped either, a heartbeat is sent
+        let mut h = Harness::with_json_metrics_and_builder(|b| {
+            b.filter_map_message(without_lists_or_unsupported as TestMapper)
+                .with_mapper_check(MapperCheck::Ignore)
+        });
+        let (subscription, recorder) = h.subscribe();
+        subscription.get_bosses();
+        h.run();
+        assert_eq!(recorder.take(), vec![Recorded::Heartbeat]);
+        assert_eq!(h.metrics()["unmappable_messages"]["BossList"], 1);
+    }
+
+    fn tweets_only(message: Message) -> Option<Recorded> {
+        match message {
+            Message::Tweet(_) => record(message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn set_message_mapper() {
+        let (builder, hasher, raids) = builder();
+        let builder = builder
+            .with_dynamic_mapper()
+            .with_mapper_check(MapperCheck::Ignore);
+        let mut h = Harness::from_builder(builder, hasher, raids);
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        subscription.get_bosses();
+        h.client.heartbeat();
+        h.run();
+        let messages = recorder.take();
+        assert_eq!(tweet_ids(&messages), vec![1]);
+        assert!(messages.contains(&Recorded::Heartbeat));
+        assert_eq!(messages.len(), 4);
+
+        // The cached heartbeat and boss list are mapped with the new mapper
+        let replaced = h.client.set_message_mapper(tweets_only);
+        h.request(replaced);
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        subscription.get_bosses();
+        h.client.heartbeat();
+        h.run();
+        let messages = recorder.take();
+        assert_eq!(tweet_ids(&messages), vec![2]);
+        assert_eq!(messages.len(), 1);
+
+        // Mappers can't be replaced without `with_dynamic_mapper`
+        let mut h = Harness::new();
+        let replaced = h.client.set_message_mapper(tweets_only);
+        h.run();
+        assert!(replaced.wait().is_err());
+    }
+
+    thread_local! {
+        static MAPPED_BOSS_LISTS: Cell<usize> = Cell::new(0);
+    }
+
+    fn count_mapped_boss_lists(message: Message) -> Option<Recorded> {
+        if let Message::BossList(_) = message {
+            MAPPED_BOSS_LISTS.with(|c| c.set(c.get() + 1));
+        }
+        record(message)
+    }
+
+    #[test]
+    fn map_boss_list_on_request() {
+        let mut h =
+            Harness::with_builder(|b| b.filter_map_message(count_mapped_boss_lists as TestMapper));
+        // The builder also maps a boss list, to check that it isn't ignored
+        let initial = MAPPED_BOSS_LISTS.with(|c| c.get());
+        let mapped = || MAPPED_BOSS_LISTS.with(|c| c.get()) - initial;
+        let (mut subscription, recorder) = h.subscribe();
+
+        h.push_all((0..1000).map(|i| raid_info(&format!("Lv{} Boss {}", i % 200, i), i, 0)));
+        assert_eq!(h.worker.bosses.len(), 1000);
+        assert_eq!(mapped(), 0);
+
+        subscription.follow("Lv0 Boss 0");
+        subscription.get_bosses();
+        subscription.get_bosses();
+        h.run();
+        assert_eq!(mapped(), 1);
+
+        let boss_lists = recorder
+            .take()
+            .into_iter()
+            .filter_map(|m| match m {
+                Recorded::BossList(bosses) => Some(bosses.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(boss_lists, vec![1000, 1000]);
+
+        // Removed bosses are no longer listed
+        h.client.remove_bosses(|meta| meta.boss.level == 0);
+        subscription.get_bosses();
+        h.run();
+        assert_eq!(mapped(), 2);
+
+        match recorder.take().pop() {
+            Some(Recorded::BossList(bosses)) => assert_eq!(bosses.len(), 995),
+            other => panic!("expected boss list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn skip_mapping_tweets_without_followers() {
+        let mut h =
+            Harness::with_builder(|b| b.filter_map_message(count_mapped_tweets as TestMapper));
+        let mapped = || MAPPED_TWEETS.with(|c| c.get());
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        assert_eq!(mapped(), 0);
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 4, 0));
+        assert_eq!(mapped(), 1);
+    }
+
+    thread_local! {
+        static MAPPED_SHARED_TWEETS: Cell<usize> = Cell::new(0);
+    }
+
+    fn count_mapped_shared_tweets(message: Message) -> Option<Recorded> {
+        if let Message::Tweet(_) = message {
+            MAPPED_SHARED_TWEETS.with(|c| c.set(c.get() + 1));
+        }
+        record(message)
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct SharedRecorder(Rc<RefCell<Vec<Arc<Recorded>>>>);
+
+    impl Subscriber for SharedRecorder {
+        type Item = Arc<Recorded>;
+
+        fn send(&mut self, message: &Arc<Recorded>) -> ::std::result::Result<(), ()> {
+            self.0.borrow_mut().push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn share_mapped_tweets() {
+        let (builder, hasher, raids) = builder();
+        let builder = builder
+            .with_subscriber::<SharedRecorder>()
+            .filter_map_message(protocol::shared(count_mapped_shared_tweets));
+        let mut h = Harness::from_builder(builder, hasher, raids);
+        let mapped = || MAPPED_SHARED_TWEETS.with(|c| c.get());
+
+        h.set_image_hash("Lv60 オオゾラッコ", 0b10);
+        h.set_image_hash("Lvl 60 Ozorotter", 0b10);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+
+        let mut recorders = Vec::new();
+        let mut subscriptions = Vec::new();
+        for boss_name in &["Lv60 オオゾラッコ", "Lvl 60 Ozorotter", "Lv60 オオゾラッコ"] {
+            let recorder = SharedRecorder::default();
+            let subscription = h.client.subscribe(recorder.clone());
+            let mut subscription = h.request(subscription);
+            subscription.follow(*boss_name);
+            recorder.0.borrow_mut().clear();
+            recorders.push(recorder);
+            subscriptions.push(subscription);
+        }
+        h.run();
+
+        // The tweet is mapped once, and every follower (including followers
+        // of the translation) is sent the same item
+        let initial = mapped();
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        assert_eq!(mapped() - initial, 1);
+
+        let received = recorders
+            .iter()
+            .map(|recorder| ::std::mem::replace(&mut *recorder.0.borrow_mut(), Vec::new()))
+            .collect::<Vec<_>>();
+        assert!(received.iter().all(|messages| messages.len() == 1));
+        assert!(Arc::ptr_eq(&received[0][0], &received[1][0]));
+        assert!(Arc::ptr_eq(&received[0][0], &received[2][0]));
+    }
+
+    #[test]
+    fn count_delivered_tweets() {
+        let mut h = Harness::with_json_metrics();
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.run();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+
+        let metrics = h.metrics();
+        let counts = &metrics["boss_counts"];
+        assert_eq!(counts["Lv60 オオゾラッコ"]["counters"]["tweets"], 2);
+        assert_eq!(counts["Lv60 オオゾラッコ"]["counters"]["delivered_tweets"], 2);
+        assert_eq!(counts["Lv75 スーペルヒガンテ"]["counters"]["tweets"], 1);
+        assert_eq!(counts["Lv75 スーペルヒガンテ"]["counters"]["delivered_tweets"], 0);
+    }
+
+    // Records the kind of each event the worker handles
+    #[derive(Clone, Debug, Default)]
+    struct EventKinds(Rc<RefCell<Vec<EventKind>>>);
+
+    impl Metrics for EventKinds {
+        type Export = ();
+
+        fn set_total_subscriber_count(&mut self, _count: u32) {}
+        fn set_follower_count(&mut self, _boss_name: &BossName, _count: u32) {}
+        fn inc_tweet_count(&mut self, _boss_name: &BossName, _language: Language) {}
+        fn remove_boss(&mut self, _boss_name: &BossName) {}
+        fn export(&self) {}
+
+        fn observe_event_duration(&mut self, kind: EventKind, _micros: u64) {
+            self.0.borrow_mut().push(kind);
+        }
+    }
+
+    #[test]
+    fn observe_event_durations() {
+        let kinds = EventKinds::default();
+        let (builder, hasher, raids) = builder();
+        let mut h = Harness::from_builder(builder.with_metrics(kinds.clone()), hasher, raids);
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        let bosses = h.client.bosses();
+        h.request(bosses);
+
+        // Unfollows, then unsubscribes
+        drop(subscription);
+        h.run();
+
+        assert_eq!(
+            *kinds.0.borrow(),
+            vec![
+                EventKind::Subscriber,
+                EventKind::Subscriber,
+                EventKind::Ingest,
+                EventKind::Client,
+                EventKind::Subscriber,
+                EventKind::Subscriber,
+            ]
+        );
+    }
+
+    #[test]
+    fn count_tweets_by_language() {
+        let mut h = Harness::with_json_metrics();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+
+        let metrics = h.metrics();
+        let counts = &metrics["language_counts"];
+        let key = |language: Language| serde_json::to_value(language).unwrap();
+        let count = |language| &counts[key(language).as_str().unwrap()];
+        assert_eq!(count(Language::Japanese), 2);
+        assert_eq!(count(Language::English), 1);
+        assert!(count(Language::Other).is_null());
+    }
+
+    #[test]
+    fn export_metrics_and_reset() {
+        let mut h = Harness::with_json_metrics();
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+
+        let export = h.client.export_metrics_and_reset();
+        let metrics = h.request(export);
+        let counts = &metrics["boss_counts"]["Lv60 オオゾラッコ"];
+        assert_eq!(counts["counters"]["tweets"], 2);
+        assert_eq!(counts["gauges"]["followers"], 1);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+
+        let metrics = h.metrics();
+        let counts = &metrics["boss_counts"]["Lv60 オオゾラッコ"];
+        assert_eq!(counts["counters"]["tweets"], 1);
+        assert_eq!(counts["gauges"]["followers"], 1);
+    }
+
+    #[test]
+    fn evict_failing_subscriber() {
+        let mut h = Harness::with_json_metrics();
+
+        let (mut failing, failing_recorder) = h.subscribe();
+        let (mut other, other_recorder) = h.subscribe();
+        failing.follow("Lv60 オオゾラッコ");
+        other.follow("Lv60 オオゾラッコ");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+
+        failing_recorder.close();
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 1);
+
+        let metrics = h.metrics();
+        assert_eq!(metrics["total_subscriber_count"], 1);
+        assert_eq!(
+            metrics["boss_counts"]["Lv60 オオゾラッコ"]["gauges"]["followers"],
+            1
+        );
+
+        // The evicted subscriber's ID is reused, but the old subscription
+        // can no longer affect the new subscriber
+        let (mut next, next_recorder) = h.subscribe();
+        assert_eq!(next.id.value(), failing.id.value());
+        assert_ne!(next.id, failing.id);
+
+        next.follow("Lv60 オオゾラッコ");
+        drop(failing);
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+
+        assert_eq!(tweet_ids(&next_recorder.take()), vec![3]);
+        assert_eq!(tweet_ids(&other_recorder.take()), vec![1, 2, 3]);
+
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 2);
+    }
+
+    #[test]
+    fn roll_back_undelivered_subscription() {
+        let mut h = Harness::with_json_metrics();
+        let recorder = Recorder::new();
+        drop(h.client.subscribe(recorder.clone()));
+
+        // The subscriber is removed before any later events are handled
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 0);
+        assert_eq!(h.metrics()["total_subscriber_count"], 0);
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        assert!(recorder.take().is_empty());
+
+        // The rolled back ID is recycled
+        let (subscription, _) = h.subscribe();
+        assert_eq!(subscription.id.value(), 0);
+    }
+
+    // Records each message with the subscriber's sequence number, reusing
+    // `Recorded::Sequenced`
+    fn record_seq(seq: u64, message: Message) -> Option<Recorded> {
+        record(message).map(|recorded| Recorded::Sequenced(seq, Box::new(recorded)))
+    }
+
+    // Splits sequenced messages into their sequence numbers and messages
+    fn unsequence(messages: Vec<Recorded>) -> (Vec<u64>, Vec<Recorded>) {
+        messages
+            .into_iter()
+            .map(|message| match message {
+                Recorded::Sequenced(seq, inner) => (seq, *inner),
+                other => panic!("unsequenced message: {:?}", other),
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn sequencing() {
+        let (builder, hasher, raids) = builder();
+        let builder = builder
+            .filter_map_message_seq(record_seq as fn(u64, Message) -> Option<Recorded>)
+            .with_sequencing(true);
+        let mut h = Harness::from_builder(builder, hasher, raids);
+
+        let (mut follower, follower_recorder) = h.subscribe();
+        let (other, other_recorder) = h.subscribe();
+        follower.follow_with_catch_up("Lv60 オオゾラッコ");
+        h.run();
+
+        // Boss updates, tweets, heartbeats, boss lists, and tweet lists
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 2, 0));
+        h.client.heartbeat();
+        h.run();
+        follower.get_bosses();
+        follower.get_tweets("Lv60 オオゾラッコ");
+        h.run();
+        h.push(raid_info("Lvl 60 Ozorotter", 3, 0));
+        other.get_bosses();
+        h.client.heartbeat();
+        h.run();
+
+        let (seqs, messages) = unsequence(follower_recorder.take());
+        assert_eq!(seqs, (0..8).collect::<Vec<_>>());
+        assert_eq!(tweet_ids(&messages), vec![1, 2, 1, 2]);
+
+        // Sequence numbers are independent between subscribers
+        let (seqs, _) = unsequence(other_recorder.take());
+        assert_eq!(seqs, (0..5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sequencing_shared_between_follows() {
+        let (builder, hasher, raids) = builder();
+        let builder = builder
+            .filter_map_message_seq(record_seq as fn(u64, Message) -> Option<Recorded>)
+            .with_sequencing(true);
+        let mut h = Harness::from_builder(builder, hasher, raids);
+
+        // Tweets for different bosses are sent through different broadcasts,
+        // but share the subscriber's sequence
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow("Lv60 オオゾラッコ");
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+        h.push(raid_info("Lvl 60 Ozorotter", 2, 0));
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+
+        let (seqs, messages) = unsequence(recorder.take());
+        assert_eq!(tweet_ids(&messages), vec![1, 2, 3]);
+        assert_eq!(seqs, (0..messages.len() as u64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn evict_stalled_subscribers() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_idle_subscriber_timeout(Duration::from_secs(10))
+        });
+
+        let (_stalled, stalled_recorder) = h.subscribe();
+        let (_recovered, recovered_recorder) = h.subscribe();
+        let (_other, _) = h.subscribe();
+        stalled_recorder.set_stalled(true);
+        recovered_recorder.set_stalled(true);
+
+        clock.advance(Duration::from_secs(10));
+        h.run();
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 3);
+
+        recovered_recorder.set_stalled(false);
+        clock.advance(Duration::from_secs(10));
+        h.run();
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 2);
+
+        // Only subscribers that are stalled on consecutive sweeps are evicted
+        recovered_recorder.set_stalled(true);
+        clock.advance(Duration::from_secs(10));
+        h.run();
+        let stats = h.client.stats();
+        assert_eq!(h.request(stats).subscribers, 2);
+    }
+
+    #[test]
+    fn evict_subscriber_on_master_broadcast_failure() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+
+        let (mut dead, dead_recorder) = h.subscribe();
+        dead.follow("Lv60 オオゾラッコ");
+        dead.follow("Lvl 60 Ozorotter");
+        h.run();
+
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+        assert_eq!(h.worker.bosses[&boss_name].broadcast.subscriber_count(), 1);
+        assert_eq!(h.worker.requested_bosses.len(), 1);
+
+        // Heartbeats are only sent through the master broadcast
+        dead_recorder.close();
+        h.client.heartbeat();
+        h.run();
+
+        assert!(h.worker.subscribers.is_empty());
+        assert_eq!(h.worker.bosses[&boss_name].broadcast.subscriber_count(), 0);
+        assert!(h.worker.requested_bosses.is_empty());
+
+        let (next, _next_recorder) = h.subscribe();
+        assert_eq!(next.id.value(), dead.id.value());
+    }
+
+    fn sequenced(messages: &[Recorded]) -> Vec<(u64, TweetId)> {
+        messages
+            .iter()
+            .filter_map(|m| match *m {
+                Recorded::Sequenced(seq, ref inner) => match **inner {
+                    Recorded::Tweet(ref t) => Some((seq, t.tweet_id)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn redeliver_unacked_tweets_on_resume() {
+        let mut h = Harness::new();
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.follow_reliable("Lv60 オオゾラッコ");
+        h.run();
+
+        for id in 1..4 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+        assert_eq!(sequenced(&recorder.take()), vec![(0, 1), (1, 2), (2, 3)]);
+        subscription.ack(0);
+
+        // Simulate a disconnect. Tweets keep being buffered after the
+        // subscription has been evicted and dropped.
+        recorder.close();
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 0));
+        let resume_token = subscription.resume_token();
+        drop(subscription);
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 5, 0));
+
+        let recorder = Recorder::new();
+        let resumed = h.client
+            .resume_subscription(recorder.clone(), resume_token);
+        let resumed = h.request(resumed);
+
+        assert_eq!(resumed.resume_token(), resume_token);
+        assert!(!resumed.is_desynced());
+        assert_eq!(
+            sequenced(&recorder.take()),
+            vec![(1, 2), (2, 3), (3, 4), (4, 5)]
+        );
+
+        resumed.ack(4);
+        h.push(raid_info("Lv60 オオゾラッコ", 6, 0));
+        assert_eq!(sequenced(&recorder.take()), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn drop_oldest_unacked_tweets_when_full() {
+        let mut h = Harness::with_builder(|b| b.with_unacked_buffer_size(2));
+
+        let (mut subscription, _recorder) = h.subscribe();
+        subscription.follow_reliable("Lv60 オオゾラッコ");
+        let resume_token = subscription.resume_token();
+        drop(subscription);
+        h.run();
+
+        for id in 1..4 {
+            h.push(raid_info("Lv60 オオゾラッコ", id, 0));
+        }
+
+        let recorder = Recorder::new();
+        let resumed = h.client
+            .resume_subscription(recorder.clone(), resume_token);
+        let resumed = h.request(resumed);
+
+        assert!(resumed.is_desynced());
+        assert_eq!(sequenced(&recorder.take()), vec![(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn drop_oldest_detached_sessions() {
+        let mut h = Harness::with_builder(|b| b.with_max_detached_sessions(1));
+
+        let detach = |h: &mut Harness| {
+            let (mut subscription, _recorder) = h.subscribe();
+            subscription.follow_reliable("Lv60 オオゾラッコ");
+            let resume_token = subscription.resume_token();
+            drop(subscription);
+            h.run();
+            resume_token
+        };
+
+        let first = detach(&mut h);
+        let second = detach(&mut h);
+        assert_eq!(h.worker.reliable_sessions.len(), 1);
+
+        let resumed = h.client.resume_subscription(Recorder::new(), second);
+        assert_eq!(h.request(resumed).resume_token(), second);
+
+        let resumed = h.client.resume_subscription(Recorder::new(), first);
+        assert!(h.request(resumed).resume_token() != first);
+    }
+
+    #[test]
+    fn expire_detached_sessions_on_idle_sweep() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let mut h = Harness::with_builder(|b| {
+            b.with_clock(clock.clone())
+                .with_idle_subscriber_timeout(Duration::from_secs(10))
+        });
+
+        let detach = |h: &mut Harness| {
+            let (mut subscription, _recorder) = h.subscribe();
+            subscription.follow_reliable("Lv60 オオゾラッコ");
+            let resume_token = subscription.resume_token();
+            drop(subscription);
+            h.run();
+            resume_token
+        };
+
+        let expired = detach(&mut h);
+        let resumed_in_time = detach(&mut h);
+
+        clock.advance(Duration::from_secs(10));
+        h.run();
+        let resumed = h.client
+            .resume_subscription(Recorder::new(), resumed_in_time);
+        let resumed = h.request(resumed);
+        assert_eq!(resumed.resume_token(), resumed_in_time);
+
+        // Only sessions that are detached on consecutive sweeps are dropped
+        clock.advance(Duration::from_secs(10));
+        h.run();
+        assert_eq!(h.worker.reliable_sessions.len(), 1);
+
+        let resumed = h.client.resume_subscription(Recorder::new(), expired);
+        assert!(h.request(resumed).resume_token() != expired);
+    }
+
+    #[test]
+    fn resume_unknown_subscription() {
+        let mut h = Harness::new();
+
+        let (subscription, _recorder) = h.subscribe();
+        let unknown = ResumeToken::random();
+
+        let resumed = h.client.resume_subscription(Recorder::new(), unknown);
+        let resumed = h.request(resumed);
+
+        assert!(resumed.resume_token() != unknown);
+        assert!(resumed.resume_token() != subscription.resume_token());
+        assert!(!resumed.is_desynced());
+    }
+
+    #[test]
+    fn rehash_boss() {
+        let mut h = Harness::new();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        // The English boss starts out with a bad hash
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 2);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+        assert_eq!(h.hasher.requests.get(), 2);
+        assert!(h.worker.bosses[&en].boss_data.boss.translations.is_empty());
+
+        // Bosses without images aren't hashed
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+        h.client.rehash_boss("Lv75 スーペルヒガンテ");
+        h.client.rehash_boss("Lvl 100 Unknown");
+        h.run();
+        assert_eq!(h.hasher.requests.get(), 2);
+
+        // A boss is hashed again even though it already has a hash
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.run();
+        assert_eq!(h.hasher.requests.get(), 3);
+
+        let en_entry = &h.worker.bosses[&en];
+        assert_eq!(en_entry.boss_data.image_hash, Some(ImageHash::from(1)));
+        assert!(en_entry.boss_data.boss.translations.contains(&ja));
+        assert!(h.worker.bosses[&ja].boss_data.boss.translations.contains(&en));
+    }
+
+    #[test]
+    fn rehash_boss_unlinks_stale_matches() {
+        let mut h = Harness::new();
+        let (_subscription, recorder) = h.subscribe();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        let is_linked = |h: &Harness<_>| {
+            let ja_translations = &h.worker.bosses[&ja].boss_data.boss.translations;
+            let en_translations = &h.worker.bosses[&en].boss_data.boss.translations;
+            assert_eq!(ja_translations.contains(&en), en_translations.contains(&ja));
+            ja_translations.contains(&en)
+        };
+
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+        assert!(is_linked(&h));
+        recorder.take();
+
+        // A new hash that doesn't match anymore unlinks both bosses
+        h.set_image_hash("Lvl 60 Ozorotter", 2);
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.run();
+        assert!(!is_linked(&h));
+
+        let updates = recorder
+            .take()
+            .into_iter()
+            .filter_map(|m| match m {
+                Recorded::BossUpdate(boss) => Some((boss.name, boss.translations.len())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(updates, vec![(ja.clone(), 0), (en.clone(), 0)]);
+
+        // So does a degenerate hash
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.run();
+        assert!(is_linked(&h));
+
+        h.set_image_hash("Lvl 60 Ozorotter", 0);
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.run();
+        assert!(!is_linked(&h));
+        assert_eq!(h.worker.bosses[&en].boss_data.image_hash, None);
+
+        // Confirmed translations don't depend on image hashes
+        h.client.confirm_translation("Lvl 60 Ozorotter", "Lv60 オオゾラッコ");
+        h.set_image_hash("Lvl 60 Ozorotter", 2);
+        h.client.rehash_boss("Lvl 60 Ozorotter");
+        h.run();
+        assert!(is_linked(&h));
+    }
+
+    // Links the Japanese and English Ozorotter bosses as translations, then
+    // removes the English boss. Removal strips the link from the Japanese
+    // boss, so it's put back by hand to stand in for a translation that was
+    // confirmed without the English boss ever being seen.
+    fn harness_with_removed_translation() -> Harness<JsonMetrics> {
+        let mut h = Harness::with_json_metrics();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+        h.push(raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"));
+        h.push(raid_info_with_image("Lvl 60 Ozorotter", 2, 0, "http://example.com/b.png"));
+        h.run();
+
+        h.client
+            .remove_bosses(|meta| meta.boss.name.as_str() == "Lvl 60 Ozorotter");
+        h.run();
+
+        h.worker
+            .bosses
+            .get_mut(&BossName::from("Lv60 オオゾラッコ"))
+            .unwrap()
+            .boss_data
+            .boss
+            .translations
+            .insert(BossName::from("Lvl 60 Ozorotter"));
+        h
+    }
+
+    #[test]
+    fn resolve_boss_name() {
+        let mut h = harness_with_removed_translation();
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        assert_eq!(h.worker.resolve_boss_name(&ja), Resolution::Exact);
+        assert_eq!(
+            h.worker.resolve_boss_name(&en),
+            Resolution::Translated(ja.clone())
+        );
+        assert_eq!(
+            h.worker.resolve_boss_name(&"Lvl 100 Unknown".into()),
+            Resolution::Miss
+        );
+
+        // When multiple bosses claim a translation, prefer the matching level
+        h.push(raid_info("Lv75 スーペルヒガンテ", 3, 0));
+        h.push(raid_info("Lv60 リヴァイアサン", 4, 0));
+        for name in &["Lv75 スーペルヒガンテ", "Lv60 リヴァイアサン"] {
+            let entry = h.worker.bosses.get_mut(&BossName::from(*name)).unwrap();
+            entry.boss_data.boss.translations.insert(en.clone());
+            entry.boss_data.boss.translations.insert("Lvl 100 Foo".into());
+        }
+
+        assert_eq!(
+            h.worker.resolve_boss_name(&"Lvl 100 Foo".into()),
+            Resolution::Ambiguous
+        );
+
+        // Two level 60 bosses claim the English name
+        assert_eq!(h.worker.resolve_boss_name(&en), Resolution::Ambiguous);
+
+        h.worker
+            .bosses
+            .get_mut(&BossName::from("Lv60 リヴァイアサン"))
+            .unwrap()
+            .boss_data
+            .boss
+            .translations
+            .remove(&en);
+        assert_eq!(h.worker.resolve_boss_name(&en), Resolution::Translated(ja));
+    }
+
+    #[test]
+    fn resolve_translated_boss_names_in_requests() {
+        let mut h = harness_with_removed_translation();
+
+        let boss = h.client.boss("Lvl 60 Ozorotter");
+        let boss = h.request(boss).expect("boss not found");
+        assert_eq!(boss.name, BossName::from("Lv60 オオゾラッコ"));
+
+        let tweets = h.client.tweets("Lvl 60 Ozorotter");
+        let tweets = h.request(tweets);
+        assert_eq!(
+            tweets.iter().map(|t| t.tweet_id).collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        let (mut subscription, recorder) = h.subscribe();
+        subscription.get_tweets("Lvl 60 Ozorotter");
+        subscription.follow("Lvl 60 Ozorotter");
+        h.run();
+        assert_eq!(tweet_ids(&recorder.take()), vec![1]);
+
+        // Interest in the requested name is also registered
+        assert!(
+            h.worker
+                .requested_bosses
+                .contains_key(&BossName::from("Lvl 60 Ozorotter"))
+        );
+
+        h.push(raid_info("Lv60 オオゾラッコ", 3, 0));
+        assert_eq!(tweet_ids(&recorder.take()), vec![3]);
+
+        let metrics = h.metrics();
+        let counters = &metrics["boss_counts"]["Lv60 オオゾラッコ"]["counters"];
+        assert_eq!(counters["translated_resolutions"], 4);
+
+        // Unfollowing the requested name also unfollows the translation
+        subscription.unfollow("Lvl 60 Ozorotter");
+        h.run();
+        h.push(raid_info("Lv60 オオゾラッコ", 4, 0));
+        assert!(tweet_ids(&recorder.take()).is_empty());
+    }
+
+    #[test]
+    fn unresolved_boss_names_in_requests() {
+        let mut h = Harness::new();
+        h.push(raid_info("Lv60 オオゾラッコ", 1, 0));
+
+        let boss = h.client.boss("Lvl 60 Ozorotter");
+        assert_eq!(h.request(boss), None);
+
+        let tweets = h.client.tweets("Lvl 60 Ozorotter");
+        assert!(h.request(tweets).is_empty());
+    }
 }
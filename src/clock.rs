@@ -0,0 +1,186 @@
+use chrono;
+use error::*;
+#[cfg(any(test, feature = "test-util"))]
+use futures::{Async, Poll};
+use futures::Stream;
+#[cfg(any(test, feature = "test-util"))]
+use futures::task::{self, Task};
+use model::DateTime;
+#[cfg(any(test, feature = "test-util"))]
+use std::cell::RefCell;
+use std::fmt;
+#[cfg(any(test, feature = "test-util"))]
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_core::reactor::{self, Handle};
+
+pub type Interval = Box<Stream<Item = (), Error = Error>>;
+
+pub trait Clock: fmt::Debug {
+    fn now(&self) -> DateTime;
+
+    // Returns a stream that emits once every `duration`, starting
+    // `duration` from now
+    fn interval(&self, duration: Duration) -> Interval;
+}
+
+#[derive(Clone, Debug)]
+pub struct SystemClock(Handle);
+
+impl SystemClock {
+    pub fn new(handle: &Handle) -> Self {
+        SystemClock(handle.clone())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime {
+        chrono::Utc::now()
+    }
+
+    fn interval(&self, duration: Duration) -> Interval {
+        match reactor::Interval::new(duration, &self.0) {
+            Ok(interval) => Box::new(interval.then(|r| r.chain_err(|| ErrorKind::Timer))),
+            Err(e) => Box::new(::futures::stream::once(
+                Err(e).chain_err(|| ErrorKind::Timer),
+            )),
+        }
+    }
+}
+
+// A clock that only moves forward when `advance` is called. Intended
+// for deterministic tests of time-dependent behavior.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Clone, Debug)]
+pub struct ManualClock(Rc<RefCell<ManualClockState>>);
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug)]
+struct ManualClockState {
+    now: DateTime,
+    // Tasks polling an interval that isn't ready yet, at most once each
+    waiting: Vec<Task>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl ManualClock {
+    pub fn new(start: DateTime) -> Self {
+        ManualClock(Rc::new(RefCell::new(ManualClockState {
+            now: start,
+            waiting: Vec::new(),
+        })))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut state = self.0.borrow_mut();
+            state.now = state.now + to_chrono(duration);
+            ::std::mem::replace(&mut state.waiting, Vec::new())
+        };
+
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime {
+        self.0.borrow().now
+    }
+
+    fn interval(&self, duration: Duration) -> Interval {
+        let period = to_chrono(duration);
+
+        Box::new(ManualInterval {
+            clock: self.clone(),
+            next: self.now() + period,
+            period,
+        })
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[must_use = "streams do nothing unless polled"]
+struct ManualInterval {
+    clock: ManualClock,
+    next: DateTime,
+    period: chrono::Duration,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Stream for ManualInterval {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut state = self.clock.0.borrow_mut();
+
+        if state.now >= self.next {
+            self.next = self.next + self.period;
+            Ok(Async::Ready(Some(())))
+        } else {
+            if !state.waiting.iter().any(Task::will_notify_current) {
+                state.waiting.push(task::current());
+            }
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+pub(crate) fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use futures::{future, Future};
+
+    fn poll_once<S: Stream>(s: &mut S) -> Poll<Option<S::Item>, S::Error> {
+        future::lazy(|| Ok::<_, ()>(s.poll())).wait().unwrap()
+    }
+
+    #[test]
+    fn manual_interval() {
+        let clock = ManualClock::new(chrono::Utc.timestamp(0, 0));
+        let mut interval = clock.interval(Duration::from_secs(10));
+
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::NotReady);
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::NotReady);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::Ready(Some(())));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::NotReady);
+
+        // Skipping multiple periods emits once per elapsed period
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::Ready(Some(())));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::Ready(Some(())));
+        assert_eq!(poll_once(&mut interval).unwrap(), Async::NotReady);
+    }
+
+    #[test]
+    fn wait_once_per_task() {
+        let clock = ManualClock::new(chrono::Utc.timestamp(0, 0));
+        let mut a = clock.interval(Duration::from_secs(10));
+        let mut b = clock.interval(Duration::from_secs(20));
+
+        future::lazy(|| {
+            for _ in 0..3 {
+                assert_eq!(a.poll().unwrap(), Async::NotReady);
+                assert_eq!(b.poll().unwrap(), Async::NotReady);
+            }
+            Ok::<_, ()>(())
+        }).wait()
+            .unwrap();
+        assert_eq!(clock.0.borrow().waiting.len(), 1);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(clock.0.borrow().waiting.is_empty());
+    }
+}
@@ -13,5 +13,27 @@ error_chain!{
         ImageHash {
             description("failed to compute image hash")
         }
+        ImageTooSmall(width: u32, height: u32) {
+            description("image is too small to hash")
+            display("image is too small to hash: {}x{}", width, height)
+        }
+        DegenerateImageHash {
+            description("image has too little detail to hash")
+        }
+        Timer {
+            description("timer failed")
+        }
+        Build(e: ::client::BuildError) {
+            description("invalid client configuration")
+            display("invalid client configuration: {}", e)
+        }
+        InvalidConfig(var: &'static str, value: String) {
+            description("invalid configuration")
+            display("invalid value for {} environment variable: {:?}", var, value)
+        }
+        InvalidToken(component: &'static str) {
+            description("invalid Twitter token")
+            display("invalid Twitter token: {} is empty", component)
+        }
     }
 }
@@ -0,0 +1,173 @@
+// Settings for running the HTTP service, which are read from environment
+// variables so that deployments don't have to re-implement them
+use error::*;
+use std::env::{self, VarError};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:3000";
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 30;
+const DEFAULT_HISTORY_SIZE: usize = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub bind_address: SocketAddr,
+    pub heartbeat_interval: Duration,
+    // Passed to `ClientBuilder::with_history_size`
+    pub history_size: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: DEFAULT_BIND_ADDRESS.parse().unwrap(),
+            heartbeat_interval: Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS),
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
+    }
+}
+
+impl ServerConfig {
+    // Reads the following environment variables, using the defaults for
+    // any that aren't set:
+    //
+    // * `BIND_ADDRESS`: IP address and port, e.g., `0.0.0.0:8080`
+    // * `PORT`: replaces the port in `BIND_ADDRESS`
+    // * `HEARTBEAT_INTERVAL_SECS`: must be greater than 0
+    // * `HISTORY_SIZE`
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| match env::var(name) {
+            Ok(value) => Some(value),
+            Err(VarError::NotPresent) => None,
+            Err(VarError::NotUnicode(value)) => Some(value.to_string_lossy().into_owned()),
+        })
+    }
+
+    fn from_vars<F>(var: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = ServerConfig::default();
+
+        if let Some(address) = parse_var(&var, "BIND_ADDRESS")? {
+            config.bind_address = address;
+        }
+
+        if let Some(port) = parse_var(&var, "PORT")? {
+            config.bind_address.set_port(port);
+        }
+
+        if let Some(secs) = parse_var(&var, "HEARTBEAT_INTERVAL_SECS")? {
+            if secs == 0 {
+                bail!(ErrorKind::InvalidConfig("HEARTBEAT_INTERVAL_SECS", "0".into()));
+            }
+            config.heartbeat_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(size) = parse_var(&var, "HISTORY_SIZE")? {
+            config.history_size = size;
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_var<F, T>(var: &F, name: &'static str) -> Result<Option<T>>
+where
+    F: Fn(&str) -> Option<String>,
+    T: FromStr,
+{
+    match var(name) {
+        Some(value) => match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(ErrorKind::InvalidConfig(name, value).into()),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<ServerConfig> {
+        let vars = vars.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        ServerConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    fn invalid_var(result: Result<ServerConfig>) -> Option<(&'static str, String)> {
+        match result {
+            Err(Error(ErrorKind::InvalidConfig(var, value), _)) => Some((var, value)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = from_vars(&[]).unwrap();
+
+        assert_eq!(config, ServerConfig::default());
+        assert_eq!(config.bind_address, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(config.heartbeat_interval, Duration::from_secs(30));
+        assert_eq!(config.history_size, 10);
+    }
+
+    #[test]
+    fn parse_vars() {
+        let config = from_vars(&[
+            ("BIND_ADDRESS", "0.0.0.0:8080"),
+            ("HEARTBEAT_INTERVAL_SECS", "5"),
+            ("HISTORY_SIZE", "50"),
+        ]).unwrap();
+
+        assert_eq!(
+            config,
+            ServerConfig {
+                bind_address: "0.0.0.0:8080".parse().unwrap(),
+                heartbeat_interval: Duration::from_secs(5),
+                history_size: 50,
+            }
+        );
+
+        let config = from_vars(&[("PORT", "4000")]).unwrap();
+        assert_eq!(config.bind_address, "127.0.0.1:4000".parse().unwrap());
+
+        let config = from_vars(&[("BIND_ADDRESS", "[::1]:8080"), ("PORT", "4000")]).unwrap();
+        assert_eq!(config.bind_address, "[::1]:4000".parse().unwrap());
+    }
+
+    #[test]
+    fn invalid_vars() {
+        assert_eq!(
+            invalid_var(from_vars(&[("BIND_ADDRESS", "localhost:3000")])),
+            Some(("BIND_ADDRESS", "localhost:3000".into()))
+        );
+        assert_eq!(
+            invalid_var(from_vars(&[("BIND_ADDRESS", "127.0.0.1")])),
+            Some(("BIND_ADDRESS", "127.0.0.1".into()))
+        );
+        assert_eq!(
+            invalid_var(from_vars(&[("PORT", "70000")])),
+            Some(("PORT", "70000".into()))
+        );
+        assert_eq!(
+            invalid_var(from_vars(&[("HEARTBEAT_INTERVAL_SECS", "0")])),
+            Some(("HEARTBEAT_INTERVAL_SECS", "0".into()))
+        );
+        assert_eq!(
+            invalid_var(from_vars(&[("HISTORY_SIZE", "-1")])),
+            Some(("HISTORY_SIZE", "-1".into()))
+        );
+
+        let error = from_vars(&[("PORT", "abc")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value for PORT environment variable: \"abc\""
+        );
+    }
+}
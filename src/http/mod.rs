@@ -0,0 +1,592 @@
+// A `hyper` service exposing a `Client` over HTTP, with JSON responses
+mod config;
+
+pub use self::config::ServerConfig;
+use broadcast::Subscriber;
+use bytes::Bytes;
+use client::{Client, Subscription};
+use futures::{future, AsyncSink, Future, Poll, Sink, Stream};
+use futures::sync::mpsc;
+use hyper::{self, header, Method, StatusCode};
+use hyper::header::{Header, Headers};
+use hyper::server::{Request, Response, Service};
+use model::{BossName, Message};
+use percent_encoding;
+use regex::Regex;
+use serde::Serialize;
+use serde_json;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Forwards messages to the body of a streaming HTTP response
+#[derive(Clone, Debug)]
+pub struct Sender {
+    sender: mpsc::Sender<hyper::Result<hyper::Chunk>>,
+    // Whether the last message was dropped because the body's buffer was
+    // full. Shared between clones, since each broadcast has its own clone.
+    stalled: Rc<Cell<bool>>,
+}
+
+impl Sender {
+    fn new(sender: mpsc::Sender<hyper::Result<hyper::Chunk>>) -> Self {
+        Sender {
+            sender,
+            stalled: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+impl Subscriber for Sender {
+    type Item = Bytes;
+
+    fn send(&mut self, bytes: &Bytes) -> ::std::result::Result<(), ()> {
+        let sent = self.sender.start_send(Ok(bytes.clone().into()));
+        self.stalled.set(match sent {
+            Ok(AsyncSink::NotReady(_)) => true,
+            _ => false,
+        });
+
+        sent.and_then(|_| self.sender.poll_complete().map(|_| ()))
+            .map_err(|_| ())
+    }
+
+    // The body's buffer stays full if the client stops reading
+    fn is_stalled(&mut self) -> bool {
+        self.stalled.get()
+    }
+}
+
+// Default message mapper, for use with `ClientBuilder::filter_map_message`.
+// Serializes each message as a line of JSON, skipping heartbeats.
+pub fn json_mapper(message: Message) -> Option<Bytes> {
+    let mut bytes = match message {
+        Message::Heartbeat => return None,
+        Message::TweetList(tweets) => {
+            let mut tweet_vec = tweets.to_vec();
+            tweet_vec.sort_by_key(|t| t.created_at);
+            serde_json::to_vec(&tweet_vec)
+        }
+        other => serde_json::to_vec(&other),
+    }.unwrap();
+
+    bytes.push(b'\n');
+    Some(bytes.into())
+}
+
+// Response body that keeps a subscription alive for as long as the
+// response is being streamed
+pub struct Body<M> {
+    body: hyper::Body,
+    _subscription: Option<Subscription<Sender, M>>,
+}
+
+impl<M> Stream for Body<M> {
+    type Item = hyper::Chunk;
+    type Error = hyper::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.body.poll()
+    }
+}
+
+impl<M, T> From<T> for Body<M>
+where
+    T: Into<hyper::Body>,
+{
+    fn from(t: T) -> Self {
+        Body {
+            body: t.into(),
+            _subscription: None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref REGEX_BOSS_TWEETS: Regex = Regex::new(
+        r"^/bosses/(?P<boss_name>.+)/tweets$"
+    ).unwrap();
+
+    static ref REGEX_BOSS_STREAM: Regex = Regex::new(
+        r"^/bosses/(?P<boss_name>.+)/stream$"
+    ).unwrap();
+
+    static ref REGEX_BOSS: Regex = Regex::new(
+        r"^/bosses/(?P<boss_name>[^/]+)$"
+    ).unwrap();
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    error: String,
+}
+
+pub type ServiceResponse<M> = Response<Body<M>>;
+pub type ServiceFuture<M> = Box<Future<Item = ServiceResponse<M>, Error = hyper::Error>>;
+
+// The parts of a response, which routes return instead of a `Response`.
+// hyper 0.11 only allows taking the body back out of a `Response` whose body
+// is a `hyper::Body`, so this keeps the body reachable for tests.
+struct Reply<M> {
+    status: StatusCode,
+    headers: Headers,
+    body: Option<Body<M>>,
+}
+
+impl<M> Reply<M> {
+    fn new(status: StatusCode) -> Self {
+        Reply {
+            status,
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+
+    fn with_header<H: Header>(mut self, header: H) -> Self {
+        self.headers.set(header);
+        self
+    }
+
+    fn with_body<B: Into<Body<M>>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    fn into_response(self) -> ServiceResponse<M> {
+        let resp = Response::new()
+            .with_status(self.status)
+            .with_headers(self.headers);
+
+        match self.body {
+            Some(body) => resp.with_body(body),
+            None => resp,
+        }
+    }
+}
+
+type ReplyFuture<M> = Box<Future<Item = Reply<M>, Error = hyper::Error>>;
+
+fn json_response<T: Serialize, M>(status: StatusCode, t: &T) -> Reply<M> {
+    let json = serde_json::to_vec(t).unwrap();
+
+    Reply::new(status)
+        .with_header(header::ContentLength(json.len() as u64))
+        .with_header(header::ContentType::json())
+        .with_body(json)
+}
+
+fn not_found<M>(error: String) -> Reply<M> {
+    json_response(StatusCode::NotFound, &JsonError { error })
+}
+
+// Serves the following routes, relative to an optional prefix:
+//
+// * `GET /bosses`
+// * `GET /bosses/{name}`
+// * `DELETE /bosses/{name}`
+// * `GET /bosses/{name}/tweets`
+// * `GET /bosses/{name}/stream`
+// * `GET /metrics`
+// * `GET /debug/bosses`
+//
+// The client should be built with a `Sender` subscriber, and metrics that
+// export to a JSON byte string.
+pub struct PetronelService<M> {
+    client: Client<Sender, M>,
+    prefix: String,
+}
+
+impl<M> Clone for PetronelService<M> {
+    fn clone(&self) -> Self {
+        PetronelService {
+            client: self.client.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<M> PetronelService<M>
+where
+    M: Into<Vec<u8>> + 'static,
+{
+    pub fn new(client: Client<Sender, M>) -> Self {
+        PetronelService {
+            client,
+            prefix: String::new(),
+        }
+    }
+
+    // Serve routes under the given path, e.g., `/api`
+    pub fn with_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        let mut prefix = prefix.into();
+        while prefix.ends_with('/') {
+            prefix.pop();
+        }
+
+        self.prefix = prefix;
+        self
+    }
+
+    fn get_bosses(&self) -> ReplyFuture<M> {
+        let resp = self.client
+            .bosses()
+            .map(|bosses| json_response(StatusCode::Ok, &bosses))
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn get_debug_bosses(&self) -> ReplyFuture<M> {
+        let resp = self.client
+            .debug_bosses()
+            .map(|bosses| json_response(StatusCode::Ok, &bosses))
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn get_boss(&self, name: BossName) -> ReplyFuture<M> {
+        let resp = self.client
+            .boss(name)
+            .map(|boss| match boss {
+                Some(boss) => json_response(StatusCode::Ok, &boss),
+                None => not_found("boss not found".to_string()),
+            })
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn remove_boss(&self, name: BossName) -> ReplyFuture<M> {
+        self.client
+            .remove_bosses(move |meta| meta.boss.name == name);
+
+        Box::new(future::ok(Reply::new(StatusCode::Accepted)))
+    }
+
+    fn get_tweets(&self, name: BossName) -> ReplyFuture<M> {
+        let resp = self.client
+            .tweets(name)
+            .map(|tweets| json_response(StatusCode::Ok, &tweets))
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn stream(&self, name: BossName) -> ReplyFuture<M> {
+        let (sender, chunks) = hyper::Body::pair();
+
+        let resp = self.client
+            .subscribe(Sender::new(sender))
+            .map(move |mut subscription| {
+                subscription.follow_with_backlog(name);
+
+                let body = Body {
+                    body: chunks,
+                    _subscription: Some(subscription),
+                };
+
+                Reply::new(StatusCode::Ok)
+                    .with_header(header::TransferEncoding::chunked())
+                    .with_header(header::Connection::keep_alive())
+                    .with_body(body)
+            })
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn get_metrics(&self) -> ReplyFuture<M> {
+        let resp = self.client
+            .export_metrics()
+            .map(|metrics| {
+                let body: Vec<u8> = metrics.into();
+
+                Reply::new(StatusCode::Ok)
+                    .with_header(header::ContentLength(body.len() as u64))
+                    .with_header(header::ContentType::json())
+                    .with_body(body)
+            })
+            .map_err(|_| hyper::Error::Incomplete);
+
+        Box::new(resp)
+    }
+
+    fn route(&self, req: &Request) -> ReplyFuture<M> {
+        let full_path = percent_encoding::percent_decode(req.path().as_bytes()).decode_utf8_lossy();
+        let method = req.method();
+
+        let path = if full_path.starts_with(&self.prefix) {
+            &full_path[self.prefix.len()..]
+        } else {
+            ""
+        };
+
+        let boss_name = |regex: &Regex| {
+            regex
+                .captures(path)
+                .map(|c| BossName::normalized(c.name("boss_name").unwrap().as_str()))
+        };
+
+        let unrecognized = || -> ReplyFuture<M> {
+            let error = format!("unrecognized endpoint: {} {}", method, full_path);
+            Box::new(future::ok(not_found(error)))
+        };
+
+        match *method {
+            Method::Get => if path == "/bosses" {
+                self.get_bosses()
+            } else if path == "/metrics" {
+                self.get_metrics()
+            } else if path == "/debug/bosses" {
+                self.get_debug_bosses()
+            } else if let Some(name) = boss_name(&REGEX_BOSS) {
+                self.get_boss(name)
+            } else if let Some(name) = boss_name(&REGEX_BOSS_TWEETS) {
+                self.get_tweets(name)
+            } else if let Some(name) = boss_name(&REGEX_BOSS_STREAM) {
+                self.stream(name)
+            } else {
+                unrecognized()
+            },
+            Method::Delete => if let Some(name) = boss_name(&REGEX_BOSS) {
+                self.remove_boss(name)
+            } else {
+                unrecognized()
+            },
+            _ => unrecognized(),
+        }
+    }
+}
+
+impl<M> Service for PetronelService<M>
+where
+    M: Into<Vec<u8>> + 'static,
+{
+    type Request = Request;
+    type Response = ServiceResponse<M>;
+    type Error = hyper::Error;
+
+    type Future = ServiceFuture<M>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        Box::new(self.route(&req).map(Reply::into_response))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use client::harness::{self, raid_info, Harness};
+    use futures::Async;
+    use metrics::{self, Simple, SimpleMetrics};
+    use serde_json::Value;
+
+    type TestMetrics = Simple<fn(&SimpleMetrics) -> Vec<u8>>;
+    type HttpHarness = Harness<TestMetrics, Sender, fn(Message) -> Option<Bytes>>;
+
+    fn to_json(metrics: &SimpleMetrics) -> Vec<u8> {
+        serde_json::to_vec(metrics).unwrap()
+    }
+
+    fn harness() -> (HttpHarness, PetronelService<Vec<u8>>) {
+        let (builder, hasher, raids) = harness::builder();
+        let builder = builder
+            .with_subscriber::<Sender>()
+            .filter_map_message(json_mapper as fn(Message) -> Option<Bytes>)
+            .with_metrics(metrics::simple(
+                to_json as fn(&SimpleMetrics) -> Vec<u8>,
+            ));
+
+        let harness = Harness::from_builder(builder, hasher, raids);
+        let service = PetronelService::new(harness.client.clone());
+        (harness, service)
+    }
+
+    fn call(
+        harness: &mut HttpHarness,
+        service: &PetronelService<Vec<u8>>,
+        method: Method,
+        path: &str,
+    ) -> Reply<Vec<u8>> {
+        let resp = service.route(&Request::new(method, path.parse().unwrap()));
+        harness.run();
+        resp.wait().expect("request failed")
+    }
+
+    fn body_json(resp: Reply<Vec<u8>>) -> Value {
+        let body = resp.body.expect("missing body").concat2().wait().unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn poll_chunk(body: &mut Body<Vec<u8>>) -> Option<Value> {
+        match future::lazy(|| Ok::<_, ()>(body.poll())).wait().unwrap() {
+            Ok(Async::Ready(Some(chunk))) => Some(serde_json::from_slice(&chunk).unwrap()),
+            _ => None,
+        }
+    }
+
+    const BOSS: &str = "Lvl 60 Ozorotter";
+    const BOSS_PATH: &str = "/bosses/Lvl%2060%20Ozorotter";
+
+    #[test]
+    fn get_bosses() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let resp = call(&mut h, &service, Method::Get, "/bosses");
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let json = body_json(resp);
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["name"], BOSS);
+    }
+
+    #[test]
+    fn get_debug_bosses() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let resp = call(&mut h, &service, Method::Get, "/debug/bosses");
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let json = body_json(resp);
+        assert_eq!(json[0]["boss"]["name"], BOSS);
+    }
+
+    #[test]
+    fn get_boss() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let resp = call(&mut h, &service, Method::Get, BOSS_PATH);
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(body_json(resp)["name"], BOSS);
+
+        let resp = call(&mut h, &service, Method::Get, "/bosses/Unknown");
+        assert_eq!(resp.status, StatusCode::NotFound);
+        assert!(body_json(resp)["error"].is_string());
+    }
+
+    #[test]
+    fn get_boss_with_decomposed_name() {
+        use percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
+        use unicode_normalization::UnicodeNormalization;
+
+        let (mut h, service) = harness();
+        let boss_name = "Lv100 ケルベロス";
+        h.push(raid_info(boss_name, 1, 0));
+
+        let decomposed = boss_name.nfd().collect::<String>();
+        assert_ne!(decomposed, boss_name);
+
+        let path = format!("/bosses/{}", utf8_percent_encode(&decomposed, DEFAULT_ENCODE_SET));
+        let resp = call(&mut h, &service, Method::Get, &path);
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(body_json(resp)["name"], boss_name);
+    }
+
+    #[test]
+    fn remove_boss() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let resp = call(&mut h, &service, Method::Delete, BOSS_PATH);
+        assert_eq!(resp.status, StatusCode::Accepted);
+
+        let resp = call(&mut h, &service, Method::Get, "/bosses");
+        assert!(body_json(resp).as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_tweets() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+        h.push(raid_info(BOSS, 2, 1));
+
+        let path = format!("{}/tweets", BOSS_PATH);
+        let resp = call(&mut h, &service, Method::Get, &path);
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let json = body_json(resp);
+        let ids = json.as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["tweet_id"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn stream() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let path = format!("{}/stream", BOSS_PATH);
+        let resp = call(&mut h, &service, Method::Get, &path);
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        // Process the subscription's initial requests
+        h.run();
+        let mut body = resp.body.expect("missing body");
+
+        let backfill = poll_chunk(&mut body).expect("missing tweet list");
+        assert_eq!(backfill[0]["tweet_id"], 1);
+
+        h.push(raid_info(BOSS, 2, 1));
+        let tweet = poll_chunk(&mut body).expect("missing tweet");
+        assert_eq!(tweet["Tweet"]["tweet_id"], 2);
+
+        assert_eq!(poll_chunk(&mut body), None);
+    }
+
+    #[test]
+    fn get_metrics() {
+        let (mut h, service) = harness();
+        h.push(raid_info(BOSS, 1, 0));
+
+        let resp = call(&mut h, &service, Method::Get, "/metrics");
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert!(body_json(resp).is_object());
+    }
+
+    #[test]
+    fn build_response() {
+        let (mut h, service) = harness();
+
+        let resp = service.call(Request::new(Method::Get, "/metrics".parse().unwrap()));
+        h.run();
+        let resp = resp.wait().expect("request failed");
+
+        assert_eq!(resp.status(), StatusCode::Ok);
+        assert_eq!(
+            resp.headers().get::<header::ContentType>(),
+            Some(&header::ContentType::json())
+        );
+        assert!(resp.body_ref().is_some());
+    }
+
+    #[test]
+    fn prefix() {
+        let (mut h, service) = harness();
+        let service = service.with_prefix("/api/");
+
+        let resp = call(&mut h, &service, Method::Get, "/api/bosses");
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        let resp = call(&mut h, &service, Method::Get, "/bosses");
+        assert_eq!(resp.status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn unrecognized_endpoint() {
+        let (mut h, service) = harness();
+
+        let resp = call(&mut h, &service, Method::Post, "/bosses");
+        assert_eq!(resp.status, StatusCode::NotFound);
+        assert_eq!(
+            body_json(resp)["error"],
+            "unrecognized endpoint: POST /bosses"
+        );
+    }
+}
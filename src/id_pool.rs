@@ -1,10 +1,34 @@
+use serde::{Serialize, Serializer};
+
+// The generation is incremented each time an ID is recycled, so that an
+// old copy of a recycled ID never refers to whoever is using it now
 #[derive(Clone, Hash, Debug, PartialEq, Eq)]
-pub struct Id(u32);
+pub struct Id {
+    index: u32,
+    generation: u32,
+}
+
+impl Id {
+    // Recycled IDs share their value with the IDs they were recycled from
+    pub fn value(&self) -> u32 {
+        self.index
+    }
+}
 
-#[derive(Debug)]
+impl Serialize for Id {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(self.index)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct IdPool {
     max_id: u32,
     available: Vec<Id>,
+    recycle: bool,
 }
 
 impl IdPool {
@@ -12,26 +36,60 @@ impl IdPool {
         IdPool {
             max_id: 0,
             available: Vec::new(),
+            recycle: true,
+        }
+    }
+
+    // For tests that need to know which IDs will be given out
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_start(start: u32) -> Self {
+        IdPool {
+            max_id: start,
+            ..IdPool::new()
         }
     }
 
+    // Never recycle IDs, so that each ID only depends on how many IDs were
+    // given out before it, and not on when they were recycled
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn deterministic(mut self) -> Self {
+        self.recycle = false;
+        self
+    }
+
     pub fn get(&mut self) -> Id {
         self.available.pop().unwrap_or_else(|| {
-            let id = self.max_id;
+            let index = self.max_id;
 
             // This will panic when max_id is at u32's max value,
             // but in practice, there would never be so many
             // IDs being used at the same time.
             self.max_id += 1;
 
-            Id(id)
+            Id {
+                index,
+                generation: 0,
+            }
         })
     }
 
     // This should never be called with `id` less than `max_id`
     pub fn recycle(&mut self, id: Id) {
-        debug_assert!(id.0 <= self.max_id);
-        self.available.push(id);
+        debug_assert!(id.index <= self.max_id);
+        if !self.recycle {
+            return;
+        }
+
+        self.available.push(Id {
+            index: id.index,
+            generation: id.generation.wrapping_add(1),
+        });
+    }
+}
+
+impl Default for IdPool {
+    fn default() -> Self {
+        IdPool::new()
     }
 }
 
@@ -39,19 +97,58 @@ impl IdPool {
 mod test {
     use super::*;
 
+    fn id(index: u32, generation: u32) -> Id {
+        Id { index, generation }
+    }
+
     #[test]
     fn use_id() {
         let mut pool = IdPool::new();
 
-        assert_eq!(pool.get(), Id(0));
-        assert_eq!(pool.get(), Id(1));
-        assert_eq!(pool.get(), Id(2));
+        assert_eq!(pool.get(), id(0, 0));
+        assert_eq!(pool.get(), id(1, 0));
+        assert_eq!(pool.get(), id(2, 0));
+
+        pool.recycle(id(1, 0));
+        pool.recycle(id(0, 0));
+        assert_eq!(pool.get(), id(0, 1));
+        assert_eq!(pool.get(), id(1, 1));
+        assert_eq!(pool.get(), id(3, 0));
+        assert_eq!(pool.get(), id(4, 0));
+    }
+
+    #[test]
+    fn recycled_id_is_distinct() {
+        let mut pool = IdPool::new();
+
+        let first = pool.get();
+        pool.recycle(first.clone());
+        let second = pool.get();
+
+        assert_eq!(first.value(), second.value());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn deterministic_pool() {
+        let mut pool = IdPool::with_start(10).deterministic();
+
+        let first = pool.get();
+        let second = pool.get();
+        pool.recycle(first.clone());
+        pool.recycle(second);
+
+        assert_eq!(first, id(10, 0));
+        assert_eq!(pool.get(), id(12, 0));
+        assert_eq!(pool.get(), id(13, 0));
+    }
+
+    #[test]
+    fn serialize_as_value() {
+        let mut pool = IdPool::with_start(3);
+        let first = pool.get();
+        pool.recycle(first);
 
-        pool.recycle(Id(1));
-        pool.recycle(Id(0));
-        assert_eq!(pool.get(), Id(0));
-        assert_eq!(pool.get(), Id(1));
-        assert_eq!(pool.get(), Id(3));
-        assert_eq!(pool.get(), Id(4));
+        assert_eq!(::serde_json::to_string(&pool.get()).unwrap(), "3");
     }
 }
@@ -0,0 +1,99 @@
+// Bosses sometimes share an image (e.g., the same boss at different levels,
+// or re-posted official art), so hashes are remembered by image URL
+use image_hash::ImageHash;
+use std::collections::HashMap;
+
+// Twitter media URLs can end with a size, like `:orig` or `:large`.
+// Every size of an image is treated as the same image.
+const SIZE_SUFFIXES: &[&str] = &[":orig", ":large", ":medium", ":small", ":thumb"];
+
+pub(crate) fn canonical_url(url: &str) -> String {
+    SIZE_SUFFIXES
+        .iter()
+        .find(|suffix| url.ends_with(*suffix))
+        .map_or(url, |suffix| &url[..url.len() - suffix.len()])
+        .to_string()
+}
+
+// Keeps the hashes of the most recently used URLs
+#[derive(Debug)]
+pub(crate) struct HashCache {
+    capacity: usize,
+    // Each entry has the tick it was last used at
+    entries: HashMap<String, (ImageHash, u64)>,
+    tick: u64,
+}
+
+impl HashCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        HashCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            tick: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, url: &str) -> Option<ImageHash> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        self.entries.get_mut(url).map(|entry| {
+            entry.1 = tick;
+            entry.0
+        })
+    }
+
+    pub(crate) fn insert(&mut self, url: String, image_hash: ImageHash) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&url) {
+            let oldest = self.entries
+                .iter()
+                .min_by_key(|&(_, &(_, tick))| tick)
+                .map(|(url, _)| url.clone());
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.tick += 1;
+        self.entries.insert(url, (image_hash, self.tick));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn canonicalize_size_suffixes() {
+        let url = "https://pbs.twimg.com/media/abc.jpg";
+        assert_eq!(canonical_url(url), url);
+        assert_eq!(canonical_url(&format!("{}:orig", url)), url);
+        assert_eq!(canonical_url(&format!("{}:large", url)), url);
+        assert_eq!(canonical_url("http://example.com:8080"), "http://example.com:8080");
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut cache = HashCache::new(2);
+        cache.insert("a".into(), ImageHash::from(1));
+        cache.insert("b".into(), ImageHash::from(2));
+
+        // Using an entry keeps it from being evicted
+        assert_eq!(cache.get("a"), Some(ImageHash::from(1)));
+        cache.insert("c".into(), ImageHash::from(3));
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(ImageHash::from(1)));
+        assert_eq!(cache.get("c"), Some(ImageHash::from(3)));
+
+        // Replacing an entry doesn't evict anything
+        cache.insert("c".into(), ImageHash::from(4));
+        assert_eq!(cache.get("a"), Some(ImageHash::from(1)));
+        assert_eq!(cache.get("c"), Some(ImageHash::from(4)));
+    }
+}
@@ -0,0 +1,93 @@
+// Twitter deletes media along with its tweet, so a boss' image URL can stop
+// working long after the boss was first seen
+use error::*;
+use futures::Future;
+use hyper::{Client, Method, Request, StatusCode, Uri};
+use hyper::client::Connect;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageStatus {
+    Live,
+    // The image was deleted (404 or 410)
+    Gone,
+    // The request failed, or the response didn't say either way
+    Unknown,
+}
+
+pub trait ImageChecker {
+    type Check: Future<Item = ImageStatus, Error = Error>;
+
+    fn check(&self, uri: Uri) -> Self::Check;
+}
+
+// An `ImageChecker` with its type erased, so it can be stored by the builder
+// without adding another type parameter
+pub(crate) type DynImageChecker = Rc<Fn(Uri) -> Box<Future<Item = ImageStatus, Error = Error>>>;
+
+pub(crate) fn boxed<C>(checker: C) -> DynImageChecker
+where
+    C: ImageChecker + 'static,
+{
+    Rc::new(move |uri| Box::new(checker.check(uri)))
+}
+
+// Checks images with a HEAD request, so the image itself isn't downloaded
+#[derive(Debug)]
+pub struct HyperImageChecker<C> {
+    client: Client<C>,
+}
+
+impl<C> HyperImageChecker<C>
+where
+    C: Connect,
+{
+    pub fn new(client: Client<C>) -> Self {
+        HyperImageChecker { client }
+    }
+}
+
+impl<C> ImageChecker for HyperImageChecker<C>
+where
+    C: Connect,
+{
+    type Check = Box<Future<Item = ImageStatus, Error = Error>>;
+
+    fn check(&self, uri: Uri) -> Self::Check {
+        let result = self.client
+            .request(Request::new(Method::Head, uri))
+            .then(|resp| {
+                let status = match resp {
+                    Ok(resp) => status_of(resp.status()),
+                    // Counted by the worker with `Metrics::inc_inconclusive_image_check`
+                    Err(_) => ImageStatus::Unknown,
+                };
+
+                Ok(status)
+            });
+
+        Box::new(result)
+    }
+}
+
+fn status_of(code: StatusCode) -> ImageStatus {
+    match code {
+        StatusCode::NotFound | StatusCode::Gone => ImageStatus::Gone,
+        code if code.is_success() => ImageStatus::Live,
+        _ => ImageStatus::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(status_of(StatusCode::Ok), ImageStatus::Live);
+        assert_eq!(status_of(StatusCode::NotFound), ImageStatus::Gone);
+        assert_eq!(status_of(StatusCode::Gone), ImageStatus::Gone);
+        assert_eq!(status_of(StatusCode::Forbidden), ImageStatus::Unknown);
+        assert_eq!(status_of(StatusCode::ServiceUnavailable), ImageStatus::Unknown);
+    }
+}
@@ -0,0 +1,79 @@
+// Groups bosses with similar image hashes, e.g., to find visually identical
+// bosses across levels. Not used by the worker, but useful for offline tools.
+use image_hash::ImageHash;
+use model::BossName;
+
+// Single-linkage clustering: two bosses are in the same cluster if there is
+// a chain of bosses between them where each step is within `max_distance`.
+// Clusters are ordered by their first boss, and bosses keep their order.
+pub fn cluster(hashes: &[(BossName, ImageHash)], max_distance: u32) -> Vec<Vec<BossName>> {
+    // Each boss starts in its own cluster, identified by the boss' index
+    let mut parents = (0..hashes.len()).collect::<Vec<_>>();
+
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for (i, &(_, a)) in hashes.iter().enumerate() {
+        for (j, &(_, b)) in hashes.iter().enumerate().skip(i + 1) {
+            if a.distance(b) <= max_distance {
+                let (x, y) = (root(&mut parents, i), root(&mut parents, j));
+                // Keep the lower index as the root, to preserve order
+                parents[x.max(y)] = x.min(y);
+            }
+        }
+    }
+
+    let mut clusters: Vec<(usize, Vec<BossName>)> = Vec::new();
+    for (i, &(ref boss_name, _)) in hashes.iter().enumerate() {
+        let r = root(&mut parents, i);
+        match clusters.iter().position(|&(id, _)| id == r) {
+            Some(index) => clusters[index].1.push(boss_name.clone()),
+            None => clusters.push((r, vec![boss_name.clone()])),
+        }
+    }
+
+    clusters.into_iter().map(|(_, names)| names).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(clusters: &[Vec<BossName>]) -> Vec<Vec<String>> {
+        clusters
+            .iter()
+            .map(|c| c.iter().map(|name| name.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn single_linkage() {
+        let hashes = vec![
+            ("Lv60 オオゾラッコ".into(), ImageHash::from(0b0000_0000)),
+            ("Lvl 75 Celeste".into(), ImageHash::from(0b1111_0000)),
+            ("Lvl 60 Ozorotter".into(), ImageHash::from(0b0000_0001)),
+            // Within 1 of Ozorotter, but 2 away from オオゾラッコ
+            ("Lvl 30 Ozorotter".into(), ImageHash::from(0b0000_0011)),
+            ("Lvl 100 Celeste".into(), ImageHash::from(0b1111_1000)),
+            ("Lvl 120 Ultimate Bahamut".into(), ImageHash::from(!0)),
+        ];
+
+        assert_eq!(
+            names(&cluster(&hashes, 1)),
+            vec![
+                vec!["Lv60 オオゾラッコ", "Lvl 60 Ozorotter", "Lvl 30 Ozorotter"],
+                vec!["Lvl 75 Celeste", "Lvl 100 Celeste"],
+                vec!["Lvl 120 Ultimate Bahamut"],
+            ]
+        );
+
+        assert_eq!(cluster(&hashes, 0).len(), hashes.len());
+        assert_eq!(cluster(&hashes, 64).len(), 1);
+        assert!(cluster(&[], 1).is_empty());
+    }
+}
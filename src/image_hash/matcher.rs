@@ -0,0 +1,108 @@
+use model::RaidBossMetadata;
+use std::fmt;
+
+// Decides whether two bosses are translations of each other, when one of
+// them gets a new image hash
+pub trait TranslationMatcher: fmt::Debug {
+    fn matches(&self, a: &RaidBossMetadata, b: &RaidBossMetadata) -> bool;
+
+    // Returns a matcher that allows image hashes to differ by at most
+    // `threshold` bits, used by `Client::set_hash_threshold`. Matchers that
+    // don't compare image hashes by distance return `None`, and are kept.
+    fn with_hash_threshold(&self, _threshold: u32) -> Option<Box<TranslationMatcher>> {
+        None
+    }
+}
+
+// Translations are always the same level, in different languages
+fn is_candidate(a: &RaidBossMetadata, b: &RaidBossMetadata) -> bool {
+    a.boss.level == b.boss.level && a.boss.language != b.boss.language
+}
+
+// Matches bosses whose image hashes are equal. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExactHashMatcher;
+
+impl TranslationMatcher for ExactHashMatcher {
+    fn matches(&self, a: &RaidBossMetadata, b: &RaidBossMetadata) -> bool {
+        is_candidate(a, b) && a.image_hash.is_some() && a.image_hash == b.image_hash
+    }
+
+    fn with_hash_threshold(&self, threshold: u32) -> Option<Box<TranslationMatcher>> {
+        Some(Box::new(HammingMatcher(threshold)))
+    }
+}
+
+// Matches bosses whose image hashes differ by at most this many bits, for
+// images that were re-encoded with slightly different artifacts
+#[derive(Clone, Copy, Debug)]
+pub struct HammingMatcher(pub u32);
+
+impl TranslationMatcher for HammingMatcher {
+    fn matches(&self, a: &RaidBossMetadata, b: &RaidBossMetadata) -> bool {
+        match (a.image_hash, b.image_hash) {
+            (Some(x), Some(y)) => is_candidate(a, b) && x.distance(y) <= self.0,
+            _ => false,
+        }
+    }
+
+    fn with_hash_threshold(&self, threshold: u32) -> Option<Box<TranslationMatcher>> {
+        Some(Box::new(HammingMatcher(threshold)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use image_hash::ImageHash;
+    use model::{Language, RaidBoss};
+    use std::collections::HashSet;
+
+    fn metadata(name: &str, language: Language, image_hash: Option<u64>) -> RaidBossMetadata {
+        RaidBossMetadata {
+            boss: RaidBoss {
+                name: name.into(),
+                level: 60,
+                image: None,
+                language,
+                translations: HashSet::new(),
+                muted: false,
+            },
+            first_seen: Utc.timestamp(0, 0),
+            last_seen: Utc.timestamp(0, 0),
+            image_hash: image_hash.map(ImageHash::from),
+        }
+    }
+
+    #[test]
+    fn exact_hash_matcher() {
+        let ja = metadata("Lv60 オオゾラッコ", Language::Japanese, Some(0b10));
+        let en = |hash| metadata("Lvl 60 Ozorotter", Language::English, hash);
+
+        assert!(ExactHashMatcher.matches(&ja, &en(Some(0b10))));
+        assert!(!ExactHashMatcher.matches(&ja, &en(Some(0b110))));
+        assert!(!ExactHashMatcher.matches(&ja, &en(None)));
+        assert!(!ExactHashMatcher.matches(&en(None), &en(None)));
+
+        // Bosses in the same language are never translations
+        let other = metadata("Lv60 Other", Language::Japanese, Some(0b10));
+        assert!(!ExactHashMatcher.matches(&ja, &other));
+    }
+
+    #[test]
+    fn hamming_matcher() {
+        let ja = metadata("Lv60 オオゾラッコ", Language::Japanese, Some(0b10));
+        let en = |hash| metadata("Lvl 60 Ozorotter", Language::English, hash);
+
+        assert!(HammingMatcher(1).matches(&ja, &en(Some(0b10))));
+        assert!(HammingMatcher(1).matches(&ja, &en(Some(0b110))));
+        assert!(!HammingMatcher(1).matches(&ja, &en(Some(0b1110))));
+        assert!(HammingMatcher(2).matches(&ja, &en(Some(0b1110))));
+        assert!(!HammingMatcher(64).matches(&ja, &en(None)));
+
+        let mut other_level = en(Some(0b10));
+        other_level.boss.level = 75;
+        assert!(!HammingMatcher(1).matches(&ja, &other_level));
+    }
+}
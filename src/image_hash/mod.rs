@@ -1,15 +1,31 @@
+mod cache;
+mod check;
+mod cluster;
+mod matcher;
 mod phash;
 
+pub(crate) use self::check::{boxed as boxed_image_checker, DynImageChecker};
+pub use self::check::{HyperImageChecker, ImageChecker, ImageStatus};
+pub use self::cluster::cluster;
+pub use self::matcher::{ExactHashMatcher, HammingMatcher, TranslationMatcher};
 pub use self::phash::ImageHash;
+use self::cache::HashCache;
+use clock::{Clock, Interval};
 use error::*;
-use futures::{Async, Future, IntoFuture, Poll, Stream};
-use futures::stream::BufferUnordered;
+use futures::{Async, Future, Poll, Stream};
+use futures::stream::{BufferUnordered, FuturesUnordered};
 use futures::unsync::mpsc;
+use futures_cpupool::CpuPool;
 use hyper::{Client, Uri};
 use hyper::client::Connect;
-use image::{self, GenericImage};
+use image::{self, DynamicImage, GenericImage};
 use model::BossName;
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Entry;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct BossImageHash {
@@ -17,42 +33,160 @@ pub struct BossImageHash {
     pub image_hash: Option<ImageHash>,
 }
 
-pub fn channel<H, F>(image_hasher: H, concurrency: usize) -> (ImageHashSender, ImageHashReceiver<H>)
+// The raw image for a boss, or `None` if it couldn't be fetched
+#[derive(Debug)]
+pub struct FetchedImage {
+    pub boss_name: BossName,
+    pub bytes: Option<Vec<u8>>,
+}
+
+// Limits how many new image hash requests can be started per interval,
+// independent of the number of requests allowed to run concurrently.
+// Requests over the limit are delayed until the next interval.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PacingOptions {
+    pub max_per_interval: usize,
+    pub interval: Duration,
+}
+
+pub struct Pacing {
+    max_per_interval: usize,
+    started: usize,
+    ticks: Interval,
+}
+
+impl Pacing {
+    pub fn new(options: PacingOptions, clock: &Clock) -> Self {
+        Pacing {
+            max_per_interval: options.max_per_interval,
+            started: 0,
+            ticks: clock.interval(options.interval),
+        }
+    }
+
+    // Returns whether another request can be started in the current interval
+    fn poll_budget(&mut self) -> Poll<(), Error> {
+        while let Async::Ready(Some(())) = self.ticks.poll()? {
+            self.started = 0;
+        }
+
+        if self.started < self.max_per_interval {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+// The number of image URLs whose hashes are remembered
+const URL_CACHE_CAPACITY: usize = 512;
+
+// Images are fetched and hashed in separate stages, each with its own
+// concurrency limit. Fetched images wait for a free hashing slot before
+// more fetches are started. Each image URL is only fetched once at a time,
+// and recently hashed URLs aren't fetched again.
+pub fn channel<H>(
+    mut image_hasher: H,
+    fetch_concurrency: usize,
+    hash_concurrency: usize,
+    pacing: Option<Pacing>,
+) -> (ImageHashSender, ImageHashReceiver<H>)
 where
-    H: ImageHasher<Future = F>,
-    F: Future<Item = BossImageHash, Error = Error>,
+    H: ImageHasher,
 {
+    image_hasher.set_hash_concurrency(hash_concurrency);
+
     let (sink, stream) = mpsc::unbounded();
+    let pending = Rc::new(Cell::new(0));
     let inner = Inner {
-        image_hasher: image_hasher,
+        image_hasher,
         stream,
         outstanding: HashSet::new(),
+        pacing,
+        pending: pending.clone(),
+        cache: HashCache::new(URL_CACHE_CAPACITY),
+        fetching: HashMap::new(),
+        waiting: HashMap::new(),
+        ready: VecDeque::new(),
     };
 
     (
-        ImageHashSender { sink },
-        ImageHashReceiver(inner.buffer_unordered(concurrency)),
+        ImageHashSender { sink, pending },
+        ImageHashReceiver {
+            fetches: inner.buffer_unordered(fetch_concurrency),
+            fetches_done: false,
+            hashes: FuturesUnordered::new(),
+            hash_concurrency,
+        },
     )
 }
 
 // TODO: Rename to something like "requester"
 #[derive(Debug)]
 pub struct ImageHashSender {
-    sink: mpsc::UnboundedSender<(BossName, Uri)>,
+    sink: mpsc::UnboundedSender<Request>,
+    // Requests that are queued or in flight, shared with the receiver
+    pending: Rc<Cell<usize>>,
 }
 
 impl ImageHashSender {
-    pub fn request(&self, boss_name: BossName, image_url: &str) {
-        if let Ok(url) = image_url.parse() {
-            let _ = self.sink.unbounded_send((boss_name, url));
+    // Returns whether the request was queued
+    pub fn request(&self, boss_name: BossName, image_url: &str) -> bool {
+        self.send(boss_name, image_url, true)
+    }
+
+    // Like `request`, but the image is fetched even if its URL was hashed
+    // recently, e.g., in case the image behind the URL has changed
+    pub fn request_uncached(&self, boss_name: BossName, image_url: &str) -> bool {
+        self.send(boss_name, image_url, false)
+    }
+
+    fn send(&self, boss_name: BossName, image_url: &str, use_cache: bool) -> bool {
+        let queued = image_url.parse().ok().map_or(false, |uri| {
+            let request = Request {
+                boss_name,
+                uri,
+                use_cache,
+            };
+            self.sink.unbounded_send(request).is_ok()
+        });
+
+        if queued {
+            self.pending.set(self.pending.get() + 1);
         }
+
+        queued
     }
+
+    // The number of requests that haven't produced a result yet. Duplicate
+    // requests for a boss that's already in flight are counted until
+    // they're taken off the queue.
+    pub fn pending_estimate(&self) -> usize {
+        self.pending.get()
+    }
+}
+
+fn dec(pending: &Cell<usize>) {
+    pending.set(pending.get().saturating_sub(1));
+}
+
+#[derive(Debug)]
+struct Request {
+    boss_name: BossName,
+    uri: Uri,
+    use_cache: bool,
 }
 
 #[must_use = "streams do nothing unless polled"]
-pub struct ImageHashReceiver<H>(BufferUnordered<Inner<H>>)
+pub struct ImageHashReceiver<H>
 where
-    H: ImageHasher;
+    H: ImageHasher,
+{
+    fetches: BufferUnordered<Inner<H>>,
+    fetches_done: bool,
+    hashes: FuturesUnordered<H::Hash>,
+    hash_concurrency: usize,
+}
 
 impl<H> Stream for ImageHashReceiver<H>
 where
@@ -62,93 +196,669 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if let Some(result) = try_ready!(self.0.poll()) {
-            self.0.get_mut().outstanding.remove(&result.boss_name);
-            Ok(Async::Ready(Some(result)))
-        } else {
-            Ok(Async::Ready(None))
+        while !self.fetches_done && self.hashes.len() < self.hash_concurrency {
+            match self.fetches.poll()? {
+                Async::Ready(Some(image)) => {
+                    let hash = self.fetches.get_ref().image_hasher.hash(image);
+                    self.hashes.push(hash);
+                }
+                Async::Ready(None) => self.fetches_done = true,
+                Async::NotReady => break,
+            }
+        }
+
+        // Results for requests that didn't need their own fetch
+        {
+            let inner = self.fetches.get_mut();
+            if let Some(result) = inner.ready.pop_front() {
+                inner.finish(&result);
+                return Ok(Async::Ready(Some(result)));
+            }
+        }
+
+        match try_ready!(self.hashes.poll()) {
+            Some(result) => {
+                let inner = self.fetches.get_mut();
+                inner.share_result(&result);
+                inner.finish(&result);
+                Ok(Async::Ready(Some(result)))
+            }
+            None if self.fetches_done => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
         }
     }
 }
 
 pub trait ImageHasher {
-    type Future: Future<Item = BossImageHash, Error = Error>;
+    type Fetch: Future<Item = FetchedImage, Error = Error>;
+    type Hash: Future<Item = BossImageHash, Error = Error>;
+
+    fn fetch(&self, boss_name: BossName, uri: Uri) -> Self::Fetch;
+    fn hash(&self, image: FetchedImage) -> Self::Hash;
 
-    fn hash(&self, boss_name: BossName, uri: Uri) -> Self::Future;
+    // Called by `channel` with the maximum number of images hashed at once,
+    // before anything is hashed
+    fn set_hash_concurrency(&mut self, _concurrency: usize) {}
 }
 
-pub struct HyperImageHasher<'a, C>(pub &'a Client<C>)
+// Applied to boss images after cropping, before they're hashed. Images are
+// hashed on a thread pool, so this has to be shareable between threads.
+pub type ImagePreprocessor = Arc<Fn(&DynamicImage) -> DynamicImage + Send + Sync>;
+
+pub struct HyperImageHasher<'a, C>
 where
-    C: Connect + 'a;
+    C: Connect + 'a,
+{
+    client: &'a Client<C>,
+    // Images are decoded and hashed here, so that hashing doesn't block the
+    // thread that polls the hash futures
+    pool: CpuPool,
+    preprocessor: Option<ImagePreprocessor>,
+    square_tolerance: Option<f64>,
+}
+
+impl<'a, C> HyperImageHasher<'a, C>
+where
+    C: Connect + 'a,
+{
+    // Hashes on a single thread, until `set_hash_concurrency` is called
+    pub fn new(client: &'a Client<C>) -> Self {
+        HyperImageHasher {
+            client,
+            pool: CpuPool::new(1),
+            preprocessor: None,
+            square_tolerance: None,
+        }
+    }
+
+    pub fn with_preprocessor(mut self, preprocessor: ImagePreprocessor) -> Self {
+        self.preprocessor = Some(preprocessor);
+        self
+    }
+
+    // Images with a width to height ratio within `tolerance` of 1 are
+    // assumed to have no name banner, so they're hashed without cropping
+    pub fn with_square_image_tolerance(mut self, tolerance: f64) -> Self {
+        self.square_tolerance = Some(tolerance);
+        self
+    }
+}
 
 impl<'a, C> ImageHasher for HyperImageHasher<'a, C>
 where
     C: Connect + 'a,
 {
-    type Future = Box<Future<Item = BossImageHash, Error = Error>>;
+    type Fetch = Box<Future<Item = FetchedImage, Error = Error>>;
+    type Hash = Box<Future<Item = BossImageHash, Error = Error>>;
 
-    fn hash(&self, boss_name: BossName, uri: Uri) -> Self::Future {
-        let result = self.0
+    fn fetch(&self, boss_name: BossName, uri: Uri) -> Self::Fetch {
+        let result = self.client
             .get(uri)
             .and_then(|resp| resp.body().concat2())
             .then(|r| r.chain_err(|| ErrorKind::ImageHash))
-            .and_then(|bytes| crop_and_hash(&bytes).into_future())
-            .then(move |image_hash| {
-                // If image hashing fails, we don't want to error out,
+            .then(move |bytes| {
+                // If fetching fails, we don't want to error out,
                 // we can just retry next time we get an image.
-                if let Err(ref e) = image_hash {
+                if let Err(ref e) = bytes {
                     // TODO: Maybe don't eprintln
-                    eprintln!("Failed to get image hash: {:?}", e);
+                    eprintln!("Failed to fetch image: {:?}", e);
                 }
 
-                Ok(BossImageHash {
+                Ok(FetchedImage {
                     boss_name,
-                    image_hash: image_hash.ok(),
+                    bytes: bytes.ok().map(|chunk| chunk.to_vec()),
                 })
             });
 
         Box::new(result)
     }
+
+    // Images are hashed on the thread pool, which has a thread for each
+    // image that can be hashed at once
+    fn hash(&self, image: FetchedImage) -> Self::Hash {
+        let preprocessor = self.preprocessor.clone();
+        let square_tolerance = self.square_tolerance;
+
+        let result = self.pool.spawn_fn(move || {
+            let hashed =
+                |bytes: Vec<u8>| crop_and_hash(&bytes, preprocessor.as_ref(), square_tolerance);
+            let image_hash = image.bytes.and_then(|bytes| match hashed(bytes) {
+                Ok(image_hash) => Some(image_hash),
+                Err(e) => {
+                    // TODO: Maybe don't eprintln
+                    eprintln!("Failed to get image hash: {:?}", e);
+                    None
+                }
+            });
+
+            Ok(BossImageHash {
+                boss_name: image.boss_name,
+                image_hash,
+            })
+        });
+
+        Box::new(result)
+    }
+
+    fn set_hash_concurrency(&mut self, concurrency: usize) {
+        self.pool = CpuPool::new(concurrency);
+    }
 }
 
-#[derive(Debug)]
 #[must_use = "streams do nothing unless polled"]
 struct Inner<H> {
     image_hasher: H,
     outstanding: HashSet<BossName>,
-    stream: mpsc::UnboundedReceiver<(BossName, Uri)>,
+    stream: mpsc::UnboundedReceiver<Request>,
+    pacing: Option<Pacing>,
+    pending: Rc<Cell<usize>>,
+    cache: HashCache,
+    // The canonical URL being fetched for each boss
+    fetching: HashMap<BossName, String>,
+    // Bosses waiting for a URL that's already being fetched for another boss
+    waiting: HashMap<String, Vec<BossName>>,
+    ready: VecDeque<BossImageHash>,
+}
+
+impl<H> Inner<H> {
+    // Gives the result of a fetch to every boss waiting for the same URL
+    fn share_result(&mut self, result: &BossImageHash) {
+        let url = match self.fetching.remove(&result.boss_name) {
+            Some(url) => url,
+            None => return,
+        };
+
+        if let Some(image_hash) = result.image_hash {
+            self.cache.insert(url.clone(), image_hash);
+        }
+
+        for boss_name in self.waiting.remove(&url).unwrap_or_default() {
+            self.ready.push_back(BossImageHash {
+                boss_name,
+                image_hash: result.image_hash,
+            });
+        }
+    }
+
+    fn finish(&mut self, result: &BossImageHash) {
+        self.outstanding.remove(&result.boss_name);
+        dec(&self.pending);
+    }
 }
 
 impl<H> Stream for Inner<H>
 where
     H: ImageHasher,
 {
-    type Item = H::Future;
+    type Item = H::Fetch;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
+            // Leave pending requests in the channel until there's budget for them
+            if let Some(ref mut pacing) = self.pacing {
+                try_ready!(pacing.poll_budget());
+            }
+
             let polled = self.stream.poll().map_err(|()| ErrorKind::ImageHash);
 
-            if let Some((boss_name, uri)) = try_ready!(polled) {
-                if !self.outstanding.contains(&boss_name) {
-                    self.outstanding.insert(boss_name.clone());
-                    let result = self.image_hasher.hash(boss_name, uri);
-                    return Ok(Async::Ready(Some(result)));
+            let request = match try_ready!(polled) {
+                Some(request) => request,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let boss_name = request.boss_name;
+            if self.outstanding.contains(&boss_name) {
+                // Duplicates are dropped, so they'll never produce a result
+                dec(&self.pending);
+                continue;
+            }
+
+            let url = cache::canonical_url(&request.uri.to_string());
+            if request.use_cache {
+                if let Some(image_hash) = self.cache.get(&url) {
+                    self.ready.push_back(BossImageHash {
+                        boss_name,
+                        image_hash: Some(image_hash),
+                    });
+                    continue;
                 }
-            } else {
-                return Ok(Async::Ready(None));
             }
+
+            self.outstanding.insert(boss_name.clone());
+
+            match self.waiting.entry(url.clone()) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().push(boss_name);
+                    continue;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(Vec::new());
+                }
+            }
+
+            if let Some(ref mut pacing) = self.pacing {
+                pacing.started += 1;
+            }
+
+            self.fetching.insert(boss_name.clone(), url);
+            let result = self.image_hasher.fetch(boss_name, request.uri);
+            return Ok(Async::Ready(Some(result)));
         }
     }
 }
 
+// Smaller images (e.g., tracking pixels) are never boss images, and cropping
+// them may leave nothing to hash
+const MIN_IMAGE_SIZE: u32 = 32;
+
+fn crop_and_hash(
+    bytes: &[u8],
+    preprocessor: Option<&ImagePreprocessor>,
+    square_tolerance: Option<f64>,
+) -> Result<ImageHash> {
+    let img = image::load_from_memory(bytes).chain_err(|| ErrorKind::ImageHash)?;
+    hash_boss_image(img, preprocessor, square_tolerance)
+}
+
 // Specifically for raid boss images. Remove the lower 25% of the image
-// to get the boss image without the language-specific boss name.
-fn crop_and_hash(bytes: &[u8]) -> Result<ImageHash> {
-    let mut img = image::load_from_memory(bytes).chain_err(|| ErrorKind::ImageHash)?;
+// to get the boss image without the language-specific boss name, unless
+// the image is close enough to square that it can't have a name banner.
+fn hash_boss_image(
+    mut img: DynamicImage,
+    preprocessor: Option<&ImagePreprocessor>,
+    square_tolerance: Option<f64>,
+) -> Result<ImageHash> {
     let (w, h) = img.dimensions();
-    img = img.crop(0, 0, w, h * 3 / 4);
+    if w < MIN_IMAGE_SIZE || h < MIN_IMAGE_SIZE {
+        bail!(ErrorKind::ImageTooSmall(w, h));
+    }
+
+    let is_square = square_tolerance.map_or(false, |tolerance| {
+        (f64::from(w) / f64::from(h) - 1.0).abs() <= tolerance
+    });
+
+    if !is_square {
+        img = img.crop(0, 0, w, h * 3 / 4);
+    }
+
+    if let Some(preprocess) = preprocessor {
+        img = preprocess(&img);
+    }
 
-    Ok(ImageHash::new(&img))
+    let hash = ImageHash::new(&img);
+    if hash.is_degenerate() {
+        bail!(ErrorKind::DegenerateImageHash);
+    }
+
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use clock::ManualClock;
+    use futures::future;
+    use futures::future::FutureResult;
+    use futures::unsync::oneshot;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use tokio_core::reactor::Core;
+
+    struct CountingHasher(Rc<Cell<usize>>);
+
+    impl ImageHasher for CountingHasher {
+        type Fetch = FutureResult<FetchedImage, Error>;
+        type Hash = FutureResult<BossImageHash, Error>;
+
+        fn fetch(&self, boss_name: BossName, _uri: Uri) -> Self::Fetch {
+            self.0.set(self.0.get() + 1);
+
+            future::ok(FetchedImage {
+                boss_name,
+                bytes: Some(vec![]),
+            })
+        }
+
+        fn hash(&self, image: FetchedImage) -> Self::Hash {
+            future::ok(BossImageHash {
+                boss_name: image.boss_name,
+                image_hash: Some(ImageHash::from(0)),
+            })
+        }
+    }
+
+    type Pending = Rc<RefCell<Vec<oneshot::Sender<()>>>>;
+
+    // Each stage stays in flight until released by the test
+    #[derive(Default)]
+    struct StagedHasher {
+        fetches: Pending,
+        hashes: Pending,
+    }
+
+    impl StagedHasher {
+        fn wait(pending: &Pending) -> oneshot::Receiver<()> {
+            let (tx, rx) = oneshot::channel();
+            pending.borrow_mut().push(tx);
+            rx
+        }
+
+        fn release(pending: &Pending) {
+            let senders = pending.replace(vec![]);
+            for tx in senders {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    impl ImageHasher for StagedHasher {
+        type Fetch = Box<Future<Item = FetchedImage, Error = Error>>;
+        type Hash = Box<Future<Item = BossImageHash, Error = Error>>;
+
+        fn fetch(&self, boss_name: BossName, _uri: Uri) -> Self::Fetch {
+            let result = Self::wait(&self.fetches)
+                .map_err(|_| ErrorKind::ImageHash.into())
+                .map(|()| FetchedImage {
+                    boss_name,
+                    bytes: Some(vec![]),
+                });
+
+            Box::new(result)
+        }
+
+        fn hash(&self, image: FetchedImage) -> Self::Hash {
+            let result = Self::wait(&self.hashes)
+                .map_err(|_| ErrorKind::ImageHash.into())
+                .map(|()| BossImageHash {
+                    boss_name: image.boss_name,
+                    image_hash: Some(ImageHash::from(0)),
+                });
+
+            Box::new(result)
+        }
+    }
+
+    fn poll_once<S: Stream>(s: &mut S) -> Poll<Option<S::Item>, S::Error> {
+        future::lazy(|| Ok::<_, ()>(s.poll())).wait().unwrap()
+    }
+
+    fn drain<S: Stream>(s: &mut S) -> usize {
+        let mut count = 0;
+        while let Ok(Async::Ready(Some(_))) = poll_once(s) {
+            count += 1;
+        }
+        count
+    }
+
+    // Each boss has a different image, so that none of them share a fetch
+    fn request(sender: &ImageHashSender, i: usize) {
+        let url = format!("http://example.com/{}.png", i);
+        sender.request(format!("Lv{} Boss", i).into(), &url);
+    }
+
+    #[test]
+    fn pacing_limits_requests_per_interval() {
+        let clock = ManualClock::new(Utc.timestamp(0, 0));
+        let started = Rc::new(Cell::new(0));
+
+        let options = PacingOptions {
+            max_per_interval: 2,
+            interval: Duration::from_secs(60),
+        };
+
+        let (sender, mut receiver) = channel(
+            CountingHasher(started.clone()),
+            5,
+            5,
+            Some(Pacing::new(options, &clock)),
+        );
+
+        for i in 0..5 {
+            request(&sender, i);
+        }
+
+        assert_eq!(drain(&mut receiver), 2);
+        assert_eq!(started.get(), 2);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(started.get(), 2);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(drain(&mut receiver), 2);
+        assert_eq!(started.get(), 4);
+
+        // Requests are delayed, never dropped
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(drain(&mut receiver), 1);
+        assert_eq!(started.get(), 5);
+    }
+
+    #[test]
+    fn no_pacing() {
+        let started = Rc::new(Cell::new(0));
+        let (sender, mut receiver) = channel(CountingHasher(started.clone()), 5, 5, None);
+
+        for i in 0..5 {
+            request(&sender, i);
+        }
+
+        assert_eq!(drain(&mut receiver), 5);
+        assert_eq!(started.get(), 5);
+    }
+
+    #[test]
+    fn share_fetches_for_the_same_url() {
+        let started = Rc::new(Cell::new(0));
+        let (sender, mut receiver) = channel(CountingHasher(started.clone()), 5, 5, None);
+        let url = "http://example.com/a.png";
+
+        sender.request("Lv60 Boss".into(), url);
+        sender.request("Lv75 Boss".into(), url);
+        sender.request("Lv100 Boss".into(), &format!("{}:orig", url));
+        sender.request("Lv120 Boss".into(), "http://example.com/b.png");
+
+        let mut results = Vec::new();
+        while let Ok(Async::Ready(Some(result))) = poll_once(&mut receiver) {
+            results.push(result.boss_name.to_string());
+        }
+        results.sort();
+
+        assert_eq!(started.get(), 2);
+        assert_eq!(results, vec!["Lv100 Boss", "Lv120 Boss", "Lv60 Boss", "Lv75 Boss"]);
+        assert_eq!(sender.pending_estimate(), 0);
+
+        // Hashed URLs aren't fetched again, unless asked to
+        sender.request("Lv150 Boss".into(), url);
+        assert_eq!(drain(&mut receiver), 1);
+        assert_eq!(started.get(), 2);
+
+        sender.request_uncached("Lv150 Boss".into(), url);
+        assert_eq!(drain(&mut receiver), 1);
+        assert_eq!(started.get(), 3);
+    }
+
+    #[test]
+    fn fetch_limited_separately_from_hashing() {
+        let hasher = StagedHasher::default();
+        let (fetches, hashes) = (hasher.fetches.clone(), hasher.hashes.clone());
+        let (sender, mut receiver) = channel(hasher, 3, 1, None);
+
+        for i in 0..5 {
+            request(&sender, i);
+        }
+
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(fetches.borrow().len(), 3);
+        assert_eq!(hashes.borrow().len(), 0);
+
+        // Completed fetches wait for a free hashing slot
+        StagedHasher::release(&fetches);
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(fetches.borrow().len(), 0);
+        assert_eq!(hashes.borrow().len(), 1);
+
+        let mut completed = 0;
+        while completed < 5 {
+            StagedHasher::release(&fetches);
+            StagedHasher::release(&hashes);
+            completed += drain(&mut receiver);
+
+            assert!(fetches.borrow().len() <= 3);
+            assert!(hashes.borrow().len() <= 1);
+        }
+    }
+
+    #[test]
+    fn hashing_limited_separately_from_fetch() {
+        let hasher = StagedHasher::default();
+        let (fetches, hashes) = (hasher.fetches.clone(), hasher.hashes.clone());
+        let (sender, mut receiver) = channel(hasher, 1, 3, None);
+
+        for i in 0..5 {
+            request(&sender, i);
+        }
+
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(fetches.borrow().len(), 1);
+
+        for expected_hashes in 1..3 {
+            StagedHasher::release(&fetches);
+            assert_eq!(drain(&mut receiver), 0);
+            assert_eq!(fetches.borrow().len(), 1);
+            assert_eq!(hashes.borrow().len(), expected_hashes);
+        }
+
+        // Once hashing is full, no more fetches are started until a slot frees up
+        StagedHasher::release(&fetches);
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(fetches.borrow().len(), 0);
+        assert_eq!(hashes.borrow().len(), 3);
+
+        StagedHasher::release(&hashes);
+        assert_eq!(drain(&mut receiver), 3);
+        assert_eq!(fetches.borrow().len(), 1);
+        assert_eq!(hashes.borrow().len(), 0);
+
+        StagedHasher::release(&fetches);
+        assert_eq!(drain(&mut receiver), 0);
+        assert_eq!(fetches.borrow().len(), 1);
+        assert_eq!(hashes.borrow().len(), 1);
+
+        StagedHasher::release(&fetches);
+        StagedHasher::release(&hashes);
+        assert_eq!(drain(&mut receiver), 1);
+        StagedHasher::release(&hashes);
+        assert_eq!(drain(&mut receiver), 1);
+    }
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(width, height, |x, y| {
+            image::Luma([((x * 7 + y * 3) % 256) as u8])
+        }))
+    }
+
+    #[test]
+    fn preprocess_before_hashing() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let preprocessor: ImagePreprocessor = {
+            let calls = calls.clone();
+            Arc::new(move |img: &DynamicImage| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                img.fliph()
+            })
+        };
+
+        let img = gradient(64, 64);
+        let hash = hash_boss_image(img.clone(), Some(&preprocessor), None).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Applied to the cropped image, with a stable result
+        assert_eq!(hash, ImageHash::new(&img.clone().crop(0, 0, 64, 48).fliph()));
+        assert_eq!(hash_boss_image(img.clone(), Some(&preprocessor), None).unwrap(), hash);
+        assert_ne!(hash_boss_image(img, None, None).unwrap(), hash);
+    }
+
+    #[test]
+    fn skip_cropping_square_images() {
+        let square = gradient(64, 64);
+        let tall = gradient(64, 96);
+
+        let hash = |img: &DynamicImage, tolerance| hash_boss_image(img.clone(), None, tolerance);
+        let cropped = |img: &DynamicImage| {
+            let (w, h) = img.dimensions();
+            ImageHash::new(&img.clone().crop(0, 0, w, h * 3 / 4))
+        };
+
+        assert_eq!(hash(&square, Some(0.05)).unwrap(), ImageHash::new(&square));
+        assert_eq!(hash(&tall, Some(0.05)).unwrap(), cropped(&tall));
+
+        // Everything is cropped by default
+        assert_eq!(hash(&square, None).unwrap(), cropped(&square));
+
+        // Images that are only close to square need a larger tolerance
+        let near_square = gradient(64, 60);
+        assert_eq!(hash(&near_square, Some(0.05)).unwrap(), cropped(&near_square));
+        assert_eq!(hash(&near_square, Some(0.1)).unwrap(), ImageHash::new(&near_square));
+    }
+
+    #[test]
+    fn reject_tiny_images() {
+        match hash_boss_image(gradient(2, 2), None, None) {
+            Err(Error(ErrorKind::ImageTooSmall(2, 2), _)) => {}
+            other => panic!("expected ImageTooSmall, got {:?}", other),
+        }
+
+        assert!(hash_boss_image(gradient(32, 32), None, None).is_ok());
+    }
+
+    #[test]
+    fn hash_on_thread_pool() {
+        let core = Core::new().unwrap();
+        let client = Client::new(&core.handle());
+
+        let hashed_on = Arc::new(Mutex::new(None));
+        let preprocessor: ImagePreprocessor = {
+            let hashed_on = hashed_on.clone();
+            Arc::new(move |img: &DynamicImage| {
+                *hashed_on.lock().unwrap() = Some(thread::current().id());
+                img.clone()
+            })
+        };
+
+        let mut hasher = HyperImageHasher::new(&client).with_preprocessor(preprocessor);
+        hasher.set_hash_concurrency(2);
+
+        let img = gradient(64, 64);
+        let mut bytes = Vec::new();
+        img.save(&mut bytes, image::ImageFormat::PNG).unwrap();
+
+        let image = FetchedImage {
+            boss_name: BossName::from("Lvl 60 Ozorotter"),
+            bytes: Some(bytes),
+        };
+        let result = hasher.hash(image).wait().unwrap();
+
+        assert_eq!(result.image_hash, Some(hash_boss_image(img, None, None).unwrap()));
+        let hashed_on = hashed_on.lock().unwrap().expect("image wasn't hashed");
+        assert_ne!(hashed_on, thread::current().id());
+    }
+
+    #[test]
+    fn reject_degenerate_hashes() {
+        match hash_boss_image(DynamicImage::new_rgb8(64, 64), None, None) {
+            Err(Error(ErrorKind::DegenerateImageHash, _)) => {}
+            other => panic!("expected DegenerateImageHash, got {:?}", other),
+        }
+
+        assert!(ImageHash::from(0).is_degenerate());
+        assert!(ImageHash::from(!0).is_degenerate());
+        assert!(ImageHash::from(!1).is_degenerate());
+        assert!(!ImageHash::from(2).is_degenerate());
+    }
 }
@@ -1,9 +1,12 @@
 use image::{DynamicImage, FilterType};
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
 
 const SIZE: usize = 32;
 const SMALL_SIZE: usize = 8;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct ImageHash(u64);
 
 impl ImageHash {
@@ -14,6 +17,37 @@ impl ImageHash {
     pub fn value(&self) -> u64 {
         self.0
     }
+
+    // Images without any detail (e.g., a solid color) have every bit set to
+    // the same value, and would match each other. The lowest bit is always 0.
+    pub fn is_degenerate(self) -> bool {
+        self.0 == 0 || self.0 | 1 == !0
+    }
+
+    // The number of bits that differ between two hashes
+    pub fn distance(self, other: ImageHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    // From the least significant bit to the most significant
+    pub fn bits(self) -> impl Iterator<Item = bool> {
+        (0..64).map(move |i| self.0 & (1 << i) != 0)
+    }
+}
+
+// Formatted as 16 hex digits
+impl fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for ImageHash {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(ImageHash)
+    }
 }
 
 impl From<u64> for ImageHash {
@@ -91,3 +125,46 @@ fn apply_dct(f: &[[f64; SIZE]; SIZE]) -> [[f64; SIZE]; SIZE] {
 
     out
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        let a = ImageHash::from(0b1011);
+        let b = ImageHash::from(0b0110);
+
+        assert_eq!(a.distance(b), 3);
+        assert_eq!(b.distance(a), 3);
+        assert_eq!(a.distance(a), 0);
+        assert_eq!(ImageHash::from(0).distance(ImageHash::from(!0)), 64);
+    }
+
+    #[test]
+    fn bits() {
+        let hash = ImageHash::from(0b101 | 1 << 63);
+        let bits = hash.bits().collect::<Vec<_>>();
+
+        assert_eq!(bits.len(), 64);
+        assert_eq!(&bits[0..4], &[true, false, true, false]);
+        assert!(bits[63]);
+        assert_eq!(bits.iter().filter(|&&bit| bit).count(), 3);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        for &value in &[0, 1, 0xabc, 0x8000_0000_0000_0001, !0] {
+            let hash = ImageHash::from(value);
+            let hex = hash.to_string();
+
+            assert_eq!(hex.len(), 16);
+            assert_eq!(hex.parse::<ImageHash>(), Ok(hash));
+        }
+
+        assert_eq!(ImageHash::from(0xabc).to_string(), "0000000000000abc");
+        assert_eq!("ABC".parse::<ImageHash>(), Ok(ImageHash::from(0xabc)));
+        assert!("xyz".parse::<ImageHash>().is_err());
+        assert!("10000000000000000".parse::<ImageHash>().is_err());
+    }
+}
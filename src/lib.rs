@@ -8,13 +8,30 @@ extern crate lazy_static;
 extern crate serde_derive;
 
 extern crate chrono;
+extern crate futures_cpupool;
 extern crate hyper;
 extern crate image;
+extern crate rand;
 extern crate regex;
+extern crate serde;
+extern crate serde_json;
 extern crate string_cache;
 extern crate tokio_core;
 extern crate twitter_stream;
 
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(any(feature = "gbf-compat", feature = "http-service"))]
+extern crate bytes;
+#[cfg(feature = "backfill")]
+extern crate oauthcli;
+#[cfg(feature = "http-service")]
+extern crate percent_encoding;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+#[cfg(feature = "backfill")]
+extern crate url;
+
 mod client;
 pub mod model;
 pub mod raid;
@@ -22,9 +39,25 @@ pub mod error;
 mod id_pool;
 mod broadcast;
 mod circular_buffer;
-mod image_hash;
+pub mod image_hash;
+mod token;
+pub mod clock;
 pub mod metrics;
+pub mod protocol;
+pub mod tweet_store;
+#[cfg(feature = "http-service")]
+pub mod http;
+
+pub use broadcast::{BoxMapper, DynamicMapper, MessageMapper, MessageStream, NoOpSubscriber,
+                    SeqMapper, SharedPayload, Subscriber};
+pub use client::{ActivityStats, BuildError, Client, ClientBuilder, ImageHashStatus, MapperCheck,
+                 ResumeToken, Subscription, Worker, WorkerConfig, WorkerStats};
+pub use id_pool::Id;
+#[cfg(feature = "test-util")]
+pub use id_pool::IdPool;
+pub use image_hash::PacingOptions;
+pub use token::{Token, TokenExt};
+pub use tweet_store::TweetStore;
 
-pub use broadcast::{NoOpSubscriber, Subscriber};
-pub use client::{Client, ClientBuilder, Subscription, Worker};
-pub use twitter_stream::Token;
+#[deprecated(note = "renamed to `Client`")]
+pub type Petronel<Sub, M = ()> = Client<Sub, M>;
@@ -1,15 +1,73 @@
-use model::BossName;
+use chrono::Duration;
+use clock::Clock;
+use model::{BossName, DateTime, Language, MessageKind};
+use raid::StreamWarnings;
+use std::cell::{Cell, RefCell};
+use std::cmp::max;
 use std::collections::HashMap;
-use std::collections::hash_map::Entry;
+use std::rc::Rc;
+
+// The categories of events handled by the worker
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub enum EventKind {
+    // Raid tweets and image hash results
+    Ingest,
+    // Requests from subscriptions, and heartbeats
+    Subscriber,
+    // Requests from `Client`
+    Client,
+}
 
 pub trait Metrics {
     type Export;
 
     fn set_total_subscriber_count(&mut self, count: u32);
     fn set_follower_count(&mut self, boss_name: &BossName, count: u32);
-    fn inc_tweet_count(&mut self, boss_name: &BossName);
+    fn inc_tweet_count(&mut self, boss_name: &BossName, language: Language);
+    // Called for each tweet with the number of followers it was sent to
+    // (including followers of translated bosses)
+    fn inc_tweet_delivered(&mut self, _boss_name: &BossName, _followers: u32) {}
+    // Called for tweets from `Client::inject_raid`, which are also counted
+    // by `inc_tweet_count`
+    fn inc_injected_tweet(&mut self, _boss_name: &BossName) {}
+    // Called for each tweet with the number of distinct users among the
+    // boss' recent tweets
+    fn set_unique_users(&mut self, _boss_name: &BossName, _count: u32) {}
+    // Called when a request for a boss name that doesn't exist is resolved to
+    // `boss_name`, an existing boss that lists the name as a translation
+    fn inc_translated_resolution(&mut self, _boss_name: &BossName) {}
+    // Called when a boss from `ClientBuilder::with_bosses` is seen with the
+    // image it was seeded with, so its image hash is reused instead of being
+    // fetched again
+    fn inc_seeded_hash_reused(&mut self, _boss_name: &BossName) {}
+    // Called when an image hash is received for a boss
+    fn inc_image_hashed(&mut self, _boss_name: &BossName) {}
+    // Called for each other boss whose image matches a newly hashed boss'
+    // image, including matches that are waiting for review
+    fn inc_hash_match(&mut self, _boss_name: &BossName) {}
+    // Called for each translation that is linked automatically because of
+    // a hash match, with the boss whose image was hashed
+    fn inc_translation_created(&mut self, _boss_name: &BossName) {}
+    // Called when a check from `ClientBuilder::with_image_checker` fails or
+    // can't tell whether the boss' image still exists
+    fn inc_inconclusive_image_check(&mut self, _boss_name: &BossName) {}
+    // Called after each event is handled, with the time it took according
+    // to the worker's clock
+    fn observe_event_duration(&mut self, _kind: EventKind, _micros: u64) {}
+    // Called when a subscriber asks for a message (e.g., a boss list) that
+    // the mapper returned `None` for
+    fn inc_unmappable_message(&mut self, _kind: MessageKind) {}
+    // Called before each export with the counts from the stream's
+    // `StreamWarnings`, if they were given to `ClientBuilder`
+    fn set_stream_warnings(&mut self, _warnings: &StreamWarnings) {}
     fn remove_boss(&mut self, boss_name: &BossName);
     fn export(&self) -> Self::Export;
+
+    // For collectors that want per-interval deltas. Implementations
+    // should reset counters, but keep gauges (e.g., follower counts).
+    fn export_and_reset(&mut self) -> Self::Export {
+        self.export()
+    }
 }
 
 pub struct NoOp;
@@ -18,7 +76,7 @@ impl Metrics for NoOp {
 
     fn set_total_subscriber_count(&mut self, _count: u32) {}
     fn set_follower_count(&mut self, _boss_name: &BossName, _count: u32) {}
-    fn inc_tweet_count(&mut self, _boss_name: &BossName) {}
+    fn inc_tweet_count(&mut self, _boss_name: &BossName, _language: Language) {}
     fn remove_boss(&mut self, _boss_name: &BossName) {}
     fn export(&self) -> Self::Export {}
 }
@@ -31,8 +89,14 @@ where
         inner: SimpleMetrics {
             total_subscriber_count: 0,
             boss_counts: HashMap::new(),
+            boss_counts_today: None,
+            language_counts: HashMap::new(),
+            event_durations: HashMap::new(),
+            unmappable_messages: HashMap::new(),
+            stream_warnings: WarningCounts::default(),
         },
         export_function,
+        daily: None,
     }
 }
 
@@ -40,18 +104,192 @@ where
 pub struct Simple<F> {
     inner: SimpleMetrics,
     export_function: F,
+    #[serde(skip_serializing)]
+    daily: Option<DailyRollover>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct SimpleMetrics {
     total_subscriber_count: u32,
     boss_counts: HashMap<BossName, Counts>,
+    // Counters since the most recent daily reset, if enabled. These are
+    // reset on export too, which only borrows the metrics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boss_counts_today: Option<RefCell<HashMap<BossName, Counters>>>,
+    // Tweets received in each language, across all bosses. Like the other
+    // counters, these are zeroed by `export_and_reset`.
+    language_counts: HashMap<Language, u64>,
+    // How long the worker took to handle each kind of event. These are
+    // also cleared by `export_and_reset`.
+    event_durations: HashMap<EventKind, EventDurations>,
+    // Requested messages that the mapper couldn't represent, by kind. These
+    // are zeroed by `export_and_reset`.
+    unmappable_messages: HashMap<MessageKind, u64>,
+    // Totals since the stream started, which are kept by `export_and_reset`
+    stream_warnings: WarningCounts,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+impl SimpleMetrics {
+    pub fn language_counts(&self) -> &HashMap<Language, u64> {
+        &self.language_counts
+    }
+
+    pub fn event_durations(&self) -> &HashMap<EventKind, EventDurations> {
+        &self.event_durations
+    }
+
+    pub fn unmappable_messages(&self) -> &HashMap<MessageKind, u64> {
+        &self.unmappable_messages
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct EventDurations {
+    pub count: u64,
+    pub total_micros: u64,
+    pub avg_micros: u64,
+    pub max_micros: u64,
+}
+
+// Tracks when the daily counters should next be reset. Resets happen
+// lazily, when a counter is incremented or the metrics are exported after
+// the reset time has passed.
+#[derive(Clone, Debug)]
+struct DailyRollover {
+    clock: Rc<Clock>,
+    reset_hour: u32,
+    next_reset: Cell<DateTime>,
+}
+
+impl PartialEq for DailyRollover {
+    fn eq(&self, other: &Self) -> bool {
+        self.reset_hour == other.reset_hour && self.next_reset == other.next_reset
+    }
+}
+
+impl DailyRollover {
+    fn new(clock: Rc<Clock>, reset_hour: u32) -> Self {
+        let next_reset = Self::next_reset_after(clock.now(), reset_hour);
+
+        DailyRollover {
+            clock,
+            reset_hour,
+            next_reset: Cell::new(next_reset),
+        }
+    }
+
+    fn next_reset_after(now: DateTime, reset_hour: u32) -> DateTime {
+        let reset_today = now.date().and_hms(reset_hour, 0, 0);
+
+        if reset_today > now {
+            reset_today
+        } else {
+            reset_today + Duration::days(1)
+        }
+    }
+
+    // Returns true if the reset time has passed since this was last called
+    fn should_reset(&self) -> bool {
+        let now = self.clock.now();
+
+        if now >= self.next_reset.get() {
+            self.next_reset.set(Self::next_reset_after(now, self.reset_hour));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<F> Simple<F> {
+    // Additionally keep counters for the current day, which are reset every
+    // day at `reset_hour` (0-23) UTC
+    pub fn with_daily_counts<C>(mut self, clock: C, reset_hour: u32) -> Self
+    where
+        C: Clock + 'static,
+    {
+        assert!(reset_hour < 24, "reset hour must be between 0 and 23");
+
+        self.daily = Some(DailyRollover::new(Rc::new(clock), reset_hour));
+        self.inner.boss_counts_today = Some(RefCell::new(HashMap::new()));
+        self
+    }
+
+    // Clears today's counters if the reset time has passed
+    fn roll_over(&self) {
+        if let (Some(daily), Some(today)) =
+            (self.daily.as_ref(), self.inner.boss_counts_today.as_ref())
+        {
+            if daily.should_reset() {
+                today.borrow_mut().clear();
+            }
+        }
+    }
+
+    // Applies `f` to the boss' counters (and today's counters, if enabled),
+    // if the boss has any counts
+    fn update_counters<G>(&mut self, boss_name: &BossName, f: G)
+    where
+        G: Fn(&mut Counters),
+    {
+        match self.inner.boss_counts.get_mut(boss_name) {
+            Some(counts) => f(&mut counts.counters),
+            None => return,
+        }
+
+        self.roll_over();
+
+        if let Some(ref today) = self.inner.boss_counts_today {
+            // TODO: Maybe have a way that doesn't require cloning
+            f(today
+                .borrow_mut()
+                .entry(boss_name.clone())
+                .or_insert_with(Counters::default));
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 struct Counts {
+    gauges: Gauges,
+    counters: Counters,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct WarningCounts {
+    stall_warnings: u64,
+    other_warnings: u64,
+    disconnects: u64,
+    parse_errors: u64,
+}
+
+// Point-in-time values, which are kept by `export_and_reset`
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct Gauges {
     followers: u32,
-    tweets: u32,
+    unique_users: u32,
+}
+
+// Cumulative values, which are zeroed by `export_and_reset`
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct Counters {
+    tweets: u64,
+    // Tweets from `Client::inject_raid`, which are included in `tweets`
+    injected_tweets: u64,
+    // Tweets that were sent to at least one follower
+    delivered_tweets: u64,
+    // Requests for a translated name that were resolved to this boss
+    translated_resolutions: u64,
+    // Image hashes from `ClientBuilder::with_bosses` that were kept
+    seeded_hashes_reused: u64,
+    images_hashed: u64,
+    // Other bosses with a matching image hash, counted each time this
+    // boss' image is matched
+    hash_matches: u64,
+    // Translations linked because of a matching image hash
+    translations_created: u64,
+    // Image checks that failed, or didn't say whether the image still exists
+    inconclusive_image_checks: u64,
 }
 
 impl<T, F> Metrics for Simple<F>
@@ -66,40 +304,314 @@ where
 
     fn set_follower_count(&mut self, boss_name: &BossName, count: u32) {
         // TODO: Maybe have a way that doesn't require cloning
-        match self.inner.boss_counts.entry(boss_name.clone()) {
-            Entry::Occupied(mut e) => {
-                e.get_mut().followers = count;
-            }
-            Entry::Vacant(e) => {
-                e.insert(Counts {
-                    followers: 0,
-                    tweets: 1,
-                });
-            }
-        }
+        self.inner
+            .boss_counts
+            .entry(boss_name.clone())
+            .or_insert_with(Counts::default)
+            .gauges
+            .followers = count;
     }
 
-    fn inc_tweet_count(&mut self, boss_name: &BossName) {
+    fn inc_tweet_count(&mut self, boss_name: &BossName, language: Language) {
         // TODO: Maybe have a way that doesn't require cloning
-        match self.inner.boss_counts.entry(boss_name.clone()) {
-            Entry::Occupied(mut e) => {
-                let counts = e.get_mut();
-                counts.tweets = counts.tweets.wrapping_add(1);
-            }
-            Entry::Vacant(e) => {
-                e.insert(Counts {
-                    followers: 0,
-                    tweets: 1,
-                });
-            }
+        self.inner
+            .boss_counts
+            .entry(boss_name.clone())
+            .or_insert_with(Counts::default);
+
+        {
+            let count = self.inner.language_counts.entry(language).or_insert(0);
+            *count = count.saturating_add(1);
         }
+
+        self.update_counters(boss_name, |c| c.tweets = c.tweets.saturating_add(1));
+    }
+
+    fn set_unique_users(&mut self, boss_name: &BossName, count: u32) {
+        if let Some(counts) = self.inner.boss_counts.get_mut(boss_name) {
+            counts.gauges.unique_users = count;
+        }
+    }
+
+    fn inc_tweet_delivered(&mut self, boss_name: &BossName, followers: u32) {
+        if followers == 0 {
+            return;
+        }
+
+        self.update_counters(boss_name, |c| {
+            c.delivered_tweets = c.delivered_tweets.saturating_add(1)
+        });
+    }
+
+    fn inc_injected_tweet(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.injected_tweets = c.injected_tweets.saturating_add(1)
+        });
+    }
+
+    fn inc_translated_resolution(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.translated_resolutions = c.translated_resolutions.saturating_add(1)
+        });
+    }
+
+    fn inc_seeded_hash_reused(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.seeded_hashes_reused = c.seeded_hashes_reused.saturating_add(1)
+        });
+    }
+
+    fn inc_image_hashed(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.images_hashed = c.images_hashed.saturating_add(1)
+        });
+    }
+
+    fn inc_hash_match(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.hash_matches = c.hash_matches.saturating_add(1)
+        });
+    }
+
+    fn inc_translation_created(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.translations_created = c.translations_created.saturating_add(1)
+        });
+    }
+
+    fn inc_inconclusive_image_check(&mut self, boss_name: &BossName) {
+        self.update_counters(boss_name, |c| {
+            c.inconclusive_image_checks = c.inconclusive_image_checks.saturating_add(1)
+        });
+    }
+
+    fn observe_event_duration(&mut self, kind: EventKind, micros: u64) {
+        let durations = self.inner
+            .event_durations
+            .entry(kind)
+            .or_insert_with(EventDurations::default);
+
+        durations.count = durations.count.saturating_add(1);
+        durations.total_micros = durations.total_micros.saturating_add(micros);
+        durations.avg_micros = durations.total_micros / durations.count;
+        durations.max_micros = max(durations.max_micros, micros);
+    }
+
+    fn inc_unmappable_message(&mut self, kind: MessageKind) {
+        let count = self.inner.unmappable_messages.entry(kind).or_insert(0);
+        *count = count.saturating_add(1);
+    }
+
+    fn set_stream_warnings(&mut self, warnings: &StreamWarnings) {
+        self.inner.stream_warnings = WarningCounts {
+            stall_warnings: warnings.stall_warnings(),
+            other_warnings: warnings.other_warnings(),
+            disconnects: warnings.disconnects(),
+            parse_errors: warnings.parse_errors(),
+        };
     }
 
     fn remove_boss(&mut self, boss_name: &BossName) {
         self.inner.boss_counts.remove(boss_name);
+
+        if let Some(ref today) = self.inner.boss_counts_today {
+            today.borrow_mut().remove(boss_name);
+        }
     }
 
     fn export(&self) -> Self::Export {
+        // Otherwise, a day without new counts would be exported with the
+        // previous day's counts
+        self.roll_over();
         (self.export_function)(&self.inner)
     }
+
+    fn export_and_reset(&mut self) -> Self::Export {
+        let exported = self.export();
+
+        for counts in self.inner.boss_counts.values_mut() {
+            counts.counters = Counters::default();
+        }
+        self.inner.language_counts.clear();
+        self.inner.event_durations.clear();
+        self.inner.unmappable_messages.clear();
+
+        exported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use clock::ManualClock;
+    use std::time::Duration as StdDuration;
+
+    fn tweets(metrics: &SimpleMetrics) -> u64 {
+        metrics.boss_counts[&BossName::from("Lv60 オオゾラッコ")]
+            .counters
+            .tweets
+    }
+
+    #[test]
+    fn export_and_reset() {
+        let mut metrics = simple(tweets);
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        metrics.set_follower_count(&boss_name, 3);
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export_and_reset(), 2);
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), 1);
+        assert_eq!(metrics.inner.boss_counts[&boss_name].gauges.followers, 3);
+    }
+
+    #[test]
+    fn export_without_reset() {
+        let mut metrics = simple(tweets);
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), 2);
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), 3);
+    }
+
+    fn tweets_today(metrics: &SimpleMetrics) -> u64 {
+        metrics
+            .boss_counts_today
+            .as_ref()
+            .map_or(0, |today| {
+                today
+                    .borrow()
+                    .get(&BossName::from("Lv60 オオゾラッコ"))
+                    .map_or(0, |counters| counters.tweets)
+            })
+    }
+
+    #[test]
+    fn saturate_tweet_count() {
+        let mut metrics = simple(tweets);
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        metrics
+            .inner
+            .boss_counts
+            .get_mut(&boss_name)
+            .unwrap()
+            .counters
+            .tweets = u64::max_value() - 1;
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), u64::max_value());
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), u64::max_value());
+    }
+
+    #[test]
+    fn daily_counts_roll_over() {
+        let clock = ManualClock::new(Utc.ymd(2017, 9, 1).and_hms(3, 30, 0));
+        let mut metrics = simple(|m| (tweets(m), tweets_today(m)))
+            .with_daily_counts(clock.clone(), 4);
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (2, 2));
+
+        // Just before the reset time
+        clock.advance(StdDuration::from_secs(30 * 60 - 1));
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (3, 3));
+
+        // Exactly at the reset time
+        clock.advance(StdDuration::from_secs(1));
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (4, 1));
+
+        // The next reset is a day later
+        clock.advance(StdDuration::from_secs(24 * 60 * 60 - 1));
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (5, 2));
+
+        // Skipping multiple days only resets once
+        clock.advance(StdDuration::from_secs(3 * 24 * 60 * 60));
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (6, 1));
+
+        // Export and reset doesn't affect daily counts
+        metrics.export_and_reset();
+        assert_eq!(metrics.export(), (0, 1));
+    }
+
+    #[test]
+    fn daily_counts_roll_over_on_export() {
+        let clock = ManualClock::new(Utc.ymd(2017, 9, 1).and_hms(3, 30, 0));
+        let mut metrics = simple(|m| (tweets(m), tweets_today(m)))
+            .with_daily_counts(clock.clone(), 4);
+        let boss_name = BossName::from("Lv60 オオゾラッコ");
+
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        assert_eq!(metrics.export(), (2, 2));
+
+        // No tweets since the reset time
+        clock.advance(StdDuration::from_secs(30 * 60));
+        assert_eq!(metrics.export(), (2, 0));
+        assert_eq!(metrics.export_and_reset(), (2, 0));
+
+        clock.advance(StdDuration::from_secs(24 * 60 * 60));
+        metrics.inc_tweet_count(&boss_name, Language::Japanese);
+        clock.advance(StdDuration::from_secs(24 * 60 * 60));
+        assert_eq!(metrics.export_and_reset(), (1, 0));
+    }
+
+    #[test]
+    fn language_counts() {
+        let mut metrics = simple(|m| {
+            let count = |language| m.language_counts().get(&language).cloned();
+            (count(Language::Japanese), count(Language::English))
+        });
+        let ja = BossName::from("Lv60 オオゾラッコ");
+        let en = BossName::from("Lvl 60 Ozorotter");
+
+        assert_eq!(metrics.export(), (None, None));
+
+        metrics.inc_tweet_count(&ja, Language::Japanese);
+        metrics.inc_tweet_count(&ja, Language::Japanese);
+        metrics.inc_tweet_count(&en, Language::English);
+        metrics.inc_tweet_count(&ja, Language::Japanese);
+        assert_eq!(metrics.export_and_reset(), (Some(3), Some(1)));
+
+        metrics.inc_tweet_count(&en, Language::English);
+        assert_eq!(metrics.export(), (None, Some(1)));
+    }
+
+    #[test]
+    fn event_durations() {
+        let mut metrics = simple(|m| {
+            m.event_durations()
+                .get(&EventKind::Ingest)
+                .map(|d| (d.count, d.avg_micros, d.max_micros))
+        });
+
+        metrics.observe_event_duration(EventKind::Ingest, 10);
+        metrics.observe_event_duration(EventKind::Ingest, 50);
+        metrics.observe_event_duration(EventKind::Client, 1000);
+        metrics.observe_event_duration(EventKind::Ingest, 30);
+        assert_eq!(metrics.export_and_reset(), Some((3, 30, 50)));
+        assert_eq!(metrics.export(), None);
+    }
+
+    #[test]
+    fn daily_counts_disabled_by_default() {
+        let metrics = simple(|m| m.boss_counts_today.is_some());
+        assert!(!metrics.export());
+    }
 }
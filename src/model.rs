@@ -1,6 +1,8 @@
 use chrono;
 pub use image_hash::ImageHash;
 use regex::Regex;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde::de::{self, Visitor};
 use std::collections::HashSet;
 use std::fmt;
 use std::ops::Deref;
@@ -20,11 +22,116 @@ lazy_static! {
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum Message<'a> {
     Heartbeat,
+    // Sent instead of `Heartbeat` if `ClientBuilder::with_stats_in_heartbeat`
+    // is enabled
+    HeartbeatWithStats { subscribers: u32, bosses: u32 },
     Tweet(&'a RaidTweet),
+    // Sent to a boss' followers right before a tweet whose ID is out of
+    // order or too far ahead of the last one, if gap detection is enabled
+    // with `ClientBuilder::with_gap_detection`. Clients may want to fetch
+    // the boss' recent tweets again.
+    Gap {
+        boss_name: &'a BossName,
+        last_tweet_id: TweetId,
+        tweet_id: TweetId,
+    },
     TweetList(&'a [Arc<RaidTweet>]),
     BossUpdate(&'a RaidBoss),
-    BossList(&'a [&'a RaidBoss]),
+    // Includes each boss' metadata, e.g., for `last_seen`. Only the bosses
+    // themselves are serialized.
+    BossList(#[serde(serialize_with = "serialize_boss_list")] &'a [&'a RaidBossMetadata]),
     BossRemove(&'a BossName),
+    // Sent by `Client::broadcast_notice`, e.g., for maintenance announcements
+    SystemNotice(&'a str),
+    // The last message sent to subscribers before the worker stops
+    ServerClosing,
+    // A message sent to a reliable follower, which should be acknowledged
+    // with `Subscription::ack`
+    Sequenced { seq: u64, inner: &'a Message<'a> },
+    // Sent instead of a message that a subscriber asked for (e.g., with
+    // `Subscription::get_bosses`), if the mapper returned `None` for it
+    Unsupported(MessageKind),
+}
+
+impl<'a> Message<'a> {
+    pub fn kind(&self) -> MessageKind {
+        match *self {
+            Message::Heartbeat | Message::HeartbeatWithStats { .. } => MessageKind::Heartbeat,
+            // Gaps are only meaningful alongside the tweets they precede
+            Message::Tweet(_) | Message::Gap { .. } => MessageKind::Tweet,
+            Message::TweetList(_) => MessageKind::TweetList,
+            Message::BossUpdate(_) => MessageKind::BossUpdate,
+            Message::BossList(_) => MessageKind::BossList,
+            Message::BossRemove(_) => MessageKind::BossRemove,
+            Message::SystemNotice(_) => MessageKind::SystemNotice,
+            Message::ServerClosing => MessageKind::ServerClosing,
+            Message::Sequenced { inner, .. } => inner.kind(),
+            Message::Unsupported(kind) => kind,
+        }
+    }
+}
+
+// Serialized the same way as a list of `RaidBoss`es
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn serialize_boss_list<S>(
+    bosses: &&[&RaidBossMetadata],
+    serializer: S,
+) -> ::std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(bosses.iter().map(|metadata| &metadata.boss))
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum MessageKind {
+    Heartbeat,
+    Tweet,
+    TweetList,
+    BossUpdate,
+    BossList,
+    BossRemove,
+    SystemNotice,
+    ServerClosing,
+}
+
+impl MessageKind {
+    fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+// The kinds of messages a subscriber wants to receive. Messages of other
+// kinds are never sent to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MessageFilter(u8);
+
+impl MessageFilter {
+    pub fn all() -> Self {
+        MessageFilter(!0)
+    }
+
+    pub fn none() -> Self {
+        MessageFilter(0)
+    }
+
+    pub fn only(kinds: &[MessageKind]) -> Self {
+        MessageFilter(kinds.iter().fold(0, |bits, kind| bits | kind.bit()))
+    }
+
+    pub fn with(self, kind: MessageKind) -> Self {
+        MessageFilter(self.0 | kind.bit())
+    }
+
+    pub fn contains(self, kind: MessageKind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+}
+
+impl Default for MessageFilter {
+    fn default() -> Self {
+        MessageFilter::all()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -35,17 +142,78 @@ pub struct RaidBoss {
     pub image: Option<BossImageUrl>,
     pub language: Language,
     pub translations: HashSet<BossName>,
+    // Muted bosses keep their tweet history and followers, but their
+    // tweets aren't broadcast until they're unmuted
+    #[serde(default, skip_serializing_if = "::std::ops::Not::not")]
+    pub muted: bool,
+}
+
+// A boss with extra information for debugging translation links. The image
+// hash is a hex string, since JSON numbers can't represent every `u64`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DebugRaidBoss {
+    pub boss: RaidBoss,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_hash: Option<String>,
+}
+
+impl DebugRaidBoss {
+    pub fn new(metadata: &RaidBossMetadata, include_image_hash: bool) -> Self {
+        let image_hash = if include_image_hash {
+            metadata
+                .image_hash
+                .map(|hash| hash.to_string())
+        } else {
+            None
+        };
+
+        DebugRaidBoss {
+            boss: metadata.boss.clone(),
+            image_hash,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct RaidBossMetadata {
     pub boss: RaidBoss,
+    pub first_seen: DateTime,
     pub last_seen: DateTime,
     pub image_hash: Option<ImageHash>,
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+// Metadata exported before `first_seen` existed doesn't have it, in which
+// case it defaults to `last_seen`
+#[derive(Deserialize)]
+struct RaidBossMetadataFields {
+    boss: RaidBoss,
+    first_seen: Option<DateTime>,
+    last_seen: DateTime,
+    image_hash: Option<ImageHash>,
+}
+
+impl<'de> Deserialize<'de> for RaidBossMetadata {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let fields = RaidBossMetadataFields::deserialize(deserializer)?;
+
+        Ok(RaidBossMetadata {
+            boss: fields.boss,
+            first_seen: fields.first_seen.unwrap_or(fields.last_seen),
+            last_seen: fields.last_seen,
+            image_hash: fields.image_hash,
+        })
+    }
+}
+
+// Interned for cheap comparisons and clones. Dynamic atoms are reference
+// counted, so a name is removed from the atom table once the last
+// `BossName` (or other atom) holding it is dropped.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct BossName(DefaultAtom);
+
 impl Deref for BossName {
     type Target = str;
     fn deref(&self) -> &Self::Target {
@@ -70,6 +238,22 @@ where
 }
 
 impl BossName {
+    // Names in Japanese can be composed (NFC) or decomposed (NFD) depending
+    // on where they came from, e.g., URLs typed in macOS browsers are NFD.
+    // With the `unicode-normalization` feature (enabled by `http-service`),
+    // this converts them to NFC, so that names from tweets and requests
+    // match. Otherwise, it's the same as `BossName::from`.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalized(name: &str) -> Self {
+        use unicode_normalization::UnicodeNormalization;
+        BossName::from(name.nfc().collect::<String>())
+    }
+
+    #[cfg(not(feature = "unicode-normalization"))]
+    pub fn normalized(name: &str) -> Self {
+        BossName::from(name)
+    }
+
     pub fn parse_level(&self) -> Option<BossLevel> {
         REGEX_BOSS_NAME.captures(self.0.as_ref()).and_then(|c| {
             c.name("level")
@@ -127,11 +311,414 @@ pub struct RaidTweet {
     pub text: Option<String>,
     pub created_at: DateTime,
     pub language: Language,
+    // Assigned by the worker in the order tweets are received, starting from
+    // 1. Unlike tweet IDs, this doesn't depend on Twitter, so clients can use
+    // it as a cursor when reconnecting.
+    #[serde(default)]
+    pub seq: u64,
+    // Only serialized for tweets that didn't come from Twitter
+    #[serde(default, skip_serializing_if = "is_twitter")]
+    pub source: TweetSource,
+}
+
+// Where a tweet came from
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TweetSource {
+    Twitter,
+    // Added with `Client::inject_raid`, e.g., by an operator
+    Manual,
+}
+
+impl TweetSource {
+    pub fn is_twitter(self) -> bool {
+        self == TweetSource::Twitter
+    }
+}
+
+// `skip_serializing_if` passes the field by reference
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_twitter(source: &TweetSource) -> bool {
+    source.is_twitter()
+}
+
+impl Default for TweetSource {
+    fn default() -> Self {
+        TweetSource::Twitter
+    }
+}
+
+// The raid ID is appended to this to get a link that joins the raid
+pub const DEFAULT_RAID_URL_BASE: &str = "http://game.granbluefantasy.jp/#quest/assist/multi/0/";
+
+impl RaidTweet {
+    pub fn raid_url(&self) -> String {
+        self.raid_url_with_base(DEFAULT_RAID_URL_BASE)
+    }
+
+    // For when the game's URLs change, or to link somewhere else
+    pub fn raid_url_with_base(&self, base: &str) -> String {
+        format!("{}{}", base, self.raid_id)
+    }
+}
+
+// Renditions of a Twitter profile image. Twitter sends `Normal` (48x48) in
+// tweets, and the others are found by changing the file name's suffix.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ProfileImageSize {
+    // 24x24
+    Mini,
+    // 48x48
+    Normal,
+    // 73x73
+    Bigger,
+    // The image as it was uploaded
+    Original,
+}
+
+impl ProfileImageSize {
+    fn suffix(self) -> &'static str {
+        match self {
+            ProfileImageSize::Mini => "_mini",
+            ProfileImageSize::Normal => "_normal",
+            ProfileImageSize::Bigger => "_bigger",
+            ProfileImageSize::Original => "",
+        }
+    }
+}
+
+// Changes the size suffix at the end of a profile image's file name, e.g.,
+// `abc_normal.jpg` to `abc_bigger.jpg`. The extension and query string are
+// optional, and URLs without a size suffix are treated as the original.
+pub fn profile_image_variant(url: &str, size: ProfileImageSize) -> String {
+    let query_start = url.find(|c| c == '?' || c == '#').unwrap_or_else(|| url.len());
+    let (path, query) = url.split_at(query_start);
+    let file_start = path.rfind('/').map_or(0, |i| i + 1);
+    let stem_end = path[file_start..]
+        .rfind('.')
+        .map_or(path.len(), |i| file_start + i);
+    let (stem, extension) = path.split_at(stem_end);
+
+    let suffixes = [ProfileImageSize::Mini, ProfileImageSize::Normal, ProfileImageSize::Bigger];
+    let stem = suffixes
+        .iter()
+        .map(|size| size.suffix())
+        .find(|suffix| stem[file_start..].ends_with(suffix))
+        .map_or(stem, |suffix| &stem[..stem.len() - suffix.len()]);
+
+    format!("{}{}{}{}", stem, size.suffix(), extension, query)
 }
 
+// The key used to order tweets in sorted queries. `TweetId` is the order
+// in which Twitter assigned IDs, which doesn't depend on its clock.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TweetOrder {
+    CreatedAt,
+    TweetId,
+}
+
+impl TweetOrder {
+    pub fn sort(self, tweets: &mut [Arc<RaidTweet>]) {
+        match self {
+            TweetOrder::CreatedAt => tweets.sort_by_key(|t| (t.created_at, t.tweet_id)),
+            TweetOrder::TweetId => tweets.sort_by_key(|t| t.tweet_id),
+        }
+    }
+}
+
+// Serialized as the variant name, or as the language code with the
+// `language-codes` feature. Either form can be deserialized.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
 pub enum Language {
+    #[cfg_attr(feature = "language-codes", serde(rename = "ja"))]
     Japanese,
+    #[cfg_attr(feature = "language-codes", serde(rename = "en"))]
     English,
+    #[cfg_attr(feature = "language-codes", serde(rename = "other"))]
     Other,
 }
+
+impl Language {
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Japanese => "ja",
+            Language::English => "en",
+            Language::Other => "other",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Language> {
+        match code {
+            "ja" => Some(Language::Japanese),
+            "en" => Some(Language::English),
+            "other" => Some(Language::Other),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Language> {
+        match name {
+            "Japanese" => Some(Language::Japanese),
+            "English" => Some(Language::English),
+            "Other" => Some(Language::Other),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LanguageVisitor;
+
+        impl<'de> Visitor<'de> for LanguageVisitor {
+            type Value = Language;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a language name or code")
+            }
+
+            fn visit_str<E>(self, s: &str) -> ::std::result::Result<Language, E>
+            where
+                E: de::Error,
+            {
+                const VARIANTS: &[&str] = &["ja", "en", "other", "Japanese", "English", "Other"];
+
+                Language::from_code(s)
+                    .or_else(|| Language::from_name(s))
+                    .ok_or_else(|| E::unknown_variant(s, VARIANTS))
+            }
+        }
+
+        deserializer.deserialize_str(LanguageVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json;
+
+    const LANGUAGES: &[Language] = &[Language::Japanese, Language::English, Language::Other];
+
+    #[cfg(feature = "language-codes")]
+    fn serialized(language: Language) -> &'static str {
+        language.code()
+    }
+
+    #[cfg(not(feature = "language-codes"))]
+    fn serialized(language: Language) -> &'static str {
+        match language {
+            Language::Japanese => "Japanese",
+            Language::English => "English",
+            Language::Other => "Other",
+        }
+    }
+
+    fn boss_metadata_json(first_seen: Option<&str>) -> String {
+        let first_seen = first_seen.map_or(String::new(), |t| format!(r#""first_seen":"{}","#, t));
+
+        format!(
+            r#"{{
+                "boss":{{
+                    "name":"Lvl 60 Ozorotter",
+                    "level":60,
+                    "language":"English",
+                    "translations":[]
+                }},
+                {}
+                "last_seen":"2017-09-01T00:00:00Z",
+                "image_hash":null
+            }}"#,
+            first_seen
+        )
+    }
+
+    #[test]
+    fn deserialize_boss_metadata_first_seen() {
+        let json = boss_metadata_json(Some("2017-08-01T00:00:00Z"));
+        let metadata = serde_json::from_str::<RaidBossMetadata>(&json).unwrap();
+        assert_eq!(metadata.first_seen, Utc.ymd(2017, 8, 1).and_hms(0, 0, 0));
+        assert_eq!(metadata.last_seen, Utc.ymd(2017, 9, 1).and_hms(0, 0, 0));
+
+        let round_trip = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RaidBossMetadata>(&round_trip).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn deserialize_boss_metadata_without_first_seen() {
+        let json = boss_metadata_json(None);
+        let metadata = serde_json::from_str::<RaidBossMetadata>(&json).unwrap();
+        assert_eq!(metadata.first_seen, metadata.last_seen);
+        assert_eq!(metadata.boss.name, BossName::from("Lvl 60 Ozorotter"));
+    }
+
+    #[test]
+    fn serialize_muted_only_when_set() {
+        let json = boss_metadata_json(None);
+        let mut metadata = serde_json::from_str::<RaidBossMetadata>(&json).unwrap();
+        assert!(!metadata.boss.muted);
+        assert!(!serde_json::to_string(&metadata.boss).unwrap().contains("muted"));
+
+        metadata.boss.muted = true;
+        let round_trip = serde_json::to_string(&metadata).unwrap();
+        assert!(round_trip.contains(r#""muted":true"#));
+        assert_eq!(
+            serde_json::from_str::<RaidBossMetadata>(&round_trip).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn language_codes() {
+        for &language in LANGUAGES {
+            assert_eq!(Language::from_code(language.code()), Some(language));
+        }
+
+        assert_eq!(Language::from_code("English"), None);
+        assert_eq!(Language::from_code("fr"), None);
+    }
+
+    #[test]
+    fn language_round_trip() {
+        for &language in LANGUAGES {
+            let json = serde_json::to_string(&language).unwrap();
+            assert_eq!(json, format!("\"{}\"", serialized(language)));
+            assert_eq!(serde_json::from_str::<Language>(&json).unwrap(), language);
+        }
+    }
+
+    #[test]
+    fn deserialize_language_names_and_codes() {
+        let names = ["\"Japanese\"", "\"English\"", "\"Other\""];
+        let codes = ["\"ja\"", "\"en\"", "\"other\""];
+
+        for (&language, (name, code)) in LANGUAGES.iter().zip(names.iter().zip(codes.iter())) {
+            assert_eq!(serde_json::from_str::<Language>(name).unwrap(), language);
+            assert_eq!(serde_json::from_str::<Language>(code).unwrap(), language);
+        }
+
+        assert!(serde_json::from_str::<Language>("\"fr\"").is_err());
+    }
+
+    #[test]
+    fn serialize_tweet_message_language() {
+        let tweet = RaidTweet {
+            tweet_id: 1,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user: "walfieee".into(),
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(0, 0),
+            language: Language::English,
+            seq: 0,
+            source: TweetSource::Twitter,
+        };
+
+        let json = serde_json::to_value(&Message::Tweet(&tweet)).unwrap();
+        assert_eq!(json["Tweet"]["language"], serialized(Language::English));
+
+        let parsed = serde_json::from_value::<RaidTweet>(json["Tweet"].clone()).unwrap();
+        assert_eq!(parsed, tweet);
+    }
+
+    #[test]
+    fn serialize_tweet_source() {
+        let mut tweet = RaidTweet {
+            tweet_id: 1,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user: "walfieee".into(),
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(0, 0),
+            language: Language::English,
+            seq: 0,
+            source: TweetSource::Twitter,
+        };
+
+        // Tweets from Twitter are serialized the same way as before
+        let json = serde_json::to_value(&tweet).unwrap();
+        assert!(json.get("source").is_none());
+        assert_eq!(serde_json::from_value::<RaidTweet>(json).unwrap(), tweet);
+
+        tweet.source = TweetSource::Manual;
+        let json = serde_json::to_value(&tweet).unwrap();
+        assert_eq!(json["source"], "manual");
+        assert_eq!(serde_json::from_value::<RaidTweet>(json).unwrap(), tweet);
+    }
+
+    #[test]
+    fn raid_url() {
+        let tweet = RaidTweet {
+            tweet_id: 1,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user: "walfieee".into(),
+            user_image: None,
+            text: None,
+            created_at: Utc.timestamp(0, 0),
+            language: Language::English,
+            seq: 0,
+            source: TweetSource::Twitter,
+        };
+
+        assert_eq!(
+            tweet.raid_url(),
+            "http://game.granbluefantasy.jp/#quest/assist/multi/0/ABCD1234"
+        );
+        assert_eq!(
+            tweet.raid_url_with_base("https://example.com/raids/"),
+            "https://example.com/raids/ABCD1234"
+        );
+    }
+
+    #[test]
+    fn profile_image_variants() {
+        let base = "https://pbs.twimg.com/profile_images/123456/AbC-d_eF";
+        let variant = |url: &str, size| profile_image_variant(url, size);
+
+        let jpg = format!("{}_normal.jpg", base);
+        assert_eq!(variant(&jpg, ProfileImageSize::Bigger), format!("{}_bigger.jpg", base));
+        assert_eq!(variant(&jpg, ProfileImageSize::Mini), format!("{}_mini.jpg", base));
+        assert_eq!(variant(&jpg, ProfileImageSize::Normal), jpg);
+        assert_eq!(variant(&jpg, ProfileImageSize::Original), format!("{}.jpg", base));
+
+        let png = format!("{}_bigger.png", base);
+        assert_eq!(variant(&png, ProfileImageSize::Original), format!("{}.png", base));
+
+        let extensionless = format!("{}_normal", base);
+        assert_eq!(variant(&extensionless, ProfileImageSize::Original), base);
+        assert_eq!(
+            variant(&extensionless, ProfileImageSize::Bigger),
+            format!("{}_bigger", base)
+        );
+
+        let query = format!("{}_normal.jpeg?format=jpg&name=small", base);
+        assert_eq!(
+            variant(&query, ProfileImageSize::Original),
+            format!("{}.jpeg?format=jpg&name=small", base)
+        );
+
+        let original = format!("{}.gif", base);
+        assert_eq!(variant(&original, ProfileImageSize::Original), original);
+        assert_eq!(
+            variant(&original, ProfileImageSize::Normal),
+            format!("{}_normal.gif", base)
+        );
+
+        // Dots and suffixes in directory names are left alone
+        let dotted = "https://pbs.twimg.com/v1.1_normal/123/abc";
+        assert_eq!(
+            variant(dotted, ProfileImageSize::Bigger),
+            "https://pbs.twimg.com/v1.1_normal/123/abc_bigger"
+        );
+    }
+}
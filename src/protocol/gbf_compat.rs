@@ -0,0 +1,499 @@
+// A message mapper and request parser for frontends written against the
+// original gbf-raidfinder server, e.g.:
+//
+//     ClientBuilder::new(...)
+//         .filter_map_message(protocol::gbf_compat::filter_map_message())
+//
+// The legacy server sent JSON objects tagged with a camelCase `type` field,
+// with camelCase field names and timestamps in milliseconds since the
+// epoch. Messages the legacy server didn't have (e.g., `Gap`) follow the
+// same conventions, so legacy clients can ignore types they don't know.
+//
+// These formats are written from the legacy server's message definitions
+// rather than captured traffic, so they follow the legacy protocol but
+// aren't known to be byte-for-byte identical to it. Details that only
+// captured fixtures could settle are noted next to the fields they affect.
+use bytes::Bytes;
+use client::Subscription;
+use model::{BossLevel, BossName, DateTime, Language, Message, MessageKind, RaidBoss,
+            RaidBossMetadata, RaidTweet, TweetId};
+use serde_json;
+
+fn epoch_millis(time: &DateTime) -> i64 {
+    time.timestamp() * 1000 + i64::from(time.timestamp_subsec_millis())
+}
+
+fn language_name(language: Language) -> &'static str {
+    match language {
+        Language::Japanese => "Japanese",
+        Language::English => "English",
+        // The legacy server only knew about Japanese and English bosses
+        Language::Other => "Other",
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyTweet<'a> {
+    boss_name: &'a str,
+    raid_id: &'a str,
+    screen_name: &'a str,
+    tweet_id: TweetId,
+    // Omitted for users with the default profile image. The legacy server
+    // may have sent the default image's URL instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile_image: Option<&'a str>,
+    // The legacy server always had the text, so tweets without any (e.g.,
+    // from `PayloadOptions::include_text`) have an empty string
+    text: &'a str,
+    created_at: i64,
+    language: &'static str,
+}
+
+impl<'a> From<&'a RaidTweet> for LegacyTweet<'a> {
+    fn from(tweet: &'a RaidTweet) -> Self {
+        LegacyTweet {
+            boss_name: &tweet.boss_name,
+            raid_id: &tweet.raid_id,
+            screen_name: &tweet.user,
+            tweet_id: tweet.tweet_id,
+            profile_image: tweet.user_image.as_ref().map(String::as_str),
+            text: tweet.text.as_ref().map_or("", String::as_str),
+            created_at: epoch_millis(&tweet.created_at),
+            language: language_name(tweet.language),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyBoss<'a> {
+    name: &'a str,
+    level: BossLevel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a str>,
+    // Only known for bosses in a `BossList`. The legacy server always sent
+    // it, so boss updates to legacy clients lack a field they may expect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<i64>,
+    language: &'static str,
+    // The legacy server grouped each boss with at most one translation. If a
+    // boss has several, the first in sorted order is used, since whichever
+    // one the legacy server would have picked depends on its history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translated_name: Option<&'a str>,
+}
+
+impl<'a> LegacyBoss<'a> {
+    fn new(boss: &'a RaidBoss, last_seen: Option<&DateTime>) -> Self {
+        LegacyBoss {
+            name: &boss.name,
+            level: boss.level,
+            image: boss.image.as_ref().map(|image| &**image),
+            last_seen: last_seen.map(epoch_millis),
+            language: language_name(boss.language),
+            translated_name: boss.translations.iter().map(|name| &**name).min(),
+        }
+    }
+}
+
+impl<'a> From<&'a RaidBossMetadata> for LegacyBoss<'a> {
+    fn from(metadata: &'a RaidBossMetadata) -> Self {
+        LegacyBoss::new(&metadata.boss, Some(&metadata.last_seen))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Response<'a> {
+    // Stats are only included if `ClientBuilder::with_stats_in_heartbeat`
+    // is enabled. The legacy server never sent them.
+    KeepAlive {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subscribers: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bosses: Option<u32>,
+    },
+    RaidTweet(LegacyTweet<'a>),
+    RaidTweets {
+        #[serde(rename = "raidTweets")]
+        raid_tweets: Vec<LegacyTweet<'a>>,
+    },
+    // Boss updates and boss lists are both sent as a list of bosses, like the
+    // legacy server did
+    RaidBosses {
+        #[serde(rename = "raidBosses")]
+        raid_bosses: Vec<LegacyBoss<'a>>,
+    },
+    Gap {
+        #[serde(rename = "bossName")]
+        boss_name: &'a str,
+        #[serde(rename = "lastTweetId")]
+        last_tweet_id: TweetId,
+        #[serde(rename = "tweetId")]
+        tweet_id: TweetId,
+    },
+    RaidBossRemoved {
+        #[serde(rename = "bossName")]
+        boss_name: &'a str,
+    },
+    SystemNotice { message: &'a str },
+    ServerClosing,
+    Sequenced { seq: u64, message: Box<Response<'a>> },
+    Unsupported {
+        #[serde(rename = "messageKind")]
+        message_kind: MessageKind,
+    },
+}
+
+impl<'a> Response<'a> {
+    fn new(message: &Message<'a>) -> Self {
+        match *message {
+            Message::Heartbeat => Response::KeepAlive {
+                subscribers: None,
+                bosses: None,
+            },
+            Message::HeartbeatWithStats {
+                subscribers,
+                bosses,
+            } => Response::KeepAlive {
+                subscribers: Some(subscribers),
+                bosses: Some(bosses),
+            },
+            Message::Tweet(tweet) => Response::RaidTweet(tweet.into()),
+            Message::TweetList(tweets) => Response::RaidTweets {
+                raid_tweets: tweets.iter().map(|tweet| (&**tweet).into()).collect(),
+            },
+            Message::Gap {
+                boss_name,
+                last_tweet_id,
+                tweet_id,
+            } => Response::Gap {
+                boss_name,
+                last_tweet_id,
+                tweet_id,
+            },
+            Message::BossUpdate(boss) => Response::RaidBosses {
+                raid_bosses: vec![LegacyBoss::new(boss, None)],
+            },
+            Message::BossList(bosses) => Response::RaidBosses {
+                raid_bosses: bosses.iter().map(|&metadata| metadata.into()).collect(),
+            },
+            Message::BossRemove(boss_name) => Response::RaidBossRemoved { boss_name },
+            Message::SystemNotice(message) => Response::SystemNotice { message },
+            Message::ServerClosing => Response::ServerClosing,
+            Message::Sequenced { seq, inner } => Response::Sequenced {
+                seq,
+                message: Box::new(Response::new(inner)),
+            },
+            Message::Unsupported(message_kind) => Response::Unsupported { message_kind },
+        }
+    }
+}
+
+// Serializes messages in the legacy format, for use with
+// `ClientBuilder::filter_map_message`
+pub fn filter_map_message() -> impl Fn(Message) -> Option<Bytes> {
+    |message: Message| {
+        serde_json::to_vec(&Response::new(&message))
+            .ok()
+            .map(Bytes::from)
+    }
+}
+
+// A request sent by a legacy client
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ClientRequest {
+    AllRaidBosses,
+    Follow {
+        #[serde(rename = "bossNames")]
+        boss_names: Vec<BossName>,
+    },
+    Unfollow {
+        #[serde(rename = "bossNames")]
+        boss_names: Vec<BossName>,
+    },
+}
+
+impl ClientRequest {
+    pub fn apply<Sub, M>(self, subscription: &mut Subscription<Sub, M>) {
+        match self {
+            ClientRequest::AllRaidBosses => subscription.get_bosses(),
+            ClientRequest::Follow { boss_names } => subscription.follow_many(boss_names),
+            ClientRequest::Unfollow { boss_names } => subscription.unfollow_many(boss_names),
+        }
+    }
+}
+
+// Returns `None` if the bytes aren't a request the legacy server accepted
+pub fn parse_client_request(bytes: &[u8]) -> Option<ClientRequest> {
+    serde_json::from_slice(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use client::harness::{self, Harness, Recorded};
+    use model::TweetSource;
+    use std::str;
+    use std::sync::Arc;
+
+    // The expected JSON below is written by hand from the legacy server's
+    // message definitions. It pins down the current output, but it isn't a
+    // capture of the legacy server's output.
+
+    fn tweet(tweet_id: TweetId) -> RaidTweet {
+        RaidTweet {
+            tweet_id,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: "ABCD1234".into(),
+            user: "walfieee".into(),
+            user_image: Some("http://example.com/user.png".into()),
+            text: Some("Help".into()),
+            created_at: Utc.timestamp(1_500_000_000, 123_000_000),
+            language: Language::English,
+            seq: 0,
+            source: TweetSource::Twitter,
+        }
+    }
+
+    fn boss() -> RaidBoss {
+        RaidBoss {
+            name: "Lvl 60 Ozorotter".into(),
+            level: 60,
+            image: Some("http://example.com/boss.png".into()),
+            language: Language::English,
+            muted: false,
+            translations: vec![BossName::from("Lv60 オオゾラッコ")].into_iter().collect(),
+        }
+    }
+
+    fn json(message: Message) -> String {
+        let bytes = filter_map_message()(message).expect("message not serialized");
+        str::from_utf8(&bytes).unwrap().to_string()
+    }
+
+    const TWEET_JSON: &str = concat!(
+        r#"{"type":"raidTweet","bossName":"Lvl 60 Ozorotter","raidId":"ABCD1234","#,
+        r#""screenName":"walfieee","tweetId":1,"profileImage":"http://example.com/user.png","#,
+        r#""text":"Help","createdAt":1500000000123,"language":"English"}"#
+    );
+
+    const BOSS_JSON: &str = concat!(
+        r#"{"name":"Lvl 60 Ozorotter","level":60,"image":"http://example.com/boss.png","#,
+        r#""language":"English","translatedName":"Lv60 オオゾラッコ"}"#
+    );
+
+    #[test]
+    fn heartbeat() {
+        assert_eq!(json(Message::Heartbeat), r#"{"type":"keepAlive"}"#);
+    }
+
+    #[test]
+    fn heartbeat_with_stats() {
+        let message = Message::HeartbeatWithStats {
+            subscribers: 2,
+            bosses: 3,
+        };
+        assert_eq!(
+            json(message),
+            r#"{"type":"keepAlive","subscribers":2,"bosses":3}"#
+        );
+    }
+
+    #[test]
+    fn tweet_message() {
+        assert_eq!(json(Message::Tweet(&tweet(1))), TWEET_JSON);
+    }
+
+    #[test]
+    fn tweet_without_image_or_text() {
+        let mut tweet = tweet(1);
+        tweet.user_image = None;
+        tweet.text = None;
+        tweet.language = Language::Japanese;
+
+        assert_eq!(
+            json(Message::Tweet(&tweet)),
+            concat!(
+                r#"{"type":"raidTweet","bossName":"Lvl 60 Ozorotter","raidId":"ABCD1234","#,
+                r#""screenName":"walfieee","tweetId":1,"text":"","createdAt":1500000000123,"#,
+                r#""language":"Japanese"}"#
+            )
+        );
+    }
+
+    #[test]
+    fn tweet_list() {
+        let tweets = vec![Arc::new(tweet(1))];
+        assert_eq!(
+            json(Message::TweetList(&tweets)),
+            concat!(
+                r#"{"type":"raidTweets","raidTweets":[{"bossName":"Lvl 60 Ozorotter","#,
+                r#""raidId":"ABCD1234","screenName":"walfieee","tweetId":1,"#,
+                r#""profileImage":"http://example.com/user.png","text":"Help","#,
+                r#""createdAt":1500000000123,"language":"English"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn gap() {
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        let message = Message::Gap {
+            boss_name: &boss_name,
+            last_tweet_id: 1,
+            tweet_id: 5,
+        };
+        assert_eq!(
+            json(message),
+            r#"{"type":"gap","bossName":"Lvl 60 Ozorotter","lastTweetId":1,"tweetId":5}"#
+        );
+    }
+
+    #[test]
+    fn boss_update() {
+        assert_eq!(
+            json(Message::BossUpdate(&boss())),
+            format!(r#"{{"type":"raidBosses","raidBosses":[{}]}}"#, BOSS_JSON)
+        );
+    }
+
+    #[test]
+    fn boss_list() {
+        let mut untranslated = boss();
+        untranslated.name = "Lvl 100 Ozorotter".into();
+        untranslated.level = 100;
+        untranslated.image = None;
+        untranslated.translations.clear();
+
+        let metadata = |boss: RaidBoss| RaidBossMetadata {
+            boss,
+            first_seen: Utc.timestamp(0, 0),
+            last_seen: Utc.timestamp(10, 5_000_000),
+            image_hash: None,
+        };
+        let bosses = vec![metadata(boss()), metadata(untranslated)];
+        let bosses = bosses.iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            json(Message::BossList(&bosses)),
+            concat!(
+                r#"{"type":"raidBosses","raidBosses":["#,
+                r#"{"name":"Lvl 60 Ozorotter","level":60,"image":"http://example.com/boss.png","#,
+                r#""lastSeen":10005,"language":"English","translatedName":"Lv60 オオゾラッコ"},"#,
+                r#"{"name":"Lvl 100 Ozorotter","level":100,"lastSeen":10005,"#,
+                r#""language":"English"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn boss_with_several_translations() {
+        let mut boss = boss();
+        boss.translations.insert("Lv60 オオゾラッコ (2)".into());
+        boss.translations.insert("Lv60 オオゾラッコ (1)".into());
+
+        assert!(json(Message::BossUpdate(&boss)).contains(r#""translatedName":"Lv60 オオゾラッコ""#));
+    }
+
+    #[test]
+    fn boss_remove() {
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+        assert_eq!(
+            json(Message::BossRemove(&boss_name)),
+            r#"{"type":"raidBossRemoved","bossName":"Lvl 60 Ozorotter"}"#
+        );
+    }
+
+    #[test]
+    fn system_notice() {
+        assert_eq!(
+            json(Message::SystemNotice("Maintenance soon")),
+            r#"{"type":"systemNotice","message":"Maintenance soon"}"#
+        );
+    }
+
+    #[test]
+    fn server_closing() {
+        assert_eq!(json(Message::ServerClosing), r#"{"type":"serverClosing"}"#);
+    }
+
+    #[test]
+    fn sequenced() {
+        let tweet = tweet(1);
+        let inner = Message::Tweet(&tweet);
+        assert_eq!(
+            json(Message::Sequenced {
+                seq: 7,
+                inner: &inner,
+            }),
+            format!(r#"{{"type":"sequenced","seq":7,"message":{}}}"#, TWEET_JSON)
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(
+            json(Message::Unsupported(MessageKind::BossList)),
+            r#"{"type":"unsupported","messageKind":"BossList"}"#
+        );
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            parse_client_request(br#"{"type":"allRaidBosses"}"#),
+            Some(ClientRequest::AllRaidBosses)
+        );
+        assert_eq!(
+            parse_client_request(br#"{"type":"follow","bossNames":["Lvl 60 Ozorotter"]}"#),
+            Some(ClientRequest::Follow {
+                boss_names: vec!["Lvl 60 Ozorotter".into()],
+            })
+        );
+        assert_eq!(
+            parse_client_request(br#"{"bossNames":[],"type":"unfollow"}"#),
+            Some(ClientRequest::Unfollow { boss_names: vec![] })
+        );
+    }
+
+    #[test]
+    fn parse_invalid_requests() {
+        assert_eq!(parse_client_request(b"follow"), None);
+        assert_eq!(parse_client_request(br#"{"type":"follow"}"#), None);
+        assert_eq!(parse_client_request(br#"{"type":"raidBosses"}"#), None);
+    }
+
+    #[test]
+    fn apply_requests() {
+        let mut h = Harness::new();
+        h.push(harness::raid_info("Lvl 60 Ozorotter", 1, 0));
+        let (mut subscription, recorder) = h.subscribe();
+
+        parse_client_request(br#"{"type":"follow","bossNames":["Lvl 60 Ozorotter"]}"#)
+            .unwrap()
+            .apply(&mut subscription);
+        h.run();
+        recorder.take();
+
+        h.push(harness::raid_info("Lvl 60 Ozorotter", 2, 0));
+        assert_eq!(harness::tweet_ids(&recorder.take()), vec![2]);
+
+        ClientRequest::AllRaidBosses.apply(&mut subscription);
+        h.run();
+        match recorder.take().as_slice() {
+            [Recorded::BossList(bosses)] => assert_eq!(bosses.len(), 1),
+            messages => panic!("unexpected messages: {:?}", messages),
+        }
+
+        ClientRequest::Unfollow {
+            boss_names: vec!["Lvl 60 Ozorotter".into()],
+        }.apply(&mut subscription);
+        h.run();
+        h.push(harness::raid_info("Lvl 60 Ozorotter", 3, 0));
+        assert_eq!(harness::tweet_ids(&recorder.take()), Vec::<TweetId>::new());
+    }
+}
@@ -0,0 +1,465 @@
+// Owned versions of the messages sent to subscribers, for Rust programs
+// that consume petronel's serialized output
+#[cfg(feature = "gzip")]
+use flate2::Compression;
+#[cfg(feature = "gzip")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+use model::{BossName, Message, MessageKind, RaidBoss, RaidBossMetadata, RaidTweet, TweetId};
+#[cfg(feature = "gzip")]
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+#[cfg(feature = "gbf-compat")]
+pub mod gbf_compat;
+
+// Mirrors `Message`, and is serialized the same way
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum OwnedMessage {
+    Heartbeat,
+    HeartbeatWithStats { subscribers: u32, bosses: u32 },
+    Tweet(RaidTweet),
+    Gap {
+        boss_name: BossName,
+        last_tweet_id: TweetId,
+        tweet_id: TweetId,
+    },
+    TweetList(Vec<RaidTweet>),
+    BossUpdate(RaidBoss),
+    BossList(Vec<RaidBoss>),
+    BossRemove(BossName),
+    SystemNotice(String),
+    ServerClosing,
+    Sequenced { seq: u64, inner: Box<OwnedMessage> },
+    Unsupported(MessageKind),
+}
+
+impl<'a> From<Message<'a>> for OwnedMessage {
+    fn from(message: Message<'a>) -> Self {
+        match message {
+            Message::Heartbeat => OwnedMessage::Heartbeat,
+            Message::HeartbeatWithStats {
+                subscribers,
+                bosses,
+            } => OwnedMessage::HeartbeatWithStats {
+                subscribers,
+                bosses,
+            },
+            Message::Tweet(tweet) => OwnedMessage::Tweet(tweet.clone()),
+            Message::Gap {
+                boss_name,
+                last_tweet_id,
+                tweet_id,
+            } => OwnedMessage::Gap {
+                boss_name: boss_name.clone(),
+                last_tweet_id,
+                tweet_id,
+            },
+            Message::TweetList(tweets) => {
+                OwnedMessage::TweetList(tweets.iter().map(|t| (**t).clone()).collect())
+            }
+            Message::BossUpdate(boss) => OwnedMessage::BossUpdate(boss.clone()),
+            Message::BossList(bosses) => {
+                OwnedMessage::BossList(bosses.iter().map(|b| b.boss.clone()).collect())
+            }
+            Message::BossRemove(boss_name) => OwnedMessage::BossRemove(boss_name.clone()),
+            Message::SystemNotice(notice) => OwnedMessage::SystemNotice(notice.to_string()),
+            Message::ServerClosing => OwnedMessage::ServerClosing,
+            Message::Sequenced { seq, inner } => OwnedMessage::Sequenced {
+                seq,
+                inner: Box::new(inner.clone().into()),
+            },
+            Message::Unsupported(kind) => OwnedMessage::Unsupported(kind),
+        }
+    }
+}
+
+// Optional fields to include in the messages sent to subscribers. This
+// doesn't affect what the worker stores, e.g., `Client::bosses` and
+// `Client::tweets` always return the complete data.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PayloadOptions {
+    pub include_boss_image: bool,
+    pub include_user_image: bool,
+    pub include_text: bool,
+}
+
+impl Default for PayloadOptions {
+    fn default() -> Self {
+        PayloadOptions {
+            include_boss_image: true,
+            include_user_image: true,
+            include_text: true,
+        }
+    }
+}
+
+impl PayloadOptions {
+    fn is_complete(self) -> bool {
+        self.include_boss_image && self.include_user_image && self.include_text
+    }
+
+    fn shape_tweet(self, tweet: &RaidTweet) -> RaidTweet {
+        let mut tweet = tweet.clone();
+        if !self.include_user_image {
+            tweet.user_image = None;
+        }
+        if !self.include_text {
+            tweet.text = None;
+        }
+        tweet
+    }
+
+    fn shape_boss(self, boss: &RaidBoss) -> RaidBoss {
+        let mut boss = boss.clone();
+        if !self.include_boss_image {
+            boss.image = None;
+        }
+        boss
+    }
+
+    fn shape_metadata(self, metadata: &RaidBossMetadata) -> RaidBossMetadata {
+        RaidBossMetadata {
+            boss: self.shape_boss(&metadata.boss),
+            ..metadata.clone()
+        }
+    }
+
+    fn shape<T>(self, message: Message, f: &Fn(Message) -> Option<T>) -> Option<T> {
+        match message {
+            Message::Tweet(tweet) => f(Message::Tweet(&self.shape_tweet(tweet))),
+            Message::TweetList(tweets) => {
+                let tweets = tweets
+                    .iter()
+                    .map(|t| Arc::new(self.shape_tweet(t)))
+                    .collect::<Vec<_>>();
+                f(Message::TweetList(&tweets))
+            }
+            Message::BossUpdate(boss) => f(Message::BossUpdate(&self.shape_boss(boss))),
+            Message::BossList(bosses) => {
+                let bosses = bosses
+                    .iter()
+                    .map(|b| self.shape_metadata(b))
+                    .collect::<Vec<_>>();
+                f(Message::BossList(&bosses.iter().collect::<Vec<_>>()))
+            }
+            Message::Sequenced { seq, inner } => self.shape(inner.clone(), &|inner| {
+                f(Message::Sequenced {
+                    seq,
+                    inner: &inner,
+                })
+            }),
+            other => f(other),
+        }
+    }
+}
+
+// Wraps a message mapper, so that it's given copies of messages without
+// the fields excluded by `options`
+pub fn shape_payload<F, T>(options: PayloadOptions, f: F) -> impl Fn(Message) -> Option<T>
+where
+    F: Fn(Message) -> Option<T>,
+{
+    move |message| {
+        if options.is_complete() {
+            f(message)
+        } else {
+            options.shape(message, &f)
+        }
+    }
+}
+
+// Wraps a message mapper, so that every subscriber shares its output. `Sink`
+// subscribers clone the item they're given, which for an `Arc` only
+// increments its reference count, instead of copying the payload.
+pub fn shared<F, T>(f: F) -> impl Fn(Message) -> Option<Arc<T>>
+where
+    F: Fn(Message) -> Option<T>,
+{
+    move |message| f(message).map(Arc::new)
+}
+
+// Frames produced by `gzip_if_larger_than` are a single flag byte followed
+// by the payload. If the flag is `FRAME_GZIP`, the payload is gzipped, so
+// consumers (e.g., JS clients using a gzip library) should check the first
+// byte and decompress the rest of the frame if needed.
+pub const FRAME_RAW: u8 = 0;
+pub const FRAME_GZIP: u8 = 1;
+
+// Wraps a message mapper, so that its output is framed and gzipped if it's
+// larger than `threshold` bytes, and gzipping actually makes it smaller.
+// Large payloads are mostly `BossList` and `TweetList` messages, so with a
+// reasonable threshold, individual tweets are sent uncompressed.
+#[cfg(feature = "gzip")]
+pub fn gzip_if_larger_than<F, T>(threshold: usize, f: F) -> impl Fn(Message) -> Option<Vec<u8>>
+where
+    F: Fn(Message) -> Option<T>,
+    T: AsRef<[u8]>,
+{
+    move |message| f(message).map(|payload| frame(threshold, payload.as_ref()))
+}
+
+#[cfg(feature = "gzip")]
+fn frame(threshold: usize, payload: &[u8]) -> Vec<u8> {
+    if payload.len() > threshold {
+        let mut encoder = GzEncoder::new(vec![FRAME_GZIP], Compression::default());
+        let compressed = encoder.write_all(payload).and_then(|()| encoder.finish());
+
+        match compressed {
+            Ok(ref frame) if frame.len() <= payload.len() => return frame.clone(),
+            _ => {}
+        }
+    }
+
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(FRAME_RAW);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// Returns the original payload of a frame from `gzip_if_larger_than`
+#[cfg(feature = "gzip")]
+pub fn decode_frame(frame: &[u8]) -> io::Result<Vec<u8>> {
+    match frame.split_first() {
+        Some((&FRAME_RAW, payload)) => Ok(payload.to_vec()),
+        Some((&FRAME_GZIP, payload)) => {
+            let mut decoded = Vec::new();
+            GzDecoder::new(payload).read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        Some((&flag, _)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown frame flag {}", flag),
+        )),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty frame")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use model::{Language, TweetSource};
+    use serde_json;
+
+    fn tweet(tweet_id: u64) -> RaidTweet {
+        RaidTweet {
+            tweet_id,
+            boss_name: "Lvl 60 Ozorotter".into(),
+            raid_id: format!("{:08X}", tweet_id),
+            user: "walfieee".into(),
+            user_image: Some("http://example.com/user.png".into()),
+            text: if tweet_id % 2 == 0 {
+                Some("Help".into())
+            } else {
+                None
+            },
+            created_at: Utc.timestamp(tweet_id as i64, 0),
+            language: Language::English,
+            seq: 0,
+            source: TweetSource::Twitter,
+        }
+    }
+
+    fn boss(name: &str) -> RaidBoss {
+        RaidBoss {
+            name: name.into(),
+            level: 60,
+            image: Some("http://example.com/boss.png".into()),
+            language: Language::English,
+            muted: false,
+            translations: vec![BossName::from("Lv60 オオゾラッコ")].into_iter().collect(),
+        }
+    }
+
+    fn metadata(boss: &RaidBoss) -> RaidBossMetadata {
+        RaidBossMetadata {
+            boss: boss.clone(),
+            first_seen: Utc.timestamp(0, 0),
+            last_seen: Utc.timestamp(10, 0),
+            image_hash: None,
+        }
+    }
+
+    // Serializing a `Message` and deserializing it as an `OwnedMessage`
+    // should be the same as converting it directly, and serializing the
+    // `OwnedMessage` should produce the same JSON
+    fn assert_round_trip(message: Message) {
+        let json = serde_json::to_string(&message).unwrap();
+        let owned = serde_json::from_str::<OwnedMessage>(&json).unwrap();
+
+        assert_eq!(owned, OwnedMessage::from(message));
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+    }
+
+    #[test]
+    fn round_trip_every_variant() {
+        let tweet = tweet(1);
+        let tweets = vec![Arc::new(tweet.clone()), Arc::new(self::tweet(2))];
+        let boss = boss("Lvl 60 Ozorotter");
+        let other_boss = self::boss("Lvl 75 Luminiera Omega");
+        let metadata = [metadata(&boss), metadata(&other_boss)];
+        let boss_list = [&metadata[0], &metadata[1]];
+        let boss_name = BossName::from("Lvl 60 Ozorotter");
+
+        let messages = vec![
+            Message::Heartbeat,
+            Message::HeartbeatWithStats {
+                subscribers: 3,
+                bosses: 2,
+            },
+            Message::Tweet(&tweet),
+            Message::TweetList(&tweets),
+            Message::TweetList(&[]),
+            Message::Gap {
+                boss_name: &boss_name,
+                last_tweet_id: 100,
+                tweet_id: 90,
+            },
+            Message::BossUpdate(&boss),
+            Message::BossList(&boss_list),
+            Message::BossRemove(&boss_name),
+            Message::SystemNotice("Maintenance in 10 minutes"),
+            Message::ServerClosing,
+            Message::Unsupported(MessageKind::BossList),
+        ];
+
+        for message in messages.iter() {
+            assert_round_trip(message.clone());
+
+            assert_round_trip(Message::Sequenced {
+                seq: 5,
+                inner: message,
+            });
+        }
+    }
+
+    fn to_json(message: Message) -> Option<serde_json::Value> {
+        serde_json::to_value(&message).ok()
+    }
+
+    #[test]
+    fn shape_payload_excludes_fields() {
+        let options = PayloadOptions {
+            include_boss_image: false,
+            include_user_image: false,
+            include_text: false,
+        };
+        let shaped = shape_payload(options, to_json);
+
+        let tweet = tweet(2);
+        let json = shaped(Message::Tweet(&tweet)).unwrap();
+        assert_eq!(json["Tweet"]["raid_id"], "00000002");
+        assert!(json["Tweet"].get("user_image").is_none());
+        assert!(json["Tweet"].get("text").is_none());
+
+        let tweets = vec![Arc::new(tweet.clone())];
+        let json = shaped(Message::TweetList(&tweets)).unwrap();
+        assert!(json["TweetList"][0].get("text").is_none());
+
+        let boss = boss("Lvl 60 Ozorotter");
+        let json = shaped(Message::BossUpdate(&boss)).unwrap();
+        assert_eq!(json["BossUpdate"]["name"], "Lvl 60 Ozorotter");
+        assert!(json["BossUpdate"].get("image").is_none());
+
+        let json = shaped(Message::BossList(&[&metadata(&boss)])).unwrap();
+        assert!(json["BossList"][0].get("image").is_none());
+
+        let inner = Message::BossUpdate(&boss);
+        let json = shaped(Message::Sequenced {
+            seq: 1,
+            inner: &inner,
+        }).unwrap();
+        assert_eq!(json["Sequenced"]["seq"], 1);
+        assert!(json["Sequenced"]["inner"]["BossUpdate"].get("image").is_none());
+
+        assert_eq!(shaped(Message::Heartbeat), to_json(Message::Heartbeat));
+    }
+
+    #[test]
+    fn shape_payload_options_are_independent() {
+        let options = PayloadOptions {
+            include_text: false,
+            ..PayloadOptions::default()
+        };
+        let shaped = shape_payload(options, to_json);
+
+        let tweet = tweet(2);
+        let json = shaped(Message::Tweet(&tweet)).unwrap();
+        assert_eq!(json["Tweet"]["user_image"], "http://example.com/user.png");
+        assert!(json["Tweet"].get("text").is_none());
+
+        let boss = boss("Lvl 60 Ozorotter");
+        assert_eq!(
+            shaped(Message::BossUpdate(&boss)),
+            to_json(Message::BossUpdate(&boss))
+        );
+
+        // Everything is included by default
+        let shaped = shape_payload(PayloadOptions::default(), to_json);
+        assert_eq!(
+            shaped(Message::Tweet(&tweet)),
+            to_json(Message::Tweet(&tweet))
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    fn to_json_bytes(message: Message) -> Option<Vec<u8>> {
+        serde_json::to_vec(&message).ok()
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_small_payload() {
+        let mapper = gzip_if_larger_than(1024, to_json_bytes);
+        let tweet = tweet(1);
+
+        let frame = mapper(Message::Tweet(&tweet)).unwrap();
+        assert_eq!(frame[0], FRAME_RAW);
+        assert_eq!(&frame[1..], &to_json_bytes(Message::Tweet(&tweet)).unwrap()[..]);
+        assert_eq!(decode_frame(&frame).unwrap(), &frame[1..]);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_large_payload() {
+        let mapper = gzip_if_larger_than(1024, to_json_bytes);
+        let tweets = (0..50).map(|id| Arc::new(tweet(id))).collect::<Vec<_>>();
+        let json = to_json_bytes(Message::TweetList(&tweets)).unwrap();
+        assert!(json.len() > 1024);
+
+        let frame = mapper(Message::TweetList(&tweets)).unwrap();
+        assert_eq!(frame[0], FRAME_GZIP);
+        assert!(frame.len() < json.len());
+        assert_eq!(decode_frame(&frame).unwrap(), json);
+
+        let decoded = serde_json::from_slice::<OwnedMessage>(&decode_frame(&frame).unwrap());
+        assert_eq!(decoded.unwrap(), Message::TweetList(&tweets).into());
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_only_if_smaller() {
+        // Pseudorandom bytes don't compress
+        let mut state = 1u32;
+        let payload = (0..2048)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+
+        let frame = frame(1024, &payload);
+        assert_eq!(frame[0], FRAME_RAW);
+        assert_eq!(decode_frame(&frame).unwrap(), payload);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn decode_invalid_frames() {
+        assert!(decode_frame(&[]).is_err());
+        assert!(decode_frame(&[2, 0]).is_err());
+        assert!(decode_frame(&[FRAME_GZIP, 1, 2, 3]).is_err());
+    }
+}
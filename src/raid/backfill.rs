@@ -0,0 +1,232 @@
+// Seeds the worker with recent raid tweets from Twitter's REST search API,
+// since the streaming API only has tweets posted after connecting
+use error::*;
+use futures::{stream, Future, Stream};
+use hyper::{self, Method, Request};
+use hyper::client::Connect;
+use hyper::header::UserAgent;
+use oauthcli::{OAuthAuthorizationHeaderBuilder, SignatureMethod};
+use raid::{RaidInfo, RaidInfoStream};
+use serde_json;
+use twitter_stream::Token;
+use twitter_stream::message::Tweet;
+use url::Url;
+
+const SEARCH_URL: &str = "https://api.twitter.com/1.1/search/tweets.json";
+
+pub type Backfill = Box<Future<Item = Vec<RaidInfo>, Error = Error>>;
+
+#[derive(Deserialize)]
+struct SearchResponse<'a> {
+    #[serde(borrow)]
+    statuses: Vec<Tweet<'a>>,
+}
+
+// Fetches recent raid tweets for the same keywords as `RaidInfoStream`,
+// oldest first
+pub fn search_recent<C>(hyper_client: &hyper::Client<C>, token: &Token) -> Backfill
+where
+    C: Connect,
+{
+    let url = search_url();
+
+    let authorization = {
+        let mut oauth = OAuthAuthorizationHeaderBuilder::new(
+            "GET",
+            &url,
+            token.consumer_key.as_ref(),
+            token.consumer_secret.as_ref(),
+            SignatureMethod::HmacSha1,
+        );
+        oauth.token(token.access_key.as_ref(), token.access_secret.as_ref());
+        oauth.finish_for_twitter().to_string()
+    };
+
+    let mut request = Request::new(
+        Method::Get,
+        url.as_str().parse().expect("invalid search URL"),
+    );
+    request
+        .headers_mut()
+        .set_raw("Authorization", authorization);
+    request.headers_mut().set(UserAgent::new("petronel"));
+
+    let response = hyper_client
+        .request(request)
+        .and_then(|resp| {
+            let status = resp.status();
+            resp.body().concat2().map(move |body| (status, body))
+        })
+        .then(|result| result.chain_err(|| ErrorKind::Twitter))
+        .and_then(|(status, body)| {
+            if !status.is_success() {
+                bail!(ErrorKind::Twitter);
+            }
+            parse_search_response(&body)
+        });
+
+    Box::new(response)
+}
+
+// Emits the backfilled tweets before any from `stream`. If the backfill
+// fails, `on_error` is called with the error and streaming starts anyway.
+pub fn with_backfill<S, F>(
+    backfill: Backfill,
+    stream: S,
+    on_error: F,
+) -> Box<Stream<Item = RaidInfo, Error = Error>>
+where
+    S: Stream<Item = RaidInfo, Error = Error> + 'static,
+    F: FnOnce(Error) + 'static,
+{
+    let backfilled = backfill
+        .then(|result| {
+            let infos = result.unwrap_or_else(|e| {
+                on_error(e);
+                Vec::new()
+            });
+            Ok(stream::iter_ok(infos))
+        })
+        .flatten_stream();
+
+    Box::new(backfilled.chain(stream))
+}
+
+fn parse_search_response(json: &[u8]) -> Result<Vec<RaidInfo>> {
+    let response = serde_json::from_slice::<SearchResponse>(json)
+        .chain_err(|| ErrorKind::Json(String::from_utf8_lossy(json).into_owned()))?;
+
+    // Search results are newest first
+    Ok(response
+        .statuses
+        .into_iter()
+        .rev()
+        .filter_map(RaidInfo::from_tweet)
+        .collect())
+}
+
+// Stream keywords are comma-separated, and are matched as phrases
+fn search_url() -> Url {
+    let query = RaidInfoStream::track()
+        .split(',')
+        .map(|keyword| format!("\"{}\"", keyword))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let url = format!(
+        "{}?count=100&q={}&result_type=recent",
+        SEARCH_URL,
+        percent_encode(&query)
+    );
+    Url::parse(&url).expect("invalid search URL")
+}
+
+// OAuth signatures require everything except unreserved characters to be
+// percent-encoded, including spaces as `%20`
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use client::harness::Harness;
+    use futures::future;
+    use model::Language;
+    use raid::GRANBLUE_APP_SOURCE;
+    use raid::test::tweet_json as tweet;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn search_response() -> String {
+        let english = "I need backup!\nLvl 60 Ozorotter";
+        let statuses = [
+            tweet(3, GRANBLUE_APP_SOURCE, &format!("ABCD0003 :Battle ID\n{}", english)),
+            tweet(2, "Twitter Web Client", &format!("ABCD0002 :Battle ID\n{}", english)),
+            tweet(1, GRANBLUE_APP_SOURCE, "ABCD0001 :参戦ID\n参加者募集！\nLv60 オオゾラッコ"),
+        ];
+
+        format!(r#"{{"statuses": [{}], "search_metadata": {{}}}}"#, statuses.join(","))
+    }
+
+    #[test]
+    fn parse_response() {
+        let infos = parse_search_response(search_response().as_bytes()).unwrap();
+
+        let tweets = infos
+            .iter()
+            .map(|info| (info.tweet.tweet_id, info.tweet.raid_id.as_str(), info.tweet.language))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tweets,
+            vec![(1, "ABCD0001", Language::Japanese), (3, "ABCD0003", Language::English)]
+        );
+
+        assert!(parse_search_response(b"{}").is_err());
+    }
+
+    #[test]
+    fn seed_worker() {
+        let infos = parse_search_response(search_response().as_bytes()).unwrap();
+        let mut h = Harness::new();
+        h.push_all(infos);
+
+        let bosses = h.client.bosses();
+        assert_eq!(h.request(bosses).len(), 2);
+
+        let tweets = h.client.tweets("Lvl 60 Ozorotter");
+        assert_eq!(h.request(tweets)[0].raid_id, "ABCD0003");
+    }
+
+    #[test]
+    fn backfill_before_live_tweets() {
+        let backfilled = parse_search_response(search_response().as_bytes()).unwrap();
+        let live = ::client::harness::raid_info("Lvl 60 Ozorotter", 4, 0);
+
+        let backfilled = Box::new(future::ok(backfilled));
+        let stream = with_backfill(backfilled, stream::iter_ok(vec![live]), |_| {
+            panic!("backfill shouldn't fail")
+        });
+        let ids = stream
+            .map(|info| info.tweet.tweet_id)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(ids, vec![1, 3, 4]);
+
+        // Streaming continues if the backfill fails
+        let failed: Backfill = Box::new(future::err(ErrorKind::Twitter.into()));
+        let live = ::client::harness::raid_info("Lvl 60 Ozorotter", 5, 0);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let stream = with_backfill(failed, stream::iter_ok(vec![live]), {
+            let errors = errors.clone();
+            move |e: Error| {
+                errors.borrow_mut().push(e.to_string());
+            }
+        });
+        assert_eq!(stream.collect().wait().unwrap().len(), 1);
+        assert_eq!(*errors.borrow(), vec!["Twitter streaming error"]);
+    }
+
+    #[test]
+    fn encoded_search_url() {
+        let url = search_url();
+        assert_eq!(url.path(), "/1.1/search/tweets.json");
+
+        let query = url.query_pairs()
+            .find(|&(ref key, _)| key == "q")
+            .map(|(_, value)| value.into_owned());
+        assert_eq!(
+            query.as_ref().map(String::as_str),
+            Some("\"参加者募集！\" OR \":参戦ID\" OR \"I need backup!\" OR \":Battle ID\"")
+        );
+        assert!(!url.as_str().contains('+'));
+    }
+}
@@ -0,0 +1,86 @@
+// Tags each raid tweet with the time it was received, which can be compared
+// with the tweet's `created_at` to measure end-to-end latency
+use clock::Clock;
+use error::*;
+use futures::{Async, Poll, Stream};
+use model::DateTime;
+use raid::RaidInfo;
+
+#[must_use = "streams do nothing unless polled"]
+pub struct WithIngestionTime<S, C> {
+    stream: S,
+    clock: C,
+}
+
+impl<S, C> WithIngestionTime<S, C>
+where
+    S: Stream<Item = RaidInfo, Error = Error>,
+    C: Clock,
+{
+    pub fn new(stream: S, clock: C) -> Self {
+        WithIngestionTime { stream, clock }
+    }
+}
+
+impl<S, C> Stream for WithIngestionTime<S, C>
+where
+    S: Stream<Item = RaidInfo, Error = Error>,
+    C: Clock,
+{
+    type Item = (RaidInfo, DateTime);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let polled = try_ready!(self.stream.poll());
+        Ok(Async::Ready(polled.map(|info| (info, self.clock.now()))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use client::harness::raid_info;
+    use clock::ManualClock;
+    use futures::unsync::mpsc;
+    use std::time::Duration;
+
+    fn closed(_: ()) -> Error {
+        ErrorKind::Closed.into()
+    }
+
+    #[test]
+    fn attach_ingestion_time() {
+        let clock = ManualClock::new(Utc.timestamp(100, 0));
+        let (tx, rx) = mpsc::unbounded();
+        let rx = rx.map_err(closed as fn(()) -> Error);
+        let mut stream = WithIngestionTime::new(rx, clock.clone());
+
+        tx.unbounded_send(raid_info("Lvl 60 Ozorotter", 1, 0)).unwrap();
+        clock.advance(Duration::from_secs(5));
+        tx.unbounded_send(raid_info("Lvl 60 Ozorotter", 2, 0)).unwrap();
+        drop(tx);
+
+        let (info, ingested_at) = match stream.poll() {
+            Ok(Async::Ready(Some(item))) => item,
+            other => panic!("expected raid info, got {:?}", other),
+        };
+        assert_eq!(info.tweet.tweet_id, 1);
+        assert_eq!(ingested_at, Utc.timestamp(105, 0));
+        assert_eq!(
+            ingested_at.signed_duration_since(info.tweet.created_at),
+            ::chrono::Duration::seconds(105)
+        );
+
+        clock.advance(Duration::from_secs(1));
+        match stream.poll() {
+            Ok(Async::Ready(Some((ref info, ingested_at)))) => {
+                assert_eq!(info.tweet.tweet_id, 2);
+                assert_eq!(ingested_at, Utc.timestamp(106, 0));
+            }
+            other => panic!("expected raid info, got {:?}", other),
+        }
+
+        assert_eq!(stream.poll().unwrap(), Async::Ready(None));
+    }
+}
@@ -0,0 +1,1136 @@
+use clock::Clock;
+use error::*;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::FlattenStream;
+use hyper;
+use model::{profile_image_variant, BossImageUrl, BossName, Language, ProfileImageSize,
+            RaidTweet, TweetSource};
+use regex::Regex;
+use serde_json;
+use std::cell::Cell;
+use std::rc::Rc;
+use tokio_core::reactor::Handle;
+use twitter_stream::{FutureTwitterStream, Token, TwitterStreamBuilder};
+use twitter_stream::message::StreamMessage;
+use twitter_stream::message::message::{Disconnect, Warning, WarningCode};
+use twitter_stream::message::Tweet;
+use twitter_stream::message::entities::Media;
+
+#[cfg(feature = "backfill")]
+pub mod backfill;
+mod ingestion;
+// Built for tests too, so that its tests run without the feature
+#[cfg(any(test, feature = "raid-recorder"))]
+pub mod recorder;
+mod switchable;
+
+pub use self::ingestion::WithIngestionTime;
+pub(crate) use self::switchable::{Replacement, StreamReplacer};
+pub use self::switchable::{BoxRaidInfoStream, SwitchableStream};
+
+const GRANBLUE_APP_SOURCE: &'static str =
+r#"<a href="http://granbluefantasy.jp/" rel="nofollow">グランブルー ファンタジー</a>"#;
+
+// Part of `GRANBLUE_APP_SOURCE` that is unaffected by JSON escaping
+const GRANBLUE_APP_SOURCE_DOMAIN: &str = "granbluefantasy.jp";
+
+// Real boss names are much shorter than this
+const MAX_BOSS_NAME_CHARS: usize = 64;
+
+lazy_static! {
+    static ref REGEX_JAPANESE: Regex = Regex::new("\
+        (?P<text>(?s).*)(?P<id>[0-9A-F]{8}) :参戦ID\n\
+        参加者募集！\n\
+        (?P<boss>.+)\n?\
+        (?:[\t ]*\n)?\
+        (?P<url>.*)\
+    ").expect("invalid Japanese raid tweet regex");
+
+    static ref REGEX_ENGLISH: Regex = Regex::new("\
+        (?P<text>(?s).*)(?P<id>[0-9A-F]{8}) :Battle ID\n\
+        I need backup!\n\
+        (?P<boss>.+)\n?\
+        (?:[\t ]*\n)?\
+        (?P<url>.*)\
+    ").expect("invalid English raid tweet regex");
+
+    static ref REGEX_IMAGE_URL: Regex = Regex::new("^https?://[^ ]+$")
+        .expect("invalid image URL regex");
+
+    static ref REGEX_URL_TOKEN: Regex = Regex::new(r"(?:^|\s)https?://")
+        .expect("invalid URL token regex");
+}
+
+#[must_use = "streams do nothing unless polled"]
+pub struct RaidInfoStream {
+    stream: FlattenStream<FutureTwitterStream>,
+    parser: MessageParser,
+}
+
+// Options for turning messages from the stream into `RaidInfo`s
+#[derive(Debug, Default)]
+struct MessageParser {
+    collapse_text_newlines: bool,
+    // If unset, user images are kept as Twitter sent them
+    profile_image_size: Option<ProfileImageSize>,
+    // If unset, messages that can't be deserialized are skipped
+    fail_on_parse_error: bool,
+    media_selection: MediaSelection,
+    warnings: StreamWarnings,
+}
+
+// Which of a tweet's media items to use as the boss image, if it has more
+// than one. Since translations are matched by image hash, this should stay
+// the same for the lifetime of a worker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MediaSelection {
+    First,
+    Last,
+    // The item with the most pixels in its `large` size, or the last one
+    // of the largest items
+    Largest,
+}
+
+impl Default for MediaSelection {
+    fn default() -> Self {
+        MediaSelection::Last
+    }
+}
+
+impl MediaSelection {
+    fn select<'a>(self, mut media: Vec<Media<'a>>) -> Option<Media<'a>> {
+        match self {
+            MediaSelection::First => media.into_iter().next(),
+            MediaSelection::Last => media.pop(),
+            MediaSelection::Largest => media
+                .into_iter()
+                .max_by_key(|m| m.sizes.large.w.saturating_mul(m.sizes.large.h)),
+        }
+    }
+}
+
+// Counts of warning and disconnect messages received from Twitter. Stall
+// warnings mean the connection is falling behind Twitter's buffer, and
+// will be disconnected if it doesn't catch up.
+#[derive(Clone, Debug, Default)]
+pub struct StreamWarnings(Rc<WarningCounts>);
+
+#[derive(Debug, Default)]
+struct WarningCounts {
+    stall_warnings: Cell<u64>,
+    other_warnings: Cell<u64>,
+    disconnects: Cell<u64>,
+    parse_errors: Cell<u64>,
+}
+
+impl StreamWarnings {
+    pub fn stall_warnings(&self) -> u64 {
+        self.0.stall_warnings.get()
+    }
+
+    pub fn other_warnings(&self) -> u64 {
+        self.0.other_warnings.get()
+    }
+
+    pub fn disconnects(&self) -> u64 {
+        self.0.disconnects.get()
+    }
+
+    // Messages that were skipped because they couldn't be deserialized
+    pub fn parse_errors(&self) -> u64 {
+        self.0.parse_errors.get()
+    }
+
+    fn inc(counter: &Cell<u64>) {
+        counter.set(counter.get() + 1);
+    }
+
+    fn count_warning(&self, warning: &Warning) {
+        if let WarningCode::FallingBehind(_) = warning.code {
+            Self::inc(&self.0.stall_warnings);
+        } else {
+            Self::inc(&self.0.other_warnings);
+        }
+    }
+}
+
+// TODO: Add version that reconnects on disconnect/error
+impl RaidInfoStream {
+    fn track() -> &'static str {
+        "参加者募集！,:参戦ID,I need backup!,:Battle ID"
+    }
+
+    pub fn with_client<C, B>(hyper_client: &hyper::Client<C, B>, token: &Token) -> Self
+    where
+        C: hyper::client::Connect,
+        B: From<Vec<u8>> + Stream<Error = hyper::Error> + 'static,
+        B::Item: AsRef<[u8]>,
+    {
+        let stream = TwitterStreamBuilder::filter(token)
+            .client(&hyper_client)
+            .user_agent(Some("petronel")) // TODO: Make this configurable?
+            .timeout(None)
+            .track(Some(Self::track()))
+            .listen()
+            .flatten_stream();
+
+        RaidInfoStream {
+            stream,
+            parser: MessageParser::default(),
+        }
+    }
+
+    // TODO: Clean up duplicated code
+    pub fn with_handle(handle: &Handle, token: &Token) -> Self {
+        let stream = TwitterStreamBuilder::filter(token)
+            .handle(handle)
+            .user_agent(Some("petronel")) // TODO: Make this configurable?
+            .timeout(None)
+            .track(Some(&Self::track()))
+            .listen()
+            .flatten_stream();
+
+        RaidInfoStream {
+            stream,
+            parser: MessageParser::default(),
+        }
+    }
+
+    // A handle to the warning counts for this stream, which stays
+    // up to date after the stream is passed to the worker
+    pub fn warnings(&self) -> StreamWarnings {
+        self.parser.warnings.clone()
+    }
+
+    // Replace runs of whitespace (including newlines) in the extra text of
+    // raid tweets with a single space, for single-line display
+    pub fn with_collapse_text_newlines(mut self, collapse: bool) -> Self {
+        self.parser.collapse_text_newlines = collapse;
+        self
+    }
+
+    // Store this rendition of each user's profile image, instead of the
+    // 48x48 one that Twitter sends
+    pub fn with_profile_image_size(mut self, size: ProfileImageSize) -> Self {
+        self.parser.profile_image_size = Some(size);
+        self
+    }
+
+    // End the stream with an error if a message can't be deserialized.
+    // By default, such messages are skipped and counted in `warnings`.
+    pub fn with_fail_on_parse_error(mut self, fail: bool) -> Self {
+        self.parser.fail_on_parse_error = fail;
+        self
+    }
+
+    // Which image to use for tweets with more than one. Defaults to the
+    // last one.
+    pub fn with_media_selection(mut self, selection: MediaSelection) -> Self {
+        self.parser.media_selection = selection;
+        self
+    }
+
+    // Pairs each `RaidInfo` with the time it was received, according to
+    // the clock
+    pub fn with_ingestion_time<C: Clock>(self, clock: C) -> WithIngestionTime<Self, C> {
+        WithIngestionTime::new(self, clock)
+    }
+}
+
+impl Stream for RaidInfoStream {
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.parser.poll(&mut self.stream)
+    }
+}
+
+impl MessageParser {
+    fn poll<S>(&self, stream: &mut S) -> Poll<Option<RaidInfo>, Error>
+    where
+        S: Stream,
+        S::Item: AsRef<str>,
+        S::Error: ::std::error::Error + Send + 'static,
+    {
+        loop {
+            let polled = stream.poll().chain_err(|| ErrorKind::Twitter);
+            let json = match try_ready!(polled) {
+                Some(json) => json,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let parsed = parse_message(
+                json.as_ref(),
+                self.collapse_text_newlines,
+                self.profile_image_size,
+                self.media_selection,
+                &self.warnings,
+                deserialize_message,
+            );
+
+            match parsed {
+                Ok(Some(raid_info)) => return Ok(Async::Ready(Some(raid_info))),
+                Ok(None) => {}
+                Err(e) => {
+                    if self.fail_on_parse_error {
+                        return Err(e);
+                    }
+
+                    StreamWarnings::inc(&self.warnings.0.parse_errors);
+                }
+            }
+        }
+    }
+}
+
+fn deserialize_message(json: &str) -> Result<StreamMessage> {
+    StreamMessage::from_str(json).chain_err(|| ErrorKind::Json(json.to_string()))
+}
+
+// Messages from Twitter about the connection itself, rather than tweets.
+// Each is an object with a single top-level key.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ControlMessage<'a> {
+    Warning {
+        #[serde(borrow)]
+        warning: Warning<'a>,
+    },
+    Disconnect {
+        // Only deserialized so that other objects aren't counted
+        #[allow(dead_code)]
+        #[serde(borrow)]
+        disconnect: Disconnect<'a>,
+    },
+}
+
+// Messages that can't possibly be from the Granblue app are only checked
+// for warnings and disconnects, without being deserialized as tweets
+fn parse_message<'a, D>(
+    json: &'a str,
+    collapse_text_newlines: bool,
+    profile_image_size: Option<ProfileImageSize>,
+    media_selection: MediaSelection,
+    warnings: &StreamWarnings,
+    deserialize: D,
+) -> Result<Option<RaidInfo>>
+where
+    D: FnOnce(&'a str) -> Result<StreamMessage<'a>>,
+{
+    if !json.contains(GRANBLUE_APP_SOURCE_DOMAIN) {
+        match serde_json::from_str(json) {
+            Ok(ControlMessage::Warning { warning }) => warnings.count_warning(&warning),
+            Ok(ControlMessage::Disconnect { .. }) => StreamWarnings::inc(&warnings.0.disconnects),
+            Err(_) => {}
+        }
+        return Ok(None);
+    }
+
+    match deserialize(json)? {
+        StreamMessage::Tweet(tweet) => Ok(RaidInfo::parse_tweet(
+            *tweet,
+            collapse_text_newlines,
+            profile_image_size,
+            media_selection,
+        )),
+        StreamMessage::Warning(warning) => {
+            warnings.count_warning(&warning);
+            Ok(None)
+        }
+        StreamMessage::Disconnect(_) => {
+            StreamWarnings::inc(&warnings.0.disconnects);
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct TweetParts<'a> {
+    language: Language,
+    text: Option<&'a str>,
+    raid_id: &'a str,
+    boss_name: &'a str,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RaidInfo {
+    pub tweet: RaidTweet,
+    pub image: Option<BossImageUrl>,
+}
+
+impl RaidInfo {
+    pub fn from_tweet(tweet: Tweet) -> Option<RaidInfo> {
+        Self::parse_tweet(tweet, false, None, MediaSelection::default())
+    }
+
+    // Like `from_tweet`, but chooses the boss image with `selection` if the
+    // tweet has more than one image
+    pub fn from_tweet_with_media_selection(
+        tweet: Tweet,
+        selection: MediaSelection,
+    ) -> Option<RaidInfo> {
+        Self::parse_tweet(tweet, false, None, selection)
+    }
+
+    // Like `from_tweet`, but stores the given rendition of the user's
+    // profile image
+    pub fn from_tweet_with_profile_image_size(
+        tweet: Tweet,
+        size: ProfileImageSize,
+    ) -> Option<RaidInfo> {
+        Self::parse_tweet(tweet, false, Some(size), MediaSelection::default())
+    }
+
+    fn parse_tweet(
+        mut tweet: Tweet,
+        collapse_text_newlines: bool,
+        profile_image_size: Option<ProfileImageSize>,
+        media_selection: MediaSelection,
+    ) -> Option<RaidInfo> {
+        if tweet.source != GRANBLUE_APP_SOURCE {
+            return None;
+        }
+
+        let text = ::std::mem::replace(&mut tweet.text, "".into());
+
+        parse_text(&text).map(move |parsed| {
+            let user_image = if tweet.user.default_profile_image
+                || tweet
+                    .user
+                    .profile_image_url_https
+                    .contains("default_profile")
+            {
+                None
+            } else {
+                let url = tweet.user.profile_image_url_https;
+                Some(match profile_image_size {
+                    Some(size) => profile_image_variant(&url, size),
+                    None => url.into(),
+                })
+            };
+
+            let raid_tweet = RaidTweet {
+                tweet_id: tweet.id,
+                boss_name: BossName::normalized(parsed.boss_name),
+                raid_id: parsed.raid_id.into(),
+                user: tweet.user.screen_name.into(),
+                user_image,
+                text: parsed
+                    .text
+                    .map(|text| format_text(text, collapse_text_newlines)),
+                created_at: tweet.created_at,
+                language: parsed.language,
+                seq: 0,
+                source: TweetSource::Twitter,
+            };
+
+            let image = tweet
+                .entities
+                .media
+                .and_then(|media| media_selection.select(media))
+                .map(|m| m.media_url_https.into());
+
+            RaidInfo {
+                tweet: raid_tweet,
+                image,
+            }
+        })
+    }
+}
+
+fn format_text(text: &str, collapse_newlines: bool) -> String {
+    if collapse_newlines {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    } else {
+        text.into()
+    }
+}
+
+fn parse_text<'a>(tweet_text: &'a str) -> Option<TweetParts<'a>> {
+    REGEX_JAPANESE
+        .captures(tweet_text)
+        .map(|c| (Language::Japanese, c))
+        .or_else(|| {
+            REGEX_ENGLISH
+                .captures(tweet_text)
+                .map(|c| (Language::English, c))
+        })
+        .and_then(|(lang, c)| {
+            if let (Some(text), Some(id), Some(boss), Some(url)) =
+                (c.name("text"), c.name("id"), c.name("boss"), c.name("url"))
+            {
+                let boss_name = strip_url(boss.as_str());
+                let url_str = url.as_str();
+
+                if !is_valid_boss_name(boss_name)
+                    || !url_str.is_empty() && !REGEX_IMAGE_URL.is_match(url_str)
+                {
+                    return None;
+                }
+
+                let t = text.as_str().trim();
+
+                Some(TweetParts {
+                    language: lang,
+                    text: if t.is_empty() { None } else { Some(t) },
+                    raid_id: id.as_str().trim(),
+                    boss_name,
+                })
+            } else {
+                None
+            }
+        })
+}
+
+// Rejects names that can't belong to a real boss, e.g., from tweets that
+// were edited by hand. This is checked on `&str` before a `BossName` is
+// created, so that rejected names are never interned.
+fn is_valid_boss_name(boss_name: &str) -> bool {
+    !boss_name.is_empty()
+        && boss_name.chars().count() <= MAX_BOSS_NAME_CHARS
+        && !boss_name.chars().any(char::is_control)
+}
+
+// Some game clients put the game's URL on the same line as the boss name,
+// without a newline in between, so the boss name ends at the first URL
+fn strip_url(boss_name: &str) -> &str {
+    match REGEX_URL_TOKEN.find(boss_name) {
+        Some(m) => boss_name[..m.start()].trim(),
+        None => boss_name.trim(),
+    }
+}
+
+#[cfg(test)]
+impl<'a> TweetParts<'a> {
+    fn new(
+        language: Language,
+        text: Option<&'a str>,
+        raid_id: &'a str,
+        boss_name: &'a str,
+    ) -> Self {
+        TweetParts {
+            language,
+            text,
+            raid_id,
+            boss_name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Language::{English, Japanese};
+
+    // A full tweet from the streaming or REST API
+    pub(super) fn tweet_json(id: u64, source: &str, text: &str) -> String {
+        format!(
+            r#"{{
+                "created_at": "Sat Oct 17 12:00:{:02} +0000 2026",
+                "entities": {{"hashtags": [], "urls": [], "user_mentions": [], "symbols": []}},
+                "id": {},
+                "is_quote_status": false,
+                "retweet_count": 0,
+                "retweeted": false,
+                "source": {},
+                "text": {},
+                "truncated": false,
+                "user": {{
+                    "contributors_enabled": false,
+                    "created_at": "Sat Oct 17 12:00:00 +0000 2026",
+                    "default_profile": true,
+                    "default_profile_image": true,
+                    "description": null,
+                    "favourites_count": 0,
+                    "followers_count": 0,
+                    "friends_count": 0,
+                    "geo_enabled": false,
+                    "id": 1,
+                    "is_translator": false,
+                    "lang": "ja",
+                    "listed_count": 0,
+                    "location": null,
+                    "name": "walfie",
+                    "profile_background_color": "000000",
+                    "profile_background_image_url": "",
+                    "profile_background_image_url_https": "",
+                    "profile_background_tile": false,
+                    "profile_banner_url": null,
+                    "profile_image_url": "",
+                    "profile_image_url_https": "",
+                    "profile_link_color": "000000",
+                    "profile_sidebar_border_color": "000000",
+                    "profile_sidebar_fill_color": "000000",
+                    "profile_text_color": "000000",
+                    "profile_use_background_image": false,
+                    "protected": false,
+                    "screen_name": "walfieee",
+                    "statuses_count": 0,
+                    "time_zone": null,
+                    "url": null,
+                    "verified": false,
+                    "withheld_in_countries": null
+                }}
+            }}"#,
+            id,
+            id,
+            serde_json::to_string(source).unwrap(),
+            serde_json::to_string(text).unwrap()
+        )
+    }
+
+    #[test]
+    fn skip_deserializing_non_granblue_messages() {
+        let deserialized = ::std::cell::Cell::new(0);
+        let count = |json| {
+            deserialized.set(deserialized.get() + 1);
+            deserialize_message(json)
+        };
+
+        let warnings = StreamWarnings::default();
+        let parse = |json| {
+            parse_message(json, false, None, MediaSelection::Last, &warnings, &count).unwrap()
+        };
+
+        let other_source = r#"{"source":"<a href=\"http://twitter.com\">Twitter Web Client</a>"}"#;
+        assert_eq!(parse(other_source), None);
+        assert_eq!(deserialized.get(), 0);
+
+        // Twitter escapes forward slashes
+        let granblue = r#"{"source":"<a href=\"http:\/\/granbluefantasy.jp\/\">"}"#;
+        assert_eq!(parse(granblue), None);
+        assert_eq!(deserialized.get(), 1);
+    }
+
+    #[test]
+    fn count_warnings() {
+        let warnings = StreamWarnings::default();
+        let parse = |json| {
+            let selection = MediaSelection::Last;
+            parse_message(json, false, None, selection, &warnings, deserialize_message).unwrap()
+        };
+
+        let stall = r#"{"warning":{"code":"FALLING_BEHIND","message":"Falling behind",
+            "percent_full":60}}"#;
+        assert_eq!(parse(stall), None);
+        assert_eq!(parse(stall), None);
+
+        let other = r#"{"warning":{"code":"FOLLOWS_OVER_LIMIT","message":"Too many follows",
+            "user_id":1}}"#;
+        assert_eq!(parse(other), None);
+
+        let disconnect = r#" {"disconnect":{"code":4,"stream_name":"petronel",
+            "reason":"Stall"}}"#;
+        assert_eq!(parse(disconnect), None);
+
+        // Recognized by their contents, not how they're formatted
+        let spaced = r#"{ "disconnect": {"reason":"Stall","stream_name":"petronel",
+            "code":4} }"#;
+        assert_eq!(parse(spaced), None);
+
+        let limit = r#"{"limit":{"track":10,"timestamp_ms":"1500000000000"}}"#;
+        assert_eq!(parse(limit), None);
+        assert_eq!(parse(r#"{"warning":"not a warning"}"#), None);
+
+        assert_eq!(warnings.stall_warnings(), 2);
+        assert_eq!(warnings.other_warnings(), 1);
+        assert_eq!(warnings.disconnects(), 2);
+        assert_eq!(warnings.parse_errors(), 0);
+    }
+
+    #[test]
+    fn report_warnings_to_metrics() {
+        use client::harness::Harness;
+
+        let warnings = StreamWarnings::default();
+        let mut h = Harness::with_json_metrics_and_builder({
+            let warnings = warnings.clone();
+            move |b| b.with_stream_warnings(warnings)
+        });
+
+        let stall = r#"{"warning":{"code":"FALLING_BEHIND","message":"Falling behind",
+            "percent_full":60}}"#;
+        let selection = MediaSelection::Last;
+        parse_message(stall, false, None, selection, &warnings, deserialize_message).unwrap();
+
+        let metrics = h.metrics();
+        assert_eq!(metrics["stream_warnings"]["stall_warnings"], 1);
+        assert_eq!(metrics["stream_warnings"]["disconnects"], 0);
+
+        // Totals are kept after a reset
+        let metrics = h.client.export_metrics_and_reset();
+        h.request(metrics);
+        assert_eq!(h.metrics()["stream_warnings"]["stall_warnings"], 1);
+    }
+
+    fn parse_all(parser: MessageParser, messages: Vec<String>) -> Result<Vec<RaidInfo>> {
+        use futures::stream;
+
+        let mut messages = stream::iter_ok::<_, ::twitter_stream::Error>(messages);
+        stream::poll_fn(move || parser.poll(&mut messages))
+            .collect()
+            .wait()
+    }
+
+    fn with_malformed_message() -> Vec<String> {
+        let text = |id| format!("{} :Battle ID\nI need backup!\nLvl 60 Ozorotter", id);
+
+        vec![
+            tweet_json(1, GRANBLUE_APP_SOURCE, &text("ABCD0001")),
+            r#"{"source":"granbluefantasy.jp","id":"#.to_string(),
+            tweet_json(2, GRANBLUE_APP_SOURCE, &text("ABCD0002")),
+        ]
+    }
+
+    #[test]
+    fn skip_parse_errors() {
+        let parser = MessageParser::default();
+        let warnings = parser.warnings.clone();
+
+        let infos = parse_all(parser, with_malformed_message()).unwrap();
+        let ids = infos.iter().map(|info| info.tweet.tweet_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(warnings.parse_errors(), 1);
+    }
+
+    #[test]
+    fn fail_on_parse_errors() {
+        let parser = MessageParser {
+            fail_on_parse_error: true,
+            ..MessageParser::default()
+        };
+
+        assert!(parse_all(parser, with_malformed_message()).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_boss_names() {
+        let parse = |boss_name: &str| {
+            let text = format!("ABCD1234 :Battle ID\nI need backup!\n{}", boss_name);
+            let json = tweet_json(1, GRANBLUE_APP_SOURCE, &text);
+            RaidInfo::from_tweet(::serde_json::from_str::<Tweet>(&json).unwrap())
+        };
+
+        let too_long = format!("Lvl 60 {}", "Ozorotter".repeat(8));
+        assert_eq!(parse(&too_long), None);
+        assert_eq!(parse("Lvl 60\tOzorotter"), None);
+
+        let info = parse("Lvl 60 Ozorotter").unwrap();
+        assert_eq!(&*info.tweet.boss_name, "Lvl 60 Ozorotter");
+    }
+
+    #[test]
+    fn profile_image_size() {
+        let text = "ABCD1234 :参戦ID\n参加者募集！\nLv60 オオゾラッコ";
+        let json = tweet_json(1, GRANBLUE_APP_SOURCE, text);
+        let mut tweet = ::serde_json::from_str::<Tweet>(&json).unwrap();
+        tweet.user.default_profile_image = false;
+        tweet.user.profile_image_url_https =
+            "https://pbs.twimg.com/profile_images/1/a_normal.jpg".into();
+
+        let info = RaidInfo::from_tweet(tweet.clone()).unwrap();
+        assert_eq!(
+            info.tweet.user_image.unwrap(),
+            "https://pbs.twimg.com/profile_images/1/a_normal.jpg"
+        );
+
+        let info = RaidInfo::from_tweet_with_profile_image_size(tweet, ProfileImageSize::Bigger);
+        assert_eq!(
+            info.unwrap().tweet.user_image.unwrap(),
+            "https://pbs.twimg.com/profile_images/1/a_bigger.jpg"
+        );
+    }
+
+    fn media_json(url: &str, width: u64, height: u64) -> String {
+        let size = |w, h, resize| format!(r#"{{"w": {}, "h": {}, "resize": "{}"}}"#, w, h, resize);
+
+        format!(
+            r#"{{
+                "display_url": "pic.twitter.com/abc",
+                "expanded_url": "https://twitter.com/walfieee/status/1/photo/1",
+                "id": 1,
+                "indices": [0, 0],
+                "media_url": "{url}",
+                "media_url_https": "{url}",
+                "sizes": {{
+                    "thumb": {thumb},
+                    "large": {large},
+                    "medium": {large},
+                    "small": {large}
+                }},
+                "source_status_id": null,
+                "type": "photo",
+                "url": "https://t.co/abc"
+            }}"#,
+            url = url,
+            thumb = size(150, 150, "crop"),
+            large = size(width, height, "fit"),
+        )
+    }
+
+    #[test]
+    fn media_selection() {
+        let text = "ABCD1234 :Battle ID\nI need backup!\nLvl 60 Ozorotter";
+        let json = tweet_json(1, GRANBLUE_APP_SOURCE, text);
+        let media = format!(
+            "[{}, {}, {}]",
+            media_json("https://pbs.twimg.com/media/a.jpg", 400, 300),
+            media_json("https://pbs.twimg.com/media/b.jpg", 1200, 900),
+            media_json("https://pbs.twimg.com/media/c.jpg", 600, 450),
+        );
+        let mut tweet = ::serde_json::from_str::<Tweet>(&json).unwrap();
+        tweet.entities.media = Some(::serde_json::from_str(&media).unwrap());
+
+        let image = |selection| {
+            let info = RaidInfo::from_tweet_with_media_selection(tweet.clone(), selection);
+            info.unwrap().image.unwrap().to_string()
+        };
+
+        assert_eq!(image(MediaSelection::First), "https://pbs.twimg.com/media/a.jpg");
+        assert_eq!(image(MediaSelection::Last), "https://pbs.twimg.com/media/c.jpg");
+        assert_eq!(image(MediaSelection::Largest), "https://pbs.twimg.com/media/b.jpg");
+
+        let info = RaidInfo::from_tweet(tweet.clone()).unwrap();
+        assert_eq!(info.image.unwrap().to_string(), image(MediaSelection::default()));
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalize_boss_names() {
+        use unicode_normalization::UnicodeNormalization;
+
+        // In NFD, the dakuten in "ベ" is a separate combining character
+        let text = "ABCD1234 :参戦ID\n参加者募集！\nLv100 ケルベロス".nfd().collect::<String>();
+        let json = tweet_json(1, GRANBLUE_APP_SOURCE, &text);
+        let tweet = ::serde_json::from_str::<Tweet>(&json).unwrap();
+
+        let info = RaidInfo::from_tweet(tweet).unwrap();
+        assert_eq!(info.tweet.boss_name.as_str(), "Lv100 ケルベロス");
+    }
+
+    #[test]
+    fn format_multiline_text() {
+        let parts = parse_text(
+            "Help\n\n me  \n out ABCD1234 :Battle ID\n\
+             I need backup!\n\
+             Lvl 60 Ozorotter",
+        ).unwrap();
+
+        let text = parts.text.unwrap();
+        assert_eq!(format_text(text, false), "Help\n\n me  \n out");
+        assert_eq!(format_text(text, true), "Help me out");
+    }
+
+    #[test]
+    fn parse_ignore_invalid_text() {
+        assert_eq!(
+            parse_text("#GranblueHaiku http://example.com/haiku.png"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_ignore_daily_refresh() {
+        // Ignore tweets made via the daily Twitter refresh
+        // https://github.com/walfie/gbf-raidfinder/issues/98
+        assert_eq!(
+            parse_text(
+                "救援依頼 参加者募集！参戦ID：114514810\n\
+                 Lv100 ケルベロス スマホRPGは今これをやってるよ。\
+                 今の推しキャラはこちら！　\
+                 ゲーム内プロフィール→　\
+                 https://t.co/5Xgohi9wlE https://t.co/Xlu7lqQ3km",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_ignore_another_daily_refresh() {
+        // First two lines are user input
+        assert_eq!(
+            parse_text(
+                "救援依頼 参加者募集！参戦ID：114514810\n\
+                 Lv100 ケルベロス\n\
+                 スマホRPGは今これをやってるよ。\
+                 今の推しキャラはこちら！　\
+                 ゲーム内プロフィール→　\
+                 https://t.co/5Xgohi9wlE https://t.co/Xlu7lqQ3km",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_ignore_extra_space_in_image_url() {
+        // First two lines are user input
+        assert_eq!(
+            parse_text(
+                "救援依頼 参加者募集！参戦ID：114514810\n\
+                 Lv100 ケルベロス\n\
+                 https://t.co/5Xgohi9wlE https://t.co/Xlu7lqQ3km",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_without_extra_text() {
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                None,
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                English,
+                None,
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_without_image_url() {
+        assert_eq!(
+            parse_text(
+                "Help me ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                Some("Help me"),
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "Help me ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter",
+            ),
+            Some(TweetParts::new(
+                English,
+                Some("Help me"),
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_with_extra_newline() {
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ\n",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                None,
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\n",
+            ),
+            Some(TweetParts::new(
+                English,
+                None,
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_blank_line_before_image_url() {
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ\n\
+                 \n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                None,
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\n  \n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                English,
+                None,
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_url_on_boss_line() {
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ https://game.granbluefantasy.jp/#quest/supporter",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                None,
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\thttp://game.granbluefantasy.jp/ http://example.com/a.png",
+            ),
+            Some(TweetParts::new(
+                English,
+                None,
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+
+        // Nothing is left of the boss name
+        assert_eq!(
+            parse_text(
+                "ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 https://game.granbluefantasy.jp/",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_ignore_daily_refresh_after_blank_line() {
+        assert_eq!(
+            parse_text(
+                "救援依頼 参加者募集！参戦ID：114514810\n\
+                 Lv100 ケルベロス\n\
+                 \n\
+                 スマホRPGは今これをやってるよ。\
+                 https://t.co/5Xgohi9wlE https://t.co/Xlu7lqQ3km",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_extra_text() {
+        assert_eq!(
+            parse_text(
+                "Help me ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                Some("Help me"),
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "Help me ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                English,
+                Some("Help me"),
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_newlines_in_extra_text() {
+        assert_eq!(
+            parse_text(
+                "Hey\n\
+                 Newlines\n\
+                 Are\n\
+                 Cool\n\
+                 ABCD1234 :参戦ID\n\
+                 参加者募集！\n\
+                 Lv60 オオゾラッコ\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                Japanese,
+                Some("Hey\nNewlines\nAre\nCool"),
+                "ABCD1234",
+                "Lv60 オオゾラッコ",
+            ))
+        );
+
+        assert_eq!(
+            parse_text(
+                "Hey\n\
+                 Newlines\n\
+                 Are\n\
+                 Cool\n\
+                 ABCD1234 :Battle ID\n\
+                 I need backup!\n\
+                 Lvl 60 Ozorotter\n\
+                 http://example.com/image-that-is-ignored.png",
+            ),
+            Some(TweetParts::new(
+                English,
+                Some("Hey\nNewlines\nAre\nCool"),
+                "ABCD1234",
+                "Lvl 60 Ozorotter",
+            ))
+        );
+    }
+}
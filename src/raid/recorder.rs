@@ -0,0 +1,149 @@
+// Records raid tweets to a file as lines of JSON, so that a session can be
+// replayed later, e.g., for regression tests against real data
+use error::*;
+use futures::{Async, Poll, Stream};
+use raid::RaidInfo;
+use serde_json;
+use std::io::{BufRead, Lines, Write};
+
+// Passes through each `RaidInfo` from the underlying stream, after writing
+// it to `writer`
+#[must_use = "streams do nothing unless polled"]
+pub struct Recorder<S, W> {
+    stream: S,
+    writer: W,
+}
+
+impl<S, W> Recorder<S, W> {
+    pub fn new(stream: S, writer: W) -> Self {
+        Recorder { stream, writer }
+    }
+
+    pub fn into_inner(self) -> (S, W) {
+        (self.stream, self.writer)
+    }
+}
+
+impl<S, W> Stream for Recorder<S, W>
+where
+    S: Stream<Item = RaidInfo, Error = Error>,
+    W: Write,
+{
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let info = match try_ready!(self.stream.poll()) {
+            Some(info) => info,
+            None => {
+                self.writer
+                    .flush()
+                    .chain_err(|| "failed to flush recorded raid tweets")?;
+                return Ok(Async::Ready(None));
+            }
+        };
+
+        serde_json::to_writer(&mut self.writer, &info)
+            .chain_err(|| "failed to serialize raid tweet")?;
+        self.writer
+            .write_all(b"\n")
+            .chain_err(|| "failed to record raid tweet")?;
+
+        Ok(Async::Ready(Some(info)))
+    }
+}
+
+// Reads back the raid tweets written by a `Recorder`
+#[must_use = "streams do nothing unless polled"]
+pub struct Replay<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> Replay<R> {
+    pub fn new(reader: R) -> Self {
+        Replay {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Stream for Replay<R> {
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        for line in &mut self.lines {
+            let line = line.chain_err(|| "failed to read recorded raid tweet")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let info = serde_json::from_str(&line).chain_err(|| ErrorKind::Json(line.clone()))?;
+            return Ok(Async::Ready(Some(info)));
+        }
+
+        Ok(Async::Ready(None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use client::harness::{raid_info, raid_info_with_image, Harness};
+    use futures::{stream, Future};
+    use model::{BossName, RaidBossMetadata};
+
+    fn session() -> Vec<RaidInfo> {
+        let mut info = raid_info_with_image("Lvl 60 Ozorotter", 2, 10, "http://example.com/b.png");
+        info.tweet.text = Some("Help\nplease".into());
+
+        vec![
+            raid_info_with_image("Lv60 オオゾラッコ", 1, 0, "http://example.com/a.png"),
+            info,
+            raid_info("Lv75 スーペルヒガンテ", 3, 20),
+            raid_info("Lv60 オオゾラッコ", 4, 30),
+        ]
+    }
+
+    fn boss_state(infos: Vec<RaidInfo>) -> Vec<RaidBossMetadata> {
+        let mut h = Harness::new();
+        h.set_image_hash("Lv60 オオゾラッコ", 1);
+        h.set_image_hash("Lvl 60 Ozorotter", 1);
+
+        for info in infos {
+            h.push(info);
+        }
+        h.run();
+
+        let metadata = h.client.export_metadata();
+        let mut metadata = h.request(metadata);
+        metadata.sort_by_key(|m| m.boss.name.to_string());
+        metadata
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut recording = Vec::new();
+        let recorded = Recorder::new(stream::iter_ok(session()), &mut recording)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(recorded, session());
+
+        let replayed = Replay::new(&recording[..]).collect().wait().unwrap();
+        assert_eq!(replayed, session());
+
+        let state = boss_state(replayed);
+        assert_eq!(state, boss_state(recorded));
+        assert_eq!(state.len(), 3);
+
+        let translations = &state[0].boss.translations;
+        assert!(translations.contains(&BossName::from("Lvl 60 Ozorotter")));
+    }
+
+    #[test]
+    fn replay_invalid_json() {
+        let mut replay = Replay::new(&b"\n{\"tweet\":"[..]);
+        assert!(replay.poll().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+// A raid tweet stream whose source can be replaced while the worker is
+// running, e.g., to migrate to a different upstream without restarting
+use error::*;
+use futures::{Async, Poll, Stream};
+use futures::unsync::{mpsc, oneshot};
+use raid::RaidInfo;
+use std::fmt;
+
+pub type BoxRaidInfoStream = Box<Stream<Item = RaidInfo, Error = Error>>;
+
+pub(crate) struct Replacement {
+    pub(crate) stream: BoxRaidInfoStream,
+    // Completed once the stream has been replaced
+    pub(crate) sender: oneshot::Sender<()>,
+}
+
+impl fmt::Debug for Replacement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        write!(f, "Replacement")
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct StreamReplacer(mpsc::UnboundedSender<Replacement>);
+
+impl StreamReplacer {
+    pub(crate) fn replace(&self, replacement: Replacement) {
+        let _ = self.0.unbounded_send(replacement);
+    }
+}
+
+impl fmt::Debug for StreamReplacer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> ::std::result::Result<(), fmt::Error> {
+        write!(f, "StreamReplacer")
+    }
+}
+
+#[must_use = "streams do nothing unless polled"]
+pub struct SwitchableStream {
+    current: BoxRaidInfoStream,
+    replacements: mpsc::UnboundedReceiver<Replacement>,
+}
+
+impl SwitchableStream {
+    pub(crate) fn new(stream: BoxRaidInfoStream) -> (Self, StreamReplacer) {
+        let (tx, rx) = mpsc::unbounded();
+
+        let stream = SwitchableStream {
+            current: stream,
+            replacements: rx,
+        };
+
+        (stream, StreamReplacer(tx))
+    }
+}
+
+impl Stream for SwitchableStream {
+    type Item = RaidInfo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // The previous stream is dropped, along with anything it buffered.
+        // If every `StreamReplacer` is gone, the current stream is kept.
+        while let Ok(Async::Ready(Some(replacement))) = self.replacements.poll() {
+            self.current = replacement.stream;
+            let _ = replacement.sender.send(());
+        }
+
+        self.current.poll()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use client::harness::{self, tweet_ids, Harness, Recorder};
+    use futures::{future, Future};
+
+    fn closed(_: ()) -> Error {
+        ErrorKind::Closed.into()
+    }
+
+    fn boxed_stream() -> (mpsc::UnboundedSender<RaidInfo>, BoxRaidInfoStream) {
+        let (tx, rx) = mpsc::unbounded();
+        (tx, Box::new(rx.map_err(closed as fn(()) -> Error)))
+    }
+
+    #[test]
+    fn replace_stream() {
+        let (builder, _hasher, raids) = harness::builder();
+        let (client, mut worker) = builder.with_dynamic_stream().build().unwrap();
+
+        let mut run = || {
+            let polled = future::lazy(|| Ok::<_, ()>(worker.poll())).wait().unwrap();
+            assert_eq!(polled.expect("worker failed"), Async::NotReady);
+        };
+
+        let recorder = Recorder::new();
+        let subscription = client.subscribe(recorder.clone());
+        run();
+        let mut subscription = subscription.wait().unwrap();
+        subscription.follow("Lv60 オオゾラッコ");
+        run();
+
+        raids.unbounded_send(harness::raid_info("Lv60 オオゾラッコ", 1, 0)).unwrap();
+        raids.unbounded_send(harness::raid_info("Lv60 オオゾラッコ", 2, 0)).unwrap();
+        run();
+
+        let (next_raids, next_stream) = boxed_stream();
+        let replaced = client.replace_stream(next_stream);
+        run();
+        replaced.wait().expect("failed to replace stream");
+
+        // The original stream has been dropped
+        assert!(raids.unbounded_send(harness::raid_info("Lv60 オオゾラッコ", 3, 0)).is_err());
+
+        next_raids.unbounded_send(harness::raid_info("Lv60 オオゾラッコ", 4, 0)).unwrap();
+        next_raids.unbounded_send(harness::raid_info("Lv60 オオゾラッコ", 5, 0)).unwrap();
+        run();
+
+        assert_eq!(tweet_ids(&recorder.take()), vec![1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn replace_stream_requires_dynamic_stream() {
+        let mut h = Harness::new();
+        let (_raids, stream) = boxed_stream();
+        let replaced = h.client.replace_stream(stream);
+        h.run();
+        assert!(replaced.wait().is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use error::*;
+use std::borrow::Cow;
+pub use twitter_stream::Token;
+
+// `Token` is defined in `twitter_stream`, so validation is provided through
+// an extension trait. With this trait in scope, `Token::new_checked` can be
+// used in place of `Token::new`.
+pub trait TokenExt<'a>: Sized {
+    // Fails if any component is empty (or only whitespace), rather than
+    // failing to authenticate after connecting
+    fn new_checked<CK, CS, AK, AS>(
+        consumer_key: CK,
+        consumer_secret: CS,
+        access_key: AK,
+        access_secret: AS,
+    ) -> Result<Self>
+    where
+        CK: Into<Cow<'a, str>>,
+        CS: Into<Cow<'a, str>>,
+        AK: Into<Cow<'a, str>>,
+        AS: Into<Cow<'a, str>>;
+}
+
+impl<'a> TokenExt<'a> for Token<'a> {
+    fn new_checked<CK, CS, AK, AS>(
+        consumer_key: CK,
+        consumer_secret: CS,
+        access_key: AK,
+        access_secret: AS,
+    ) -> Result<Self>
+    where
+        CK: Into<Cow<'a, str>>,
+        CS: Into<Cow<'a, str>>,
+        AK: Into<Cow<'a, str>>,
+        AS: Into<Cow<'a, str>>,
+    {
+        let token = Token::new(consumer_key, consumer_secret, access_key, access_secret);
+
+        {
+            let components = [
+                ("consumer key", &token.consumer_key),
+                ("consumer secret", &token.consumer_secret),
+                ("access key", &token.access_key),
+                ("access secret", &token.access_secret),
+            ];
+
+            for &(name, value) in components.iter() {
+                if value.trim().is_empty() {
+                    bail!(ErrorKind::InvalidToken(name));
+                }
+            }
+        }
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_token() {
+        let token = Token::new_checked("ck", "cs", "ak", "as").unwrap();
+        assert_eq!(token.consumer_key, "ck");
+        assert_eq!(token.access_secret, "as");
+    }
+
+    #[test]
+    fn reject_empty_components() {
+        let cases = [
+            (["", "cs", "ak", "as"], "consumer key"),
+            (["ck", "", "ak", "as"], "consumer secret"),
+            (["ck", "cs", " ", "as"], "access key"),
+            (["ck", "cs", "ak", "\n"], "access secret"),
+        ];
+
+        for &(ref parts, expected) in cases.iter() {
+            match Token::new_checked(parts[0], parts[1], parts[2], parts[3]) {
+                Err(Error(ErrorKind::InvalidToken(name), _)) => assert_eq!(name, expected),
+                other => panic!("expected invalid {}, got {:?}", expected, other),
+            }
+        }
+    }
+}
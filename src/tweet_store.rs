@@ -0,0 +1,150 @@
+use circular_buffer::CircularBuffer;
+use model::{BossName, DateTime, RaidTweet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Holds the recent tweets for a single boss. Tweets are kept in the order
+// they were added, which is mostly, but not always, the order they were
+// created in.
+pub trait TweetStore {
+    // Adds a tweet as the newest one. If the store is full, the oldest
+    // tweet is removed and returned.
+    fn push(&mut self, tweet: Arc<RaidTweet>) -> Option<Arc<RaidTweet>>;
+
+    // Adds a tweet after the newest tweet that was created at or before
+    // it, for tweets that arrive late. If the store is full, the oldest
+    // tweet is removed and returned, which may be the given tweet.
+    fn insert_by_created_at(&mut self, tweet: Arc<RaidTweet>) -> Option<Arc<RaidTweet>>;
+
+    // From oldest to newest
+    fn recent(&self) -> Vec<Arc<RaidTweet>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Checked for every incoming tweet, to find tweets that arrived late
+    fn newest_created_at(&self) -> Option<DateTime> {
+        self.recent().iter().map(|t| t.created_at).max()
+    }
+
+    // Removes up to `count` of the oldest tweets. Returns the number of
+    // tweets removed.
+    fn remove_oldest(&mut self, count: usize) -> usize;
+}
+
+impl TweetStore for CircularBuffer<Arc<RaidTweet>> {
+    fn push(&mut self, tweet: Arc<RaidTweet>) -> Option<Arc<RaidTweet>> {
+        CircularBuffer::push(self, tweet)
+    }
+
+    fn insert_by_created_at(&mut self, tweet: Arc<RaidTweet>) -> Option<Arc<RaidTweet>> {
+        self.insert_by_key(tweet, |t| t.created_at)
+    }
+
+    fn recent(&self) -> Vec<Arc<RaidTweet>> {
+        let (older, newer) = self.as_slices();
+        older.iter().chain(newer).cloned().collect()
+    }
+
+    fn len(&self) -> usize {
+        CircularBuffer::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        CircularBuffer::is_empty(self)
+    }
+
+    fn newest_created_at(&self) -> Option<DateTime> {
+        self.as_unordered_slice().iter().map(|t| t.created_at).max()
+    }
+
+    fn remove_oldest(&mut self, count: usize) -> usize {
+        CircularBuffer::remove_oldest(self, count)
+    }
+}
+
+// The default store, which keeps up to `capacity` tweets in memory
+pub fn in_memory(_boss_name: &BossName, capacity: usize) -> Box<TweetStore> {
+    Box::new(CircularBuffer::with_capacity(capacity))
+}
+
+// Creates a store for each boss, given the boss' name and the history size
+#[derive(Clone)]
+pub(crate) struct StoreFactory(Rc<Fn(&BossName, usize) -> Box<TweetStore>>);
+
+impl StoreFactory {
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&BossName, usize) -> Box<TweetStore> + 'static,
+    {
+        StoreFactory(Rc::new(f))
+    }
+
+    pub(crate) fn create(&self, boss_name: &BossName, capacity: usize) -> Box<TweetStore> {
+        (self.0)(boss_name, capacity)
+    }
+}
+
+impl Default for StoreFactory {
+    fn default() -> Self {
+        StoreFactory::new(in_memory)
+    }
+}
+
+impl fmt::Debug for StoreFactory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("StoreFactory")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use client::harness::raid_info;
+    use model::TweetId;
+
+    fn tweet(tweet_id: TweetId, secs: i64) -> Arc<RaidTweet> {
+        Arc::new(raid_info("Lvl 60 Ozorotter", tweet_id, secs).tweet)
+    }
+
+    fn ids(store: &TweetStore) -> Vec<TweetId> {
+        store.recent().iter().map(|t| t.tweet_id).collect()
+    }
+
+    #[test]
+    fn in_memory_store() {
+        let mut store = in_memory(&"Lvl 60 Ozorotter".into(), 3);
+        assert!(store.is_empty());
+        assert!(store.recent().is_empty());
+
+        assert_eq!(store.push(tweet(1, 10)), None);
+        assert_eq!(store.push(tweet(2, 20)), None);
+        assert_eq!(store.push(tweet(3, 30)), None);
+        assert_eq!(ids(&*store), vec![1, 2, 3]);
+
+        // The oldest tweet is evicted once full, across wrap-arounds
+        assert_eq!(store.push(tweet(4, 40)), Some(tweet(1, 10)));
+        assert_eq!(store.push(tweet(5, 50)), Some(tweet(2, 20)));
+        assert_eq!(ids(&*store), vec![3, 4, 5]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.newest_created_at(), Some(tweet(5, 50).created_at));
+
+        // Late tweets are inserted by creation time
+        assert_eq!(store.insert_by_created_at(tweet(6, 45)), Some(tweet(3, 30)));
+        assert_eq!(ids(&*store), vec![4, 6, 5]);
+        assert_eq!(store.insert_by_created_at(tweet(7, 0)), Some(tweet(7, 0)));
+        assert_eq!(ids(&*store), vec![4, 6, 5]);
+
+        assert_eq!(store.remove_oldest(2), 2);
+        assert_eq!(ids(&*store), vec![5]);
+        assert_eq!(store.push(tweet(8, 60)), None);
+        assert_eq!(ids(&*store), vec![5, 8]);
+
+        assert_eq!(store.remove_oldest(5), 2);
+        assert!(store.is_empty());
+    }
+}